@@ -0,0 +1,21 @@
+// Compares move thread throughput with the particle system behind a lock against the lock-free atomic prototype and its double-buffered variant
+// Run with `cargo bench --bench atomic_positions`
+use particles::atomic::{run_atomic_simulation, run_double_buffered_simulation};
+use particles::config::SimConfig;
+use particles::run_simulation;
+use std::time::Duration;
+
+fn main() {
+    for &particle_count in &[100, 1000, 10000] {
+        let config = SimConfig { particle_count, duration: Duration::from_secs(2), seed: Some(1), ..SimConfig::default() };
+
+        let locked = run_simulation::<2>(&config);
+        let atomic = run_atomic_simulation::<2>(&config);
+        let double_buffered = run_double_buffered_simulation::<2>(&config);
+
+        println!("{} particles, {} move threads", particle_count, config.thread_count);
+        println!("    lock:   {:.0} iterations per thread per second, {} collision frames", locked.avg_move_iterations_per_thread / config.duration.as_secs_f64(), locked.total_frames);
+        println!("    atomic: {:.0} iterations per thread per second, {} collision frames", atomic.avg_move_iterations_per_thread / config.duration.as_secs_f64(), atomic.total_frames);
+        println!("    double: {:.0} iterations per thread per second, {} collision frames", double_buffered.avg_move_iterations_per_thread / config.duration.as_secs_f64(), double_buffered.total_frames);
+    }
+}
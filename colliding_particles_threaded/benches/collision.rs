@@ -0,0 +1,81 @@
+// Times one detect_collisions call on each broadphase, on the same seeded particles at several sizes
+// Then the dense grid against the spatial hash, on particles packed into the usual enclosure and on the same number spread thinly over a huge one
+// Run with `cargo bench --bench collision`, Criterion compares each run against the last so a change shows up as a percentage
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use particles::broadphase::{Broadphase, BruteForce, QuadTree, SpatialGrid, SpatialHash, SweepAndPrune};
+use particles::soa::ParticleSoA;
+use particles::{detect_collisions, Enclosure, Layout, ParticleSystem, ENCLOSURE_D, ENCLOSURE_H, ENCLOSURE_W, PARTICLE_RADIUS};
+
+const COLLISION_DISTANCE : f32 = PARTICLE_RADIUS * 2.0;
+const SPARSE_ENCLOSURE_SIZE : f32 = 200.0; // 2000 by 2000 grid cells, nearly all of them empty
+
+fn collision_detection(c: &mut Criterion) {
+    let mut group = c.benchmark_group("detect_collisions");
+    group.sample_size(10); // Brute force on 10000 particles takes a while per call
+
+    for &particle_count in &[100, 1000, 10000] {
+        let particles = ParticleSystem::builder().particle_count(particle_count).initial_layout(Layout::RandomUniform).seed(1).build().particles;
+
+        let broadphases : Vec<(&str, Box<dyn Broadphase>)> = vec![
+            ("brute-force", Box::new(BruteForce::new())),
+            ("grid", Box::new(SpatialGrid::new(ENCLOSURE_W, ENCLOSURE_H, ENCLOSURE_D, COLLISION_DISTANCE))),
+            ("hash", Box::new(SpatialHash::new(COLLISION_DISTANCE))),
+            ("quadtree", Box::new(QuadTree::new(ENCLOSURE_W, ENCLOSURE_H, COLLISION_DISTANCE))),
+            ("sweep", Box::new(SweepAndPrune::new())), // Benchmarked on the same particles, so every call after the first starts sorted
+        ];
+
+        for (name, mut broadphase) in broadphases {
+            group.bench_with_input(BenchmarkId::new(name, particle_count), &particles, |b, particles| {
+                b.iter(|| detect_collisions(particles, broadphase.as_mut()));
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn grid_against_hash(c: &mut Criterion) {
+    let mut group = c.benchmark_group("grid_vs_hash");
+    group.sample_size(10);
+
+    let particle_count = 10000;
+    let distributions = [
+        ("dense", ENCLOSURE_W, ENCLOSURE_H),
+        ("sparse", SPARSE_ENCLOSURE_SIZE, SPARSE_ENCLOSURE_SIZE),
+    ];
+
+    for &(distribution, width, height) in &distributions {
+        let particles = ParticleSystem::builder().particle_count(particle_count).enclosure(Enclosure::Rect { w: width, h: height }).initial_layout(Layout::RandomUniform).seed(1).build().particles;
+
+        let broadphases : Vec<(&str, Box<dyn Broadphase>)> = vec![
+            ("grid", Box::new(SpatialGrid::new(width, height, ENCLOSURE_D, COLLISION_DISTANCE))),
+            ("hash", Box::new(SpatialHash::new(COLLISION_DISTANCE))),
+        ];
+
+        for (name, mut broadphase) in broadphases {
+            group.bench_with_input(BenchmarkId::new(name, distribution), &particles, |b, particles| {
+                b.iter(|| detect_collisions(particles, broadphase.as_mut()));
+            });
+        }
+    }
+
+    group.finish();
+}
+
+// Brute force over 10000 particles laid out as an array of particles against the same particles as one array per field
+fn particles_against_arrays(c: &mut Criterion) {
+    let mut group = c.benchmark_group("layout_10000");
+    group.sample_size(10);
+
+    let particles = ParticleSystem::builder().particle_count(10000).initial_layout(Layout::RandomUniform).seed(1).build().particles;
+    let soa = ParticleSoA::from_particles(&particles);
+    let mut brute_force = BruteForce::new();
+
+    group.bench_function("array_of_structs", |b| b.iter(|| detect_collisions(&particles, &mut brute_force)));
+    group.bench_function("struct_of_arrays", |b| b.iter(|| soa.detect_collisions()));
+
+    group.finish();
+}
+
+criterion_group!(benches, collision_detection, grid_against_hash, particles_against_arrays);
+criterion_main!(benches);
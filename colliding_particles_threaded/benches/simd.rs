@@ -0,0 +1,36 @@
+// Checks every pair of 10000 particles one pair at a time, then eight at a time with the simd feature's vectors
+// Run with `cargo bench --bench simd --features simd`
+use criterion::{criterion_group, criterion_main, Criterion};
+use particles::simd::Columns;
+use particles::{Layout, ParticleSystem};
+
+fn scalar_against_simd(c: &mut Criterion) {
+    let mut group = c.benchmark_group("all_pairs_10000");
+    group.sample_size(10);
+
+    let particles = ParticleSystem::builder().particle_count(10000).initial_layout(Layout::RandomUniform).seed(1).build().particles;
+    let mut columns = Columns::default();
+    columns.rebuild(&particles);
+
+    group.bench_function("scalar", |b| b.iter(|| {
+        let mut count = 0;
+        for i in 0..particles.len() {
+            for j in i + 1..particles.len() {
+                count += usize::from(particles[i].perform_collision_check(&particles[j]));
+            }
+        }
+        count
+    }));
+    group.bench_function("simd", |b| b.iter(|| {
+        let mut count = 0;
+        for i in 0..particles.len() {
+            columns.for_each_collision_after(i, |_| count += 1);
+        }
+        count
+    }));
+
+    group.finish();
+}
+
+criterion_group!(benches, scalar_against_simd);
+criterion_main!(benches);
@@ -0,0 +1,25 @@
+// Compares how many frames the same run gets through in a fixed time with the particles shared behind a Mutex, an RwLock,
+// or the double buffer, with the same seed, particle count and thread counts for each
+// Run with `cargo bench --bench sync_strategies`
+use particles::atomic::{run_double_buffered_simulation, run_mutex_simulation, run_rwlock_simulation};
+use particles::config::SimConfig;
+use particles::SimReport;
+use std::time::Duration;
+
+type Backend = (&'static str, fn(&SimConfig) -> SimReport);
+
+fn main() {
+    for &particle_count in &[100, 1000, 10000] {
+        for &collision_thread_count in &[1, 2] {
+            let config = SimConfig { particle_count, collision_thread_count, duration: Duration::from_secs(2), seed: Some(1), ..SimConfig::default() };
+            let seconds = config.duration.as_secs_f64();
+
+            println!("{} particles, {} move threads, {} collision threads, {:.0}s each", particle_count, config.thread_count, collision_thread_count, seconds);
+            let backends : [Backend; 3] = [("mutex", run_mutex_simulation), ("rwlock", run_rwlock_simulation), ("double", run_double_buffered_simulation)];
+            for (name, run) in backends {
+                let report = run(&config);
+                println!("    {:<7} {:>8} collision frames, {:.0} move iterations per thread per second", format!("{}:", name), report.total_frames, report.avg_move_iterations_per_thread / seconds);
+            }
+        }
+    }
+}
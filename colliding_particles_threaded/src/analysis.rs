@@ -0,0 +1,262 @@
+// Measurements taken on a snapshot of the particles for looking at afterwards, outside the collision loop
+use crate::kdtree::KdTree;
+use crate::Particle;
+use rayon::prelude::*;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+pub const SPEED_HISTOGRAM_BINS : usize = 20; // Bins in a speed histogram written without a bin count being given
+const HISTOGRAM_BAR_WIDTH : usize = 40; // Characters in the longest bar of a printed histogram
+
+// The distance between every pair of particles in a snapshot, by index
+// Only the pairs with i < j are stored, as the matrix is symmetric with zeros down the diagonal. They are packed row by row,
+// row i holding (i, i + 1) to (i, n - 1), so for n particles there are n * (n - 1) / 2 distances and (i, j) is at
+// i * n - i * (i + 1) / 2 + j - i - 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistanceMatrix {
+    len: usize,
+    packed: Vec<f32>,
+}
+
+impl DistanceMatrix {
+    // Number of particles, so the matrix is len by len
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    // Either way round, as the distance from i to j is the distance from j to i
+    pub fn at(&self, i: usize, j: usize) -> f32 {
+        assert!(i < self.len && j < self.len, "({}, {}) is outside a distance matrix of {} particles", i, j, self.len);
+        match i.cmp(&j) {
+            std::cmp::Ordering::Equal => 0.0,
+            std::cmp::Ordering::Less => self.packed[packed_index(self.len, i, j)],
+            std::cmp::Ordering::Greater => self.packed[packed_index(self.len, j, i)],
+        }
+    }
+
+    // The upper triangle in the packing order described above
+    pub fn packed(&self) -> &[f32] {
+        &self.packed
+    }
+
+    pub fn into_packed(self) -> Vec<f32> {
+        self.packed
+    }
+}
+
+fn packed_index(len: usize, i: usize, j: usize) -> usize {
+    row_start(len, i) + j - i - 1
+}
+
+fn row_start(len: usize, i: usize) -> usize {
+    i * len - i * (i + 1) / 2
+}
+
+// The pair at position k of the packing order, the inverse of packed_index
+// Row i starts at i * len - i * (i + 1) / 2, so the row is the smaller root of that quadratic at k, estimated in f64 and
+// then nudged onto the right row in case rounding left it one out
+fn pair_at(len: usize, k: usize) -> (usize, usize) {
+    let b = (2 * len - 1) as f64;
+    let mut i = ((b - (b * b - 8.0 * k as f64).max(0.0).sqrt()) / 2.0) as usize;
+    while i > 0 && row_start(len, i) > k {
+        i -= 1;
+    }
+    while i + 1 < len && row_start(len, i + 1) <= k {
+        i += 1;
+    }
+    (i, k - row_start(len, i) + i + 1)
+}
+
+// Every pair of particles once, lower index first, in the packing order above for any analysis to run over on Rayon's thread pool
+// It is the pairs that are shared out rather than the rows, so each thread gets the same number of pairs to work through,
+// where splitting by the first index would give the threads with the early rows far more than the ones with the late rows
+// Being indexed it can be zipped with anything else in the packing order, a distance matrix's buffer for one
+pub fn par_pairs<const N: usize>(particles: &[Particle<N>]) -> impl IndexedParallelIterator<Item = (usize, usize)> {
+    let len = particles.len();
+    (0..len * len.saturating_sub(1) / 2).into_par_iter().map(move |k| pair_at(len, k))
+}
+
+// Every pairwise distance, with the pairs shared out evenly across Rayon's thread pool
+// Distances are measured directly, so with periodic boundaries particles either side of an edge are a whole enclosure apart
+pub fn distance_matrix<const N: usize>(particles: &[Particle<N>]) -> DistanceMatrix {
+    let len = particles.len();
+    let mut packed = vec![0.0; len * len.saturating_sub(1) / 2];
+
+    packed.par_iter_mut().zip(par_pairs(particles)).for_each(|(distance, (i, j))| {
+        *distance = particles[i].squared_distance(&particles[j]).sqrt();
+    });
+
+    DistanceMatrix { len, packed }
+}
+
+// For each particle by index, the index of the closest other particle and the squared distance to it
+// Found with a k-d tree, so it's O(n log n) rather than trying every pair. The tree is split on every axis the particles have
+// With fewer than two particles nobody has a neighbour, and it's empty
+pub fn nearest_neighbors<const N: usize>(particles: &[Particle<N>]) -> Vec<(usize, f32)> {
+    if particles.len() < 2 {
+        return Vec::new();
+    }
+
+    let tree = KdTree::new(particles);
+    particles.par_iter().map(|p| tree.nearest(p).expect("there is another particle")).collect()
+}
+
+// The speed of the fastest particle, which the last bin of a speed histogram ends at, 0 if there are none
+pub fn fastest_speed<const N: usize>(particles: &[Particle<N>]) -> f32 {
+    particles.iter().map(Particle::speed).fold(0.0, f32::max)
+}
+
+// How many particles move at each speed, in bins of equal width from 0 up to the fastest particle, which goes in the last bin
+// A thermalised gas should come out close to the Maxwell-Boltzmann distribution, and keep to it as collisions share the energy out
+// If nothing is moving every particle is in the first bin
+pub fn speed_histogram<const N: usize>(particles: &[Particle<N>], bins: usize) -> Vec<usize> {
+    let mut counts = vec![0; bins];
+    let fastest = fastest_speed(particles);
+    if bins == 0 {
+        return counts;
+    }
+
+    for p in particles {
+        let bin = if fastest > 0.0 { (p.speed() / fastest * bins as f32) as usize } else { 0 };
+        counts[bin.min(bins - 1)] += 1;
+    }
+    counts
+}
+
+// The range of speeds bin i of a histogram up to fastest covers
+fn bin_range(i: usize, bins: usize, fastest: f32) -> (f32, f32) {
+    let width = fastest / bins as f32;
+    (i as f32 * width, (i + 1) as f32 * width)
+}
+
+// One line per bin of the speeds it covers, its count and a bar as long as its share of the fullest bin
+pub fn format_speed_histogram(histogram: &[usize], fastest: f32) -> String {
+    let fullest = histogram.iter().copied().max().unwrap_or(0).max(1);
+    let mut table = String::new();
+    for (i, &count) in histogram.iter().enumerate() {
+        let (from, to) = bin_range(i, histogram.len(), fastest);
+        let _ = writeln!(table, "{:>9.4} - {:<9.4} {:>7} {}", from, to, count, "#".repeat(count * HISTOGRAM_BAR_WIDTH / fullest));
+    }
+    table
+}
+
+// One speed_from,speed_to,count line per bin
+pub fn save_speed_histogram(path: &str, histogram: &[usize], fastest: f32) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    writeln!(file, "speed_from,speed_to,count")?;
+    for (i, &count) in histogram.iter().enumerate() {
+        let (from, to) = bin_range(i, histogram.len(), fastest);
+        writeln!(file, "{},{},{}", from, to, count)?;
+    }
+
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Layout, ParticleSystem, PARTICLE_RADIUS};
+
+    #[test]
+    fn distances_are_packed_row_by_row_above_the_diagonal() {
+        let particles = vec![
+            Particle::new_3d(0.0, 0.0, 0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new_3d(3.0, 4.0, 0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new_3d(3.0, 0.0, 0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new_3d(0.0, 0.0, 2.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS),
+        ];
+
+        let matrix = distance_matrix(&particles);
+
+        assert_eq!(matrix.len(), 4);
+        assert_eq!(matrix.packed().len(), 6);
+        assert_eq!(matrix.at(0, 1), 5.0);
+        assert_eq!(matrix.at(2, 1), 4.0);
+        assert_eq!(matrix.at(3, 0), 2.0);
+        assert_eq!(matrix.at(2, 2), 0.0);
+        for i in 0..4 {
+            for j in 0..4 {
+                assert_eq!(matrix.at(i, j), particles[i].squared_distance(&particles[j]).sqrt());
+            }
+        }
+
+        assert!(distance_matrix::<2>(&[]).is_empty());
+        assert!(distance_matrix(&particles[..1]).packed().is_empty());
+    }
+
+    #[test]
+    fn parallel_pairs_are_every_pair_once_in_the_packing_order() {
+        let particles = vec![Particle::new(0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS); 40];
+        let pairs : Vec<(usize, usize)> = par_pairs(&particles).collect();
+        let expected : Vec<(usize, usize)> = (0..40).flat_map(|i| (i + 1..40).map(move |j| (i, j))).collect();
+        assert_eq!(pairs, expected);
+        assert_eq!(par_pairs(&particles).len(), 40 * 39 / 2);
+        assert_eq!(par_pairs(&particles[..1]).count(), 0);
+        assert_eq!(par_pairs::<2>(&[]).count(), 0);
+
+        // Millions of rows in, where rounding in the f64 estimate of the row would show
+        let len = 3_000_000;
+        for &(i, j) in &[(0, 1), (0, len - 1), (1, 2), (len / 2, len / 2 + 1), (len - 3, len - 1), (len - 2, len - 1)] {
+            assert_eq!(pair_at(len, packed_index(len, i, j)), (i, j));
+        }
+
+        // A parallel count over the pairs, as collision detection would be written with it
+        let close = par_pairs(&particles).filter(|&(i, j)| particles[i].perform_collision_check(&particles[j])).count();
+        assert_eq!(close, 40 * 39 / 2);
+    }
+
+    #[test]
+    fn every_particle_gets_its_nearest_other_particle() {
+        let particles = vec![
+            Particle::new_3d(1.0, 1.0, 0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new_3d(1.5, 1.0, 0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new_3d(4.0, 1.0, 0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new_3d(1.0, 1.0, 0.2, 0.0, 0.0, 0.0, PARTICLE_RADIUS),
+        ];
+        let neighbours = nearest_neighbors(&particles);
+
+        assert_eq!(neighbours.iter().map(|&(j, _)| j).collect::<Vec<_>>(), vec![3, 0, 1, 0]);
+        assert!((neighbours[0].1 - 0.04).abs() < 1e-6 && (neighbours[2].1 - 6.25).abs() < 1e-6);
+        assert!(nearest_neighbors(&particles[..1]).is_empty());
+
+        // The same as trying every other particle
+        let particles = ParticleSystem::builder().particle_count(300).initial_layout(Layout::RandomUniform).seed(6).build().particles;
+        for (i, &(j, squared_distance)) in nearest_neighbors(&particles).iter().enumerate() {
+            let closest = particles.iter().enumerate().filter(|&(k, _)| k != i).map(|(_, other)| particles[i].squared_distance(other)).fold(f32::INFINITY, f32::min);
+            assert_ne!(i, j);
+            assert_eq!(squared_distance, closest);
+        }
+    }
+
+    #[test]
+    fn speeds_are_binned_up_to_the_fastest() {
+        let particles = vec![
+            Particle::new(1.0, 1.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new(1.0, 1.0, 0.3, 0.4, PARTICLE_RADIUS),
+            Particle::new(1.0, 1.0, 0.0, -1.5, PARTICLE_RADIUS),
+            Particle::new(1.0, 1.0, 4.0, 0.0, PARTICLE_RADIUS),
+        ];
+
+        assert_eq!(fastest_speed(&particles), 4.0);
+        assert_eq!(speed_histogram(&particles, 4), vec![2, 1, 0, 1]); // The fastest is at the top of the last bin
+        assert_eq!(speed_histogram(&particles, 1), vec![4]);
+        assert_eq!(speed_histogram(&particles[..1], 3), vec![1, 0, 0]);
+        assert!(speed_histogram(&particles, 0).is_empty());
+
+        let table = format_speed_histogram(&[2, 1, 0, 1], 4.0);
+        assert_eq!(table.lines().count(), 4);
+        assert!(table.lines().next().unwrap().ends_with(&format!(" 2 {}", "#".repeat(HISTOGRAM_BAR_WIDTH))));
+
+        let path = std::env::temp_dir().join(format!("particles_speeds_{}.csv", std::process::id()));
+        save_speed_histogram(path.to_str().unwrap(), &[2, 1, 0, 1], 4.0).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "speed_from,speed_to,count\n0,1,2\n1,2,1\n2,3,0\n3,4,1\n");
+    }
+}
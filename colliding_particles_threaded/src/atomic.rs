@@ -0,0 +1,439 @@
+// Prototype of running the move threads without any lock, by keeping positions in atomics
+//
+// Each coordinate is an f32 bit-cast into an AtomicU32, and every load and store is Relaxed. That means:
+// - A single coordinate is never torn, but each axis is a separate atomic, so a reader can see a particle's
+//   new x with its old y. This is accepted as a sampling approximation, the error is at most one timestep of movement
+// - Relaxed gives no ordering between particles either, a snapshot can mix moves from different iterations,
+//   which the free-running threads already do under the lock
+// - Velocities stay with the move thread that owns the particle, so collisions are detected but not bounced
+//
+// The double-buffered variant keeps two copies of the positions instead, so readers only ever see complete frames:
+// - The move threads write the next frame into the back buffer, meet at a barrier, and the last to arrive swaps it to the front
+// - Readers copy the front buffer, then check no swap happened while they were copying, as the move threads would then have
+//   started overwriting what they read, and copy again if one did
+// - The move threads have to keep in step to share a swap, so the slowest chunk sets the pace
+//
+// For a fair comparison the same stripped-down run can also share the particles behind a Mutex or an RwLock, with each
+// move thread moving its chunk in place while it holds the lock
+use crate::broadphase::{make_strip_detector, CollisionDetector};
+use crate::config::SimConfig;
+use crate::walls::WallCounts;
+use crate::{catch_panic, chunk_ranges, detect_collisions, move_particles, panic_message, starting_system, CollisionStats, Particle, ParticleSystem, SimError, SimReport, TIMESTEP};
+use rand::random;
+use std::collections::HashSet;
+use std::sync::atomic::{fence, AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Barrier, Mutex, PoisonError, RwLock};
+use std::thread;
+use std::time::Instant;
+
+// Every axis of every particle, stored one after another
+pub struct AtomicPositions<const N: usize = 2> {
+    coordinates: Vec<AtomicU32>,
+}
+
+impl<const N: usize> AtomicPositions<N> {
+    pub fn new(particles: &[Particle<N>]) -> Self {
+        let coordinates = particles.iter().flat_map(|p| p.position).map(|value| AtomicU32::new(value.to_bits())).collect();
+        AtomicPositions { coordinates }
+    }
+
+    pub fn len(&self) -> usize {
+        self.coordinates.len() / N
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.coordinates.is_empty()
+    }
+
+    // Store the positions of a chunk of particles starting at index start
+    pub fn store(&self, start: usize, particles: &[Particle<N>]) {
+        for (i, p) in particles.iter().enumerate() {
+            let base = (start + i) * N;
+            for (coordinate, value) in self.coordinates[base..base + N].iter().zip(p.position) {
+                coordinate.store(value.to_bits(), Ordering::Relaxed);
+            }
+        }
+    }
+
+    // Copy the latest positions into particles, leaving their other fields alone
+    pub fn load_into(&self, particles: &mut [Particle<N>]) {
+        for (i, p) in particles.iter_mut().enumerate() {
+            let base = i * N;
+            for (value, coordinate) in p.position.iter_mut().zip(&self.coordinates[base..base + N]) {
+                *value = f32::from_bits(coordinate.load(Ordering::Relaxed));
+            }
+        }
+    }
+}
+
+// Two sets of atomic positions, one being read while the next frame is written into the other
+pub struct DoubleBufferedPositions<const N: usize = 2> {
+    buffers: [AtomicPositions<N>; 2],
+    front: AtomicUsize, // Count of swaps so far, the buffer readers use is front % 2
+    barrier: Barrier, // Every move thread meets here before a swap and again after it
+    running: AtomicBool, // Whether the move threads go on to another frame, decided at each swap
+}
+
+impl<const N: usize> DoubleBufferedPositions<N> {
+    pub fn new(particles: &[Particle<N>], move_thread_count: usize) -> Self {
+        DoubleBufferedPositions {
+            buffers: [AtomicPositions::new(particles), AtomicPositions::new(particles)],
+            front: AtomicUsize::new(0),
+            barrier: Barrier::new(move_thread_count),
+            running: AtomicBool::new(true),
+        }
+    }
+
+    // Store a move thread's chunk in the back buffer, then wait for the other move threads and swap
+    // The last to arrive swaps and asks keep_running whether there is another frame, every thread returns its answer
+    pub fn publish(&self, start: usize, chunk: &[Particle<N>], keep_running: impl FnOnce() -> bool) -> bool {
+        let back = &self.buffers[(self.front.load(Ordering::Acquire) + 1) % 2];
+        fence(Ordering::Release); // A reader that sees any of the stores below is then sure to see the swap that freed the buffer
+        back.store(start, chunk);
+
+        if self.barrier.wait().is_leader() {
+            self.front.fetch_add(1, Ordering::Release); // The barrier has ordered every chunk's stores before this
+            self.running.store(keep_running(), Ordering::Relaxed);
+        }
+        self.barrier.wait(); // Nobody looks for the next back buffer until the swap is done
+        self.running.load(Ordering::Relaxed)
+    }
+
+    // Copy the latest complete frame into particles, leaving their other fields alone, and return how many swaps it came after
+    pub fn load_into(&self, particles: &mut [Particle<N>]) -> usize {
+        loop {
+            let frame = self.front.load(Ordering::Acquire);
+            self.buffers[frame % 2].load_into(particles);
+            fence(Ordering::Acquire);
+            if self.front.load(Ordering::Relaxed) == frame {
+                return frame;
+            }
+        }
+    }
+}
+
+// The particles behind a single lock, a Mutex makes readers queue like writers and an RwLock lets them share
+pub trait LockedPositions<const N: usize = 2>: Send + Sync + 'static {
+    fn new(particles: &[Particle<N>]) -> Self;
+
+    // Run move on the particles from start on, holding the lock as a writer
+    fn write_chunk(&self, start: usize, len: usize, move_chunk: impl FnOnce(&mut [Particle<N>]));
+
+    // Copy every particle into particles, holding the lock as a reader
+    fn load_into(&self, particles: &mut [Particle<N>]);
+}
+
+impl<const N: usize> LockedPositions<N> for Mutex<Vec<Particle<N>>> {
+    fn new(particles: &[Particle<N>]) -> Self {
+        Mutex::new(particles.to_vec())
+    }
+
+    fn write_chunk(&self, start: usize, len: usize, move_chunk: impl FnOnce(&mut [Particle<N>])) {
+        move_chunk(&mut self.lock().unwrap_or_else(PoisonError::into_inner)[start..start + len]);
+    }
+
+    fn load_into(&self, particles: &mut [Particle<N>]) {
+        particles.copy_from_slice(&self.lock().unwrap_or_else(PoisonError::into_inner));
+    }
+}
+
+impl<const N: usize> LockedPositions<N> for RwLock<Vec<Particle<N>>> {
+    fn new(particles: &[Particle<N>]) -> Self {
+        RwLock::new(particles.to_vec())
+    }
+
+    fn write_chunk(&self, start: usize, len: usize, move_chunk: impl FnOnce(&mut [Particle<N>])) {
+        move_chunk(&mut self.write().unwrap_or_else(PoisonError::into_inner)[start..start + len]);
+    }
+
+    fn load_into(&self, particles: &mut [Particle<N>]) {
+        particles.copy_from_slice(&self.read().unwrap_or_else(PoisonError::into_inner));
+    }
+}
+
+// Move the chunk in place behind the lock, taking it once per iteration like run_simulation's move threads
+// The chunk passed in only gives its length, and is handed back with the particles as they finished
+pub fn locked_move_thread_main<const N: usize, L: LockedPositions<N>>(positions: Arc<L>, start: usize, mut chunk: Vec<Particle<N>>, config: SimConfig) -> (u32, Vec<Particle<N>>) {
+    let mut iterations: u32 = 0;
+    let start_time = Instant::now();
+
+    while start_time.elapsed() < config.duration {
+        positions.write_chunk(start, chunk.len(), |shared| move_particles(shared, TIMESTEP, config.gravity, &config.enclosure, config.boundary, config.depth));
+        iterations += 1;
+    }
+
+    positions.write_chunk(start, chunk.len(), |shared| chunk.copy_from_slice(shared));
+    (iterations, chunk)
+}
+
+// Copy a snapshot out from behind the lock and count the collisions in it once the lock is let go
+pub fn locked_collision_thread_main<const N: usize, L: LockedPositions<N>>(positions: Arc<L>, mut snapshot: Vec<Particle<N>>, mut detector: Box<dyn CollisionDetector<N> + Send>, config: SimConfig) -> CollisionStats {
+    let start_time = Instant::now();
+    let mut stats = CollisionStats::default();
+    let mut previous_overlaps : HashSet<(u64, u64)> = HashSet::new();
+
+    while start_time.elapsed() < config.duration {
+        positions.load_into(&mut snapshot);
+
+        let overlaps : Vec<_> = detect_collisions(&snapshot, detector.as_mut()).into_iter().map(|(i, j, species)| ((snapshot[i].id, snapshot[j].id), species)).collect();
+        stats.frames += 1;
+        stats.count_overlaps(&overlaps, &mut previous_overlaps);
+    }
+
+    stats
+}
+
+// Move a private copy of the chunk and publish its positions, never taking a lock
+// Always steps with Euler and gravity only, as pair forces and the integrators read the whole system, and the movement models aren't used either
+pub fn atomic_move_thread_main<const N: usize>(positions: Arc<AtomicPositions<N>>, start: usize, mut chunk: Vec<Particle<N>>, config: SimConfig) -> (u32, Vec<Particle<N>>) {
+    let mut iterations: u32 = 0;
+    let start_time = Instant::now();
+
+    while start_time.elapsed() < config.duration {
+        move_particles(&mut chunk, TIMESTEP, config.gravity, &config.enclosure, config.boundary, config.depth);
+        positions.store(start, &chunk);
+        iterations += 1;
+    }
+
+    (iterations, chunk)
+}
+
+// As atomic_move_thread_main, but publishing whole frames in step with the other move threads
+// A thread that panics stops moving but keeps turning up to the swaps so the others aren't left waiting, then panics again at the end
+pub fn double_buffered_move_thread_main<const N: usize>(positions: Arc<DoubleBufferedPositions<N>>, start: usize, mut chunk: Vec<Particle<N>>, config: SimConfig) -> (u32, Vec<Particle<N>>) {
+    let mut iterations: u32 = 0;
+    let mut failure : Option<String> = None;
+    let start_time = Instant::now();
+
+    loop {
+        if failure.is_none() {
+            failure = catch_panic(|| move_particles(&mut chunk, TIMESTEP, config.gravity, &config.enclosure, config.boundary, config.depth)).err();
+        }
+        if failure.is_none() {
+            iterations += 1;
+        }
+        if !positions.publish(start, &chunk, || start_time.elapsed() < config.duration) {
+            break;
+        }
+    }
+
+    if let Some(message) = failure {
+        panic!("{}", message);
+    }
+    (iterations, chunk)
+}
+
+// Read snapshots straight out of the atomics and count the collisions in them
+pub fn atomic_collision_thread_main<const N: usize>(positions: Arc<AtomicPositions<N>>, mut snapshot: Vec<Particle<N>>, mut detector: Box<dyn CollisionDetector<N> + Send>, config: SimConfig) -> CollisionStats {
+    let start_time = Instant::now();
+    let mut stats = CollisionStats::default();
+    let mut previous_overlaps : HashSet<(u64, u64)> = HashSet::new();
+
+    while start_time.elapsed() < config.duration {
+        positions.load_into(&mut snapshot);
+
+        let overlaps : Vec<_> = detect_collisions(&snapshot, detector.as_mut()).into_iter().map(|(i, j, species)| ((snapshot[i].id, snapshot[j].id), species)).collect();
+        stats.frames += 1;
+        stats.count_overlaps(&overlaps, &mut previous_overlaps);
+    }
+
+    stats
+}
+
+// Only check the frames the double buffer swaps in, each of them complete and checked once
+pub fn double_buffered_collision_thread_main<const N: usize>(positions: Arc<DoubleBufferedPositions<N>>, mut snapshot: Vec<Particle<N>>, mut detector: Box<dyn CollisionDetector<N> + Send>, config: SimConfig) -> CollisionStats {
+    let start_time = Instant::now();
+    let mut stats = CollisionStats::default();
+    let mut previous_overlaps : HashSet<(u64, u64)> = HashSet::new();
+    let mut last_frame = None;
+
+    while start_time.elapsed() < config.duration {
+        let frame = positions.load_into(&mut snapshot);
+        if last_frame == Some(frame) {
+            thread::yield_now();
+            continue;
+        }
+        last_frame = Some(frame);
+
+        let overlaps : Vec<_> = detect_collisions(&snapshot, detector.as_mut()).into_iter().map(|(i, j, species)| ((snapshot[i].id, snapshot[j].id), species)).collect();
+        stats.frames += 1;
+        stats.count_overlaps(&overlaps, &mut previous_overlaps);
+    }
+
+    stats
+}
+
+// The stripped-down run of run_atomic_simulation with the particles behind a Mutex, which snapshots wait on as long as moves do
+pub fn run_mutex_simulation<const N: usize>(config: &SimConfig) -> SimReport<N> {
+    run_shared(config, |particles, _| <Mutex<Vec<Particle<N>>> as LockedPositions<N>>::new(particles), locked_move_thread_main, locked_collision_thread_main)
+}
+
+// As run_mutex_simulation with an RwLock, so snapshots from several collision threads can be taken at once
+pub fn run_rwlock_simulation<const N: usize>(config: &SimConfig) -> SimReport<N> {
+    run_shared(config, |particles, _| <RwLock<Vec<Particle<N>>> as LockedPositions<N>>::new(particles), locked_move_thread_main, locked_collision_thread_main)
+}
+
+// run_simulation with the atomic positions, for comparing throughput against the lock
+// Only movement and collision detection run, forces, recording and rendering are left out of the prototype
+// It always runs for config.duration, as the collision threads have no way to tell when the move threads have done a step count
+pub fn run_atomic_simulation<const N: usize>(config: &SimConfig) -> SimReport<N> {
+    run_shared(config, |particles, _| AtomicPositions::new(particles), atomic_move_thread_main, atomic_collision_thread_main)
+}
+
+// run_atomic_simulation with the double-buffered positions, where every frame the collision threads check is complete
+pub fn run_double_buffered_simulation<const N: usize>(config: &SimConfig) -> SimReport<N> {
+    run_shared(config, DoubleBufferedPositions::new, double_buffered_move_thread_main, double_buffered_collision_thread_main)
+}
+
+type SharedMoveThread<P, const N: usize> = fn(Arc<P>, usize, Vec<Particle<N>>, SimConfig) -> (u32, Vec<Particle<N>>);
+type SharedCollisionThread<P, const N: usize> = fn(Arc<P>, Vec<Particle<N>>, Box<dyn CollisionDetector<N> + Send>, SimConfig) -> CollisionStats;
+
+// Share positions made from the starting particles and the move thread count between threads running move_thread and collision_thread
+fn run_shared<const N: usize, P: Send + Sync + 'static>(config: &SimConfig, positions: impl FnOnce(&[Particle<N>], usize) -> P, move_thread: SharedMoveThread<P, N>, collision_thread: SharedCollisionThread<P, N>) -> SimReport<N> {
+    assert!(config.is_3d() == (N > 2), "A config with a depth of {} can't be run with {} axes", config.depth, N);
+    let start_time = Instant::now();
+    let seed = config.seed.unwrap_or_else(random);
+    let system = starting_system(config, seed);
+
+    let chunks = chunk_ranges(system.particles.len(), config.thread_count);
+    let positions = Arc::new(positions(&system.particles, chunks.len()));
+    let move_threads : Vec<_> = chunks.iter().map(|chunk| {
+        let positions = Arc::clone(&positions);
+        let (start, particles, config) = (chunk.start, system.particles[chunk.clone()].to_vec(), config.clone());
+        thread::spawn(move || move_thread(positions, start, particles, config))
+    }).collect();
+
+    let (stats_sender, stats_receiver) = mpsc::channel();
+    let collision_threads : Vec<_> = (0..config.collision_thread_count).map(|index| {
+        let detector = make_strip_detector(config.broadphase, config, index, config.collision_thread_count);
+        let (positions, snapshot, config, stats_sender) = (Arc::clone(&positions), system.particles.clone(), config.clone(), stats_sender.clone());
+        thread::spawn(move || { let _ = stats_sender.send(collision_thread(positions, snapshot, detector, config)); })
+    }).collect();
+    drop(stats_sender);
+
+    // Chunks are joined back in order, so the particles stay sorted by id
+    // A chunk whose thread panicked is put back where it started, as its moved copy went with the thread
+    let mut moved = Vec::with_capacity(system.particles.len());
+    let mut move_iterations = Vec::new();
+    let mut errors = Vec::new();
+    for (handle, chunk) in move_threads.into_iter().zip(chunks) {
+        match handle.join() {
+            Ok((iterations, particles)) => {
+                move_iterations.push(iterations);
+                moved.extend(particles);
+            }
+            Err(payload) => {
+                move_iterations.push(0);
+                moved.extend_from_slice(&system.particles[chunk.clone()]);
+                errors.push(SimError::MoveThreadPanicked { chunk, message: panic_message(payload) });
+            }
+        }
+    }
+    for handle in collision_threads {
+        if let Err(payload) = handle.join() {
+            errors.push(SimError::CollisionThreadPanicked { message: panic_message(payload) });
+        }
+    }
+
+    let collisions = stats_receiver.iter().fold(CollisionStats::default(), CollisionStats::add);
+    let avg_move_iterations_per_thread = move_iterations.iter().map(|&count| count as f64).sum::<f64>() / move_iterations.len() as f64;
+
+    SimReport {
+        config: config.clone(),
+        seed,
+        total_frames: collisions.frames,
+        unique_collisions: collisions.collision_count,
+        raw_collision_frames: collisions.overlapping_frame_count,
+        annihilations: 0, // Nothing is bounced either
+        merges: 0,
+        cluster_collisions: 0,
+        collisions_by_species: collisions.by_species,
+        max_energy_drift: 0.0, // Nothing is bounced and walls keep speeds, so there's nothing to drift
+        walls: WallCounts::default(), // The prototypes don't count them
+        move_iterations,
+        avg_move_iterations_per_thread,
+        wall_clock: start_time.elapsed(),
+        system: ParticleSystem::new(moved),
+        errors,
+        lock_profiles: Vec::new(), // Only run_simulation profiles its lock
+        recent_collisions: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PARTICLE_RADIUS;
+    use std::time::Duration;
+
+    #[test]
+    fn positions_round_trip_through_the_atomics() {
+        let particles = vec![Particle::new_3d(1.5, -2.25, 0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS), Particle::new_3d(3.0, 4.0, 5.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS)];
+        let positions = AtomicPositions::new(&particles);
+
+        let mut moved = particles.clone();
+        moved[1].position[0] = 7.0;
+        positions.store(1, &moved[1..]);
+
+        let mut loaded = vec![Particle::new_3d(0.0, 0.0, 0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS); 2];
+        positions.load_into(&mut loaded);
+
+        assert_eq!(positions.len(), 2);
+        assert_eq!(loaded[0].position, [1.5, -2.25, 0.0]);
+        assert_eq!(loaded[1].position, [7.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn readers_never_see_a_half_written_frame() {
+        const FRAMES : u32 = 2000;
+        let particles = vec![Particle::new(0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS); 64];
+        let positions = Arc::new(DoubleBufferedPositions::new(&particles, 2));
+
+        // Two writers each fill half of every frame with the frame's number, so a mixed frame shows up as more than one number
+        let writers : Vec<_> = vec![0..32, 32..64].into_iter().map(|chunk| {
+            let (positions, mut frame) = (Arc::clone(&positions), particles[chunk.clone()].to_vec());
+            thread::spawn(move || {
+                for number in 1..=FRAMES {
+                    for p in &mut frame {
+                        p.position = [number as f32; 2];
+                    }
+                    positions.publish(chunk.start, &frame, || number < FRAMES);
+                }
+            })
+        }).collect();
+
+        let mut snapshot = vec![Particle::new(0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS); 64];
+        let mut frames_seen = 0;
+        while frames_seen < FRAMES as usize {
+            let swaps = positions.load_into(&mut snapshot);
+            assert!(snapshot.iter().all(|p| p.position == [swaps as f32; 2]), "frame {} was mixed", swaps);
+            frames_seen = swaps;
+        }
+
+        for writer in writers {
+            writer.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn locked_runs_give_back_every_particle_moved() {
+        let config = SimConfig { particle_count: 40, thread_count: 3, collision_thread_count: 2, duration: Duration::from_millis(100), seed: Some(3), ..SimConfig::default() };
+        for report in [run_mutex_simulation::<2>(&config), run_rwlock_simulation(&config)] {
+            assert!(report.errors.is_empty() && report.total_frames > 0);
+            assert!(report.move_iterations.len() == 3 && report.move_iterations.iter().all(|&count| count > 0));
+            assert_eq!(report.system.particles.iter().map(|p| p.id).collect::<Vec<_>>(), (0..40).collect::<Vec<_>>());
+            assert!(report.system.particles.iter().any(|p| p.position != [0.0, 0.0]));
+        }
+    }
+
+    #[test]
+    fn double_buffered_runs_move_every_thread_the_same_number_of_frames() {
+        let config = SimConfig { particle_count: 40, thread_count: 4, duration: Duration::from_millis(200), seed: Some(3), ..SimConfig::default() };
+        let report = run_double_buffered_simulation::<2>(&config);
+
+        assert!(report.errors.is_empty());
+        assert!(report.move_iterations[0] > 0);
+        assert!(report.move_iterations.iter().all(|&count| count == report.move_iterations[0]));
+        assert_eq!(report.system.particles.iter().map(|p| p.id).collect::<Vec<_>>(), (0..40).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,25 @@
+// A particle's position and velocity have one value per axis, serialised as an array that must have exactly N values
+// Written out by hand as serde only implements arrays up to a fixed length rather than for any N
+use serde::de::{Deserialize, Deserializer, Error};
+use serde::ser::{Serialize, Serializer};
+use std::convert::TryFrom;
+
+pub fn serialize<S: Serializer, F: Serialize, const N: usize>(values: &[F; N], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(values)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>, F: Deserialize<'de>, const N: usize>(deserializer: D) -> Result<[F; N], D::Error> {
+    let values = Vec::<F>::deserialize(deserializer)?;
+    let len = values.len();
+    <[F; N]>::try_from(values).map_err(|_| D::Error::invalid_length(len, &format!("{} axes", N).as_str()))
+}
+
+// What each axis is called in the files a run writes, x, y and z and then numbered on from there
+pub fn name(axis: usize) -> String {
+    match axis {
+        0 => "x".to_string(),
+        1 => "y".to_string(),
+        2 => "z".to_string(),
+        _ => format!("axis{}", axis),
+    }
+}
@@ -0,0 +1,1026 @@
+use crate::config::SimConfig;
+#[cfg(feature = "simd")]
+use crate::simd::Columns;
+use crate::{in_region, region_bounds, BoundaryMode, Enclosure, Particle};
+use serde::Serialize;
+use rayon::prelude::*;
+use smallvec::SmallVec;
+use std::collections::HashMap;
+
+const QUADTREE_CAPACITY : usize = 8; // Particles held by a node before it subdivides
+const QUADTREE_MAX_DEPTH : usize = 8; // Stops coincident particles subdividing forever
+
+// Which broadphase the collision threads use
+// Serialised by the same names from_name takes
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum BroadphaseKind {
+    #[serde(rename = "brute-force")] BruteForce,
+    #[serde(rename = "parallel")] ParallelBruteForce,
+    #[serde(rename = "grid")] SpatialGrid,
+    #[serde(rename = "hash")] SpatialHash,
+    #[serde(rename = "quadtree")] QuadTree,
+    #[serde(rename = "sweep")] SweepAndPrune,
+}
+
+impl BroadphaseKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "brute-force" => Some(BroadphaseKind::BruteForce),
+            "parallel" => Some(BroadphaseKind::ParallelBruteForce),
+            "grid" => Some(BroadphaseKind::SpatialGrid),
+            "hash" => Some(BroadphaseKind::SpatialHash),
+            "quadtree" => Some(BroadphaseKind::QuadTree),
+            "sweep" => Some(BroadphaseKind::SweepAndPrune),
+            _ => None,
+        }
+    }
+}
+
+// Finds which particles are colliding, the collision threads only ever see one of these so any way of finding them can be plugged in
+// The grid, hash, quadtree and sweep find pairs in whatever order their cells or sweep visit them, not brute force's
+// row by row order. Bouncing a particle touching two others changes how the second pair bounces, so results are only
+// reproducible whichever detector is used because detect_collisions sorts pairs to brute force's order before anything
+// resolves them. Anything else that resolves what detect returns has to sort it by (lower index, higher index) first
+pub trait CollisionDetector<const N: usize = 2> {
+    // Every pair closer than the sum of their radii, lower index first but in no particular order
+    fn detect(&mut self, particles: &[Particle<N>]) -> Vec<(usize, usize)>;
+}
+
+// Every broadphase detects collisions by running the full check on its candidate pairs
+impl<const N: usize, B: Broadphase<N> + ?Sized> CollisionDetector<N> for B {
+    fn detect(&mut self, particles: &[Particle<N>]) -> Vec<(usize, usize)> {
+        self.colliding_pairs(particles)
+    }
+}
+
+// The detector of the given kind, sized to the config's enclosure and largest particle
+pub fn make_detector<const N: usize>(kind: BroadphaseKind, config: &SimConfig) -> Box<dyn CollisionDetector<N> + Send> {
+    let collision_distance = config.max_radius() * 2.0; // The furthest apart two particles can be and still collide
+
+    match (kind, config.boundary) {
+        (BroadphaseKind::BruteForce, BoundaryMode::Reflect) => Box::new(BruteForce::new()),
+        (BroadphaseKind::BruteForce, BoundaryMode::Periodic) => Box::new(BruteForce::periodic(config.enclosure)),
+        (BroadphaseKind::ParallelBruteForce, BoundaryMode::Reflect) => Box::new(ParallelBruteForce::new()),
+        (BroadphaseKind::ParallelBruteForce, BoundaryMode::Periodic) => Box::new(ParallelBruteForce::periodic(config.enclosure)),
+        (BroadphaseKind::SpatialGrid, BoundaryMode::Reflect) => Box::new(SpatialGrid::new(config.enclosure.width(), config.enclosure.height(), config.depth, collision_distance)),
+        (BroadphaseKind::SpatialGrid, BoundaryMode::Periodic) => Box::new(SpatialGrid::periodic(config.enclosure, config.depth, collision_distance)),
+        // The config won't allow any of the last three to be periodic
+        (BroadphaseKind::SpatialHash, _) => Box::new(SpatialHash::new(collision_distance)),
+        (BroadphaseKind::QuadTree, _) => Box::new(QuadTree::new(config.enclosure.width(), config.enclosure.height(), collision_distance)),
+        (BroadphaseKind::SweepAndPrune, _) => Box::new(SweepAndPrune::new()),
+    }
+}
+
+// The detector for one of strip_count collision threads, or the whole enclosure's detector if there is only one thread
+pub fn make_strip_detector<const N: usize>(kind: BroadphaseKind, config: &SimConfig, strip: usize, strip_count: usize) -> Box<dyn CollisionDetector<N> + Send> {
+    if strip_count == 1 {
+        return make_detector(kind, config);
+    }
+    Box::new(StripDetector::new(make_detector(kind, config), Strip::new(strip, strip_count, config)))
+}
+
+// One of a number of equally wide vertical strips the enclosure is split into, one per collision thread
+// A pair belongs to the strip its leftmost particle is in, so every pair is counted by exactly one thread
+// Its partner can be up to the collision distance further right, so a strip also looks that far into the next one, its halo
+// With periodic boundaries a pair can straddle the left and right edges, where the particle by the right edge counts as leftmost
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Strip {
+    index: usize,
+    count: usize,
+    strip_width: f32,
+    halo: f32,
+    enclosure_width: f32,
+    periodic: bool,
+}
+
+impl Strip {
+    pub fn new(index: usize, count: usize, config: &SimConfig) -> Self {
+        let enclosure_width = config.enclosure.width();
+        Strip { index, count, strip_width: enclosure_width / count as f32, halo: config.max_radius() * 2.0, enclosure_width, periodic: config.boundary == BoundaryMode::Periodic }
+    }
+
+    fn strip_of(&self, x: f32) -> usize {
+        ((x / self.strip_width).max(0.0) as usize).min(self.count - 1)
+    }
+
+    // Whether a particle at x is in the strip or its halo, and so could be part of one of its pairs
+    fn covers(&self, x: f32) -> bool {
+        let start = self.index as f32 * self.strip_width;
+        let in_strip = self.strip_of(x) == self.index || (x >= start && x < start + self.strip_width + self.halo);
+        let wraps_into_halo = self.periodic && self.index == self.count - 1 && x < self.halo;
+        in_strip || wraps_into_halo
+    }
+
+    pub fn owns<const N: usize>(&self, a: &Particle<N>, b: &Particle<N>) -> bool {
+        let (left, right) = if a.position[0] <= b.position[0] { (a, b) } else { (b, a) };
+        let leftmost = if self.periodic && right.position[0] - left.position[0] > self.enclosure_width / 2.0 { right } else { left };
+        self.strip_of(leftmost.position[0]) == self.index
+    }
+}
+
+// Runs another detector on just the particles a strip covers, keeping only the pairs the strip owns
+pub struct StripDetector<const N: usize = 2> {
+    detector: Box<dyn CollisionDetector<N> + Send>,
+    strip: Strip,
+    covered: Vec<Particle<N>>, // Reused every frame, along with where each came from in the full list
+    indices: Vec<usize>,
+}
+
+impl<const N: usize> StripDetector<N> {
+    pub fn new(detector: Box<dyn CollisionDetector<N> + Send>, strip: Strip) -> Self {
+        StripDetector { detector, strip, covered: Vec::new(), indices: Vec::new() }
+    }
+}
+
+impl<const N: usize> CollisionDetector<N> for StripDetector<N> {
+    fn detect(&mut self, particles: &[Particle<N>]) -> Vec<(usize, usize)> {
+        let strip = self.strip;
+        self.covered.clear();
+        self.indices.clear();
+        for (i, p) in particles.iter().enumerate().filter(|(_, p)| strip.covers(p.position[0])) {
+            self.covered.push(*p);
+            self.indices.push(i);
+        }
+
+        // The indices only ever go up, so lower index first still holds in the full list
+        let indices = &self.indices;
+        self.detector.detect(&self.covered).into_iter()
+            .map(|(i, j)| (indices[i], indices[j]))
+            .filter(|&(i, j)| strip.owns(&particles[i], &particles[j]))
+            .collect()
+    }
+}
+
+// Narrows down which pairs of particles need a full collision check
+pub trait Broadphase<const N: usize = 2> {
+    // Update the structure with the latest particle positions
+    fn rebuild(&mut self, particles: &[Particle<N>]);
+
+    // Call f once for every pair that might be colliding, lower index first
+    fn candidate_pairs(&self, f: &mut dyn FnMut(usize, usize));
+
+    // The enclosure if its edges wrap round, so pairs either side of an edge are found and measured the short way
+    fn wrap(&self) -> Option<&Enclosure> {
+        None
+    }
+
+    // Every pair closer than the sum of their radii, lower index first but in no particular order
+    fn colliding_pairs(&mut self, particles: &[Particle<N>]) -> Vec<(usize, usize)> {
+        check_candidate_pairs(self, particles)
+    }
+}
+
+// Rebuild the broadphase and run the full collision check on every candidate pair
+fn check_candidate_pairs<const N: usize, B: Broadphase<N> + ?Sized>(broadphase: &mut B, particles: &[Particle<N>]) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+
+    broadphase.rebuild(particles);
+    let wrap = broadphase.wrap().copied();
+    broadphase.candidate_pairs(&mut |i, j| {
+        if particles[i].perform_collision_check_wrapped(&particles[j], wrap.as_ref()) {
+            pairs.push((i, j));
+        }
+    });
+
+    pairs
+}
+
+// Checks every pair of particles, the baseline the other broadphases are compared against
+// With the simd feature each particle is checked against eight of the ones after it at once, unless the edges wrap
+#[derive(Default)]
+pub struct BruteForce {
+    particle_count: usize,
+    wrap: Option<Enclosure>,
+    #[cfg(feature = "simd")]
+    columns: Columns,
+}
+
+impl BruteForce {
+    pub fn new() -> Self {
+        BruteForce::default()
+    }
+
+    // Every pair is already a candidate, so only the distance check changes
+    pub fn periodic(enclosure: Enclosure) -> Self {
+        BruteForce { wrap: Some(enclosure), ..BruteForce::default() }
+    }
+}
+
+impl<const N: usize> Broadphase<N> for BruteForce {
+    fn rebuild(&mut self, particles: &[Particle<N>]) {
+        self.particle_count = particles.len();
+    }
+
+    fn candidate_pairs(&self, f: &mut dyn FnMut(usize, usize)) {
+        for i in 0..self.particle_count {
+            for j in i + 1..self.particle_count {
+                f(i, j);
+            }
+        }
+    }
+
+    fn wrap(&self) -> Option<&Enclosure> {
+        self.wrap.as_ref()
+    }
+
+    #[cfg(feature = "simd")]
+    fn colliding_pairs(&mut self, particles: &[Particle<N>]) -> Vec<(usize, usize)> {
+        if self.wrap.is_some() {
+            return check_candidate_pairs(self, particles);
+        }
+
+        self.columns.rebuild(particles);
+        let mut pairs = Vec::new();
+        for i in 0..particles.len() {
+            self.columns.for_each_collision_after(i, |j| pairs.push((i, j)));
+        }
+        pairs
+    }
+}
+
+// Checks every pair like BruteForce, but spreads the outer loop across Rayon's thread pool
+// This only ever works on a snapshot, so no Rayon worker touches the particle system's mutex
+#[derive(Default)]
+pub struct ParallelBruteForce {
+    serial: BruteForce,
+}
+
+impl ParallelBruteForce {
+    pub fn new() -> Self {
+        ParallelBruteForce { serial: BruteForce::new() }
+    }
+
+    pub fn periodic(enclosure: Enclosure) -> Self {
+        ParallelBruteForce { serial: BruteForce::periodic(enclosure) }
+    }
+}
+
+impl<const N: usize> Broadphase<N> for ParallelBruteForce {
+    fn rebuild(&mut self, particles: &[Particle<N>]) {
+        Broadphase::<N>::rebuild(&mut self.serial, particles);
+    }
+
+    fn candidate_pairs(&self, f: &mut dyn FnMut(usize, usize)) {
+        Broadphase::<N>::candidate_pairs(&self.serial, f);
+    }
+
+    fn wrap(&self) -> Option<&Enclosure> {
+        self.serial.wrap.as_ref()
+    }
+
+    fn colliding_pairs(&mut self, particles: &[Particle<N>]) -> Vec<(usize, usize)> {
+        #[cfg(feature = "simd")]
+        self.serial.columns.rebuild(particles);
+        let wrap = self.serial.wrap.as_ref();
+        #[cfg(feature = "simd")]
+        let columns = &self.serial.columns;
+
+        // Each worker collects its own pairs, which are then joined together
+        (0..particles.len()).into_par_iter()
+            .fold(Vec::new, |mut pairs, i| {
+                #[cfg(feature = "simd")]
+                if wrap.is_none() {
+                    columns.for_each_collision_after(i, |j| pairs.push((i, j)));
+                    return pairs;
+                }
+
+                for j in i + 1..particles.len() {
+                    if particles[i].perform_collision_check_wrapped(&particles[j], wrap) {
+                        pairs.push((i, j));
+                    }
+                }
+                pairs
+            })
+            .reduce(Vec::new, |mut pairs, mut other| {
+                pairs.append(&mut other);
+                pairs
+            })
+    }
+}
+
+// The number of cells along each of N axes, and the single index each cell is stored at with the first axis changing fastest
+// Nothing here depends on how many axes there are, so the same indexing serves a flat grid, a 3D one or anything past that
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GridShape<const N: usize> {
+    counts: [usize; N],
+}
+
+impl<const N: usize> GridShape<N> {
+    pub fn new(counts: [usize; N]) -> Self {
+        assert!(counts.iter().all(|&count| count > 0), "every axis of a grid needs at least one cell, not {:?}", counts);
+        GridShape { counts }
+    }
+
+    pub fn counts(&self) -> [usize; N] {
+        self.counts
+    }
+
+    pub fn len(&self) -> usize {
+        self.counts.iter().product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn index(&self, cell: [usize; N]) -> usize {
+        (0..N).rev().fold(0, |index, axis| index * self.counts[axis] + cell[axis])
+    }
+
+    pub fn cell_at(&self, mut index: usize) -> [usize; N] {
+        let mut cell = [0; N];
+        for (position, &count) in cell.iter_mut().zip(&self.counts) {
+            *position = index % count;
+            index /= count;
+        }
+        cell
+    }
+
+    // The cell a point is in, clamped so points on or past the far edges land in the last cell and ones below zero in the first
+    pub fn cell_of(&self, point: [f32; N], cell_size: [f32; N]) -> [usize; N] {
+        std::array::from_fn(|axis| ((point[axis] / cell_size[axis]).max(0.0) as usize).min(self.counts[axis] - 1))
+    }
+
+    // Indices of the up to 3^N - 1 cells touching this one, in index order of their offsets, wrapping round the axes that wrap
+    // and leaving out those past the edges of the ones that don't
+    // Wrapping round an axis only one or two cells long reaches the same cell more than once, so each is only given once,
+    // and never the cell itself
+    pub fn neighbours(&self, cell: [usize; N], wraps: [bool; N]) -> SmallVec<[usize; 26]> {
+        let own = self.index(cell);
+        let mut neighbours = SmallVec::new();
+
+        'offsets: for offsets in 0..3usize.pow(N as u32) {
+            let mut neighbour = [0; N];
+            let mut digits = offsets;
+            for axis in 0..N {
+                let position = cell[axis] as isize + (digits % 3) as isize - 1;
+                digits /= 3;
+                neighbour[axis] = if wraps[axis] {
+                    position.rem_euclid(self.counts[axis] as isize) as usize
+                } else if position < 0 || position >= self.counts[axis] as isize {
+                    continue 'offsets;
+                } else {
+                    position as usize
+                };
+            }
+
+            let index = self.index(neighbour);
+            if index != own && !neighbours.contains(&index) {
+                neighbours.push(index);
+            }
+        }
+        neighbours
+    }
+}
+
+// Buckets particles into cube cells so only particles in the same or adjacent cells need comparing
+// Every axis is split, so a 2D grid is an ordinary grid of squares and a 3D one is stacked in layers
+pub struct SpatialGrid<const N: usize = 2> {
+    cell_size: [f32; N], // Cells are only stretched away from cubes in a periodic grid
+    shape: GridShape<N>,
+    cells: Vec<Vec<usize>>,
+    wrap: Option<Enclosure>,
+}
+
+impl<const N: usize> SpatialGrid<N> {
+    // The cell size should be at least the largest collision distance so colliding particles are always in the same or adjacent cells
+    // Any axes past the first two are as long as the depth
+    pub fn new(width: f32, height: f32, depth: f32, cell_size: f32) -> Self {
+        let length = |axis: usize| [width, height].get(axis).copied().unwrap_or(depth);
+        let shape = GridShape::new(std::array::from_fn(|axis| ((length(axis) / cell_size).ceil() as usize).max(1)));
+
+        SpatialGrid { cell_size: [cell_size; N], shape, cells: vec![Vec::new(); shape.len()], wrap: None }
+    }
+
+    // A grid whose first and last columns, and first and last rows, are neighbours
+    // Cells are stretched to fit the enclosure exactly, as a narrow last column would let a pair across the edge skip a cell
+    pub fn periodic(enclosure: Enclosure, depth: f32, cell_size: f32) -> Self {
+        let extent = enclosure.extent::<N>(depth);
+        let shape = GridShape::new(std::array::from_fn(|axis| {
+            let cells = if axis < 2 { (extent[axis] / cell_size).floor() } else { (extent[axis] / cell_size).ceil() };
+            (cells as usize).max(1)
+        }));
+
+        SpatialGrid {
+            cell_size: std::array::from_fn(|axis| if axis < 2 { extent[axis] / shape.counts()[axis] as f32 } else { cell_size }),
+            shape,
+            cells: vec![Vec::new(); shape.len()],
+            wrap: Some(enclosure),
+        }
+    }
+
+    // Clear the grid and re-bucket every particle by its current position
+    pub fn rebuild(&mut self, particles: &[Particle<N>]) {
+        for cell in &mut self.cells {
+            cell.clear();
+        }
+
+        for (i, p) in particles.iter().enumerate() {
+            let cell = self.shape.index(self.shape.cell_of(p.position, self.cell_size));
+            self.cells[cell].push(i);
+        }
+    }
+
+    // Indices of the particles whose centres are inside the rectangle from min to max, lowest first, searching only the cells it overlaps
+    // Particles must be the ones the grid was last rebuilt with, and as the edge cells also hold any particle outside the grid those are still found
+    // The rectangle covers the whole depth, so every cell along the other axes is searched
+    pub fn query_region(&self, particles: &[Particle<N>], min: (f32, f32), max: (f32, f32)) -> Vec<usize> {
+        let bounds = match region_bounds(min, max) {
+            Some(bounds) => bounds,
+            None => return Vec::new(),
+        };
+        let ((left, bottom), (right, top)) = bounds;
+        let corner = |x: f32, y: f32| self.shape.cell_of(std::array::from_fn(|axis| [x, y].get(axis).copied().unwrap_or(0.0)), self.cell_size);
+        let (first, last) = (corner(left, bottom), corner(right, top));
+        let counts = self.shape.counts();
+        let searched = GridShape::<N>::new(std::array::from_fn(|axis| if axis < 2 { last[axis] - first[axis] + 1 } else { counts[axis] }));
+
+        let mut found = Vec::new();
+        for index in 0..searched.len() {
+            let offset = searched.cell_at(index);
+            let cell = &self.cells[self.shape.index(std::array::from_fn(|axis| if axis < 2 { first[axis] + offset[axis] } else { offset[axis] }))];
+            found.extend(cell.iter().copied().filter(|&i| in_region(&particles[i], bounds)));
+        }
+        found.sort_unstable();
+        found
+    }
+
+    // Call f once for every pair of particles in the same or neighbouring cells, lower index first
+    pub fn for_each_candidate_pair<F: FnMut(usize, usize)>(&self, mut f: F) {
+        let wraps = std::array::from_fn(|axis| axis < 2 && self.wrap.is_some());
+
+        for (cell_index, cell) in self.cells.iter().enumerate() {
+            if cell.is_empty() {
+                continue;
+            }
+
+            for a in 0..cell.len() {
+                for b in a + 1..cell.len() {
+                    f(cell[a].min(cell[b]), cell[a].max(cell[b]));
+                }
+            }
+
+            // Each pair of cells is visited once, from the lower index
+            for neighbour in self.shape.neighbours(self.shape.cell_at(cell_index), wraps) {
+                if neighbour < cell_index {
+                    continue;
+                }
+                let neighbour = &self.cells[neighbour];
+                for &i in cell {
+                    for &j in neighbour {
+                        f(i.min(j), i.max(j));
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<const N: usize> Broadphase<N> for SpatialGrid<N> {
+    fn rebuild(&mut self, particles: &[Particle<N>]) {
+        SpatialGrid::rebuild(self, particles);
+    }
+
+    fn candidate_pairs(&self, f: &mut dyn FnMut(usize, usize)) {
+        self.for_each_candidate_pair(f);
+    }
+
+    fn wrap(&self) -> Option<&Enclosure> {
+        self.wrap.as_ref()
+    }
+}
+
+// Buckets particles into square cells like SpatialGrid, but keeps only the cells something is in, keyed by their coordinates
+// Memory and rebuild time follow the particle count rather than the enclosure's area, so a few particles spread over a huge
+// enclosure cost no more than the same particles packed together, and particles outside the enclosure still land in a cell
+// Only x and y are split, so in 3D it also reports pairs far apart along the other axes, which the full collision check then throws away
+// It has no idea of periodic boundaries, pairs across the edges of the enclosure are never found
+pub struct SpatialHash {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), SmallVec<[usize; 4]>>,
+}
+
+impl SpatialHash {
+    // Cells to the right of or above a cell, so each pair of neighbouring cells is visited from only one of them
+    const FORWARD_NEIGHBOURS : [(i32, i32); 4] = [(1, 0), (-1, 1), (0, 1), (1, 1)];
+
+    // The cell size should be at least the largest collision distance so colliding particles are always in the same or adjacent cells
+    pub fn new(cell_size: f32) -> Self {
+        SpatialHash { cell_size, cells: HashMap::new() }
+    }
+
+    fn cell_of<const N: usize>(&self, p: &Particle<N>) -> (i32, i32) {
+        ((p.position[0] / self.cell_size).floor() as i32, (p.position[1] / self.cell_size).floor() as i32)
+    }
+
+    // Forget every cell and re-bucket every particle by its current position, the map keeps its capacity between rebuilds
+    pub fn rebuild<const N: usize>(&mut self, particles: &[Particle<N>]) {
+        self.cells.clear();
+
+        for (i, p) in particles.iter().enumerate() {
+            let cell = self.cell_of(p);
+            self.cells.entry(cell).or_default().push(i);
+        }
+    }
+
+    // Call f once for every pair of particles in the same or neighbouring cells, lower index first
+    pub fn for_each_candidate_pair<F: FnMut(usize, usize)>(&self, mut f: F) {
+        for (&(column, row), cell) in &self.cells {
+            for a in 0..cell.len() {
+                for b in a + 1..cell.len() {
+                    f(cell[a].min(cell[b]), cell[a].max(cell[b]));
+                }
+            }
+
+            for (dx, dy) in Self::FORWARD_NEIGHBOURS {
+                if let Some(neighbour) = self.cells.get(&(column + dx, row + dy)) {
+                    for &i in cell {
+                        for &j in neighbour {
+                            f(i.min(j), i.max(j));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl<const N: usize> Broadphase<N> for SpatialHash {
+    fn rebuild(&mut self, particles: &[Particle<N>]) {
+        SpatialHash::rebuild(self, particles);
+    }
+
+    fn candidate_pairs(&self, f: &mut dyn FnMut(usize, usize)) {
+        self.for_each_candidate_pair(f);
+    }
+}
+
+// Axis aligned box covered by a quadtree node
+#[derive(Debug, Copy, Clone)]
+struct Bounds {
+    min_x: f32,
+    min_y: f32,
+    max_x: f32,
+    max_y: f32,
+}
+
+impl Bounds {
+    fn overlaps(&self, other: &Bounds) -> bool {
+        self.min_x <= other.max_x && other.min_x <= self.max_x && self.min_y <= other.max_y && other.min_y <= self.max_y
+    }
+
+    fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.min_x && x <= self.max_x && y >= self.min_y && y <= self.max_y
+    }
+}
+
+// Recursively splits the enclosure into quarters, so densely packed regions get finer nodes than empty ones
+// Only x and y are split, so in 3D it also reports pairs far apart along the other axes, which the full collision check then throws away
+// It has no idea of periodic boundaries, pairs across the edges of the enclosure are never found
+pub struct QuadTree {
+    bounds: Bounds,
+    search_distance: f32,
+    depth: usize,
+    items: Vec<(usize, f32, f32)>, // Particle index and position
+    children: Option<Box<[QuadTree; 4]>>,
+}
+
+impl QuadTree {
+    // Pairs are reported for particles within search_distance of each other on both axes, which should be at least the largest collision distance
+    pub fn new(width: f32, height: f32, search_distance: f32) -> Self {
+        QuadTree::with_bounds(Bounds { min_x: 0.0, min_y: 0.0, max_x: width, max_y: height }, search_distance, 0)
+    }
+
+    fn with_bounds(bounds: Bounds, search_distance: f32, depth: usize) -> Self {
+        QuadTree { bounds, search_distance, depth, items: Vec::new(), children: None }
+    }
+
+    // Remove every particle, keeping the root bounds
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.children = None;
+    }
+
+    pub fn insert<const N: usize>(&mut self, index: usize, p: &Particle<N>) {
+        self.insert_point(index, p.position[0], p.position[1]);
+    }
+
+    fn insert_point(&mut self, index: usize, x: f32, y: f32) {
+        if let Some(children) = &mut self.children {
+            children[Self::quadrant(&self.bounds, x, y)].insert_point(index, x, y);
+            return;
+        }
+
+        self.items.push((index, x, y));
+
+        if self.items.len() > QUADTREE_CAPACITY && self.depth < QUADTREE_MAX_DEPTH {
+            self.subdivide();
+        }
+    }
+
+    fn subdivide(&mut self) {
+        let b = self.bounds;
+        let mid_x = (b.min_x + b.max_x) * 0.5;
+        let mid_y = (b.min_y + b.max_y) * 0.5;
+        let depth = self.depth + 1;
+        let distance = self.search_distance;
+
+        let mut children = Box::new([
+            QuadTree::with_bounds(Bounds { min_x: b.min_x, min_y: b.min_y, max_x: mid_x, max_y: mid_y }, distance, depth),
+            QuadTree::with_bounds(Bounds { min_x: mid_x, min_y: b.min_y, max_x: b.max_x, max_y: mid_y }, distance, depth),
+            QuadTree::with_bounds(Bounds { min_x: b.min_x, min_y: mid_y, max_x: mid_x, max_y: b.max_y }, distance, depth),
+            QuadTree::with_bounds(Bounds { min_x: mid_x, min_y: mid_y, max_x: b.max_x, max_y: b.max_y }, distance, depth),
+        ]);
+
+        for (index, x, y) in self.items.drain(..) {
+            children[Self::quadrant(&b, x, y)].insert_point(index, x, y);
+        }
+
+        self.children = Some(children);
+    }
+
+    // Which child a point belongs to, points outside the bounds go to the nearest child
+    fn quadrant(bounds: &Bounds, x: f32, y: f32) -> usize {
+        let mid_x = (bounds.min_x + bounds.max_x) * 0.5;
+        let mid_y = (bounds.min_y + bounds.max_y) * 0.5;
+
+        (x >= mid_x) as usize + 2 * (y >= mid_y) as usize
+    }
+
+    // Call f with every particle index inside the search box
+    fn query_range(&self, search: &Bounds, f: &mut dyn FnMut(usize)) {
+        if !self.bounds.overlaps(search) {
+            return;
+        }
+
+        for &(index, x, y) in &self.items {
+            if search.contains(x, y) {
+                f(index);
+            }
+        }
+
+        if let Some(children) = &self.children {
+            for child in children.iter() {
+                child.query_range(search, f);
+            }
+        }
+    }
+
+    fn for_each_item(&self, f: &mut dyn FnMut(usize, f32, f32)) {
+        for &(index, x, y) in &self.items {
+            f(index, x, y);
+        }
+
+        if let Some(children) = &self.children {
+            for child in children.iter() {
+                child.for_each_item(f);
+            }
+        }
+    }
+
+    // Call f once for every pair of particles within the search distance of each other, lower index first
+    pub fn query_pairs(&self, mut f: impl FnMut(usize, usize)) {
+        self.for_each_item(&mut |i, x, y| {
+            let search = Bounds {
+                min_x: x - self.search_distance,
+                min_y: y - self.search_distance,
+                max_x: x + self.search_distance,
+                max_y: y + self.search_distance,
+            };
+
+            self.query_range(&search, &mut |j| {
+                if j > i {
+                    f(i, j);
+                }
+            });
+        });
+    }
+}
+
+impl<const N: usize> Broadphase<N> for QuadTree {
+    fn rebuild(&mut self, particles: &[Particle<N>]) {
+        self.clear();
+        for (i, p) in particles.iter().enumerate() {
+            self.insert(i, p);
+        }
+    }
+
+    fn candidate_pairs(&self, f: &mut dyn FnMut(usize, usize)) {
+        self.query_pairs(f);
+    }
+}
+
+// Sorts the particles by the left edge of their extent along x, and pairs each with those whose left edge comes before its right edge
+// The order is kept between frames and fixed up with an insertion sort, which is close to linear when particles move a little each frame
+// Only x is swept, so pairs far apart along any other axis are reported and thrown away by the full collision check
+// It has no idea of periodic boundaries, pairs across the edges of the enclosure are never found
+#[derive(Default)]
+pub struct SweepAndPrune {
+    order: Vec<usize>, // Particle indices sorted by min_x
+    extents: Vec<(f32, f32)>, // min_x and max_x of every particle, by particle index
+}
+
+impl SweepAndPrune {
+    pub fn new() -> Self {
+        SweepAndPrune { order: Vec::new(), extents: Vec::new() }
+    }
+
+    // Update every particle's extent, then sort the order left over from the last frame
+    pub fn rebuild<const N: usize>(&mut self, particles: &[Particle<N>]) {
+        self.extents.clear();
+        self.extents.extend(particles.iter().map(|p| (p.position[0] - p.radius, p.position[0] + p.radius)));
+
+        if self.order.len() != particles.len() {
+            self.order = (0..particles.len()).collect();
+        }
+
+        let extents = &self.extents;
+        for i in 1..self.order.len() {
+            let moving = self.order[i];
+            let mut j = i;
+            while j > 0 && extents[self.order[j - 1]].0 > extents[moving].0 {
+                self.order[j] = self.order[j - 1];
+                j -= 1;
+            }
+            self.order[j] = moving;
+        }
+    }
+
+    // Call f once for every pair whose extents along x overlap, lower index first
+    pub fn for_each_candidate_pair<F: FnMut(usize, usize)>(&self, mut f: F) {
+        for (position, &i) in self.order.iter().enumerate() {
+            let max_x = self.extents[i].1;
+            for &j in self.order[position + 1..].iter().take_while(|&&j| self.extents[j].0 <= max_x) {
+                f(i.min(j), i.max(j));
+            }
+        }
+    }
+}
+
+impl<const N: usize> Broadphase<N> for SweepAndPrune {
+    fn rebuild(&mut self, particles: &[Particle<N>]) {
+        SweepAndPrune::rebuild(self, particles);
+    }
+
+    fn candidate_pairs(&self, f: &mut dyn FnMut(usize, usize)) {
+        self.for_each_candidate_pair(f);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ENCLOSURE_D, ENCLOSURE_H, ENCLOSURE_W, PARTICLE_RADIUS};
+    use rand::{RngExt, SeedableRng};
+    use rand::rngs::StdRng;
+
+    fn random_particles(count: usize, seed: u64) -> Vec<Particle> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..count).map(|_| {
+            let x = rng.random::<f32>() * ENCLOSURE_W;
+            let y = rng.random::<f32>() * ENCLOSURE_H;
+            Particle::new(x, y, 0.0, 0.0, PARTICLE_RADIUS)
+        }).collect()
+    }
+
+    fn colliding_pairs<const N: usize>(broadphase: &mut dyn Broadphase<N>, particles: &[Particle<N>]) -> Vec<(usize, usize)> {
+        broadphase.rebuild(particles);
+
+        let mut pairs = Vec::new();
+        broadphase.candidate_pairs(&mut |i, j| {
+            if particles[i].perform_collision_check(&particles[j]) {
+                pairs.push((i, j));
+            }
+        });
+
+        pairs.sort();
+        pairs
+    }
+
+    #[test]
+    fn spatial_grid_matches_brute_force() {
+        let particles = random_particles(2000, 7);
+
+        let brute_force_pairs = colliding_pairs(&mut BruteForce::new(), &particles);
+        let grid_pairs = colliding_pairs(&mut SpatialGrid::new(ENCLOSURE_W, ENCLOSURE_H, ENCLOSURE_D, PARTICLE_RADIUS * 2.0), &particles);
+
+        assert!(!brute_force_pairs.is_empty());
+        assert_eq!(brute_force_pairs, grid_pairs);
+    }
+
+    #[test]
+    fn spatial_grid_matches_brute_force_in_3d() {
+        let mut rng = StdRng::seed_from_u64(13);
+        let particles : Vec<Particle<3>> = (0..2000).map(|_| {
+            let (x, y, z) = (rng.random::<f32>() * 2.0, rng.random::<f32>() * 2.0, rng.random::<f32>() * 2.0);
+            Particle::new_3d(x, y, z, 0.0, 0.0, 0.0, PARTICLE_RADIUS)
+        }).collect();
+
+        let brute_force_pairs = colliding_pairs(&mut BruteForce::new(), &particles);
+        let grid_pairs = colliding_pairs(&mut SpatialGrid::new(2.0, 2.0, 2.0, PARTICLE_RADIUS * 2.0), &particles);
+
+        assert!(!brute_force_pairs.is_empty());
+        assert_eq!(brute_force_pairs, grid_pairs);
+    }
+
+    #[test]
+    fn grid_shapes_index_and_find_neighbours_in_any_number_of_axes() {
+        let shape = GridShape::new([3, 4, 3, 5]);
+        assert_eq!(shape.len(), 180);
+        let indices : Vec<usize> = (0..shape.len()).map(|i| shape.index(shape.cell_at(i))).collect();
+        assert_eq!(indices, (0..180).collect::<Vec<_>>());
+        assert_eq!(shape.index([1, 0, 0, 0]), 1);
+        assert_eq!(shape.index([0, 0, 0, 1]), 36);
+        assert_eq!(shape.cell_of([1.5, -3.0, 9.0, f32::NAN], [1.0; 4]), [1, 0, 2, 0]);
+
+        // A cell in the middle of every axis touches all 3^4 - 1 around it, a corner only those inside the grid
+        assert_eq!(shape.neighbours([1, 1, 1, 1], [false; 4]).len(), 80);
+        assert_eq!(shape.neighbours([0, 0, 0, 0], [false; 4]).len(), 15);
+        assert_eq!(shape.neighbours([0, 0, 0, 0], [true; 4]).len(), 80);
+
+        // Wrapping round an axis two cells long reaches the cell on the other side once, never the cell itself
+        let line = GridShape::new([2]);
+        assert_eq!(line.neighbours([0], [true]).as_slice(), &[1]);
+        assert!(GridShape::new([1]).neighbours([0], [true]).is_empty());
+    }
+
+    #[test]
+    fn parallel_brute_force_matches_brute_force() {
+        let particles = random_particles(2000, 5);
+
+        let brute_force_pairs = colliding_pairs(&mut BruteForce::new(), &particles);
+        let mut parallel_pairs = ParallelBruteForce::new().colliding_pairs(&particles);
+        parallel_pairs.sort();
+
+        assert!(!brute_force_pairs.is_empty());
+        assert_eq!(brute_force_pairs, parallel_pairs);
+    }
+
+    #[test]
+    fn periodic_grid_finds_pairs_across_the_edges() {
+        let enclosure = Enclosure::Rect { w: ENCLOSURE_W, h: ENCLOSURE_H };
+        let particles = vec![
+            Particle::new(0.02, 5.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new(9.95, 5.0, 0.0, 0.0, PARTICLE_RADIUS), // 0.07 away across the left and right edges
+            Particle::new(3.0, 9.99, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new(3.0, 0.04, 0.0, 0.0, PARTICLE_RADIUS), // 0.05 away across the top and bottom
+            Particle::new(5.0, 5.0, 0.0, 0.0, PARTICLE_RADIUS),
+        ];
+
+        // 0.3 doesn't divide the width, so the cells have to be stretched for the edge pairs to be neighbours
+        let mut grid = SpatialGrid::periodic(enclosure, ENCLOSURE_D, 0.3);
+        let mut pairs = grid.colliding_pairs(&particles);
+        pairs.sort();
+
+        assert_eq!(pairs, vec![(0, 1), (2, 3)]);
+        assert!(SpatialGrid::new(ENCLOSURE_W, ENCLOSURE_H, ENCLOSURE_D, 0.3).colliding_pairs(&particles).is_empty());
+
+        let mut brute_force_pairs = BruteForce::periodic(enclosure).colliding_pairs(&particles);
+        brute_force_pairs.sort();
+        assert_eq!(brute_force_pairs, pairs);
+    }
+
+    #[test]
+    fn periodic_grid_matches_periodic_brute_force() {
+        let enclosure = Enclosure::Rect { w: ENCLOSURE_W, h: ENCLOSURE_H };
+        let particles = random_particles(2000, 17);
+
+        let mut brute_force_pairs = BruteForce::periodic(enclosure).colliding_pairs(&particles);
+        let mut grid_pairs = SpatialGrid::periodic(enclosure, ENCLOSURE_D, PARTICLE_RADIUS * 2.0).colliding_pairs(&particles);
+        brute_force_pairs.sort();
+        grid_pairs.sort();
+
+        assert_eq!(brute_force_pairs, grid_pairs);
+
+        // A grid too small to have distinct neighbours on both sides still gives each pair once
+        let mut tiny_pairs = SpatialGrid::periodic(enclosure, ENCLOSURE_D, 4.0).colliding_pairs(&particles);
+        tiny_pairs.sort();
+        assert_eq!(brute_force_pairs, tiny_pairs);
+    }
+
+    #[test]
+    fn spatial_hash_matches_brute_force() {
+        let particles = random_particles(2000, 17);
+
+        let brute_force_pairs = colliding_pairs(&mut BruteForce::new(), &particles);
+        let hash_pairs = colliding_pairs(&mut SpatialHash::new(PARTICLE_RADIUS * 2.0), &particles);
+
+        assert!(!brute_force_pairs.is_empty());
+        assert_eq!(brute_force_pairs, hash_pairs);
+    }
+
+    #[test]
+    fn spatial_hash_finds_pairs_outside_the_enclosure() {
+        // Either side of the origin, where rounding towards zero instead of down would put both in cell 0
+        let particles = vec![
+            Particle::new(-0.05, -0.05, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new(0.02, 0.02, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new(-500.0, 300.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new(-500.05, 300.05, 0.0, 0.0, PARTICLE_RADIUS),
+        ];
+
+        let mut hash = SpatialHash::new(PARTICLE_RADIUS * 2.0);
+        assert_eq!(colliding_pairs(&mut hash, &particles), vec![(0, 1), (2, 3)]);
+        assert_eq!(hash.cells.len(), 4); // Only the occupied cells are stored
+    }
+
+    #[test]
+    fn sweep_and_prune_matches_brute_force_as_the_particles_move() {
+        let mut particles = random_particles(2000, 19);
+        let mut sweep = SweepAndPrune::new();
+
+        // The second and third frames start from the order the last frame left behind
+        for _ in 0..3 {
+            let brute_force_pairs = colliding_pairs(&mut BruteForce::new(), &particles);
+            assert!(!brute_force_pairs.is_empty());
+            assert_eq!(brute_force_pairs, colliding_pairs(&mut sweep, &particles));
+
+            for (i, p) in particles.iter_mut().enumerate() {
+                p.position[0] += if i % 2 == 0 { 0.03 } else { -0.03 };
+            }
+        }
+    }
+
+    #[test]
+    fn sweep_and_prune_keeps_the_order_sorted() {
+        let particles = random_particles(500, 23);
+        let mut sweep = SweepAndPrune::new();
+        sweep.rebuild(&particles);
+
+        assert!(sweep.order.windows(2).all(|pair| sweep.extents[pair[0]].0 <= sweep.extents[pair[1]].0));
+    }
+
+    #[test]
+    fn every_detector_kind_finds_the_same_pairs() {
+        let particles = random_particles(1000, 29);
+        let config = SimConfig::default();
+        let kinds = [BroadphaseKind::BruteForce, BroadphaseKind::ParallelBruteForce, BroadphaseKind::SpatialGrid, BroadphaseKind::SpatialHash, BroadphaseKind::QuadTree, BroadphaseKind::SweepAndPrune];
+
+        let expected = colliding_pairs(&mut BruteForce::new(), &particles);
+        assert!(!expected.is_empty());
+        for kind in kinds {
+            let mut pairs = make_detector(kind, &config).detect(&particles);
+            pairs.sort();
+            assert_eq!(pairs, expected, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn pairs_are_only_found_across_the_seam_when_it_wraps() {
+        let particles = vec![Particle::new(0.05, 5.0, 0.0, 0.0, PARTICLE_RADIUS), Particle::new(9.97, 5.0, 0.0, 0.0, PARTICLE_RADIUS)];
+        let reflect = SimConfig { enclosure: Enclosure::Rect { w: 10.0, h: 10.0 }, ..SimConfig::default() };
+        let periodic = SimConfig { boundary: BoundaryMode::Periodic, ..reflect.clone() };
+
+        for &kind in &[BroadphaseKind::BruteForce, BroadphaseKind::ParallelBruteForce, BroadphaseKind::SpatialGrid] {
+            assert_eq!(make_detector(kind, &periodic).detect(&particles), vec![(0, 1)], "{:?}", kind);
+            assert_eq!(make_detector(kind, &reflect).detect(&particles), vec![], "{:?}", kind);
+        }
+        for &kind in &[BroadphaseKind::SpatialHash, BroadphaseKind::QuadTree, BroadphaseKind::SweepAndPrune] {
+            assert_eq!(make_detector(kind, &reflect).detect(&particles), vec![], "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn strips_between_them_find_every_pair_once() {
+        let particles = random_particles(2000, 31);
+
+        for &boundary in &[BoundaryMode::Reflect, BoundaryMode::Periodic] {
+            let config = SimConfig { boundary, ..SimConfig::default() };
+            let mut expected = make_detector(BroadphaseKind::BruteForce, &config).detect(&particles);
+            expected.sort();
+            assert!(!expected.is_empty());
+
+            for strip_count in 1..=5 {
+                let mut pairs : Vec<_> = (0..strip_count).flat_map(|strip| make_strip_detector(BroadphaseKind::SpatialGrid, &config, strip, strip_count).detect(&particles)).collect();
+                pairs.sort();
+                assert_eq!(pairs, expected, "{:?} with {} strips", boundary, strip_count);
+            }
+        }
+
+        // A pair across the periodic edge belongs to the last strip, which reaches round to the first through its halo
+        let config = SimConfig { boundary: BoundaryMode::Periodic, ..SimConfig::default() };
+        let edge = vec![Particle::new(0.02, 5.0, 0.0, 0.0, PARTICLE_RADIUS), Particle::new(ENCLOSURE_W - 0.02, 5.0, 0.0, 0.0, PARTICLE_RADIUS)];
+        assert_eq!(make_strip_detector(BroadphaseKind::BruteForce, &config, 0, 3).detect(&edge), vec![]);
+        assert_eq!(make_strip_detector(BroadphaseKind::BruteForce, &config, 2, 3).detect(&edge), vec![(0, 1)]);
+    }
+
+    #[test]
+    fn quadtree_matches_brute_force() {
+        let particles = random_particles(2000, 11);
+
+        let brute_force_pairs = colliding_pairs(&mut BruteForce::new(), &particles);
+        let quadtree_pairs = colliding_pairs(&mut QuadTree::new(ENCLOSURE_W, ENCLOSURE_H, PARTICLE_RADIUS * 2.0), &particles);
+
+        assert!(!brute_force_pairs.is_empty());
+        assert_eq!(brute_force_pairs, quadtree_pairs);
+    }
+
+    #[test]
+    fn quadtree_handles_coincident_particles() {
+        let particles = vec![Particle::new(0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS); 500];
+
+        let quadtree_pairs = colliding_pairs(&mut QuadTree::new(ENCLOSURE_W, ENCLOSURE_H, PARTICLE_RADIUS * 2.0), &particles);
+
+        assert_eq!(quadtree_pairs.len(), 500 * 499 / 2);
+    }
+}
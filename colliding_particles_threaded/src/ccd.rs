@@ -0,0 +1,170 @@
+// Continuous collision detection, turned on with --ccd, for pairs fast enough to pass through each other within one step
+// The collision thread only sees where particles are at the end of each step, so two that cross paths mid-step are never seen overlapping
+// Sweeping each pair along its velocities over the step finds the moment they touched, however far they went past each other after it
+use crate::broadphase::SweepAndPrune;
+use crate::{dot, species_pair, Particle, SpeciesPair};
+
+// How long until two particles moving at their velocities are the sum of their radii apart, or None if they never will be
+// A pair already overlapping collides at 0, and a pair moving in parallel or apart never does
+pub fn time_to_collision<const N: usize>(a: &Particle<N>, b: &Particle<N>) -> Option<f32> {
+    // |offset + closing * t| = a.radius + b.radius is a quadratic in t
+    let offset : [f32; N] = std::array::from_fn(|axis| b.position[axis] - a.position[axis]);
+    let closing : [f32; N] = std::array::from_fn(|axis| b.velocity[axis] - a.velocity[axis]);
+
+    let c = dot(&offset, &offset) - (a.radius + b.radius).powi(2);
+    if c < 0.0 {
+        return Some(0.0);
+    }
+    let (quadratic, linear) = (dot(&closing, &closing), 2.0 * dot(&offset, &closing));
+    let discriminant = linear * linear - 4.0 * quadratic * c;
+    if quadratic == 0.0 || discriminant < 0.0 {
+        return None;
+    }
+
+    let time = (-linear - discriminant.sqrt()) / (2.0 * quadratic);
+    (time >= 0.0).then_some(time) // Both roots are behind a pair moving apart
+}
+
+// The earliest time in 0..=dt that two particles touch, as time_to_collision but only looking as far ahead as dt
+pub fn swept_collision_time<const N: usize>(a: &Particle<N>, b: &Particle<N>, dt: f32) -> Option<f32> {
+    time_to_collision(a, b).filter(|&time| time <= dt)
+}
+
+// The soonest any pair not already overlapping will collide if nothing changes course, with the indices of the pair
+// Every pair is tried, so this is for sampling now and then rather than for every frame
+pub fn next_collision<const N: usize>(particles: &[Particle<N>]) -> Option<(f32, usize, usize)> {
+    let mut soonest : Option<(f32, usize, usize)> = None;
+    for (i, a) in particles.iter().enumerate() {
+        for (j, b) in particles.iter().enumerate().skip(i + 1) {
+            if let Some(time) = time_to_collision(a, b).filter(|&time| time > 0.0) {
+                if soonest.is_none_or(|(earliest, _, _)| time < earliest) {
+                    soonest = Some((time, i, j));
+                }
+            }
+        }
+    }
+    soonest
+}
+
+// Where a particle was dt ago, if it came at its current velocity
+fn rewound<const N: usize>(p: &Particle<N>, dt: f32) -> Particle<N> {
+    Particle { position: std::array::from_fn(|axis| p.position[axis] - p.velocity[axis] * dt), ..*p }
+}
+
+// Put a pair back where they touched if they passed through each other in the last dt, returning whether they did
+// A pair that was overlapping at the start of the step is left alone, as the ordinary collision check has already seen it
+pub fn rewind_to_contact<const N: usize>(a: &mut Particle<N>, b: &mut Particle<N>, dt: f32) -> bool {
+    let (start_a, start_b) = (rewound(a, dt), rewound(b, dt));
+    match swept_collision_time(&start_a, &start_b, dt) {
+        Some(time) if time > 0.0 => {
+            *a = rewound(&start_a, -time);
+            *b = rewound(&start_b, -time);
+            true
+        }
+        _ => false,
+    }
+}
+
+// Finds the pairs that touched during the last step but aren't overlapping at the end of it
+// Each particle's path over the step is bounded by a circle round its middle, and a sweep and prune over those finds the pairs to solve for
+// It has no idea of periodic boundaries, so the config only allows it with walls
+pub struct SweptDetector<const N: usize = 2> {
+    dt: f32,
+    sweep: SweepAndPrune,
+    paths: Vec<Particle<N>>,
+}
+
+impl<const N: usize> SweptDetector<N> {
+    pub fn new(dt: f32) -> Self {
+        SweptDetector { dt, sweep: SweepAndPrune::new(), paths: Vec::new() }
+    }
+
+    pub fn dt(&self) -> f32 {
+        self.dt
+    }
+
+    // Pairs by index into particles, lower first and in order
+    pub fn tunnelled_pairs(&mut self, particles: &[Particle<N>]) -> Vec<(usize, usize, SpeciesPair)> {
+        let dt = self.dt;
+        self.paths.clear();
+        self.paths.extend(particles.iter().map(|p| Particle { radius: p.radius + p.speed() * dt * 0.5, ..rewound(p, dt * 0.5) }));
+        self.sweep.rebuild(&self.paths);
+
+        let mut pairs = Vec::new();
+        self.sweep.for_each_candidate_pair(|i, j| {
+            let (a, b) = (&particles[i], &particles[j]);
+            if !a.perform_collision_check(b) && swept_collision_time(&rewound(a, dt), &rewound(b, dt), dt).is_some_and(|time| time > 0.0) {
+                pairs.push((i, j, species_pair(a, b)));
+            }
+        });
+        pairs.sort_unstable();
+        pairs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broadphase::BruteForce;
+    use crate::{detect_collisions, PARTICLE_RADIUS, TIMESTEP};
+
+    #[test]
+    fn swept_pairs_touch_when_their_gap_closes() {
+        let still = Particle::new(1.5, 5.0, 0.0, 0.0, PARTICLE_RADIUS);
+
+        // 0.4 apart closing at 100 a second
+        let time = swept_collision_time(&Particle::new(1.0, 5.0, 100.0, 0.0, PARTICLE_RADIUS), &still, TIMESTEP).unwrap();
+        assert!((time - 0.004).abs() < 1e-6);
+
+        assert_eq!(swept_collision_time(&Particle::new(1.0, 5.0, 10.0, 0.0, PARTICLE_RADIUS), &still, TIMESTEP), None); // Not there yet
+        assert_eq!(swept_collision_time(&Particle::new(1.0, 5.0, -100.0, 0.0, PARTICLE_RADIUS), &still, TIMESTEP), None); // Moving apart
+        assert_eq!(swept_collision_time(&Particle::new(1.0, 5.0, 0.0, 100.0, PARTICLE_RADIUS), &Particle { velocity: [0.0, 100.0], ..still }, TIMESTEP), None); // Parallel
+        assert_eq!(swept_collision_time(&Particle::new(1.0, 5.0, 0.0, 100.0, PARTICLE_RADIUS), &Particle::new(1.5, 5.5, 0.0, 0.0, PARTICLE_RADIUS), TIMESTEP), None); // Passing by
+        assert_eq!(swept_collision_time(&Particle::new(1.45, 5.0, 0.0, 0.0, PARTICLE_RADIUS), &still, TIMESTEP), Some(0.0)); // Already overlapping
+    }
+
+    #[test]
+    fn collisions_are_predicted_from_the_velocities() {
+        let still = Particle::new(1.5, 5.0, 0.0, 0.0, PARTICLE_RADIUS);
+
+        let time = time_to_collision(&Particle::new(1.0, 5.0, 2.0, 0.0, PARTICLE_RADIUS), &still).unwrap();
+        assert!((time - 0.2).abs() < 1e-6);
+        assert_eq!(time_to_collision(&still, &Particle::new(1.0, 5.0, 2.0, 0.0, PARTICLE_RADIUS)), Some(time)); // Either way round
+        assert_eq!(time_to_collision(&Particle::new(1.0, 5.0, -2.0, 0.0, PARTICLE_RADIUS), &still), None); // Separating
+        assert_eq!(time_to_collision(&Particle::new(1.0, 5.0, 1.0, 1.0, PARTICLE_RADIUS), &Particle { velocity: [1.0, 1.0], ..still }), None); // Parallel
+        assert_eq!(time_to_collision(&Particle::new(1.45, 5.0, -2.0, 0.0, PARTICLE_RADIUS), &still), Some(0.0)); // Overlapping, even moving apart
+
+        // The pair heading for each other fastest, ignoring the pair that is already overlapping
+        let particles = vec![
+            Particle::new(1.0, 1.0, 0.0, 0.0, PARTICLE_RADIUS), Particle::new(1.05, 1.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new(5.0, 5.0, 1.0, 0.0, PARTICLE_RADIUS), Particle::new(6.0, 5.0, -1.0, 0.0, PARTICLE_RADIUS),
+            Particle::new(5.0, 8.0, 0.0, 0.0, PARTICLE_RADIUS), Particle::new(5.0, 9.0, 0.0, -0.5, PARTICLE_RADIUS),
+        ];
+        let (time, i, j) = next_collision(&particles).unwrap();
+        assert_eq!((i, j), (2, 3));
+        assert!((time - 0.45).abs() < 1e-6);
+        assert_eq!(next_collision(&particles[..2]), None);
+    }
+
+    #[test]
+    fn fast_particles_tunnelling_through_each_other_are_caught() {
+        // One step at 100 a second takes the fast particle from one side of the other to the other
+        let before = vec![Particle::new(1.0, 5.0, 100.0, 0.0, PARTICLE_RADIUS), Particle { id: 1, ..Particle::new(1.5, 5.0, 0.0, 0.0, PARTICLE_RADIUS) }];
+        let after = vec![Particle::new(2.0, 5.0, 100.0, 0.0, PARTICLE_RADIUS), before[1]];
+        assert!(detect_collisions(&before, &mut BruteForce::new()).is_empty());
+        assert!(detect_collisions(&after, &mut BruteForce::new()).is_empty());
+
+        let mut detector = SweptDetector::new(TIMESTEP);
+        assert_eq!(detector.tunnelled_pairs(&after), vec![(0, 1, (0, 0))]);
+        assert!(detector.tunnelled_pairs(&before).is_empty());
+
+        // Put back where they touched, so they are just the sum of their radii apart
+        let (mut a, mut b) = (after[0], after[1]);
+        assert!(rewind_to_contact(&mut a, &mut b, TIMESTEP));
+        assert!((a.position[0] - 1.4).abs() < 1e-4 && b.position[0] == 1.5);
+
+        // Overlapping at the start of the step is the ordinary check's business
+        let (mut a, mut b) = (Particle::new(1.45, 5.0, 0.0, 0.0, PARTICLE_RADIUS), before[1]);
+        assert!(!rewind_to_contact(&mut a, &mut b, TIMESTEP));
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{Particle, ParticleSystem};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+
+// Bump this when the layout changes, and teach load how to read the older versions
+// New particle fields can usually be added without a bump by giving them #[serde(default)]
+// Version 2 gave particles a position and velocity with one value per axis, in place of version 1's x, y, z, vx, vy and vz
+const CHECKPOINT_VERSION : u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct Checkpoint<P> {
+    version: u32,
+    particles: Vec<P>,
+    #[serde(default)]
+    next_id: Option<u64>, // Missing from older checkpoints, which carry on from the highest saved id
+}
+
+// Just the version, read first to know how to read the rest
+#[derive(Deserialize)]
+struct Version {
+    version: u32,
+}
+
+// A particle as version 1 saved it, always with three axes
+#[derive(Deserialize)]
+struct ParticleV1 {
+    id: u64,
+    x: f32,
+    y: f32,
+    z: f32,
+    vx: f32,
+    vy: f32,
+    vz: f32,
+    radius: f32,
+    mass: f32,
+    #[serde(default)] // Checkpoints from before species were added are all species 0
+    species: u8,
+}
+
+impl ParticleV1 {
+    // Any axes past the third start at zero, and a 2D system can only take particles that never left z = 0
+    fn upgrade<const N: usize>(self) -> io::Result<Particle<N>> {
+        let (position, velocity) = ([self.x, self.y, self.z], [self.vx, self.vy, self.vz]);
+        if N < 3 && (self.z != 0.0 || self.vz != 0.0) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("particle {} is 3D, so can't be loaded into a {}D system", self.id, N)));
+        }
+        let fitted = |values: [f32; 3]| std::array::from_fn(|axis| values.get(axis).copied().unwrap_or(0.0));
+
+        Ok(Particle { id: self.id, position: fitted(position), velocity: fitted(velocity), radius: self.radius, mass: self.mass, species: self.species })
+    }
+}
+
+impl<const N: usize> ParticleSystem<N> {
+    // Write every particle to a JSON file, which load can later restore exactly
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let checkpoint = Checkpoint { version: CHECKPOINT_VERSION, particles: self.particles.clone(), next_id: Some(self.next_id()) };
+        serde_json::to_writer(BufWriter::new(File::create(path)?), &checkpoint).map_err(io::Error::from)
+    }
+
+    // A checkpoint whose particles have a different number of axes is rejected, other than a version 1 one, which upgrade fits to N
+    pub fn load(path: &str) -> io::Result<ParticleSystem<N>> {
+        let text = fs::read_to_string(path)?;
+        let version : Version = serde_json::from_str(&text).map_err(io::Error::from)?;
+
+        let (particles, next_id) = match version.version {
+            1 => {
+                let checkpoint : Checkpoint<ParticleV1> = serde_json::from_str(&text).map_err(io::Error::from)?;
+                (checkpoint.particles.into_iter().map(ParticleV1::upgrade).collect::<io::Result<Vec<_>>>()?, checkpoint.next_id)
+            }
+            CHECKPOINT_VERSION => {
+                let checkpoint : Checkpoint<Particle<N>> = serde_json::from_str(&text).map_err(io::Error::from)?;
+                (checkpoint.particles, checkpoint.next_id)
+            }
+            unsupported => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unsupported checkpoint version {}", unsupported))),
+        };
+
+        let mut system = ParticleSystem::new(particles);
+        system.next_id = system.next_id.max(next_id.unwrap_or(0));
+        Ok(system)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broadphase::BruteForce;
+    use crate::{detect_collisions, Layout, ParticleSystemBuilder};
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("particles_{}_{}.json", name, std::process::id())).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn loaded_system_matches_the_saved_one() {
+        let system = ParticleSystem::builder().particle_count(500).initial_layout(Layout::RandomUniform).seed(9).build();
+        let path = temp_path("round_trip");
+
+        system.save(&path).unwrap();
+        let loaded = ParticleSystem::<2>::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.particles, system.particles);
+        assert_eq!(detect_collisions(&loaded.particles, &mut BruteForce::new()), detect_collisions(&system.particles, &mut BruteForce::new()));
+    }
+
+    #[test]
+    fn loaded_system_does_not_reuse_ids_removed_before_saving() {
+        let mut system = ParticleSystem::builder().particle_count(3).seed(9).build();
+        system.remove(2);
+        let path = temp_path("next_id");
+
+        system.save(&path).unwrap();
+        let mut loaded = ParticleSystem::<2>::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.spawn(Particle::new(1.0, 1.0, 0.0, 0.0, crate::PARTICLE_RADIUS)), 3);
+    }
+
+    // Written out as version 1 saved them, with named fields for every one of three axes
+    fn version_1_particle(id: u64, z: f32) -> String {
+        format!(r#"{{"id": {}, "x": 1.0, "y": 2.0, "z": {}, "vx": 0.5, "vy": -0.5, "vz": 0.0, "radius": 0.1, "mass": 0.2}}"#, id, z)
+    }
+
+    #[test]
+    fn checkpoints_without_a_next_id_carry_on_from_the_highest_particle() {
+        let path = temp_path("no_next_id");
+        let particles : Vec<String> = (0..3).map(|id| version_1_particle(id, 0.0)).collect();
+        std::fs::write(&path, format!(r#"{{"version": 1, "particles": [{}]}}"#, particles.join(","))).unwrap();
+
+        let loaded = ParticleSystem::<2>::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.next_id(), 3);
+    }
+
+    #[test]
+    fn version_1_checkpoints_are_fitted_to_the_axes_loaded_into() {
+        let path = temp_path("version_1");
+        std::fs::write(&path, format!(r#"{{"version": 1, "particles": [{}], "next_id": 5}}"#, version_1_particle(4, 0.0))).unwrap();
+        let flat = ParticleSystem::<2>::load(&path).unwrap();
+        let deep = ParticleSystem::<3>::load(&path).unwrap();
+
+        assert_eq!((flat.particles[0].id, flat.particles[0].position, flat.particles[0].velocity), (4, [1.0, 2.0], [0.5, -0.5]));
+        assert_eq!((deep.particles[0].position, deep.particles[0].velocity), ([1.0, 2.0, 0.0], [0.5, -0.5, 0.0]));
+        assert_eq!((flat.next_id(), flat.particles[0].species), (5, 0));
+
+        // A particle off z = 0 has nowhere to go in 2D
+        std::fs::write(&path, format!(r#"{{"version": 1, "particles": [{}]}}"#, version_1_particle(0, 3.0))).unwrap();
+        assert_eq!(ParticleSystem::<2>::load(&path).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        assert_eq!(ParticleSystem::<3>::load(&path).unwrap().particles[0].position, [1.0, 2.0, 3.0]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn checkpoints_with_the_wrong_number_of_axes_are_rejected() {
+        let system = ParticleSystemBuilder::<3>::default().particle_count(3).depth(2.0).seed(9).build();
+        let path = temp_path("wrong_axes");
+
+        system.save(&path).unwrap();
+        let error = ParticleSystem::<2>::load(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let path = temp_path("future_version");
+        std::fs::write(&path, r#"{"version": 99, "particles": []}"#).unwrap();
+
+        let error = ParticleSystem::<2>::load(&path).err().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,1200 @@
+use crate::broadphase::BroadphaseKind;
+use crate::emitter::EmitterSettings;
+use crate::events::COLLISION_LOG_CAPACITY;
+use crate::forces::{Potential, PotentialKind, Repulsion};
+use crate::metrics::METRICS_INTERVAL;
+use crate::integrator::IntegratorKind;
+use crate::movement::{MovementKind, DIFFUSION_COEFFICIENT, JUMP_STDDEV};
+use crate::outcome::CollisionOutcome;
+use crate::lockstep::SyncMode;
+use crate::{default_thread_count, BoundaryMode, Enclosure, Layout, RadiusDistribution, Species};
+use crate::{COLLISION_THREAD_COUNT, DRAG, ENCLOSURE_D, GRAVITY, MAX_SPEED, PARTICLE_COUNT, PARTICLE_RADIUS, RECORD_EVERY_FRAMES, RENDER_EVERY_FRAMES, REPULSION_CUTOFF, REPULSION_STRENGTH, SIMULATION_TIME, STREAM_FRAMES_PER_SECOND};
+use serde::{Deserialize, Serialize, Serializer};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+pub const USAGE : &str = "Usage: particles [options]
+    --config PATH           load settings from a TOML file, other options override it
+    --preset NAME           start from gas, billiards or dense instead of the defaults, other options override it
+    --particles N           number of particles
+    --threads N             number of move threads, one per core if not given
+    --collision-threads N   number of collision threads, each checking its own vertical strip of the enclosure
+    --width W               enclosure width
+    --height H              enclosure height
+    --circle R              use a round enclosure of radius R instead of a rectangle
+    --boundary MODE         reflect to bounce off the walls, or periodic to wrap round to the opposite side
+    --depth D               enclosure depth, anything above 0 simulates in 3D
+    --radius R              particle radius, or MIN:MAX for radii picked uniformly between them
+    --layout NAME           where particles start: origin, random, grid, circle or two-clusters
+    --temperature T         draw starting velocities from the Maxwell-Boltzmann distribution at T instead of uniformly
+    --duration T            simulation length, as 500ms, 30s, 2m or 1h, a bare number is seconds
+    --seconds S             simulation length in seconds, the same as --duration without a unit
+    --steps N               run exactly N timesteps instead of for a length of time
+    --tick-hz N             most steps a second each move thread takes, sleeping between them, 0 for as many as it can
+    --gravity G             vertical acceleration, negative pulls particles down
+    --repulsion K           strength of the push between nearby particles, 0 to turn it off
+    --repulsion-cutoff D    distance beyond which particles don't repel
+    --potential KIND:A:B    pair force instead of repulsion, lennard-jones:EPSILON:SIGMA or inverse-square:STRENGTH:CUTOFF
+    --drag D                fraction of its velocity a particle loses per second, 0 to turn it off
+    --max-speed S           fastest a particle can move, anything faster is slowed to it after every step
+    --integrator NAME       euler, or verlet for better energy conservation under forces
+    --movement NAME         ballistic along the velocities, brownian to wander randomly, teleport to jump anywhere each step or gaussian to jump nearby
+    --diffusion D           how fast brownian particles spread out, in square units a second
+    --jump-stddev S         standard deviation of each axis of a gaussian jump
+    --outcome NAME          what colliding particles do, bounce, annihilate to both disappear, or merge into one
+    --emit-every N          add a particle every N frames, the flags below set up the rest of the emitter
+    --emit-at X:Y           where emitted particles appear, the middle of the enclosure if not given
+    --emit-velocity VX:VY   velocity emitted particles start with
+    --emit-spread S         most that is added to or taken from each velocity component at random
+    --max-particles N       stop emitting while the system has this many particles
+    --ccd                   also catch pairs that pass through each other within a step, takes no value
+    --cluster-epsilon D     count a pair closer than D (e.g. 0.001) as one cluster collision, then skip it until they separate
+    --event-driven          fly particles straight to each predicted collision instead of stepping, takes no value
+    --broadphase KIND       brute-force, parallel, grid, hash, quadtree or sweep
+    --sync MODE             barrier to advance every thread a step at a time, or free-running to let each run its own loop
+    --seed N                seed for the starting state, random if not given
+    --record PATH           write particle trajectories to a CSV file
+    --record-every N        frames between trajectory and energy samples
+    --replay PATH           check a recorded trajectory file for collisions instead of running a simulation
+    --render DIR            write PNG frames into a directory, created if missing
+    --render-every N        collision frames between rendered images
+    --speed-colours MAX     colour PNG frames by speed from blue when still to red at MAX and faster
+    --trails F              fade earlier frames by F, e.g. 0.9, and draw over them for trails in the PNG frames and terminal view
+    --jsonl PATH            write particle positions to a JSON lines file, one frame's object per line
+    --jsonl-every N         collision frames between JSON lines frames
+    --heatmap PATH          write where collisions happened, as a PNG if PATH ends in .png or a CSV matrix otherwise
+    --energy PATH           write the total kinetic energy over time to a CSV file
+    --collision-log PATH    write the frame, time and particle ids of every collision to a CSV file
+    --collision-log-cap N   most collisions the log keeps, the oldest are dropped past it
+    --recent-collisions K   print the last K collisions, when and between which particles, with the report
+    --metrics PATH          write collisions, energy, speed, the centre of mass and its velocity and time to the next collision sampled through the run to a CSV file
+    --metrics-every T       time between metrics samples, with the same units as --duration
+    --gnuplot               write a gnuplot script next to the trajectory, energy and metrics CSVs to plot each, takes no value
+    --svg PATH              draw the particles as they finished to an SVG file
+    --speed-bins N          print a histogram of the particles' speeds as they finished, in N bins
+    --speed-histogram PATH  write the finishing speed histogram to a CSV file, in 20 bins unless --speed-bins is given
+    --serve PORT            stream particle positions to TCP clients as length-prefixed JSON
+    --serve-fps N           most frames a second to stream
+    --tui                   draw the particles in the terminal as they move, space pauses and q stops, takes no value
+    --progress              show a progress bar with the step rate and time left, takes no value
+    --fps                   log every move thread's frames per second once a second, takes no value
+    --json                  print the report as a single JSON object, leaving everything else on stderr, takes no value
+    --headless, --quiet     print nothing but the report, and any errors, skipping the particle dump, takes no value
+    --deterministic         insist on barrier sync and a step count, so a seed always gives the same report, takes no value
+    --profile               time how long every thread waits for and holds the particle lock, takes no value";
+
+// Flags that are on when given and take no value
+const SWITCHES : &[&str] = &["--ccd", "--event-driven", "--tui", "--profile", "--progress", "--fps", "--gnuplot", "--json", "--headless", "--quiet", "--deterministic"];
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Argument(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(error) => write!(f, "Could not read config file: {}", error),
+            ConfigError::Toml(error) => write!(f, "Could not parse config file: {}", error),
+            ConfigError::Argument(message) => write!(f, "{}", message),
+            ConfigError::Invalid(message) => write!(f, "Invalid config: {}", message),
+        }
+    }
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(error: std::io::Error) -> Self {
+        ConfigError::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(error: toml::de::Error) -> Self {
+        ConfigError::Toml(error)
+    }
+}
+
+// Every tunable value for a run, defaulting to the constants
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SimConfig {
+    pub particle_count: usize,
+    pub thread_count: usize,
+    pub collision_thread_count: usize,
+    pub enclosure: Enclosure,
+    pub boundary: BoundaryMode,
+    pub depth: f32,
+    pub radius: RadiusDistribution,
+    pub species: Vec<Species>, // Only set from a config file, replaces particle_count and radius when given
+    pub layout: Layout,
+    pub temperature: Option<f32>,
+    #[serde(serialize_with = "serialize_seconds")]
+    pub duration: Duration,
+    pub steps: Option<u32>, // Overrides duration when set, so a run's length doesn't depend on how fast the machine is
+    pub tick_hz: f32, // Most steps a second, 0 to step flat out
+    pub gravity: f32,
+    pub repulsion: f32,
+    pub repulsion_cutoff: f32,
+    pub potential: Option<PotentialKind>, // Replaces repulsion as the force between pairs of particles
+    pub drag: f32,
+    pub max_speed: f32,
+    pub integrator: IntegratorKind,
+    pub movement: MovementKind,
+    pub diffusion: f32, // Diffusion coefficient of the Brownian walk
+    pub jump_stddev: f32, // Spread of each Gaussian jump
+    pub outcome: CollisionOutcome,
+    pub emitter: Option<EmitterSettings>,
+    pub ccd: bool, // Continuous collision detection, for particles fast enough to jump through each other
+    pub cluster_epsilon: Option<f32>, // Pairs whose centres are closer than this are counted once and left alone while they overlap, off if not given
+    pub event_driven: bool, // Predict collisions and jump between them instead of stepping
+    pub broadphase: BroadphaseKind,
+    pub sync: SyncMode,
+    pub seed: Option<u64>,
+    pub record_path: Option<String>,
+    pub record_every: u32,
+    pub replay_path: Option<String>,
+    pub render_dir: Option<String>,
+    pub render_every: usize,
+    pub speed_colours: Option<f32>, // The speed drawn reddest, colours show collisions if not given
+    pub trails: f32, // How much of the last frame is kept under the next, 0 to clear each frame
+    pub jsonl_path: Option<String>,
+    pub jsonl_every: usize,
+    pub heatmap_path: Option<String>,
+    pub energy_path: Option<String>,
+    pub collision_log_path: Option<String>,
+    pub collision_log_capacity: usize,
+    pub recent_collisions: usize, // How many of the last collisions the report lists, 0 for none
+    pub metrics_path: Option<String>,
+    #[serde(serialize_with = "serialize_seconds")]
+    pub metrics_interval: Duration,
+    pub gnuplot: bool,
+    pub svg_path: Option<String>,
+    pub speed_bins: Option<usize>, // Print the finishing speed histogram with this many bins
+    pub speed_histogram_path: Option<String>,
+    pub serve_port: Option<u16>,
+    pub serve_fps: f32,
+    pub tui: bool,
+    pub progress: bool,
+    pub fps: bool,
+    pub json: bool,
+    pub headless: bool, // Only errors are logged, and just the report is printed
+    pub deterministic: bool, // Only checks the settings that make a run repeatable, so a config that would drift is refused
+    pub profile: bool,
+}
+
+// Layout of a config file, every key is optional and unknown keys are an error so typos get caught
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    particles: Option<usize>,
+    threads: Option<usize>,
+    collision_threads: Option<usize>,
+    width: Option<f32>,
+    height: Option<f32>,
+    circle: Option<f32>, // Radius of a round enclosure, can't be given with width or height
+    boundary: Option<String>,
+    depth: Option<f32>,
+    radius: Option<RadiusDistribution>, // Either a number or { min = .., max = .. }
+    species: Option<Vec<Species>>, // [[species]] tables of count, radius and an optional mass
+    layout: Option<String>,
+    temperature: Option<f32>,
+    duration: Option<String>, // With a unit, like "500ms" or "2m"
+    seconds: Option<f32>,
+    steps: Option<u32>,
+    tick_hz: Option<f32>,
+    gravity: Option<f32>,
+    repulsion: Option<f32>,
+    repulsion_cutoff: Option<f32>,
+    potential: Option<PotentialKind>, // A table with a kind, like { kind = "lennard-jones", epsilon = 1.0, sigma = 0.1 }
+    drag: Option<f32>,
+    max_speed: Option<f32>,
+    integrator: Option<String>,
+    movement: Option<String>,
+    diffusion: Option<f32>,
+    jump_stddev: Option<f32>,
+    outcome: Option<String>,
+    emitter: Option<EmitterSettings>, // An [emitter] table, any key not given takes its default
+    ccd: Option<bool>,
+    cluster_epsilon: Option<f32>,
+    event_driven: Option<bool>,
+    broadphase: Option<String>,
+    sync: Option<String>,
+    seed: Option<u64>,
+    record: Option<String>,
+    record_every: Option<u32>,
+    replay: Option<String>,
+    render: Option<String>,
+    render_every: Option<usize>,
+    speed_colours: Option<f32>,
+    trails: Option<f32>,
+    jsonl: Option<String>,
+    jsonl_every: Option<usize>,
+    heatmap: Option<String>,
+    energy: Option<String>,
+    collision_log: Option<String>,
+    collision_log_cap: Option<usize>,
+    recent_collisions: Option<usize>,
+    metrics: Option<String>,
+    metrics_every: Option<String>, // With a unit, like duration
+    gnuplot: Option<bool>,
+    svg: Option<String>,
+    speed_bins: Option<usize>,
+    speed_histogram: Option<String>,
+    serve: Option<u16>,
+    serve_fps: Option<f32>,
+    tui: Option<bool>,
+    progress: Option<bool>,
+    fps: Option<bool>,
+    json: Option<bool>,
+    headless: Option<bool>,
+    deterministic: Option<bool>,
+    profile: Option<bool>,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            particle_count: PARTICLE_COUNT,
+            thread_count: default_thread_count(),
+            collision_thread_count: COLLISION_THREAD_COUNT,
+            enclosure: Enclosure::default(),
+            boundary: BoundaryMode::Reflect,
+            depth: ENCLOSURE_D,
+            radius: RadiusDistribution::Fixed(PARTICLE_RADIUS),
+            species: Vec::new(),
+            layout: Layout::Origin,
+            temperature: None,
+            duration: SIMULATION_TIME,
+            steps: None,
+            tick_hz: 0.0,
+            gravity: GRAVITY,
+            repulsion: REPULSION_STRENGTH,
+            repulsion_cutoff: REPULSION_CUTOFF,
+            potential: None,
+            drag: DRAG,
+            max_speed: MAX_SPEED,
+            integrator: IntegratorKind::Euler,
+            movement: MovementKind::Ballistic,
+            diffusion: DIFFUSION_COEFFICIENT,
+            jump_stddev: JUMP_STDDEV,
+            outcome: CollisionOutcome::Bounce,
+            emitter: None,
+            ccd: false,
+            cluster_epsilon: None,
+            event_driven: false,
+            broadphase: BroadphaseKind::SpatialGrid,
+            sync: SyncMode::Barrier,
+            seed: None,
+            record_path: None,
+            record_every: RECORD_EVERY_FRAMES,
+            replay_path: None,
+            render_dir: None,
+            render_every: RENDER_EVERY_FRAMES,
+            speed_colours: None,
+            trails: 0.0,
+            jsonl_path: None,
+            jsonl_every: RECORD_EVERY_FRAMES as usize,
+            heatmap_path: None,
+            energy_path: None,
+            collision_log_path: None,
+            collision_log_capacity: COLLISION_LOG_CAPACITY,
+            recent_collisions: 0,
+            metrics_path: None,
+            metrics_interval: METRICS_INTERVAL,
+            gnuplot: false,
+            svg_path: None,
+            speed_bins: None,
+            speed_histogram_path: None,
+            serve_port: None,
+            serve_fps: STREAM_FRAMES_PER_SECOND,
+            tui: false,
+            progress: false,
+            fps: false,
+            json: false,
+            headless: false,
+            deterministic: false,
+            profile: false,
+        }
+    }
+}
+
+impl SimConfig {
+    // Build a config from command line arguments, not including the program name
+    // If --config is given the file is loaded first, then the remaining flags are applied on top
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Self, ConfigError> {
+        let mut flags = Vec::new();
+        let mut args = args.into_iter();
+
+        while let Some(flag) = args.next() {
+            if SWITCHES.contains(&flag.as_str()) {
+                flags.push((flag, "true".to_string()));
+                continue;
+            }
+            let value = args.next().ok_or_else(|| ConfigError::Argument(format!("Missing value for {}", flag)))?;
+            flags.push((flag, value));
+        }
+
+        let config_path = flags.iter().rev().find(|(flag, _)| flag == "--config").map(|(_, path)| path);
+        let preset_name = flags.iter().rev().find(|(flag, _)| flag == "--preset").map(|(_, name)| name);
+        let mut config = match (config_path, preset_name) {
+            (Some(_), Some(_)) => return Err(ConfigError::Invalid("a preset and a config file would each replace the other, pick one of --preset and --config".to_string())),
+            (Some(path), None) => SimConfig::from_toml_path(path)?,
+            (None, Some(name)) => preset(name).ok_or_else(|| ConfigError::Invalid(format!("unknown preset {}, expected {}", name, PRESETS.join(", "))))?,
+            (None, None) => SimConfig::default(),
+        };
+
+        for (flag, value) in &flags {
+            match flag.as_str() {
+                "--config" | "--preset" => {}
+                "--particles" => config.particle_count = parse_value(flag, value)?,
+                "--threads" => config.thread_count = parse_value(flag, value)?,
+                "--collision-threads" => config.collision_thread_count = parse_value(flag, value)?,
+                "--width" => config.enclosure = Enclosure::Rect { w: parse_value(flag, value)?, h: config.enclosure.height() },
+                "--height" => config.enclosure = Enclosure::Rect { w: config.enclosure.width(), h: parse_value(flag, value)? },
+                "--circle" => config.enclosure = Enclosure::Circle { radius: parse_value(flag, value)? },
+                "--boundary" => config.boundary = parse_boundary(value)?,
+                "--depth" => config.depth = parse_value(flag, value)?,
+                "--radius" => config.radius = parse_radius(flag, value)?,
+                "--layout" => config.layout = parse_layout(value)?,
+                "--temperature" => config.temperature = Some(parse_value(flag, value)?),
+                "--duration" | "--seconds" => config.duration = parse_duration(value)?,
+                "--steps" => config.steps = Some(parse_value(flag, value)?),
+                "--tick-hz" => config.tick_hz = parse_value(flag, value)?,
+                "--gravity" => config.gravity = parse_value(flag, value)?,
+                "--repulsion" => config.repulsion = parse_value(flag, value)?,
+                "--repulsion-cutoff" => config.repulsion_cutoff = parse_value(flag, value)?,
+                "--potential" => config.potential = Some(parse_potential(flag, value)?),
+                "--drag" => config.drag = parse_value(flag, value)?,
+                "--max-speed" => config.max_speed = parse_value(flag, value)?,
+                "--integrator" => config.integrator = parse_integrator(value)?,
+                "--movement" => config.movement = parse_movement(value)?,
+                "--diffusion" => config.diffusion = parse_value(flag, value)?,
+                "--jump-stddev" => config.jump_stddev = parse_value(flag, value)?,
+                "--outcome" => config.outcome = parse_outcome(value)?,
+                "--emit-every" => config.emitter.get_or_insert_with(EmitterSettings::default).every = parse_value(flag, value)?,
+                "--emit-at" => config.emitter.get_or_insert_with(EmitterSettings::default).position = Some(parse_pair(flag, value)?),
+                "--emit-velocity" => config.emitter.get_or_insert_with(EmitterSettings::default).velocity = parse_pair(flag, value)?,
+                "--emit-spread" => config.emitter.get_or_insert_with(EmitterSettings::default).spread = parse_value(flag, value)?,
+                "--max-particles" => config.emitter.get_or_insert_with(EmitterSettings::default).max_particles = parse_value(flag, value)?,
+                "--ccd" => config.ccd = true,
+                "--cluster-epsilon" => config.cluster_epsilon = Some(parse_value(flag, value)?),
+                "--event-driven" => config.event_driven = true,
+                "--broadphase" => config.broadphase = parse_broadphase(value)?,
+                "--sync" => config.sync = parse_sync(value)?,
+                "--seed" => config.seed = Some(parse_value(flag, value)?),
+                "--record" => config.record_path = Some(value.clone()),
+                "--record-every" => config.record_every = parse_value(flag, value)?,
+                "--replay" => config.replay_path = Some(value.clone()),
+                "--render" => config.render_dir = Some(value.clone()),
+                "--render-every" => config.render_every = parse_value(flag, value)?,
+                "--speed-colours" => config.speed_colours = Some(parse_value(flag, value)?),
+                "--trails" => config.trails = parse_value(flag, value)?,
+                "--jsonl" => config.jsonl_path = Some(value.clone()),
+                "--jsonl-every" => config.jsonl_every = parse_value(flag, value)?,
+                "--heatmap" => config.heatmap_path = Some(value.clone()),
+                "--energy" => config.energy_path = Some(value.clone()),
+                "--collision-log" => config.collision_log_path = Some(value.clone()),
+                "--collision-log-cap" => config.collision_log_capacity = parse_value(flag, value)?,
+                "--recent-collisions" => config.recent_collisions = parse_value(flag, value)?,
+                "--metrics" => config.metrics_path = Some(value.clone()),
+                "--metrics-every" => config.metrics_interval = parse_duration(value)?,
+                "--gnuplot" => config.gnuplot = true,
+                "--svg" => config.svg_path = Some(value.clone()),
+                "--speed-bins" => config.speed_bins = Some(parse_value(flag, value)?),
+                "--speed-histogram" => config.speed_histogram_path = Some(value.clone()),
+                "--serve" => config.serve_port = Some(parse_value(flag, value)?),
+                "--serve-fps" => config.serve_fps = parse_value(flag, value)?,
+                "--tui" => config.tui = true,
+                "--progress" => config.progress = true,
+                "--fps" => config.fps = true,
+                "--json" => config.json = true,
+                "--headless" | "--quiet" => config.headless = true,
+                "--deterministic" => config.deterministic = true,
+                "--profile" => config.profile = true,
+                _ => return Err(ConfigError::Argument(format!("Unknown option {}", flag))),
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml_path(path: &str) -> Result<SimConfig, ConfigError> {
+        SimConfig::from_toml_str(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<SimConfig, ConfigError> {
+        let file : ConfigFile = toml::from_str(contents)?;
+        let mut config = SimConfig::default();
+
+        if let Some(particles) = file.particles { config.particle_count = particles; }
+        if let Some(threads) = file.threads { config.thread_count = threads; }
+        if let Some(collision_threads) = file.collision_threads { config.collision_thread_count = collision_threads; }
+        config.enclosure = match (file.width, file.height, file.circle) {
+            (None, None, Some(radius)) => Enclosure::Circle { radius },
+            (width, height, None) => Enclosure::Rect { w: width.unwrap_or(config.enclosure.width()), h: height.unwrap_or(config.enclosure.height()) },
+            _ => return Err(ConfigError::Invalid("circle can't be given with width or height".to_string())),
+        };
+        if let Some(boundary) = file.boundary { config.boundary = parse_boundary(&boundary)?; }
+        if let Some(depth) = file.depth { config.depth = depth; }
+        if let Some(radius) = file.radius { config.radius = radius; }
+        if let Some(species) = file.species {
+            config.particle_count = species.iter().map(|s| s.count).sum();
+            config.species = species;
+        }
+        if let Some(layout) = file.layout { config.layout = parse_layout(&layout)?; }
+        if file.temperature.is_some() { config.temperature = file.temperature; }
+        if let Some(seconds) = file.seconds { config.duration = parse_duration(&seconds.to_string())?; }
+        if let Some(duration) = file.duration { config.duration = parse_duration(&duration)?; }
+        if file.steps.is_some() { config.steps = file.steps; }
+        if let Some(tick_hz) = file.tick_hz { config.tick_hz = tick_hz; }
+        if let Some(gravity) = file.gravity { config.gravity = gravity; }
+        if let Some(repulsion) = file.repulsion { config.repulsion = repulsion; }
+        if let Some(repulsion_cutoff) = file.repulsion_cutoff { config.repulsion_cutoff = repulsion_cutoff; }
+        if file.potential.is_some() { config.potential = file.potential; }
+        if let Some(drag) = file.drag { config.drag = drag; }
+        if let Some(max_speed) = file.max_speed { config.max_speed = max_speed; }
+        if let Some(integrator) = file.integrator { config.integrator = parse_integrator(&integrator)?; }
+        if let Some(movement) = file.movement { config.movement = parse_movement(&movement)?; }
+        if let Some(diffusion) = file.diffusion { config.diffusion = diffusion; }
+        if let Some(jump_stddev) = file.jump_stddev { config.jump_stddev = jump_stddev; }
+        if let Some(outcome) = file.outcome { config.outcome = parse_outcome(&outcome)?; }
+        if file.emitter.is_some() { config.emitter = file.emitter; }
+        if let Some(ccd) = file.ccd { config.ccd = ccd; }
+        if file.cluster_epsilon.is_some() { config.cluster_epsilon = file.cluster_epsilon; }
+        if let Some(event_driven) = file.event_driven { config.event_driven = event_driven; }
+        if let Some(broadphase) = file.broadphase { config.broadphase = parse_broadphase(&broadphase)?; }
+        if let Some(sync) = file.sync { config.sync = parse_sync(&sync)?; }
+        if file.seed.is_some() { config.seed = file.seed; }
+        if file.record.is_some() { config.record_path = file.record; }
+        if let Some(record_every) = file.record_every { config.record_every = record_every; }
+        if file.replay.is_some() { config.replay_path = file.replay; }
+        if file.render.is_some() { config.render_dir = file.render; }
+        if let Some(render_every) = file.render_every { config.render_every = render_every; }
+        if file.speed_colours.is_some() { config.speed_colours = file.speed_colours; }
+        if let Some(trails) = file.trails { config.trails = trails; }
+        if file.jsonl.is_some() { config.jsonl_path = file.jsonl; }
+        if let Some(jsonl_every) = file.jsonl_every { config.jsonl_every = jsonl_every; }
+        if file.heatmap.is_some() { config.heatmap_path = file.heatmap; }
+        if file.energy.is_some() { config.energy_path = file.energy; }
+        if file.collision_log.is_some() { config.collision_log_path = file.collision_log; }
+        if let Some(capacity) = file.collision_log_cap { config.collision_log_capacity = capacity; }
+        if let Some(recent_collisions) = file.recent_collisions { config.recent_collisions = recent_collisions; }
+        if file.metrics.is_some() { config.metrics_path = file.metrics; }
+        if let Some(metrics_every) = file.metrics_every { config.metrics_interval = parse_duration(&metrics_every)?; }
+        if let Some(gnuplot) = file.gnuplot { config.gnuplot = gnuplot; }
+        if file.svg.is_some() { config.svg_path = file.svg; }
+        if file.speed_bins.is_some() { config.speed_bins = file.speed_bins; }
+        if file.speed_histogram.is_some() { config.speed_histogram_path = file.speed_histogram; }
+        if file.serve.is_some() { config.serve_port = file.serve; }
+        if let Some(serve_fps) = file.serve_fps { config.serve_fps = serve_fps; }
+        if let Some(tui) = file.tui { config.tui = tui; }
+        if let Some(progress) = file.progress { config.progress = progress; }
+        if let Some(fps) = file.fps { config.fps = fps; }
+        if let Some(json) = file.json { config.json = json; }
+        if let Some(headless) = file.headless { config.headless = headless; }
+        if let Some(deterministic) = file.deterministic { config.deterministic = deterministic; }
+        if let Some(profile) = file.profile { config.profile = profile; }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn is_3d(&self) -> bool {
+        self.depth > 0.0
+    }
+
+    pub fn dimensions(&self) -> usize {
+        if self.is_3d() { 3 } else { 2 }
+    }
+
+    // The enclosure if its edges wrap, for measuring between particles the shortest way round
+    pub fn wrap(&self) -> Option<&Enclosure> {
+        (self.boundary == BoundaryMode::Periodic).then_some(&self.enclosure)
+    }
+
+    // The force between pairs of particles, the potential if one is given or else repulsion if it's on
+    pub fn pair_potential(&self) -> Option<Box<dyn Potential>> {
+        match self.potential {
+            Some(kind) => Some(kind.build()),
+            None => (self.repulsion > 0.0).then(|| Box::new(Repulsion { strength: self.repulsion, cutoff: self.repulsion_cutoff }) as Box<dyn Potential>),
+        }
+    }
+
+    pub fn has_pair_forces(&self) -> bool {
+        self.potential.is_some() || self.repulsion > 0.0
+    }
+
+    // The largest radius any particle can have, which sets how far apart a broadphase has to look
+    // Merged particles keep the area of both, so when collisions merge that is the size of every particle merged into one
+    // An emitter tops the system up to its cap with particles of the config's radius, and those can be merged in too
+    pub fn max_radius(&self) -> f32 {
+        let max_particles = self.emitter.as_ref().map_or(0, |emitter| emitter.max_particles);
+        match (self.outcome, self.species.is_empty()) {
+            (CollisionOutcome::Merge, true) => self.radius.max() * (self.particle_count.max(max_particles) as f32).sqrt(),
+            (CollisionOutcome::Merge, false) => {
+                let species_count : usize = self.species.iter().map(|s| s.count).sum();
+                let emitted_area = self.radius.max().powi(2) * max_particles.saturating_sub(species_count) as f32;
+                (self.species.iter().map(|s| s.radius.max().powi(2) * s.count as f32).sum::<f32>() + emitted_area).sqrt()
+            }
+            (_, true) => self.radius.max(),
+            (_, false) => self.species.iter().map(|s| s.radius.max()).fold(0.0, f32::max),
+        }
+    }
+
+    // Whether a loop that started at start_time and has done iterations steps should take another
+    pub fn keep_running(&self, iterations: u32, start_time: Instant) -> bool {
+        match self.steps {
+            Some(steps) => iterations < steps,
+            None => start_time.elapsed() < self.duration,
+        }
+    }
+
+    // Reject values the simulation can't run with
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.particle_count == 0 || self.thread_count == 0 || self.collision_thread_count == 0 || self.record_every == 0 || self.render_every == 0 || self.jsonl_every == 0 || self.steps == Some(0) || self.collision_log_capacity == 0 || self.speed_bins == Some(0) {
+            return Err(ConfigError::Invalid("particle counts, thread counts, step counts, the recording, render and JSON lines intervals, the collision log's capacity and speed bins must be at least 1".to_string()));
+        }
+
+        // Written so NaN fails too, as every comparison with it is false
+        let positive = |size: f32| size > 0.0 && size.is_finite();
+        let sized = positive(self.enclosure.width()) && positive(self.enclosure.height()) && self.depth >= 0.0 && self.depth.is_finite();
+        if !sized || self.duration.is_zero() {
+            return Err(ConfigError::Invalid("enclosure size and simulation length must be positive, or zero depth for 2D".to_string()));
+        }
+
+        if self.temperature.is_some_and(|temperature| temperature <= 0.0) {
+            return Err(ConfigError::Invalid("temperature must be positive".to_string()));
+        }
+
+        if self.speed_colours.is_some_and(|max_speed| max_speed.is_nan() || max_speed <= 0.0) {
+            return Err(ConfigError::Invalid("the speed drawn reddest must be positive".to_string()));
+        }
+
+        if !(0.0..=1.0).contains(&self.trails) {
+            return Err(ConfigError::Invalid("the trail fade must be from 0 to 1".to_string()));
+        }
+
+        if !(self.tick_hz >= 0.0 && self.tick_hz.is_finite()) {
+            return Err(ConfigError::Invalid("the tick rate can't be negative".to_string()));
+        }
+
+        if self.serve_fps <= 0.0 {
+            return Err(ConfigError::Invalid("the streaming frame rate must be positive".to_string()));
+        }
+
+        if self.tui && self.progress {
+            return Err(ConfigError::Invalid("the progress bar would draw over the terminal view, pick one of --tui and --progress".to_string()));
+        }
+
+        if self.deterministic && (self.sync != SyncMode::Barrier || self.steps.is_none()) {
+            return Err(ConfigError::Invalid("a deterministic run needs barrier sync and a step count".to_string()));
+        }
+
+        if self.headless && (self.tui || self.progress || self.fps) {
+            return Err(ConfigError::Invalid("a headless run has nothing on the console but the report, so can't have --tui, --progress or --fps".to_string()));
+        }
+
+        if self.tui && self.json {
+            return Err(ConfigError::Invalid("the terminal view draws on stdout, which --json keeps for the report".to_string()));
+        }
+
+        if self.repulsion < 0.0 || self.repulsion_cutoff <= 0.0 {
+            return Err(ConfigError::Invalid("repulsion can't be negative and its cutoff must be positive".to_string()));
+        }
+
+        match self.potential {
+            Some(_) if self.repulsion > 0.0 => return Err(ConfigError::Invalid("a potential replaces repulsion, pick one of --potential and --repulsion".to_string())),
+            Some(PotentialKind::LennardJones { epsilon, sigma }) if !(epsilon > 0.0 && sigma > 0.0 && epsilon.is_finite() && sigma.is_finite()) => {
+                return Err(ConfigError::Invalid("a Lennard-Jones potential needs a positive epsilon and sigma".to_string()));
+            }
+            Some(PotentialKind::InverseSquare { strength, cutoff }) if !(strength.is_finite() && cutoff > 0.0 && cutoff.is_finite()) => {
+                return Err(ConfigError::Invalid("an inverse-square potential needs a finite strength and a positive cutoff".to_string()));
+            }
+            _ => {}
+        }
+
+        if !(self.drag >= 0.0 && self.drag.is_finite()) {
+            return Err(ConfigError::Invalid("drag can't be negative".to_string()));
+        }
+
+        if self.max_speed.is_nan() || self.max_speed <= 0.0 {
+            return Err(ConfigError::Invalid("the max speed must be positive, or inf for no limit".to_string()));
+        }
+
+        if let Some(emitter) = &self.emitter {
+            let position_valid = emitter.position.is_none_or(|(x, y)| self.enclosure.contains(x, y));
+            let numbers_valid = [emitter.velocity.0, emitter.velocity.1, emitter.spread].iter().all(|v| v.is_finite()) && emitter.spread >= 0.0;
+            if emitter.every == 0 || emitter.max_particles == 0 || !position_valid || !numbers_valid {
+                return Err(ConfigError::Invalid("an emitter needs an interval and particle cap of at least 1, a position inside the enclosure and a finite velocity and spread".to_string()));
+            }
+        }
+
+        if !(self.diffusion >= 0.0 && self.diffusion.is_finite()) {
+            return Err(ConfigError::Invalid("the diffusion coefficient can't be negative".to_string()));
+        }
+
+        if !(self.jump_stddev >= 0.0 && self.jump_stddev.is_finite()) {
+            return Err(ConfigError::Invalid("the gaussian jump's standard deviation can't be negative".to_string()));
+        }
+
+        if self.movement != MovementKind::Ballistic && (self.gravity != 0.0 || self.has_pair_forces()) {
+            return Err(ConfigError::Invalid("only ballistic movement feels gravity, repulsion and potentials".to_string()));
+        }
+
+        if self.cluster_epsilon.is_some_and(|epsilon| !(epsilon > 0.0 && epsilon.is_finite())) {
+            return Err(ConfigError::Invalid("the cluster epsilon must be positive".to_string()));
+        }
+
+        if self.ccd && (self.boundary == BoundaryMode::Periodic || self.collision_thread_count > 1) {
+            return Err(ConfigError::Invalid("continuous collision detection needs walls and a single collision thread".to_string()));
+        }
+
+        // Particles have to fly in straight lines between events and bounce off each other and the walls
+        let straight_lines = self.gravity == 0.0 && !self.has_pair_forces() && self.drag == 0.0 && self.movement == MovementKind::Ballistic;
+        if self.event_driven && !(straight_lines && self.boundary == BoundaryMode::Reflect && self.outcome == CollisionOutcome::Bounce && self.emitter.is_none()) {
+            return Err(ConfigError::Invalid("event-driven runs need ballistic movement without gravity, pair forces or drag, walls, the bounce outcome and no emitter".to_string()));
+        }
+
+        if self.boundary == BoundaryMode::Periodic {
+            if let Enclosure::Circle { .. } = self.enclosure {
+                return Err(ConfigError::Invalid("periodic boundaries need a rectangular enclosure".to_string()));
+            }
+            if matches!(self.broadphase, BroadphaseKind::QuadTree | BroadphaseKind::SpatialHash | BroadphaseKind::SweepAndPrune) {
+                return Err(ConfigError::Invalid("only brute-force, parallel and grid can find collisions across periodic boundaries".to_string()));
+            }
+        }
+
+        let radius_valid = |radius: &RadiusDistribution| match *radius {
+            RadiusDistribution::Fixed(radius) => radius > 0.0,
+            RadiusDistribution::Uniform { min, max } => min > 0.0 && min <= max,
+        };
+        if !radius_valid(&self.radius) || !self.species.iter().all(|s| radius_valid(&s.radius)) {
+            return Err(ConfigError::Invalid("radii must be positive, with the minimum no larger than the maximum".to_string()));
+        }
+
+        if !self.species.is_empty() {
+            if self.species.len() > u8::MAX as usize + 1 || self.species.iter().any(|s| s.count == 0 || s.mass.is_some_and(|mass| mass <= 0.0)) {
+                return Err(ConfigError::Invalid("there can be at most 256 species, each with at least one particle and a positive mass".to_string()));
+            }
+            if self.species.iter().map(|s| s.count).sum::<usize>() != self.particle_count {
+                return Err(ConfigError::Invalid("the particle count can't be changed when species are given, change the species counts instead".to_string()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, ConfigError> {
+    value.parse().map_err(|_| ConfigError::Argument(format!("Invalid value {} for {}", value, flag)))
+}
+
+// Either a single radius or MIN:MAX
+fn parse_radius(flag: &str, value: &str) -> Result<RadiusDistribution, ConfigError> {
+    match value.split_once(':') {
+        Some((min, max)) => Ok(RadiusDistribution::Uniform { min: parse_value(flag, min)?, max: parse_value(flag, max)? }),
+        None => Ok(RadiusDistribution::Fixed(parse_value(flag, value)?)),
+    }
+}
+
+// Two numbers separated by a colon, like 1.5:2
+fn parse_pair(flag: &str, value: &str) -> Result<(f32, f32), ConfigError> {
+    let (a, b) = value.split_once(':').ok_or_else(|| ConfigError::Argument(format!("{} takes two numbers separated by a colon, got {}", flag, value)))?;
+    Ok((parse_value(flag, a)?, parse_value(flag, b)?))
+}
+
+// NAME:A:B, like lennard-jones:1:0.1
+fn parse_potential(flag: &str, value: &str) -> Result<PotentialKind, ConfigError> {
+    let (name, numbers) = value.split_once(':').ok_or_else(|| ConfigError::Argument(format!("{} takes a potential and two numbers, like lennard-jones:1:0.1, got {}", flag, value)))?;
+    let (a, b) = parse_pair(flag, numbers)?;
+    PotentialKind::from_parts(name, a, b).ok_or_else(|| ConfigError::Invalid(format!("unknown potential {}, expected lennard-jones or inverse-square", name)))
+}
+
+fn parse_broadphase(name: &str) -> Result<BroadphaseKind, ConfigError> {
+    BroadphaseKind::from_name(name).ok_or_else(|| ConfigError::Invalid(format!("unknown broadphase {}", name)))
+}
+
+fn parse_boundary(name: &str) -> Result<BoundaryMode, ConfigError> {
+    BoundaryMode::from_name(name).ok_or_else(|| ConfigError::Invalid(format!("unknown boundary {}, expected reflect or periodic", name)))
+}
+
+pub const PRESETS : &[&str] = &["gas", "billiards", "dense"];
+
+// Starting points that show off the simulation, picked with --preset
+// Speeds are set through the temperature, so they come out as a spread rather than all the same
+pub fn preset(name: &str) -> Option<SimConfig> {
+    let (particle_count, radius, temperature, layout) = match name {
+        "gas" => (1000, 0.03, 0.03, Layout::RandomUniform), // Many small particles moving fast, about 3 units a second
+        "billiards" => (16, 0.4, 0.5, Layout::Grid), // A few big, heavy balls, about 1 unit a second
+        "dense" => (1500, 0.1, 0.008, Layout::Grid), // Nearly half the enclosure covered, moving slowly into each other
+        _ => return None,
+    };
+
+    Some(SimConfig {
+        particle_count,
+        radius: RadiusDistribution::Fixed(radius),
+        temperature: Some(temperature),
+        layout,
+        outcome: CollisionOutcome::Bounce,
+        ..SimConfig::default()
+    })
+}
+
+fn parse_layout(name: &str) -> Result<Layout, ConfigError> {
+    Layout::from_name(name).ok_or_else(|| ConfigError::Invalid(format!("unknown layout {}, expected origin, random, grid, circle or two-clusters", name)))
+}
+
+fn parse_integrator(name: &str) -> Result<IntegratorKind, ConfigError> {
+    IntegratorKind::from_name(name).ok_or_else(|| ConfigError::Invalid(format!("unknown integrator {}, expected euler or verlet", name)))
+}
+
+fn parse_movement(name: &str) -> Result<MovementKind, ConfigError> {
+    MovementKind::from_name(name).ok_or_else(|| ConfigError::Invalid(format!("unknown movement {}, expected ballistic, brownian, teleport or gaussian", name)))
+}
+
+fn parse_outcome(name: &str) -> Result<CollisionOutcome, ConfigError> {
+    CollisionOutcome::from_name(name).ok_or_else(|| ConfigError::Invalid(format!("unknown outcome {}, expected bounce, annihilate or merge", name)))
+}
+
+// A number with an optional unit of ms, s, m or h, seconds if there isn't one
+fn parse_duration(value: &str) -> Result<Duration, ConfigError> {
+    let invalid = || ConfigError::Invalid(format!("invalid duration {}, expected a positive number with an optional unit of ms, s, m or h", value));
+    let value = value.trim();
+    let split = value.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number : f64 = number.trim().parse().map_err(|_| invalid())?;
+    let seconds_per_unit = match unit {
+        "ms" => 0.001,
+        "" | "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return Err(invalid()),
+    };
+
+    Duration::try_from_secs_f64(number * seconds_per_unit).ok().filter(|duration| !duration.is_zero()).ok_or_else(invalid)
+}
+
+// Durations go into reports as seconds, which reads better than serde's seconds and nanoseconds
+pub(crate) fn serialize_seconds<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+fn parse_sync(name: &str) -> Result<SyncMode, ConfigError> {
+    SyncMode::from_name(name).ok_or_else(|| ConfigError::Invalid(format!("unknown sync mode {}, expected barrier or free-running", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ENCLOSURE_H;
+
+    const SAMPLE_CONFIG : &str = concat!(env!("CARGO_MANIFEST_DIR"), "/sample_config.toml");
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn no_arguments_gives_defaults() {
+        assert_eq!(SimConfig::from_args(args(&[])).unwrap(), SimConfig::default());
+    }
+
+    #[test]
+    fn flags_override_defaults() {
+        let config = SimConfig::from_args(args(&["--particles", "2000", "--width", "20", "--broadphase", "quadtree"])).unwrap();
+
+        assert_eq!(config.particle_count, 2000);
+        assert_eq!(config.enclosure, Enclosure::Rect { w: 20.0, h: ENCLOSURE_H });
+        assert_eq!(config.broadphase, BroadphaseKind::QuadTree);
+        assert_eq!(config.thread_count, default_thread_count());
+    }
+
+    #[test]
+    fn bad_arguments_are_rejected() {
+        assert!(SimConfig::from_args(args(&["--particles"])).is_err());
+        assert!(SimConfig::from_args(args(&["--particles", "lots"])).is_err());
+        assert!(SimConfig::from_args(args(&["--threads", "0"])).is_err());
+        assert!(SimConfig::from_args(args(&["--speed", "1"])).is_err());
+    }
+
+    #[test]
+    fn enclosure_sizes_must_be_positive_numbers() {
+        for size in ["0", "-1", "NaN", "inf"] {
+            assert!(SimConfig::from_args(args(&["--width", size])).is_err(), "width {}", size);
+            assert!(SimConfig::from_args(args(&["--height", size])).is_err(), "height {}", size);
+            assert!(SimConfig::from_args(args(&["--circle", size])).is_err(), "circle {}", size);
+        }
+        assert!(SimConfig::from_args(args(&["--depth", "NaN"])).is_err());
+        assert!(SimConfig::from_args(args(&["--depth", "inf"])).is_err());
+        assert!(SimConfig::from_args(args(&["--depth", "-1"])).is_err());
+        assert!(SimConfig::from_args(args(&["--depth", "0"])).is_ok());
+        assert!(SimConfig::from_toml_str("width = nan").is_err());
+    }
+
+    #[test]
+    fn sample_toml_parses() {
+        let config = SimConfig::from_toml_path(SAMPLE_CONFIG).unwrap();
+
+        assert_eq!(config.particle_count, 1000);
+        assert_eq!(config.thread_count, 4);
+        assert_eq!(config.collision_thread_count, 1);
+        assert_eq!(config.enclosure, Enclosure::Rect { w: 20.0, h: 10.0 });
+        assert_eq!(config.radius, RadiusDistribution::Fixed(0.025));
+        assert_eq!(config.duration, Duration::from_secs(5));
+        assert_eq!(config.broadphase, BroadphaseKind::QuadTree);
+    }
+
+    #[test]
+    fn radius_ranges_parse() {
+        let uniform = RadiusDistribution::Uniform { min: 0.02, max: 0.08 };
+
+        assert_eq!(SimConfig::from_args(args(&["--radius", "0.02:0.08"])).unwrap().radius, uniform);
+        assert_eq!(SimConfig::from_toml_str("radius = { min = 0.02, max = 0.08 }").unwrap().radius, uniform);
+        assert!(SimConfig::from_args(args(&["--radius", "0.08:0.02"])).is_err());
+    }
+
+    #[test]
+    fn enclosure_can_be_round() {
+        let circle = Enclosure::Circle { radius: 4.0 };
+
+        assert_eq!(SimConfig::from_args(args(&["--circle", "4"])).unwrap().enclosure, circle);
+        assert_eq!(SimConfig::from_toml_str("circle = 4.0").unwrap().enclosure, circle);
+        assert!(SimConfig::from_toml_str("circle = 4.0\nwidth = 2.0").is_err());
+        assert!(SimConfig::from_args(args(&["--circle", "0"])).is_err());
+    }
+
+    #[test]
+    fn layout_can_be_chosen() {
+        assert_eq!(SimConfig::default().layout, Layout::Origin);
+        assert_eq!(SimConfig::from_args(args(&["--layout", "two-clusters"])).unwrap().layout, Layout::TwoClusters);
+        assert_eq!(SimConfig::from_toml_str("layout = \"circle\"").unwrap().layout, Layout::Circle);
+        assert!(SimConfig::from_args(args(&["--layout", "spiral"])).is_err());
+    }
+
+    #[test]
+    fn periodic_boundaries_need_a_rectangle_and_a_wrapping_broadphase() {
+        assert_eq!(SimConfig::from_args(args(&["--boundary", "periodic"])).unwrap().boundary, BoundaryMode::Periodic);
+        assert_eq!(SimConfig::from_toml_str("boundary = \"periodic\"").unwrap().boundary, BoundaryMode::Periodic);
+        assert!(SimConfig::from_args(args(&["--boundary", "periodic", "--circle", "4"])).is_err());
+        assert!(SimConfig::from_args(args(&["--boundary", "periodic", "--broadphase", "quadtree"])).is_err());
+        assert!(SimConfig::from_args(args(&["--boundary", "periodic", "--broadphase", "hash"])).is_err());
+        assert!(SimConfig::from_args(args(&["--boundary", "periodic", "--broadphase", "sweep"])).is_err());
+    }
+
+    #[test]
+    fn steps_override_seconds() {
+        let config = SimConfig::from_args(args(&["--steps", "3"])).unwrap();
+        let start_time = Instant::now();
+
+        assert!(config.keep_running(2, start_time));
+        assert!(!config.keep_running(3, start_time));
+        assert!(SimConfig::default().keep_running(1000, start_time));
+        assert!(SimConfig::from_args(args(&["--steps", "0"])).is_err());
+    }
+
+    #[test]
+    fn the_tick_rate_is_uncapped_unless_given() {
+        assert_eq!(SimConfig::default().tick_hz, 0.0);
+        assert_eq!(SimConfig::from_args(args(&["--tick-hz", "60"])).unwrap().tick_hz, 60.0);
+        assert_eq!(SimConfig::from_toml_str("tick_hz = 30.0").unwrap().tick_hz, 30.0);
+        assert!(SimConfig::from_args(args(&["--tick-hz", "-1"])).is_err());
+        assert!(SimConfig::from_args(args(&["--tick-hz", "inf"])).is_err());
+    }
+
+    #[test]
+    fn durations_take_units() {
+        assert_eq!(SimConfig::default().duration, SIMULATION_TIME);
+        assert_eq!(SimConfig::from_args(args(&["--duration", "500ms"])).unwrap().duration, Duration::from_millis(500));
+        assert_eq!(SimConfig::from_args(args(&["--duration", "2m"])).unwrap().duration, Duration::from_secs(120));
+        assert_eq!(SimConfig::from_args(args(&["--duration", "1.5"])).unwrap().duration, Duration::from_millis(1500));
+        assert_eq!(SimConfig::from_args(args(&["--seconds", "0.25"])).unwrap().duration, Duration::from_millis(250));
+        assert_eq!(SimConfig::from_toml_str("duration = \"1h\"").unwrap().duration, Duration::from_secs(3600));
+        assert!(SimConfig::from_args(args(&["--duration", "0s"])).is_err());
+        assert!(SimConfig::from_args(args(&["--duration", "-2s"])).is_err());
+        assert!(SimConfig::from_args(args(&["--duration", "5 days"])).is_err());
+        assert!(SimConfig::from_args(args(&["--duration", "ms"])).is_err());
+    }
+
+    #[test]
+    fn sync_mode_can_be_chosen() {
+        assert_eq!(SimConfig::default().sync, SyncMode::Barrier);
+        assert_eq!(SimConfig::from_args(args(&["--sync", "free-running"])).unwrap().sync, SyncMode::FreeRunning);
+        assert_eq!(SimConfig::from_toml_str("sync = \"free-running\"").unwrap().sync, SyncMode::FreeRunning);
+        assert!(SimConfig::from_args(args(&["--sync", "sometimes"])).is_err());
+    }
+
+    #[test]
+    fn integrator_can_be_chosen() {
+        assert_eq!(SimConfig::default().integrator, IntegratorKind::Euler);
+        assert_eq!(SimConfig::from_args(args(&["--integrator", "verlet"])).unwrap().integrator, IntegratorKind::VelocityVerlet);
+        assert_eq!(SimConfig::from_toml_str("integrator = \"verlet\"").unwrap().integrator, IntegratorKind::VelocityVerlet);
+        assert!(SimConfig::from_args(args(&["--integrator", "rk4"])).is_err());
+    }
+
+    #[test]
+    fn potentials_parse_from_flags_and_tables() {
+        assert!(SimConfig::default().pair_potential().is_none());
+        assert_eq!(SimConfig::from_args(args(&["--potential", "lennard-jones:1:0.1"])).unwrap().potential, Some(PotentialKind::LennardJones { epsilon: 1.0, sigma: 0.1 }));
+        let config = SimConfig::from_toml_str("potential = { kind = \"inverse-square\", strength = -2.0, cutoff = 1.5 }").unwrap();
+        assert_eq!(config.potential, Some(PotentialKind::InverseSquare { strength: -2.0, cutoff: 1.5 }));
+        assert_eq!(config.pair_potential().unwrap().cutoff(), 1.5);
+        assert_eq!(SimConfig::from_args(args(&["--repulsion", "0.1"])).unwrap().pair_potential().unwrap().cutoff(), REPULSION_CUTOFF);
+
+        assert!(SimConfig::from_args(args(&["--potential", "spring:1:2"])).is_err());
+        assert!(SimConfig::from_args(args(&["--potential", "lennard-jones:1"])).is_err());
+        assert!(SimConfig::from_args(args(&["--potential", "lennard-jones:-1:0.1"])).is_err());
+        assert!(SimConfig::from_args(args(&["--potential", "inverse-square:1:0"])).is_err());
+        assert!(SimConfig::from_args(args(&["--potential", "lennard-jones:1:0.1", "--repulsion", "0.1"])).is_err());
+    }
+
+    #[test]
+    fn drag_is_off_unless_given() {
+        assert_eq!(SimConfig::default().drag, 0.0);
+        assert_eq!(SimConfig::from_args(args(&["--drag", "0.5"])).unwrap().drag, 0.5);
+        assert_eq!(SimConfig::from_toml_str("drag = 2.0").unwrap().drag, 2.0);
+        assert!(SimConfig::from_args(args(&["--drag", "-1"])).is_err());
+        assert!(SimConfig::from_args(args(&["--drag", "NaN"])).is_err());
+    }
+
+    #[test]
+    fn max_speed_can_be_raised_or_lifted() {
+        assert_eq!(SimConfig::default().max_speed, MAX_SPEED);
+        assert_eq!(SimConfig::from_args(args(&["--max-speed", "20"])).unwrap().max_speed, 20.0);
+        assert_eq!(SimConfig::from_args(args(&["--max-speed", "inf"])).unwrap().max_speed, f32::INFINITY);
+        assert_eq!(SimConfig::from_toml_str("max_speed = 5.0").unwrap().max_speed, 5.0);
+        assert!(SimConfig::from_args(args(&["--max-speed", "0"])).is_err());
+        assert!(SimConfig::from_args(args(&["--max-speed", "NaN"])).is_err());
+    }
+
+    #[test]
+    fn movement_can_be_chosen_when_there_are_no_forces() {
+        assert_eq!(SimConfig::default().movement, MovementKind::Ballistic);
+        assert_eq!(SimConfig::from_args(args(&["--movement", "brownian"])).unwrap().movement, MovementKind::Brownian);
+        assert_eq!(SimConfig::from_toml_str("movement = \"teleport\"").unwrap().movement, MovementKind::Teleport);
+        assert!(SimConfig::from_args(args(&["--movement", "brownian", "--gravity", "-9.8"])).is_err());
+        assert!(SimConfig::from_args(args(&["--movement", "teleport", "--repulsion", "0.1"])).is_err());
+        assert!(SimConfig::from_args(args(&["--movement", "brownian", "--potential", "lennard-jones:1:0.1"])).is_err());
+        assert!(SimConfig::from_args(args(&["--movement", "hop"])).is_err());
+    }
+
+    #[test]
+    fn the_diffusion_coefficient_can_be_set() {
+        assert_eq!(SimConfig::default().diffusion, DIFFUSION_COEFFICIENT);
+        assert_eq!(SimConfig::from_args(args(&["--movement", "brownian", "--diffusion", "0.2"])).unwrap().diffusion, 0.2);
+        assert_eq!(SimConfig::from_toml_str("diffusion = 1.5").unwrap().diffusion, 1.5);
+        assert!(SimConfig::from_args(args(&["--diffusion", "-0.1"])).is_err());
+    }
+
+    #[test]
+    fn gaussian_jumps_take_a_stddev() {
+        let config = SimConfig::from_args(args(&["--movement", "gaussian", "--jump-stddev", "0.3"])).unwrap();
+        assert_eq!((config.movement, config.jump_stddev), (MovementKind::Gaussian, 0.3));
+        assert_eq!(SimConfig::default().jump_stddev, JUMP_STDDEV);
+        assert_eq!(SimConfig::from_toml_str("jump_stddev = 0.1").unwrap().jump_stddev, 0.1);
+        assert!(SimConfig::from_args(args(&["--jump-stddev", "-1"])).is_err());
+        assert!(SimConfig::from_args(args(&["--movement", "gaussian", "--gravity", "-9.8"])).is_err());
+    }
+
+    #[test]
+    fn collisions_bounce_unless_told_otherwise() {
+        assert_eq!(SimConfig::default().outcome, CollisionOutcome::Bounce);
+        assert_eq!(SimConfig::from_args(args(&["--outcome", "annihilate"])).unwrap().outcome, CollisionOutcome::Annihilate);
+        assert_eq!(SimConfig::from_toml_str("outcome = \"bounce\"").unwrap().outcome, CollisionOutcome::Bounce);
+        assert_eq!(SimConfig::from_args(args(&["--outcome", "merge"])).unwrap().outcome, CollisionOutcome::Merge);
+        let merging = SimConfig { particle_count: 100, radius: RadiusDistribution::Fixed(0.05), outcome: CollisionOutcome::Merge, ..SimConfig::default() };
+        assert!((merging.max_radius() - 0.5).abs() < 1e-6); // All of them merged into one
+        let emitting = SimConfig { emitter: Some(EmitterSettings { max_particles: 400, ..EmitterSettings::default() }), ..merging.clone() };
+        assert!((emitting.max_radius() - 1.0).abs() < 1e-6); // Topped up to 400 and all merged
+        let capped_below = SimConfig { emitter: Some(EmitterSettings { max_particles: 10, ..EmitterSettings::default() }), ..merging };
+        assert!((capped_below.max_radius() - 0.5).abs() < 1e-6);
+        assert!(SimConfig::from_args(args(&["--outcome", "explode"])).is_err());
+    }
+
+    #[test]
+    fn any_emitter_flag_turns_the_emitter_on() {
+        assert!(SimConfig::default().emitter.is_none());
+
+        let emitter = SimConfig::from_args(args(&["--emit-at", "1:2", "--emit-velocity", "3:-1", "--max-particles", "50"])).unwrap().emitter.unwrap();
+        assert_eq!(emitter, EmitterSettings { position: Some((1.0, 2.0)), velocity: (3.0, -1.0), max_particles: 50, ..EmitterSettings::default() });
+
+        let emitter = SimConfig::from_toml_str("[emitter]\nevery = 2\nposition = [1.0, 1.0]\nspread = 0.0\n").unwrap().emitter.unwrap();
+        assert_eq!((emitter.every, emitter.position, emitter.spread, emitter.velocity), (2, Some((1.0, 1.0)), 0.0, EmitterSettings::default().velocity));
+
+        assert!(SimConfig::from_args(args(&["--emit-every", "0"])).is_err());
+        assert!(SimConfig::from_args(args(&["--emit-at", "100:1"])).is_err()); // Outside the enclosure
+        assert!(SimConfig::from_args(args(&["--emit-velocity", "3"])).is_err());
+        assert!(SimConfig::from_args(args(&["--emit-spread", "-1"])).is_err());
+        assert!(SimConfig::from_toml_str("[emitter]\nrate = 2\n").is_err());
+    }
+
+    #[test]
+    fn the_collision_log_is_off_unless_given_a_path() {
+        assert_eq!(SimConfig::default().collision_log_path, None);
+
+        let config = SimConfig::from_args(args(&["--collision-log", "hits.csv", "--collision-log-cap", "500"])).unwrap();
+        assert_eq!((config.collision_log_path.as_deref(), config.collision_log_capacity), (Some("hits.csv"), 500));
+
+        let config = SimConfig::from_toml_str("collision_log = \"hits.csv\"\ncollision_log_cap = 20").unwrap();
+        assert_eq!((config.collision_log_path.as_deref(), config.collision_log_capacity), (Some("hits.csv"), 20));
+        assert!(SimConfig::from_args(args(&["--collision-log-cap", "0"])).is_err());
+
+        assert_eq!(SimConfig::default().recent_collisions, 0);
+        assert_eq!(SimConfig::from_args(args(&["--recent-collisions", "5"])).unwrap().recent_collisions, 5);
+    }
+
+    #[test]
+    fn json_lines_are_off_unless_given_a_path() {
+        assert_eq!((SimConfig::default().jsonl_path, SimConfig::default().jsonl_every), (None, RECORD_EVERY_FRAMES as usize));
+
+        let config = SimConfig::from_args(args(&["--jsonl", "out.ndjson", "--jsonl-every", "5"])).unwrap();
+        assert_eq!((config.jsonl_path.as_deref(), config.jsonl_every), (Some("out.ndjson"), 5));
+        assert_eq!(SimConfig::from_toml_str("jsonl = \"out.ndjson\"\njsonl_every = 2").unwrap().jsonl_every, 2);
+        assert!(SimConfig::from_args(args(&["--jsonl-every", "0"])).is_err());
+    }
+
+    #[test]
+    fn continuous_detection_needs_walls_and_one_collision_thread() {
+        assert!(!SimConfig::default().ccd);
+        assert!(SimConfig::from_args(args(&["--ccd"])).unwrap().ccd);
+        assert!(SimConfig::from_toml_str("ccd = true").unwrap().ccd);
+        assert!(SimConfig::from_args(args(&["--ccd", "--boundary", "periodic"])).is_err());
+        assert!(SimConfig::from_args(args(&["--ccd", "--collision-threads", "2"])).is_err());
+    }
+
+    #[test]
+    fn clusters_are_only_skipped_with_a_positive_epsilon() {
+        assert_eq!(SimConfig::default().cluster_epsilon, None);
+        assert_eq!(SimConfig::from_args(args(&["--cluster-epsilon", "0.001"])).unwrap().cluster_epsilon, Some(0.001));
+        assert_eq!(SimConfig::from_toml_str("cluster_epsilon = 0.01").unwrap().cluster_epsilon, Some(0.01));
+        assert!(SimConfig::from_args(args(&["--cluster-epsilon", "0"])).is_err());
+        assert!(SimConfig::from_args(args(&["--cluster-epsilon", "NaN"])).is_err());
+    }
+
+    #[test]
+    fn event_driven_runs_need_straight_lines_between_bounces() {
+        assert!(!SimConfig::default().event_driven);
+        assert!(SimConfig::from_args(args(&["--event-driven"])).unwrap().event_driven);
+        assert!(SimConfig::from_toml_str("event_driven = true").unwrap().event_driven);
+        for refused in [&["--gravity", "-1"][..], &["--repulsion", "0.1"], &["--boundary", "periodic"], &["--outcome", "merge"], &["--movement", "brownian"], &["--emit-every", "5"]] {
+            assert!(SimConfig::from_args(args(&[&["--event-driven"][..], refused].concat())).is_err(), "{:?}", refused);
+        }
+    }
+
+    #[test]
+    fn trails_are_off_unless_given_a_fade() {
+        assert_eq!(SimConfig::default().trails, 0.0);
+        assert_eq!(SimConfig::from_args(args(&["--trails", "0.9"])).unwrap().trails, 0.9);
+        assert_eq!(SimConfig::from_toml_str("trails = 0.8").unwrap().trails, 0.8);
+        assert!(SimConfig::from_args(args(&["--trails", "1.5"])).is_err());
+        assert!(SimConfig::from_args(args(&["--trails", "-0.1"])).is_err());
+    }
+
+    #[test]
+    fn speed_colours_are_off_unless_given_a_max_speed() {
+        assert_eq!(SimConfig::default().speed_colours, None);
+        assert_eq!(SimConfig::from_args(args(&["--speed-colours", "4"])).unwrap().speed_colours, Some(4.0));
+        assert_eq!(SimConfig::from_toml_str("speed_colours = 2.5").unwrap().speed_colours, Some(2.5));
+        assert!(SimConfig::from_args(args(&["--speed-colours", "0"])).is_err());
+    }
+
+    #[test]
+    fn speed_histograms_are_off_unless_asked_for() {
+        assert_eq!((SimConfig::default().speed_bins, SimConfig::default().speed_histogram_path), (None, None));
+        let config = SimConfig::from_args(args(&["--speed-bins", "12", "--speed-histogram", "speeds.csv"])).unwrap();
+        assert_eq!((config.speed_bins, config.speed_histogram_path.as_deref()), (Some(12), Some("speeds.csv")));
+        assert_eq!(SimConfig::from_toml_str("speed_bins = 8").unwrap().speed_bins, Some(8));
+        assert!(SimConfig::from_args(args(&["--speed-bins", "0"])).is_err());
+    }
+
+    #[test]
+    fn metrics_are_sampled_at_the_interval_given() {
+        assert_eq!((SimConfig::default().metrics_path, SimConfig::default().metrics_interval), (None, METRICS_INTERVAL));
+
+        let config = SimConfig::from_args(args(&["--metrics", "metrics.csv", "--metrics-every", "2s"])).unwrap();
+        assert_eq!((config.metrics_path.as_deref(), config.metrics_interval), (Some("metrics.csv"), Duration::from_secs(2)));
+        assert_eq!(SimConfig::from_toml_str("metrics_every = \"250ms\"").unwrap().metrics_interval, Duration::from_millis(250));
+        assert!(SimConfig::from_args(args(&["--metrics-every", "0s"])).is_err());
+    }
+
+    #[test]
+    fn streaming_is_off_unless_given_a_port() {
+        assert_eq!(SimConfig::default().serve_port, None);
+
+        let config = SimConfig::from_args(args(&["--serve", "9000", "--serve-fps", "10"])).unwrap();
+        assert_eq!((config.serve_port, config.serve_fps), (Some(9000), 10.0));
+        assert!(SimConfig::from_args(args(&["--serve", "70000"])).is_err());
+        assert!(SimConfig::from_args(args(&["--serve-fps", "0"])).is_err());
+    }
+
+    #[test]
+    fn switches_take_no_value() {
+        let config = SimConfig::from_args(args(&["--profile", "--particles", "10", "--tui"])).unwrap();
+
+        assert!(config.profile && config.tui);
+        assert_eq!(config.particle_count, 10);
+        assert!(!SimConfig::default().profile);
+        assert!(SimConfig::from_args(args(&["--progress"])).unwrap().progress);
+        assert!(SimConfig::from_args(args(&["--progress", "--tui"])).is_err());
+        assert!(SimConfig::from_args(args(&["--fps", "--particles", "10"])).unwrap().fps);
+        assert!(SimConfig::from_args(args(&["--gnuplot", "--particles", "10"])).unwrap().gnuplot);
+        assert!(SimConfig::from_args(args(&["--json"])).unwrap().json);
+        assert!(SimConfig::from_args(args(&["--json", "--tui"])).is_err());
+        assert!(SimConfig::from_args(args(&["--headless", "--json"])).unwrap().headless);
+        assert!(SimConfig::from_args(args(&["--quiet"])).unwrap().headless);
+        assert!(SimConfig::from_toml_str("headless = true").unwrap().headless);
+        assert!(SimConfig::from_args(args(&["--headless", "--fps"])).is_err());
+        assert!(SimConfig::from_args(args(&["--quiet", "--tui"])).is_err());
+    }
+
+    #[test]
+    fn deterministic_runs_need_barrier_sync_and_a_step_count() {
+        assert!(SimConfig::from_args(args(&["--deterministic", "--steps", "100"])).unwrap().deterministic);
+        assert!(SimConfig::from_toml_str("deterministic = true\nsteps = 100").unwrap().deterministic);
+        assert!(SimConfig::from_args(args(&["--deterministic"])).is_err());
+        assert!(SimConfig::from_args(args(&["--deterministic", "--steps", "100", "--sync", "free-running"])).is_err());
+        assert!(SimConfig::from_toml_str("profile = true").unwrap().profile);
+    }
+
+    #[test]
+    fn species_set_the_particle_count() {
+        let toml = "[[species]]\ncount = 30\nradius = 0.05\n\n[[species]]\ncount = 10\nradius = { min = 0.1, max = 0.2 }\nmass = 5.0\n";
+        let config = SimConfig::from_toml_str(toml).unwrap();
+
+        assert_eq!(config.particle_count, 40);
+        assert_eq!(config.species[1], Species { count: 10, radius: RadiusDistribution::Uniform { min: 0.1, max: 0.2 }, mass: Some(5.0) });
+        assert_eq!(config.max_radius(), 0.2);
+        assert!(SimConfig::from_toml_str("[[species]]\ncount = 0\nradius = 0.05\n").is_err());
+        assert!(SimConfig::from_toml_str("particles = 5\n[[species]]\ncount = 3\nradius = 0.05\n").is_ok()); // The species win
+    }
+
+    #[test]
+    fn unknown_toml_keys_are_rejected() {
+        assert!(matches!(SimConfig::from_toml_str("particels = 10"), Err(ConfigError::Toml(_))));
+    }
+
+    #[test]
+    fn flags_override_config_file() {
+        let config = SimConfig::from_args(args(&["--particles", "50", "--config", SAMPLE_CONFIG])).unwrap();
+
+        assert_eq!(config.particle_count, 50);
+        assert_eq!(config.enclosure.width(), 20.0);
+    }
+
+    #[test]
+    fn presets_are_a_starting_point_for_other_flags() {
+        for name in PRESETS {
+            assert!(preset(name).unwrap().validate().is_ok(), "{}", name);
+        }
+        assert_eq!(preset("plasma"), None);
+
+        let config = SimConfig::from_args(args(&["--preset", "billiards", "--particles", "9"])).unwrap();
+        assert_eq!((config.particle_count, config.radius, config.layout), (9, RadiusDistribution::Fixed(0.4), Layout::Grid));
+
+        assert!(SimConfig::from_args(args(&["--preset", "plasma"])).is_err());
+        assert!(SimConfig::from_args(args(&["--preset", "gas", "--config", SAMPLE_CONFIG])).is_err());
+    }
+}
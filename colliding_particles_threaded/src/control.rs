@@ -0,0 +1,119 @@
+// Stopping and pausing a run from outside it, e.g. from a Ctrl-C handler or a key pressed in the terminal view
+// Every thread of the run holds a clone and checks it once per iteration
+// Also the ticker, which slows a run's loops down to a steady rate so it can be watched
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PAUSE_POLL_INTERVAL : Duration = Duration::from_millis(10); // How often a paused thread looks to see if it can carry on
+
+#[derive(Debug, Clone, Default)]
+pub struct RunControl {
+    stop: Arc<AtomicBool>,
+    paused: Arc<AtomicBool>,
+}
+
+impl RunControl {
+    // Every thread finishes on its next iteration, paused or not
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    // Pause a running run or resume a paused one, returning whether it is now paused
+    pub fn toggle_pause(&self) -> bool {
+        !self.paused.fetch_xor(true, Ordering::Relaxed)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    // Sleep until the run is resumed or stopped, returning how long that took so the caller can leave it out of its run time
+    pub fn wait_while_paused(&self) -> Duration {
+        if !self.is_paused() {
+            return Duration::ZERO;
+        }
+
+        let paused_at = Instant::now();
+        while self.is_paused() && !self.is_stopped() {
+            thread::sleep(PAUSE_POLL_INTERVAL);
+        }
+        paused_at.elapsed()
+    }
+}
+
+// Paces a loop to at most hz iterations a second by sleeping out the rest of each interval, or lets it run flat out at 0
+// A loop that falls behind, or was paused, carries on from where it is rather than rushing to catch up
+#[derive(Debug, Clone)]
+pub struct Ticker {
+    interval: Option<Duration>,
+    next: Option<Instant>, // When the next tick is due, None before the first
+}
+
+impl Ticker {
+    pub fn new(hz: f32) -> Self {
+        Ticker { interval: (hz > 0.0).then(|| Duration::from_secs_f32(1.0 / hz)), next: None }
+    }
+
+    // Sleep until the next tick is due, returning straight away the first time
+    pub fn wait(&mut self) {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let now = Instant::now();
+        let due = self.next.map_or(now, |next| next.max(now));
+        thread::sleep(due - now);
+        self.next = Some(due + interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paused_threads_wait_until_resumed_or_stopped() {
+        let control = RunControl::default();
+        assert_eq!(control.wait_while_paused(), Duration::ZERO);
+        assert!(control.toggle_pause());
+
+        let resumer = control.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            assert!(!resumer.toggle_pause());
+        });
+        assert!(control.wait_while_paused() >= Duration::from_millis(50));
+        handle.join().unwrap();
+
+        control.set_paused(true);
+        control.stop();
+        assert!(control.wait_while_paused() < Duration::from_millis(50));
+    }
+
+    #[test]
+    fn tickers_space_iterations_out_unless_at_zero() {
+        let start = Instant::now();
+        let mut ticker = Ticker::new(100.0);
+        for _ in 0..6 {
+            ticker.wait();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let start = Instant::now();
+        let mut ticker = Ticker::new(0.0);
+        for _ in 0..1000 {
+            ticker.wait();
+        }
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,100 @@
+// A source that adds a particle to the system every so many frames, like a jet blowing particles in
+// With the annihilate outcome taking particles away this lets a run settle into a steady state rather than emptying out
+use crate::config::SimConfig;
+use crate::{Particle, ParticleSystem, RadiusDistribution};
+use rand::rngs::StdRng;
+use rand::{random, RngExt, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+pub const EMIT_EVERY_FRAMES : u32 = 10;
+pub const EMIT_VELOCITY : (f32, f32) = (2.0, 0.0);
+pub const EMIT_SPREAD : f32 = 0.5;
+pub const MAX_PARTICLES : usize = 5000;
+
+// How the emitter is set up, from --emit-every and the flags after it or an [emitter] table in a config file
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmitterSettings {
+    pub every: u32, // Frames between new particles
+    pub position: Option<(f32, f32)>, // Where they appear, the middle of the enclosure if not given
+    pub velocity: (f32, f32),
+    pub spread: f32, // Up to this much is added to or taken from each component of the velocity at random
+    pub max_particles: usize, // Nothing is emitted while the system has this many particles, so it can't grow without end
+}
+
+impl Default for EmitterSettings {
+    fn default() -> Self {
+        EmitterSettings { every: EMIT_EVERY_FRAMES, position: None, velocity: EMIT_VELOCITY, spread: EMIT_SPREAD, max_particles: MAX_PARTICLES }
+    }
+}
+
+// Only the thread that owns it emits, so it runs on the frame count of a single loop
+pub struct Emitter<const N: usize = 2> {
+    settings: EmitterSettings,
+    position: [f32; N],
+    radius: RadiusDistribution,
+    is_3d: bool,
+    rng: StdRng,
+}
+
+impl<const N: usize> Emitter<N> {
+    // Particles get the config's radius, and a 3D emitter sits halfway into the enclosure along every axis past x and y
+    // Its random numbers come from the other end of the seed's streams to the move threads', so a seeded run emits the same way every time
+    pub fn new(settings: EmitterSettings, config: &SimConfig) -> Self {
+        let (x, y) = settings.position.unwrap_or((config.enclosure.width() / 2.0, config.enclosure.height() / 2.0));
+        let seed = config.seed.unwrap_or_else(random);
+
+        Emitter {
+            settings,
+            position: std::array::from_fn(|axis| [x, y].get(axis).copied().unwrap_or(config.depth / 2.0)),
+            radius: config.radius,
+            is_3d: config.is_3d(),
+            rng: StdRng::seed_from_u64(!seed),
+        }
+    }
+
+    // Spawn a particle if this frame is one to emit on and the system isn't full, returning its id
+    pub fn emit(&mut self, frame: u32, system: &mut ParticleSystem<N>) -> Option<u64> {
+        if !frame.is_multiple_of(self.settings.every) || system.particles.len() >= self.settings.max_particles {
+            return None;
+        }
+
+        let (spread, velocity, rng) = (self.settings.spread, self.settings.velocity, &mut self.rng);
+        let mut jitter = || (rng.random::<f32>() * 2.0 - 1.0) * spread;
+        let is_3d = self.is_3d;
+        let velocity = std::array::from_fn(|axis| match axis {
+            0 => velocity.0 + jitter(),
+            1 => velocity.1 + jitter(),
+            _ if is_3d => jitter(),
+            _ => 0.0,
+        });
+
+        let radius = self.radius.sample(&mut self.rng);
+        Some(system.spawn(Particle::at(self.position, velocity, radius)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn particles_come_out_on_time_until_the_system_is_full() {
+        let settings = EmitterSettings { every: 3, position: Some((1.0, 2.0)), velocity: (4.0, 0.0), spread: 0.5, max_particles: 4 };
+        let config = SimConfig { depth: 0.0, seed: Some(1), ..SimConfig::default() };
+        let mut emitter = Emitter::new(settings, &config);
+        let mut system = ParticleSystem::new(Vec::new());
+
+        let emitted : Vec<_> = (0..20).filter_map(|frame| emitter.emit(frame, &mut system)).collect();
+
+        assert_eq!(emitted, vec![0, 1, 2, 3]); // On frames 0, 3, 6 and 9, then full
+        for p in &system.particles {
+            assert_eq!(p.position, [1.0, 2.0]);
+            assert!((p.velocity[0] - 4.0).abs() <= 0.5 && p.velocity[1].abs() <= 0.5);
+        }
+        assert_ne!(system.particles[0].velocity[0], system.particles[1].velocity[0]);
+
+        system.remove(0);
+        assert_eq!(emitter.emit(21, &mut system), Some(4));
+    }
+}
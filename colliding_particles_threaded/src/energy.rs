@@ -0,0 +1,77 @@
+use crate::Particle;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+// Kinetic energy of every particle added up, summed in f64 so thousands of particles don't lose the small ones to rounding
+pub fn kinetic_energy<const N: usize>(particles: &[Particle<N>]) -> f32 {
+    particles.iter().map(|p| 0.5 * p.mass as f64 * p.speed_squared() as f64).sum::<f64>() as f32
+}
+
+// Total kinetic energy sampled every few frames
+// With elastic collisions and reflecting walls it should stay flat, drift points at the integrator or the collision response
+// Gravity and repulsion trade kinetic energy for potential energy that isn't counted, so expect it to move with those on
+pub struct EnergyLog {
+    every: usize,
+    samples: Vec<(usize, f32)>, // Frame and energy
+}
+
+impl EnergyLog {
+    pub fn new(every: usize) -> Self {
+        EnergyLog { every, samples: Vec::new() }
+    }
+
+    // Only every few frames are kept
+    pub fn record<const N: usize>(&mut self, frame: usize, particles: &[Particle<N>]) {
+        if frame.is_multiple_of(self.every) {
+            self.samples.push((frame, kinetic_energy(particles)));
+        }
+    }
+
+    pub fn samples(&self) -> &[(usize, f32)] {
+        &self.samples
+    }
+
+    // The furthest any sample got from the first, as a fraction of the first, 0 if nothing was moving to start with
+    pub fn max_relative_drift(&self) -> f64 {
+        let initial = match self.samples.first() {
+            Some(&(_, energy)) if energy > 0.0 => energy as f64,
+            _ => return 0.0,
+        };
+
+        self.samples.iter().map(|&(_, energy)| (energy as f64 - initial).abs() / initial).fold(0.0, f64::max)
+    }
+
+    // One frame,energy line per sample
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        writeln!(file, "frame,energy")?;
+        for &(frame, energy) in &self.samples {
+            writeln!(file, "{},{}", frame, energy)?;
+        }
+
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drift_is_measured_from_the_first_sample() {
+        let mut log = EnergyLog::new(2);
+        let mut particles = vec![Particle { mass: 2.0, ..Particle::new(1.0, 1.0, 1.0, 0.0, 0.05) }];
+
+        log.record(0, &particles);
+        particles[0].velocity[0] = 1.1;
+        log.record(1, &particles); // Skipped
+        log.record(2, &particles);
+        particles[0].velocity[0] = 0.9;
+        log.record(4, &particles);
+
+        assert_eq!(log.samples().len(), 3);
+        assert_eq!(log.samples()[0], (0, 1.0));
+        assert!((log.max_relative_drift() - 0.21).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,368 @@
+// Event-driven hard-sphere simulation, picked with --event-driven instead of moving everything a timestep at a time
+// Every pair's next collision and every particle's next wall are predicted from their velocities into a queue, soonest first.
+// The whole system is then flown straight to the soonest event, just that collision is bounced, and only the events of the
+// particles it changed are predicted again. Collisions happen at the instant of contact, so nothing overlaps or tunnels
+// Particles fly in straight lines between events, which is why the config refuses gravity, forces and the other movement models
+use crate::ccd::time_to_collision;
+use crate::config::SimConfig;
+use crate::control::{RunControl, Ticker};
+use crate::energy::EnergyLog;
+use crate::walls::{Wall, WallCounter, WallHits};
+use crate::{dot, species_pair, starting_system, CollisionStats, Enclosure, Particle, ParticleSystem, SimReport, TIMESTEP};
+use log::warn;
+use rand::random;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::time::Instant;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum EventKind {
+    Pair(usize, usize),
+    Wall(usize, Wall, usize), // The particle, the wall and the axis it stops, every axis past y goes between Back and Front
+}
+
+// A predicted event, stale once either particle in it has had an event since it was predicted
+#[derive(Debug, Copy, Clone)]
+struct Event {
+    time: f64,
+    kind: EventKind,
+    counts: (u64, u64), // Events each particle had been in when this was predicted, the second is unused for walls
+}
+
+// Ordered so the soonest event is the greatest, as BinaryHeap pops the greatest first
+impl Ord for Event {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.time.total_cmp(&self.time)
+    }
+}
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Event {}
+
+pub struct EventDrivenSimulation<const N: usize = 2> {
+    particles: Vec<Particle<N>>,
+    enclosure: Enclosure,
+    depth: f32,
+    time: f64, // Simulated seconds since the start
+    queue: BinaryHeap<Event>,
+    counts: Vec<u64>, // Events each particle has been in
+    collisions: CollisionStats,
+    walls: WallCounter,
+}
+
+impl<const N: usize> EventDrivenSimulation<N> {
+    // Predicting every pair to start with is O(n²), after that each event only predicts for the one or two particles in it
+    pub fn new(particles: Vec<Particle<N>>, enclosure: Enclosure, depth: f32) -> Self {
+        let counts = vec![0; particles.len()];
+        let mut simulation = EventDrivenSimulation { particles, enclosure, depth, time: 0.0, queue: BinaryHeap::new(), counts, collisions: CollisionStats::default(), walls: WallCounter::default() };
+        for i in 0..simulation.particles.len() {
+            simulation.predict_wall(i);
+            for j in i + 1..simulation.particles.len() {
+                simulation.predict_pair(i, j);
+            }
+        }
+        simulation
+    }
+
+    pub fn particles(&self) -> &[Particle<N>] {
+        &self.particles
+    }
+
+    pub fn into_particles(self) -> Vec<Particle<N>> {
+        self.particles
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    // Bounces between pairs so far, each counted once at the instant of contact
+    pub fn collisions(&self) -> &CollisionStats {
+        &self.collisions
+    }
+
+    pub fn walls(&self) -> &WallCounter {
+        &self.walls
+    }
+
+    // Carry out every event in the next dt seconds, then fly the particles on to the end of it
+    pub fn advance(&mut self, dt: f64) {
+        let end = self.time + dt;
+        while let Some(&event) = self.queue.peek() {
+            if event.time > end {
+                break;
+            }
+            self.queue.pop();
+            if self.is_current(&event) {
+                self.drift_to(event.time);
+                self.resolve(event.kind);
+            }
+        }
+        self.drift_to(end);
+    }
+
+    fn is_current(&self, event: &Event) -> bool {
+        match event.kind {
+            EventKind::Pair(i, j) => (self.counts[i], self.counts[j]) == event.counts,
+            EventKind::Wall(i, _, _) => self.counts[i] == event.counts.0,
+        }
+    }
+
+    fn drift_to(&mut self, time: f64) {
+        let dt = (time - self.time) as f32;
+        for p in &mut self.particles {
+            p.integrate(dt);
+        }
+        self.time = time;
+    }
+
+    fn resolve(&mut self, kind: EventKind) {
+        match kind {
+            EventKind::Pair(i, j) => {
+                let (head, tail) = self.particles.split_at_mut(j);
+                let (a, b) = (&mut head[i], &mut tail[0]);
+                a.resolve_collision(b);
+
+                self.collisions.collision_count += 1;
+                self.collisions.overlapping_frame_count += 1;
+                *self.collisions.by_species.entry(species_pair(a, b)).or_insert(0) += 1;
+
+                self.counts[i] += 1;
+                self.counts[j] += 1;
+                self.predict_for(i);
+                self.predict_for(j);
+            }
+            EventKind::Wall(i, wall, axis) => {
+                bounce_off(&mut self.particles[i], wall, axis, &self.enclosure, self.depth);
+                let mut hits = WallHits::default();
+                hits.add(wall);
+                self.walls.record(hits);
+
+                self.counts[i] += 1;
+                self.predict_for(i);
+            }
+        }
+    }
+
+    // Everything particle i will run into next now that its velocity has changed
+    fn predict_for(&mut self, i: usize) {
+        self.predict_wall(i);
+        for j in 0..self.particles.len() {
+            if j != i {
+                self.predict_pair(i.min(j), i.max(j));
+            }
+        }
+    }
+
+    fn predict_pair(&mut self, i: usize, j: usize) {
+        if let Some(time) = approach_time(&self.particles[i], &self.particles[j]) {
+            self.queue.push(Event { time: self.time + time as f64, kind: EventKind::Pair(i, j), counts: (self.counts[i], self.counts[j]) });
+        }
+    }
+
+    fn predict_wall(&mut self, i: usize) {
+        if let Some((time, wall, axis)) = time_to_wall(&self.particles[i], &self.enclosure, self.depth) {
+            self.queue.push(Event { time: self.time + time as f64, kind: EventKind::Wall(i, wall, axis), counts: (self.counts[i], 0) });
+        }
+    }
+}
+
+// time_to_collision for a pair that will bounce, leaving out pairs that overlap but are already moving apart
+fn approach_time<const N: usize>(a: &Particle<N>, b: &Particle<N>) -> Option<f32> {
+    let time = time_to_collision(a, b)?;
+    let offset : [f32; N] = std::array::from_fn(|axis| b.position[axis] - a.position[axis]);
+    let relative : [f32; N] = std::array::from_fn(|axis| b.velocity[axis] - a.velocity[axis]);
+    let closing = dot(&offset, &relative);
+    (time > 0.0 || closing < 0.0).then_some(time)
+}
+
+// The soonest wall a particle will reach and how long it will take, walls are hit by the centre as in the stepped mode
+fn time_to_wall<const N: usize>(p: &Particle<N>, enclosure: &Enclosure, depth: f32) -> Option<(f32, Wall, usize)> {
+    let axis = |axis: usize, max: f32, (low, high): (Wall, Wall)| {
+        let (position, velocity) = (p.position[axis], p.velocity[axis]);
+        if velocity > 0.0 {
+            Some((((max - position) / velocity).max(0.0), high, axis))
+        } else if velocity < 0.0 {
+            Some(((position / -velocity).max(0.0), low, axis))
+        } else {
+            None
+        }
+    };
+
+    let sides = match *enclosure {
+        Enclosure::Rect { w, h } => axis(0, w, (Wall::Left, Wall::Right)).into_iter().chain(axis(1, h, (Wall::Bottom, Wall::Top))).min_by(|a, b| a.0.total_cmp(&b.0)),
+        Enclosure::Circle { radius } => {
+            // |offset + velocity * t| = radius, the later root is where a particle inside leaves
+            let (dx, dy) = (p.position[0] - radius, p.position[1] - radius);
+            let (vx, vy) = (p.velocity[0], p.velocity[1]);
+            let quadratic = vx * vx + vy * vy;
+            let half_linear = dx * vx + dy * vy;
+            let c = dx * dx + dy * dy - radius * radius;
+            (quadratic > 0.0).then(|| (((-half_linear + (half_linear * half_linear - quadratic * c).max(0.0).sqrt()) / quadratic).max(0.0), Wall::Round, 0))
+        }
+    };
+    let deep = (2..N).filter(|_| depth > 0.0).filter_map(|deep_axis| axis(deep_axis, depth, (Wall::Back, Wall::Front)));
+    sides.into_iter().chain(deep).min_by(|a, b| a.0.total_cmp(&b.0))
+}
+
+// Turn a particle that has reached a wall back inside, putting it on the wall if rounding left it just past
+fn bounce_off<const N: usize>(p: &mut Particle<N>, wall: Wall, axis: usize, enclosure: &Enclosure, depth: f32) {
+    let (position, velocity) = (&mut p.position[axis], &mut p.velocity[axis]);
+    match wall {
+        Wall::Left | Wall::Bottom | Wall::Back => (*position, *velocity) = (position.max(0.0), velocity.abs()),
+        Wall::Right => (*position, *velocity) = (position.min(enclosure.width()), -velocity.abs()),
+        Wall::Top => (*position, *velocity) = (position.min(enclosure.height()), -velocity.abs()),
+        Wall::Front => (*position, *velocity) = (position.min(depth), -velocity.abs()),
+        Wall::Round => {
+            let radius = enclosure.width() / 2.0;
+            let (dx, dy) = (p.position[0] - radius, p.position[1] - radius);
+            let distance = (dx * dx + dy * dy).sqrt();
+            if distance == 0.0 {
+                return;
+            }
+            let normal = [dx / distance, dy / distance];
+            let outward_v = p.velocity[0] * normal[0] + p.velocity[1] * normal[1];
+            for (axis, &along) in normal.iter().enumerate() {
+                if outward_v > 0.0 {
+                    p.velocity[axis] -= 2.0 * outward_v * along;
+                }
+                if distance > radius {
+                    p.position[axis] = radius + along * radius;
+                }
+            }
+        }
+    }
+}
+
+// A whole run in event-driven mode, reported like a stepped one with every TIMESTEP of simulated time counted as a frame
+// It stops after config.steps frames, or once config.duration has passed on the clock if no step count is given
+// There are no threads, so move_iterations has the one entry, and only the report comes out of it
+pub fn run_event_driven_simulation<const N: usize>(config: &SimConfig, control: &RunControl) -> SimReport<N> {
+    let start_time = Instant::now();
+    let seed = config.seed.unwrap_or_else(random);
+    if config.record_path.is_some() || config.render_dir.is_some() || config.jsonl_path.is_some() || config.energy_path.is_some() || config.metrics_path.is_some() || config.serve_port.is_some() || config.tui {
+        warn!("Recording, rendering, streaming and the terminal view only follow the stepped mode, an event-driven run only reports");
+    }
+
+    let system = starting_system(config, seed);
+    let mut simulation = EventDrivenSimulation::new(system.particles, config.enclosure, config.depth);
+    let mut energy = EnergyLog::new(config.record_every as usize);
+    let mut paused = std::time::Duration::ZERO;
+    let mut frames: usize = 0;
+    let mut ticker = Ticker::new(config.tick_hz);
+
+    energy.record(frames, simulation.particles());
+    loop {
+        paused += control.wait_while_paused();
+        let finished = match config.steps {
+            Some(steps) => frames >= steps as usize,
+            None => start_time.elapsed() - paused >= config.duration,
+        };
+        if finished || control.is_stopped() {
+            break;
+        }
+
+        simulation.advance(TIMESTEP as f64);
+        frames += 1;
+        energy.record(frames, simulation.particles());
+        ticker.wait();
+    }
+
+    let collisions = simulation.collisions().clone();
+    let walls = simulation.walls().counts();
+    SimReport {
+        config: config.clone(),
+        seed,
+        total_frames: frames,
+        unique_collisions: collisions.collision_count,
+        raw_collision_frames: collisions.overlapping_frame_count, // Each bounce is one instant of contact
+        annihilations: 0, // Only bounce is allowed
+        merges: 0,
+        cluster_collisions: 0,
+        collisions_by_species: collisions.by_species,
+        max_energy_drift: energy.max_relative_drift(),
+        walls,
+        move_iterations: vec![frames as u32],
+        avg_move_iterations_per_thread: frames as f64,
+        wall_clock: start_time.elapsed(),
+        system: ParticleSystem::new(simulation.into_particles()),
+        errors: Vec::new(),
+        lock_profiles: Vec::new(), // There is no lock to profile
+        recent_collisions: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::energy::kinetic_energy;
+    use crate::{PARTICLE_RADIUS, TIMESTEP};
+
+    const BOX : Enclosure = Enclosure::Rect { w: 10.0, h: 10.0 };
+
+    #[test]
+    fn equal_masses_meeting_head_on_swap_velocities() {
+        // 1 apart once their centres are 2 apart, closing at 2 a second, so they touch at half a second and are back where they started at 1
+        let particles = vec![Particle::new(2.0, 5.0, 1.0, 0.0, 0.5), Particle { id: 1, ..Particle::new(4.0, 5.0, -1.0, 0.0, 0.5) }];
+        let mut simulation = EventDrivenSimulation::new(particles, BOX, 0.0);
+
+        simulation.advance(0.5);
+        assert_eq!((simulation.particles()[0].position[0], simulation.particles()[1].position[0]), (2.5, 3.5));
+        assert_eq!((simulation.particles()[0].velocity[0], simulation.particles()[1].velocity[0]), (-1.0, 1.0));
+
+        simulation.advance(0.5);
+        let (a, b) = (simulation.particles()[0], simulation.particles()[1]);
+        assert_eq!((a.position, a.velocity), ([2.0, 5.0], [-1.0, 0.0]));
+        assert_eq!((b.position, b.velocity), ([4.0, 5.0], [1.0, 0.0]));
+        assert_eq!(simulation.time(), 1.0);
+        assert_eq!(simulation.collisions().collision_count, 1);
+        assert_eq!(simulation.walls().counts().total(), 0);
+    }
+
+    #[test]
+    fn walls_turn_particles_round_where_they_reach_them() {
+        let mut simulation = EventDrivenSimulation::new(vec![Particle::new(1.0, 5.0, -2.0, 0.0, PARTICLE_RADIUS)], BOX, 0.0);
+        simulation.advance(1.0);
+        assert_eq!((simulation.particles()[0].position[0], simulation.particles()[0].velocity[0]), (1.0, 2.0));
+        assert_eq!(simulation.walls().counts().get(Wall::Left), 1);
+
+        // Out from the middle of a dish, reaching its edge after 5 seconds and coming 2 back
+        let mut dish = EventDrivenSimulation::new(vec![Particle::new(5.0, 5.0, 1.0, 0.0, PARTICLE_RADIUS)], Enclosure::Circle { radius: 5.0 }, 0.0);
+        dish.advance(7.0);
+        assert_eq!((dish.particles()[0].position, dish.particles()[0].velocity[0]), ([8.0, 5.0], -1.0));
+        assert_eq!(dish.walls().counts().get(Wall::Round), 1);
+    }
+
+    #[test]
+    fn fast_pairs_never_pass_through_each_other() {
+        // Far enough in one step that the stepped mode wouldn't see them overlap
+        let particles = vec![Particle::new(1.0, 5.0, 100.0, 0.0, PARTICLE_RADIUS), Particle { id: 1, ..Particle::new(1.5, 5.0, 0.0, 0.0, PARTICLE_RADIUS) }];
+        let start_energy = kinetic_energy(&particles);
+        let mut simulation = EventDrivenSimulation::new(particles, BOX, 0.0);
+
+        simulation.advance(TIMESTEP as f64);
+        assert_eq!(simulation.collisions().collision_count, 1);
+        assert!(simulation.particles()[0].position[0] < simulation.particles()[1].position[0]);
+        assert!((kinetic_energy(simulation.particles()) - start_energy).abs() < 1e-3 * start_energy);
+    }
+
+    #[test]
+    fn overlapping_pairs_moving_apart_are_left_alone() {
+        let particles = vec![Particle::new(5.0, 5.0, -1.0, 0.0, PARTICLE_RADIUS), Particle { id: 1, ..Particle::new(5.1, 5.0, 1.0, 0.0, PARTICLE_RADIUS) }];
+        let mut simulation = EventDrivenSimulation::new(particles, BOX, 0.0);
+        simulation.advance(1.0);
+        assert_eq!(simulation.collisions().collision_count, 0);
+        assert_eq!((simulation.particles()[0].velocity[0], simulation.particles()[1].velocity[0]), (-1.0, 1.0));
+    }
+}
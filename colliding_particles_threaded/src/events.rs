@@ -0,0 +1,150 @@
+// When each collision happened, so the collision rate can be plotted over a run or the last few printed with the report
+// Only the start of a collision is logged, a pair still overlapping from the last frame isn't logged again
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::time::{Duration, Instant};
+
+pub const COLLISION_LOG_CAPACITY : usize = 100_000; // Events kept before the oldest start being dropped, a few megabytes
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct CollisionEvent {
+    pub frame: usize, // Of the collision thread that saw it, each counts its own
+    #[serde(rename = "seconds", serialize_with = "crate::config::serialize_seconds")]
+    pub time: Duration, // Since the log was made, at the start of the run
+    pub a: u64, // Ids of the two particles, lower first
+    pub b: u64,
+}
+
+// The most recent cap events, each push past that overwriting the oldest, so it never holds more than it started with room for
+// Until it first fills the oldest is at the front, after that head is where the oldest is and the next push goes
+pub struct EventRingBuffer {
+    cap: usize,
+    data: Vec<CollisionEvent>,
+    head: usize,
+}
+
+impl EventRingBuffer {
+    pub fn new(cap: usize) -> Self {
+        assert!(cap > 0, "an event ring buffer needs room for at least one event");
+        EventRingBuffer { cap, data: Vec::with_capacity(cap.min(COLLISION_LOG_CAPACITY)), head: 0 }
+    }
+
+    // Add an event, returning the one it overwrote if the buffer was full
+    pub fn push(&mut self, event: CollisionEvent) -> Option<CollisionEvent> {
+        if self.data.len() < self.cap {
+            self.data.push(event);
+            return None;
+        }
+
+        let oldest = std::mem::replace(&mut self.data[self.head], event);
+        self.head = (self.head + 1) % self.cap;
+        Some(oldest)
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    // Oldest first
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &CollisionEvent> {
+        self.data[self.head..].iter().chain(&self.data[..self.head])
+    }
+
+    // The last count events pushed, or all of them if there are fewer, oldest first
+    pub fn latest(&self, count: usize) -> Vec<CollisionEvent> {
+        self.iter().skip(self.len().saturating_sub(count)).copied().collect()
+    }
+}
+
+// Every collision thread logs into the same one behind a mutex, so the times come from one clock
+// Once it holds capacity events the oldest is dropped for each new one, so a long run can't use up the memory
+pub struct CollisionLog {
+    start: Instant,
+    events: EventRingBuffer,
+    dropped: usize,
+}
+
+impl CollisionLog {
+    pub fn new(capacity: usize) -> Self {
+        CollisionLog { start: Instant::now(), events: EventRingBuffer::new(capacity), dropped: 0 }
+    }
+
+    pub fn record(&mut self, frame: usize, a: u64, b: u64) {
+        if self.events.push(CollisionEvent { frame, time: self.start.elapsed(), a: a.min(b), b: a.max(b) }).is_some() {
+            self.dropped += 1;
+        }
+    }
+
+    // Oldest first
+    pub fn events(&self) -> impl Iterator<Item = &CollisionEvent> {
+        self.events.iter()
+    }
+
+    // The last count collisions logged, oldest first
+    pub fn latest(&self, count: usize) -> Vec<CollisionEvent> {
+        self.events.latest(count)
+    }
+
+    // Events pushed out to make room, so how many collisions there were from the start is events plus this
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    // One frame,seconds,a,b line per event, oldest first
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        writeln!(file, "frame,seconds,a,b")?;
+        for event in self.events.iter() {
+            writeln!(file, "{},{},{},{}", event.frame, event.time.as_secs_f64(), event.a, event.b)?;
+        }
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_oldest_events_make_way_for_new_ones() {
+        let mut log = CollisionLog::new(3);
+        for frame in 0..5 {
+            log.record(frame, 10 - frame as u64, 2);
+        }
+
+        let events : Vec<_> = log.events().map(|e| (e.frame, e.a, e.b)).collect();
+        assert_eq!(events, vec![(2, 2, 8), (3, 2, 7), (4, 2, 6)]);
+        assert_eq!(log.dropped(), 2);
+        assert!(log.events().zip(log.events().skip(1)).all(|(earlier, later)| earlier.time <= later.time));
+        assert_eq!(log.latest(2).iter().map(|e| e.frame).collect::<Vec<_>>(), vec![3, 4]);
+    }
+
+    #[test]
+    fn ring_buffers_keep_the_latest_events_in_order() {
+        let event = |frame| CollisionEvent { frame, time: Duration::from_millis(frame as u64), a: 0, b: 1 };
+        let frames = |buffer: &EventRingBuffer| buffer.iter().map(|e| e.frame).collect::<Vec<_>>();
+        let mut buffer = EventRingBuffer::new(4);
+
+        assert!(buffer.is_empty() && buffer.latest(3).is_empty());
+        for frame in 0..3 {
+            assert_eq!(buffer.push(event(frame)), None);
+        }
+        assert_eq!(frames(&buffer), vec![0, 1, 2]);
+
+        // Going round more than once still keeps them oldest first
+        for frame in 3..11 {
+            buffer.push(event(frame));
+        }
+        assert_eq!(buffer.push(event(11)), Some(event(7)));
+        assert_eq!(frames(&buffer), vec![8, 9, 10, 11]);
+        assert_eq!(buffer.len(), 4);
+        assert_eq!(buffer.latest(2), vec![event(10), event(11)]);
+        assert_eq!(buffer.latest(10).len(), 4);
+    }
+}
@@ -0,0 +1,20 @@
+// The floating point type particles are simulated in, f32 for speed or f64 for long runs that need the precision
+// Settings like the enclosure's size stay f32 whichever is used, and are converted as they are needed
+use num_traits::{Euclid, FloatConst, NumAssign};
+use std::fmt::{Debug, Display};
+
+pub trait Float: num_traits::Float + FloatConst + Euclid + NumAssign + Debug + Display + Default + Send + Sync + 'static {
+    fn from_f32(value: f32) -> Self;
+}
+
+impl Float for f32 {
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+}
+
+impl Float for f64 {
+    fn from_f32(value: f32) -> Self {
+        value as f64
+    }
+}
@@ -0,0 +1,244 @@
+use crate::Particle;
+use crate::broadphase::SpatialGrid;
+use crate::integrator::Acceleration;
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+const REPULSION_SOFTENING : f32 = 0.01; // Added to the distance so coincident particles get a large but finite push
+const INVERSE_SQUARE_SOFTENING : f32 = 1e-4; // Added to the squared distance, for the same reason
+const LENNARD_JONES_CUTOFF : f32 = 2.5; // In sigmas, past which the attraction is under 2% of its strongest
+
+// The force between a pair of particles as a function of how far apart they are
+// Positive pushes them apart and negative pulls them together. It takes the squared distance, as that's what the pair loop has to hand
+pub trait Potential: Send + Sync {
+    fn force(&self, r2: f32) -> f32;
+
+    // Pairs further apart than this don't affect each other, so it's also how far the force field's grid looks for neighbours
+    fn cutoff(&self) -> f32;
+}
+
+// What --repulsion turns on, a push inversely proportional to the distance
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Repulsion {
+    pub strength: f32,
+    pub cutoff: f32,
+}
+
+impl Potential for Repulsion {
+    fn force(&self, r2: f32) -> f32 {
+        self.strength / (r2.sqrt() + REPULSION_SOFTENING)
+    }
+
+    fn cutoff(&self) -> f32 {
+        self.cutoff
+    }
+}
+
+// Strong repulsion up close and a weak attraction further out, the two balancing 2^(1/6) sigma apart, where the well is epsilon deep
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LennardJones {
+    pub epsilon: f32,
+    pub sigma: f32,
+}
+
+impl LennardJones {
+    // The separation a pair settles at once something takes their energy away
+    pub fn minimum(&self) -> f32 {
+        2f32.powf(1.0 / 6.0) * self.sigma
+    }
+}
+
+impl Potential for LennardJones {
+    // -dV/dr of 4 epsilon ((sigma / r)^12 - (sigma / r)^6)
+    fn force(&self, r2: f32) -> f32 {
+        let inverse_6 = (self.sigma * self.sigma / r2).powi(3);
+        24.0 * self.epsilon * (2.0 * inverse_6 * inverse_6 - inverse_6) / r2.sqrt()
+    }
+
+    fn cutoff(&self) -> f32 {
+        LENNARD_JONES_CUTOFF * self.sigma
+    }
+}
+
+// Coulomb's law, or gravity with a negative strength, cut off past a distance so the grid has something to work with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct InverseSquare {
+    pub strength: f32,
+    pub cutoff: f32,
+}
+
+impl Potential for InverseSquare {
+    fn force(&self, r2: f32) -> f32 {
+        self.strength / (r2 + INVERSE_SQUARE_SOFTENING)
+    }
+
+    fn cutoff(&self) -> f32 {
+        self.cutoff
+    }
+}
+
+// The potentials a run can be given with --potential, as NAME:A:B on the command line or a table with a kind in a config file
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum PotentialKind {
+    LennardJones { epsilon: f32, sigma: f32 },
+    InverseSquare { strength: f32, cutoff: f32 },
+}
+
+impl PotentialKind {
+    // The name and the two numbers after it, as lennard-jones:EPSILON:SIGMA or inverse-square:STRENGTH:CUTOFF
+    pub fn from_parts(name: &str, a: f32, b: f32) -> Option<Self> {
+        match name {
+            "lennard-jones" => Some(PotentialKind::LennardJones { epsilon: a, sigma: b }),
+            "inverse-square" => Some(PotentialKind::InverseSquare { strength: a, cutoff: b }),
+            _ => None,
+        }
+    }
+
+    pub fn build(self) -> Box<dyn Potential> {
+        match self {
+            PotentialKind::LennardJones { epsilon, sigma } => Box::new(LennardJones { epsilon, sigma }),
+            PotentialKind::InverseSquare { strength, cutoff } => Box::new(InverseSquare { strength, cutoff }),
+        }
+    }
+}
+
+// Pushes and pulls every pair of particles within a potential's cutoff of each other along the line between them
+// Each move thread owns one, as the grid is rebuilt from the whole system every step
+// Distances are measured directly, so with periodic boundaries particles don't feel each other across the edges
+pub struct ForceField<const N: usize = 2> {
+    potential: Box<dyn Potential>,
+    grid: SpatialGrid<N>,
+    accelerations: Vec<Acceleration<N>>,
+}
+
+impl<const N: usize> ForceField<N> {
+    pub fn new(potential: Box<dyn Potential>, width: f32, height: f32, depth: f32) -> Self {
+        let grid = SpatialGrid::new(width, height, depth, potential.cutoff());
+        ForceField { potential, grid, accelerations: Vec::new() }
+    }
+
+    // The acceleration from every neighbour within the cutoff on each particle in chunk, in chunk order
+    // The whole system is read as a snapshot, taken while the caller holds the lock
+    // A pair straddling two chunks is seen by both threads, and each pushes only its own particle, so the pair still gets equal and opposite pushes
+    // In free-running mode the other thread may have moved its particles on a step by then, which the first version accepts
+    pub fn accelerations(&mut self, particles: &[Particle<N>], chunk: Range<usize>) -> &[Acceleration<N>] {
+        self.grid.rebuild(particles);
+
+        let cutoff_squared = self.potential.cutoff().powi(2);
+        let potential = &self.potential;
+        let accelerations = &mut self.accelerations;
+        accelerations.clear();
+        accelerations.resize(chunk.len(), [0.0; N]);
+
+        self.grid.for_each_candidate_pair(|i, j| {
+            if !chunk.contains(&i) && !chunk.contains(&j) {
+                return;
+            }
+
+            let squared_distance = particles[i].squared_distance(&particles[j]);
+            if squared_distance >= cutoff_squared {
+                return;
+            }
+
+            // Direction from j to i, coincident particles have none so are left alone rather than pushed in an arbitrary direction
+            let distance = squared_distance.sqrt();
+            if distance == 0.0 {
+                return;
+            }
+            let normal : [f32; N] = std::array::from_fn(|axis| (particles[i].position[axis] - particles[j].position[axis]) / distance);
+            let force = potential.force(squared_distance);
+
+            if chunk.contains(&i) {
+                for (acceleration, n) in accelerations[i - chunk.start].iter_mut().zip(normal) {
+                    *acceleration += force * n / particles[i].mass;
+                }
+            }
+            if chunk.contains(&j) {
+                for (acceleration, n) in accelerations[j - chunk.start].iter_mut().zip(normal) {
+                    *acceleration -= force * n / particles[j].mass;
+                }
+            }
+        });
+
+        accelerations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SimConfig;
+    use crate::integrator::ChunkMover;
+    use crate::{Enclosure, ENCLOSURE_D, ENCLOSURE_H, ENCLOSURE_W, PARTICLE_RADIUS, TIMESTEP};
+
+    fn repulsion(strength: f32, cutoff: f32) -> ForceField {
+        ForceField::new(Box::new(Repulsion { strength, cutoff }), ENCLOSURE_W, ENCLOSURE_H, ENCLOSURE_D)
+    }
+
+    #[test]
+    fn nearby_particles_are_pushed_apart_equally() {
+        let particles = vec![
+            Particle::new(5.0, 5.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new(5.2, 5.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new(9.0, 9.0, 0.0, 0.0, PARTICLE_RADIUS),
+        ];
+
+        let mut repulsion = repulsion(1.0, 0.5);
+        let accelerations = repulsion.accelerations(&particles, 0..3);
+
+        assert!(accelerations[0][0] < 0.0 && accelerations[1][0] > 0.0);
+        assert!((accelerations[0][0] + accelerations[1][0]).abs() < 1e-6);
+        assert_eq!(accelerations[2], [0.0, 0.0]); // Beyond the cutoff
+    }
+
+    #[test]
+    fn only_the_chunk_is_pushed() {
+        let particles = vec![
+            Particle::new(5.0, 5.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new(5.2, 5.0, 0.0, 0.0, PARTICLE_RADIUS),
+        ];
+
+        let mut repulsion = repulsion(1.0, 0.5);
+        let accelerations = repulsion.accelerations(&particles, 1..2);
+
+        assert_eq!(accelerations.len(), 1);
+        assert!(accelerations[0][0] > 0.0);
+    }
+
+    #[test]
+    fn potentials_push_apart_when_positive_and_pull_together_when_negative() {
+        let lennard_jones = LennardJones { epsilon: 1.0, sigma: 0.2 };
+        assert!(lennard_jones.force(0.2f32.powi(2)) > 0.0);
+        assert!(lennard_jones.force(lennard_jones.minimum().powi(2)).abs() < 1e-3);
+        assert!(lennard_jones.force(0.3f32.powi(2)) < 0.0);
+        assert_eq!(lennard_jones.cutoff(), 0.5);
+
+        let coulomb = InverseSquare { strength: 2.0, cutoff: 1.0 };
+        assert!((coulomb.force(0.25) / coulomb.force(1.0) - 4.0).abs() < 1e-2);
+        assert!(InverseSquare { strength: -2.0, ..coulomb }.force(0.25) < 0.0);
+
+        // Pulled together along the line between them
+        let particles = vec![Particle::new(5.0, 5.0, 0.0, 0.0, PARTICLE_RADIUS), Particle::new(5.0, 5.3, 0.0, 0.0, PARTICLE_RADIUS)];
+        let mut field = ForceField::new(Box::new(lennard_jones), ENCLOSURE_W, ENCLOSURE_H, ENCLOSURE_D);
+        let accelerations = field.accelerations(&particles, 0..2);
+        assert!(accelerations[0][1] > 0.0 && accelerations[1][1] < 0.0 && accelerations[0][0] == 0.0);
+    }
+
+    #[test]
+    fn lennard_jones_pairs_settle_at_the_bottom_of_the_well() {
+        // Drag takes out the energy they start with, falling in from further out
+        let potential = PotentialKind::LennardJones { epsilon: 0.01, sigma: 0.2 };
+        let config = SimConfig { potential: Some(potential), drag: 2.0, enclosure: Enclosure::Rect { w: 10.0, h: 10.0 }, depth: 0.0, ..SimConfig::default() };
+        let mut particles = vec![Particle { mass: 1.0, ..Particle::new(4.8, 5.0, 0.0, 0.0, 0.01) }, Particle { mass: 1.0, ..Particle::new(5.2, 5.0, 0.0, 0.0, 0.01) }];
+        let mut mover = ChunkMover::new(0..2, &config);
+        for _ in 0..3000 {
+            mover.step(&mut particles, TIMESTEP);
+        }
+
+        let separation = particles[1].position[0] - particles[0].position[0];
+        let minimum = LennardJones { epsilon: 0.01, sigma: 0.2 }.minimum();
+        assert!((separation - minimum).abs() < 1e-3, "settled {} apart rather than {}", separation, minimum);
+        assert!(particles[0].velocity[0].abs() < 1e-3 && (particles[0].position[0] + particles[1].position[0] - 10.0).abs() < 1e-4);
+    }
+}
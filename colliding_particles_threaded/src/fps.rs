@@ -0,0 +1,96 @@
+// A live readout of how many frames a second each move thread is managing, turned on with --fps
+// A thread well below the others is being starved, most likely waiting on the particle lock
+// Every move thread adds to its own atomic counter, so counting takes no lock and the monitor never holds anyone up
+use log::info;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub const FPS_INTERVAL : Duration = Duration::from_secs(1);
+const FPS_WAIT_SLICE : Duration = Duration::from_millis(20); // Longest the monitor sleeps before looking to see if the run is over
+
+// One iteration counter per move thread, by the index of its share of the particles
+#[derive(Debug, Clone, Default)]
+pub struct ThreadCounters {
+    counts: Arc<[AtomicU64]>,
+}
+
+impl ThreadCounters {
+    pub fn new(threads: usize) -> Self {
+        ThreadCounters { counts: (0..threads).map(|_| AtomicU64::new(0)).collect() }
+    }
+
+    pub fn tick(&self, thread: usize) {
+        self.counts[thread].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Vec<u64> {
+        self.counts.iter().map(|count| count.load(Ordering::Relaxed)).collect()
+    }
+}
+
+// Frames each thread got through between two readings of the counters taken elapsed apart
+pub fn frames_per_second(previous: &[u64], current: &[u64], elapsed: Duration) -> Vec<f64> {
+    let seconds = elapsed.as_secs_f64();
+    previous.iter().zip(current).map(|(&before, &after)| if seconds > 0.0 { after.saturating_sub(before) as f64 / seconds } else { 0.0 }).collect()
+}
+
+// The monitor thread, which logs every thread's rate once a second until this is dropped
+pub struct FpsMonitor {
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FpsMonitor {
+    pub fn start(counters: ThreadCounters) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread_done = Arc::clone(&done);
+
+        let thread = thread::spawn(move || {
+            let (mut previous, mut previous_time) = (counters.get(), Instant::now());
+            while !thread_done.load(Ordering::Relaxed) {
+                thread::sleep(FPS_WAIT_SLICE);
+                if previous_time.elapsed() < FPS_INTERVAL {
+                    continue;
+                }
+
+                let (current, now) = (counters.get(), Instant::now());
+                let rates : Vec<String> = frames_per_second(&previous, &current, now - previous_time).iter().enumerate().map(|(index, rate)| format!("{}: {:.0}", index, rate)).collect();
+                info!("Move thread frames/s {}", rates.join(", "));
+                (previous, previous_time) = (current, now);
+            }
+        });
+
+        FpsMonitor { done, thread: Some(thread) }
+    }
+}
+
+impl Drop for FpsMonitor {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_thread_counts_its_own_frames() {
+        let counters = ThreadCounters::new(3);
+        let clone = counters.clone();
+        for _ in 0..50 {
+            clone.tick(0);
+        }
+        clone.tick(2);
+
+        let current = counters.get();
+        assert_eq!(current, vec![50, 0, 1]);
+        assert_eq!(frames_per_second(&[10, 0, 0], &current, Duration::from_millis(500)), vec![80.0, 0.0, 2.0]);
+        assert_eq!(frames_per_second(&[0, 0, 0], &current, Duration::ZERO), vec![0.0; 3]);
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{lock_ignoring_poison, Particle};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+// A single slot holding the newest frame the receiver hasn't taken, plus the buffers the receiver is finished with
+// Publishing over a frame still waiting replaces it, so a receiver that falls behind skips the older frames rather than the newer ones
+#[derive(Default)]
+struct Mailbox<const N: usize> {
+    pending: Option<Vec<Particle<N>>>,
+    spare: Vec<Vec<Particle<N>>>,
+    closed: bool, // The sender has gone, so nothing newer than pending will arrive
+}
+
+#[derive(Default)]
+struct Shared<const N: usize> {
+    mailbox: Mutex<Mailbox<N>>,
+    published: Condvar,
+}
+
+// Publishing end of a frame channel, owned by the move thread that copies out whole frames
+// Buffers go round between it and the receiver, so once running no frame allocates
+pub struct FrameSender<const N: usize = 2> {
+    shared: Arc<Shared<N>>,
+    buffers: usize, // Allocated so far, which stops growing once there are enough going round
+}
+
+// Consuming end of a frame channel, owned by a collision thread
+pub struct FrameReceiver<const N: usize = 2> {
+    shared: Arc<Shared<N>>,
+    current: Option<Vec<Particle<N>>>,
+}
+
+// At most one frame is ever waiting, so a consumer that falls behind skips frames instead of building up a backlog
+pub fn frame_channel<const N: usize>() -> (FrameSender<N>, FrameReceiver<N>) {
+    let shared = Arc::new(Shared::default());
+    (FrameSender { shared: shared.clone(), buffers: 0 }, FrameReceiver { shared, current: None })
+}
+
+impl<const N: usize> FrameSender<N> {
+    // Send a copy of the particles, replacing any frame the receiver hasn't taken yet
+    pub fn publish(&mut self, particles: &[Particle<N>]) {
+        let spare = lock_ignoring_poison(&self.shared.mailbox).spare.pop();
+        let mut buffer = spare.unwrap_or_else(|| {
+            self.buffers += 1;
+            Vec::new()
+        });
+        buffer.clear();
+        buffer.extend_from_slice(particles); // Outside the lock, so the receiver isn't held up by the copy
+
+        let mut mailbox = lock_ignoring_poison(&self.shared.mailbox);
+        if let Some(stale) = mailbox.pending.replace(buffer) {
+            mailbox.spare.push(stale);
+        }
+        self.shared.published.notify_one();
+    }
+}
+
+impl<const N: usize> Drop for FrameSender<N> {
+    fn drop(&mut self) {
+        lock_ignoring_poison(&self.shared.mailbox).closed = true;
+        self.shared.published.notify_one();
+    }
+}
+
+impl<const N: usize> FrameReceiver<N> {
+    // Wait up to timeout for a frame newer than the last one, the error says whether none arrived in time or the sender has gone
+    pub fn latest(&mut self, timeout: Duration) -> Result<&[Particle<N>], RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut mailbox = lock_ignoring_poison(&self.shared.mailbox);
+        let frame = loop {
+            if let Some(frame) = mailbox.pending.take() {
+                break frame;
+            }
+            if mailbox.closed {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            mailbox = self.shared.published.wait_timeout(mailbox, deadline - now).unwrap_or_else(PoisonError::into_inner).0;
+        };
+
+        if let Some(previous) = self.current.take() {
+            mailbox.spare.push(previous);
+        }
+        drop(mailbox);
+
+        Ok(self.current.insert(frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(x: f32) -> Vec<Particle> {
+        vec![Particle::new(x, 0.0, 0.0, 0.0, 0.1); 4]
+    }
+
+    #[test]
+    fn receiver_gets_published_frames() {
+        let (mut sender, mut receiver) = frame_channel();
+
+        sender.publish(&frame(1.0));
+        assert_eq!(receiver.latest(Duration::from_millis(10)).unwrap()[0].position[0], 1.0);
+
+        sender.publish(&frame(2.0));
+        assert_eq!(receiver.latest(Duration::from_millis(10)).unwrap()[0].position[0], 2.0);
+    }
+
+    #[test]
+    fn frames_are_dropped_while_receiver_is_behind() {
+        let (mut sender, mut receiver) = frame_channel();
+
+        for x in 1..10 {
+            sender.publish(&frame(x as f32));
+        }
+
+        assert_eq!(receiver.latest(Duration::from_millis(10)).unwrap()[0].position[0], 9.0);
+        assert_eq!(receiver.latest(Duration::from_millis(10)), Err(RecvTimeoutError::Timeout));
+    }
+
+    #[test]
+    fn buffers_are_reused() {
+        let (mut sender, mut receiver) = frame_channel();
+
+        for x in 0..100 {
+            sender.publish(&frame(x as f32));
+            receiver.latest(Duration::from_millis(10)).unwrap();
+        }
+
+        assert!(sender.buffers <= 2);
+    }
+
+    #[test]
+    fn buffers_are_reused_while_receiver_is_behind() {
+        let (mut sender, mut receiver) = frame_channel();
+
+        for x in 0..100 {
+            sender.publish(&frame(x as f32));
+            sender.publish(&frame(x as f32 + 0.5));
+            assert_eq!(receiver.latest(Duration::from_millis(10)).unwrap()[0].position[0], x as f32 + 0.5);
+        }
+
+        assert!(sender.buffers <= 3);
+    }
+
+    #[test]
+    fn a_frame_published_before_the_sender_is_dropped_still_arrives() {
+        let (mut sender, mut receiver) = frame_channel();
+        sender.publish(&frame(1.0));
+        drop(sender);
+
+        assert_eq!(receiver.latest(Duration::from_millis(10)).unwrap()[0].position[0], 1.0);
+        assert_eq!(receiver.latest(Duration::from_millis(10)), Err(RecvTimeoutError::Disconnected));
+    }
+
+    #[test]
+    fn latest_waits_for_a_frame_from_another_thread() {
+        let (mut sender, mut receiver) = frame_channel();
+        let publisher = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            sender.publish(&frame(3.0));
+        });
+
+        assert_eq!(receiver.latest(Duration::from_secs(10)).unwrap()[0].position[0], 3.0);
+        publisher.join().unwrap();
+    }
+
+    #[test]
+    fn latest_ends_when_sender_is_dropped() {
+        let (sender, mut receiver) = frame_channel::<2>();
+        drop(sender);
+
+        assert_eq!(receiver.latest(Duration::from_secs(10)), Err(RecvTimeoutError::Disconnected));
+    }
+}
@@ -0,0 +1,92 @@
+// Ready-to-run gnuplot scripts written next to the CSV outputs, turned on with --gnuplot
+// Each script is named after its CSV with a .gp extension, so energy.csv gets energy.gp and `gnuplot energy.gp` plots it
+// The CSV is referred to by its full path, so the script can be run from any directory
+// The scripts skip the header line with `skip`, which needs gnuplot 5.2 or newer
+use crate::Enclosure;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const FRAME_PAUSE_SECONDS : f32 = 0.05; // Between frames of the trajectory animation
+
+// The total kinetic energy against the frame it was sampled on
+pub fn energy_script(csv: &str) -> String {
+    let mut script = preamble(csv, "The total kinetic energy through the run");
+    script.push_str("set xlabel 'frame'\nset ylabel 'kinetic energy'\n");
+    let _ = writeln!(script, "plot '{}' skip 1 using 1:2 with lines title 'energy'\npause mouse close", quoted(csv));
+    script
+}
+
+// Energy and average speed on the left axis and the number of overlapping pairs on the right, all against time
+pub fn metrics_script(csv: &str) -> String {
+    let mut script = preamble(csv, "Whole-system metrics sampled through the run");
+    script.push_str("set xlabel 'seconds'\nset ylabel 'energy, speed'\nset y2label 'active collisions'\nset y2tics\nset ytics nomirror\n");
+    let _ = writeln!(script, "plot '{0}' skip 1 using 1:3 with lines title 'kinetic energy', \\\n     '{0}' skip 1 using 1:4 with lines title 'average speed', \\\n     '{0}' skip 1 using 1:2 axes x1y2 with steps title 'active collisions'\npause mouse close", quoted(csv));
+    script
+}
+
+// Every recorded frame in turn, with each particle drawn at its radius inside the enclosure, looking down the z axis for 3D runs
+// Move threads each count their own frames, so a frame's rows are picked out by the frame column rather than by where they are in the file
+pub fn trajectory_script(csv: &str, enclosure: &Enclosure, three_d: bool, every: u32) -> String {
+    let radius_column = if three_d { 6 } else { 5 };
+    let mut script = preamble(csv, "An animation of the recorded particle positions");
+    let _ = writeln!(script, "set size ratio -1\nset key off\nset xrange [0:{}]\nset yrange [0:{}]", enclosure.width(), enclosure.height());
+    if let Enclosure::Circle { radius } = *enclosure {
+        let _ = writeln!(script, "set object 1 circle at {0},{0} size {0} fill empty", radius);
+    }
+    let _ = writeln!(script, "stats '{}' skip 1 using 1 nooutput", quoted(csv));
+    let _ = writeln!(script, "do for [frame = 0:int(STATS_max):{}] {{", every);
+    script.push_str("    set title sprintf('frame %d', frame)\n");
+    let _ = writeln!(script, "    plot '{}' skip 1 using ($1 == frame ? $3 : NaN):4:{} with circles fill solid", quoted(csv), radius_column);
+    let _ = writeln!(script, "    pause {}\n}}", FRAME_PAUSE_SECONDS);
+    script
+}
+
+// Write a script next to the CSV it plots, returning where it went
+pub fn write_script(csv: &str, script: &str) -> io::Result<PathBuf> {
+    let path = Path::new(csv).with_extension("gp");
+    std::fs::write(&path, script)?;
+    Ok(path)
+}
+
+fn preamble(csv: &str, description: &str) -> String {
+    format!("# {} from {}\n# Run with: gnuplot <this file>\nset datafile separator ','\n", description, csv)
+}
+
+// A quote in a single quoted gnuplot string is written twice
+fn quoted(csv: &str) -> String {
+    std::fs::canonicalize(csv).map(|path| path.to_string_lossy().into_owned()).unwrap_or_else(|_| csv.to_string()).replace('\'', "''")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scripts_plot_the_csv_they_are_written_for() {
+        let trajectory = trajectory_script("no_such_dir/run's.csv", &Enclosure::Rect { w: 12.0, h: 8.0 }, false, 10);
+        assert!(trajectory.contains("set xrange [0:12]\nset yrange [0:8]"));
+        assert!(trajectory.contains("do for [frame = 0:int(STATS_max):10] {"));
+        assert!(trajectory.contains("plot 'no_such_dir/run''s.csv' skip 1 using ($1 == frame ? $3 : NaN):4:5 with circles"));
+
+        let round = trajectory_script("run.csv", &Enclosure::Circle { radius: 3.0 }, true, 1);
+        assert!(round.contains("set object 1 circle at 3,3 size 3") && round.contains(":4:6 with circles"));
+
+        assert!(energy_script("energy.csv").contains("plot 'energy.csv' skip 1 using 1:2 with lines"));
+        assert!(metrics_script("metrics.csv").contains("'metrics.csv' skip 1 using 1:2 axes x1y2"));
+    }
+
+    #[test]
+    fn scripts_go_next_to_their_csv() {
+        let csv = std::env::temp_dir().join(format!("particles_gnuplot_{}.csv", std::process::id()));
+        std::fs::write(&csv, "frame,energy\n0,1\n").unwrap();
+
+        let path = write_script(csv.to_str().unwrap(), &energy_script(csv.to_str().unwrap())).unwrap();
+        let script = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&csv).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(path, csv.with_extension("gp"));
+        assert!(script.contains(&format!("plot '{}'", std::fs::canonicalize(std::env::temp_dir()).unwrap().join(csv.file_name().unwrap()).display())));
+    }
+}
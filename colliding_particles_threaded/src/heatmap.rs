@@ -0,0 +1,82 @@
+use image::{GrayImage, Luma};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+pub const HEATMAP_CELLS : usize = 64; // Cells along each side of the enclosure
+
+// Counts collisions by where they happened, on a grid over the enclosure
+// 3D collisions are counted by their x and y, as if looking down the z axis
+pub struct Heatmap {
+    width: f32,
+    height: f32,
+    counts: Vec<u64>, // Row major, row 0 at y = 0
+}
+
+impl Heatmap {
+    pub fn new(width: f32, height: f32) -> Self {
+        Heatmap { width, height, counts: vec![0; HEATMAP_CELLS * HEATMAP_CELLS] }
+    }
+
+    // Count one collision at (x, y), positions outside the enclosure go in the nearest edge cell
+    pub fn record(&mut self, x: f32, y: f32) {
+        let column = ((x / self.width * HEATMAP_CELLS as f32).max(0.0) as usize).min(HEATMAP_CELLS - 1);
+        let row = ((y / self.height * HEATMAP_CELLS as f32).max(0.0) as usize).min(HEATMAP_CELLS - 1);
+        self.counts[row * HEATMAP_CELLS + column] += 1;
+    }
+
+    pub fn count(&self, column: usize, row: usize) -> u64 {
+        self.counts[row * HEATMAP_CELLS + column]
+    }
+
+    // Write a PNG if the path ends in .png, otherwise a CSV matrix
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        if path.to_ascii_lowercase().ends_with(".png") {
+            self.save_png(path)
+        } else {
+            self.save_csv(path)
+        }
+    }
+
+    // One pixel per cell, the busiest cell is white, flipped so y points up like the simulation
+    fn save_png(&self, path: &str) -> io::Result<()> {
+        let busiest = self.counts.iter().copied().max().unwrap_or(0).max(1);
+        let image = GrayImage::from_fn(HEATMAP_CELLS as u32, HEATMAP_CELLS as u32, |px, py| {
+            let count = self.count(px as usize, HEATMAP_CELLS - 1 - py as usize);
+            Luma([(count * 255 / busiest) as u8])
+        });
+
+        image.save(path).map_err(io::Error::other)
+    }
+
+    // One line per row of cells, top row first so the file reads the same way round as the PNG
+    fn save_csv(&self, path: &str) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        for row in (0..HEATMAP_CELLS).rev() {
+            let line : Vec<String> = (0..HEATMAP_CELLS).map(|column| self.count(column, row).to_string()).collect();
+            writeln!(file, "{}", line.join(","))?;
+        }
+
+        file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collisions_land_in_their_cell() {
+        let mut heatmap = Heatmap::new(10.0, 10.0);
+
+        heatmap.record(0.0, 0.0);
+        heatmap.record(10.0, 10.0);
+        heatmap.record(5.0, 2.5);
+        heatmap.record(5.0, 2.5);
+
+        assert_eq!(heatmap.count(0, 0), 1);
+        assert_eq!(heatmap.count(HEATMAP_CELLS - 1, HEATMAP_CELLS - 1), 1);
+        assert_eq!(heatmap.count(32, 16), 2);
+        assert_eq!(heatmap.counts.iter().sum::<u64>(), 4);
+    }
+}
@@ -0,0 +1,410 @@
+// How the move threads advance particles through a timestep given the forces on them
+//
+// Every step is split in three so the forces can be measured at the right point:
+// - before_forces, anything moved here is where the forces get measured
+// - the forces are measured on the whole system, reading positions only
+// - after_forces, with the acceleration of each particle in the chunk
+use crate::config::SimConfig;
+use crate::forces::ForceField;
+use crate::movement::{MovementKind, MovementModel};
+use crate::walls::WallCounter;
+use crate::float::Float;
+use crate::{BoundaryMode, Enclosure, Particle};
+use rand::random;
+use serde::Serialize;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::ops::Range;
+
+pub type Acceleration<const N: usize = 2, F = f32> = [F; N];
+
+// Generic over the float type, though the move threads only ever step f32 particles
+pub trait Integrator<const N: usize = 2, F: Float = f32>: Send {
+    fn before_forces(&mut self, particles: &mut [Particle<N, F>], dt: F);
+    fn after_forces(&mut self, particles: &mut [Particle<N, F>], accelerations: &[Acceleration<N, F>], dt: F);
+}
+
+// Semi-implicit Euler, the velocity is kicked by the forces at the start of the step and the particle moves along the new velocity
+// First order, so energy wanders by an amount proportional to the timestep however smooth the forces are
+pub struct Euler;
+
+impl<const N: usize, F: Float> Integrator<N, F> for Euler {
+    fn before_forces(&mut self, _particles: &mut [Particle<N, F>], _dt: F) {}
+
+    fn after_forces(&mut self, particles: &mut [Particle<N, F>], accelerations: &[Acceleration<N, F>], dt: F) {
+        for (p, acceleration) in particles.iter_mut().zip(accelerations) {
+            for (velocity, &a) in p.velocity.iter_mut().zip(acceleration) {
+                *velocity += a * dt;
+            }
+            p.integrate(dt);
+        }
+    }
+}
+
+// Half a kick with the old forces, a move, then half a kick with the forces at the new positions
+// Second order and time reversible, so under a constant force like gravity it follows the exact parabola
+// A particle with no old forces to kick with, on the first step or new to the chunk, takes one Euler step instead
+pub struct VelocityVerlet<const N: usize = 2, F = f32> {
+    previous: Vec<(u64, Acceleration<N, F>)>, // The forces measured last step by particle id, at the positions the particles are about to leave
+}
+
+impl<const N: usize, F> Default for VelocityVerlet<N, F> {
+    fn default() -> Self {
+        VelocityVerlet { previous: Vec::new() }
+    }
+}
+
+impl<const N: usize, F: Float> VelocityVerlet<N, F> {
+    // Particles are in order of id, so the ones remembered are too
+    fn previous(&self, id: u64) -> Option<Acceleration<N, F>> {
+        self.previous.binary_search_by_key(&id, |&(id, _)| id).ok().map(|i| self.previous[i].1)
+    }
+}
+
+// Half a kick along every axis
+fn half_kick<const N: usize, F: Float>(p: &mut Particle<N, F>, acceleration: &Acceleration<N, F>, dt: F) {
+    let half = F::from_f32(0.5);
+    for (velocity, &a) in p.velocity.iter_mut().zip(acceleration) {
+        *velocity += half * a * dt;
+    }
+}
+
+impl<const N: usize, F: Float> Integrator<N, F> for VelocityVerlet<N, F> {
+    fn before_forces(&mut self, particles: &mut [Particle<N, F>], dt: F) {
+        for p in particles.iter_mut() {
+            if let Some(acceleration) = self.previous(p.id) {
+                half_kick(p, &acceleration, dt);
+                p.integrate(dt);
+            }
+        }
+    }
+
+    fn after_forces(&mut self, particles: &mut [Particle<N, F>], accelerations: &[Acceleration<N, F>], dt: F) {
+        for (p, acceleration) in particles.iter_mut().zip(accelerations) {
+            if self.previous(p.id).is_some() {
+                half_kick(p, acceleration, dt);
+            } else {
+                Euler.after_forces(std::slice::from_mut(p), std::slice::from_ref(acceleration), dt);
+            }
+        }
+
+        self.previous.clear();
+        self.previous.extend(particles.iter().map(|p| p.id).zip(accelerations.iter().copied()));
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum IntegratorKind {
+    #[serde(rename = "euler")] Euler,
+    #[serde(rename = "verlet")] VelocityVerlet,
+}
+
+impl IntegratorKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "euler" => Some(IntegratorKind::Euler),
+            "verlet" => Some(IntegratorKind::VelocityVerlet),
+            _ => None,
+        }
+    }
+
+    pub fn build<const N: usize>(&self) -> Box<dyn Integrator<N>> {
+        match self {
+            IntegratorKind::Euler => Box::new(Euler),
+            IntegratorKind::VelocityVerlet => Box::new(VelocityVerlet::default()),
+        }
+    }
+}
+
+// Everything one move thread needs to take its chunk through a timestep
+// Each move thread owns one, as the integrator and force field keep state for the chunk between steps
+// With no forces to measure the integrator is skipped and the movement model moves every particle on its own
+pub struct ChunkMover<const N: usize = 2> {
+    chunk: Range<usize>,
+    movement: Option<(Box<dyn MovementModel<N>>, StdRng)>,
+    integrator: Box<dyn Integrator<N>>,
+    forces: Option<ForceField<N>>,
+    accelerations: Vec<Acceleration<N>>,
+    gravity: f32,
+    drag: f32,
+    max_speed: f32,
+    enclosure: Enclosure,
+    boundary: BoundaryMode,
+    depth: f32,
+    walls: WallCounter,
+}
+
+impl<const N: usize> ChunkMover<N> {
+    // Each chunk's random numbers come from the seed and where the chunk starts, so a seeded run moves the same way every time
+    pub fn new(chunk: Range<usize>, config: &SimConfig) -> Self {
+        let has_forces = config.gravity != 0.0 || config.has_pair_forces();
+        let movement = match config.movement {
+            MovementKind::Ballistic if has_forces => None, // The config won't allow forces with the others
+            kind => Some((kind.build(config), StdRng::seed_from_u64(config.seed.unwrap_or_else(random).wrapping_add(chunk.start as u64)))),
+        };
+
+        ChunkMover {
+            chunk,
+            movement,
+            integrator: config.integrator.build(),
+            forces: config.pair_potential().map(|potential| ForceField::new(potential, config.enclosure.width(), config.enclosure.height(), config.depth)),
+            accelerations: Vec::new(),
+            gravity: config.gravity,
+            drag: config.drag,
+            max_speed: config.max_speed,
+            enclosure: config.enclosure,
+            boundary: config.boundary,
+            depth: config.depth,
+            walls: WallCounter::default(),
+        }
+    }
+
+    // Count the chunk's bounces off the walls into a counter shared with the other move threads, rather than one of its own
+    pub fn with_wall_counter(mut self, walls: WallCounter) -> Self {
+        self.walls = walls;
+        self
+    }
+
+    pub fn chunk(&self) -> Range<usize> {
+        self.chunk.clone()
+    }
+
+    // Move on to a different chunk, as the chunks shift when particles are spawned or removed
+    // The integrator keeps what it remembers by particle id, so only particles new to the chunk lose their old forces
+    pub fn set_chunk(&mut self, chunk: Range<usize>) {
+        if chunk != self.chunk {
+            self.chunk = chunk;
+            self.accelerations.clear();
+        }
+    }
+
+    // Whether measuring the forces reads other chunks' particles, so a thread mustn't measure while another is moving
+    pub fn reads_other_chunks(&self) -> bool {
+        self.forces.is_some()
+    }
+
+    // The three parts of a step in order, measuring the forces takes the whole system and the others only the chunk's particles
+    // So the chunk can be moved on a copy, without holding on to the rest of the system
+    pub fn before_forces(&mut self, chunk: &mut [Particle<N>], dt: f32) {
+        self.integrator.before_forces(chunk, dt);
+        self.apply_boundary(chunk);
+    }
+
+    // Gravity pulls down the second axis, y
+    pub fn measure_forces(&mut self, particles: &[Particle<N>]) {
+        let gravity = self.gravity;
+        let with_gravity = |mut acceleration: Acceleration<N>| {
+            acceleration[1] += gravity;
+            acceleration
+        };
+        self.accelerations.clear();
+        match &mut self.forces {
+            Some(forces) => self.accelerations.extend(forces.accelerations(particles, self.chunk.clone()).iter().copied().map(with_gravity)),
+            None => self.accelerations.resize(self.chunk.len(), with_gravity([0.0; N])),
+        }
+    }
+
+    pub fn after_forces(&mut self, chunk: &mut [Particle<N>], dt: f32) {
+        self.integrator.after_forces(chunk, &self.accelerations, dt);
+        self.apply_drag(chunk, dt);
+        self.clamp_speeds(chunk);
+        self.apply_boundary(chunk);
+    }
+
+    // All three at once on the whole system, or a copy of it
+    pub fn step(&mut self, particles: &mut [Particle<N>], dt: f32) {
+        if !self.reads_other_chunks() {
+            self.step_chunk(&mut particles[self.chunk.clone()], dt);
+            return;
+        }
+
+        self.before_forces(&mut particles[self.chunk.clone()], dt);
+        self.measure_forces(particles);
+        self.after_forces(&mut particles[self.chunk.clone()], dt);
+    }
+
+    // All three at once given only the chunk's particles, which is all a step needs unless the forces read other chunks
+    pub fn step_chunk(&mut self, chunk: &mut [Particle<N>], dt: f32) {
+        debug_assert!(!self.reads_other_chunks() && chunk.len() == self.chunk.len());
+        if let Some((model, rng)) = &mut self.movement {
+            for p in chunk.iter_mut() {
+                model.step(p, dt, rng);
+            }
+            self.apply_drag(chunk, dt);
+            self.clamp_speeds(chunk);
+            self.apply_boundary(chunk);
+            return;
+        }
+
+        self.before_forces(chunk, dt);
+        self.measure_forces(chunk); // Only gravity, which doesn't depend on where anything is
+        self.after_forces(chunk, dt);
+    }
+
+    // As step, for the chunk at chunk in particles, but leaving the particles wherever they end up for keep_inside to bring back
+    // For a copy that is merged back with whatever a collision thread changed meanwhile, so the walls see the merged particle
+    // rather than a wall's bounce being added on to one off another particle
+    pub fn step_without_walls(&mut self, particles: &mut [Particle<N>], chunk: Range<usize>, dt: f32) {
+        if let Some((model, rng)) = &mut self.movement {
+            for p in &mut particles[chunk.clone()] {
+                model.step(p, dt, rng);
+            }
+        } else {
+            self.integrator.before_forces(&mut particles[chunk.clone()], dt);
+            self.measure_forces(particles); // Only reads the others when they're all there
+            self.integrator.after_forces(&mut particles[chunk.clone()], &self.accelerations, dt);
+        }
+        self.apply_drag(&mut particles[chunk.clone()], dt);
+        self.clamp_speeds(&mut particles[chunk]);
+    }
+
+    // Bring a particle stepped by step_without_walls back inside, counting the walls it hits
+    pub fn keep_inside(&self, p: &mut Particle<N>) {
+        self.apply_boundary(std::slice::from_mut(p));
+    }
+
+    // Once the forces have had their say, so drag slows whatever velocity they left
+    // A drag too strong for the timestep stops the particle dead rather than sending it backwards
+    fn apply_drag(&self, chunk: &mut [Particle<N>], dt: f32) {
+        if self.drag == 0.0 {
+            return;
+        }
+
+        let kept = (1.0 - self.drag * dt).max(0.0);
+        for velocity in chunk.iter_mut().flat_map(|p| &mut p.velocity) {
+            *velocity *= kept;
+        }
+    }
+
+    // Last of all before the walls, so however hard the forces and collisions pushed, the next step can't carry a particle far past one
+    fn clamp_speeds(&self, chunk: &mut [Particle<N>]) {
+        for p in chunk {
+            p.clamp_speed(self.max_speed);
+        }
+    }
+
+    fn apply_boundary(&self, chunk: &mut [Particle<N>]) {
+        for p in chunk {
+            let hits = p.apply_boundary(&self.enclosure, self.boundary, self.depth);
+            if hits.any() {
+                self.walls.record(hits);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MAX_SPEED, PARTICLE_RADIUS, TIMESTEP};
+
+    // Kinetic plus gravitational potential energy, with y measured up from the floor
+    fn total_energy(p: &Particle, gravity: f32) -> f64 {
+        let speed_squared = (p.velocity[0] * p.velocity[0] + p.velocity[1] * p.velocity[1]) as f64;
+        0.5 * p.mass as f64 * speed_squared - (p.mass * gravity * p.position[1]) as f64
+    }
+
+    // Throw a particle up and across a box too big for it to reach the walls, returning the furthest its energy got from the start
+    fn energy_drift(integrator: IntegratorKind) -> f64 {
+        let config = SimConfig { gravity: -9.81, enclosure: Enclosure::Rect { w: 1000.0, h: 1000.0 }, depth: 0.0, integrator, ..SimConfig::default() };
+        let mut particles = vec![Particle::new(10.0, 10.0, 1.0, 20.0, PARTICLE_RADIUS)];
+        let mut mover = ChunkMover::new(0..1, &config);
+
+        mover.step(&mut particles, TIMESTEP); // Verlet takes an Euler step first, to measure the forces it kicks with
+        let initial = total_energy(&particles[0], config.gravity);
+
+        (0..400).map(|_| {
+            mover.step(&mut particles, TIMESTEP);
+            ((total_energy(&particles[0], config.gravity) - initial) / initial).abs()
+        }).fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn seeded_movement_models_move_the_same_way_every_time() {
+        let config = SimConfig { movement: MovementKind::Brownian, seed: Some(8), ..SimConfig::default() };
+        let start = vec![Particle::new(5.0, 5.0, 0.0, 0.0, PARTICLE_RADIUS); 4];
+
+        let run = |chunk: Range<usize>| {
+            let mut particles = start.clone();
+            let mut mover = ChunkMover::new(chunk, &config);
+            for _ in 0..10 {
+                mover.step(&mut particles, TIMESTEP);
+            }
+            particles
+        };
+
+        let moved = run(0..4);
+        assert_eq!(moved, run(0..4));
+        assert!(moved.iter().all(|p| p.position[0] != 5.0 && p.position[1] != 5.0));
+        assert_eq!(run(2..4)[..2], start[..2]); // Only the chunk moves
+    }
+
+    #[test]
+    fn drag_slows_particles_until_they_settle_on_the_floor() {
+        let config = SimConfig { drag: 1.0, depth: 0.0, ..SimConfig::default() };
+        let mut particles = vec![Particle::new(5.0, 5.0, 2.0, 0.0, PARTICLE_RADIUS)];
+        let mut mover = ChunkMover::new(0..1, &config);
+        for _ in 0..100 {
+            mover.step(&mut particles, TIMESTEP);
+        }
+        assert!((particles[0].velocity[0] - 2.0 * 0.99f32.powi(100)).abs() < 1e-4);
+
+        let heavy = SimConfig { drag: 1000.0, ..config.clone() };
+        ChunkMover::new(0..1, &heavy).step(&mut particles, TIMESTEP);
+        assert_eq!((particles[0].velocity[0], particles[0].velocity[1]), (0.0, 0.0)); // Stopped, not reversed
+
+        let falling = SimConfig { gravity: -9.81, drag: 2.0, ..config };
+        let mut particles = vec![Particle::new(5.0, 8.0, 0.0, 5.0, PARTICLE_RADIUS)];
+        let mut mover = ChunkMover::new(0..1, &falling);
+        for _ in 0..2000 {
+            mover.step(&mut particles, TIMESTEP);
+        }
+        assert!(particles[0].position[1] < 0.5 && particles[0].velocity[1].abs() < 0.5, "still at {} moving at {}", particles[0].position[1], particles[0].velocity[1]);
+    }
+
+    #[test]
+    fn enormous_velocities_are_clamped_to_the_max_speed() {
+        let config = SimConfig { depth: 0.0, ..SimConfig::default() };
+        let mut particles = vec![Particle::new(5.0, 5.0, 3.0e6, -4.0e6, PARTICLE_RADIUS), Particle::new(5.0, 5.0, 1.0, 0.0, PARTICLE_RADIUS)];
+        ChunkMover::new(0..2, &config).step(&mut particles, TIMESTEP);
+
+        let speed = (particles[0].velocity[0].powi(2) + particles[0].velocity[1].powi(2)).sqrt();
+        assert!((speed - MAX_SPEED).abs() < 1e-3, "still moving at {}", speed);
+        assert!((particles[0].velocity[0] / particles[0].velocity[1] + 0.75).abs() < 1e-5); // Same direction
+        assert!(config.enclosure.contains(particles[0].position[0], particles[0].position[1]));
+        assert_eq!(particles[1].velocity[0], 1.0); // Slower particles are left alone
+
+        let gentle = SimConfig { max_speed: 0.5, ..config };
+        ChunkMover::new(0..2, &gentle).step(&mut particles, TIMESTEP);
+        assert!((particles[1].velocity[0] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn verlet_keeps_moving_particles_when_one_is_spawned_into_the_chunk() {
+        let config = SimConfig { gravity: -9.81, depth: 0.0, integrator: IntegratorKind::VelocityVerlet, ..SimConfig::default() };
+        let mut particles = vec![Particle::new(2.0, 5.0, 1.0, 0.0, PARTICLE_RADIUS), Particle { id: 1, ..Particle::new(4.0, 5.0, 1.0, 0.0, PARTICLE_RADIUS) }];
+        let mut mover = ChunkMover::new(0..2, &config);
+        for _ in 0..5 {
+            mover.step(&mut particles, TIMESTEP);
+        }
+
+        particles.push(Particle { id: 2, ..Particle::new(6.0, 5.0, 1.0, 0.0, PARTICLE_RADIUS) });
+        mover.set_chunk(0..3);
+        let before = particles.clone();
+        mover.step(&mut particles, TIMESTEP);
+
+        for (moved, was) in particles.iter().zip(&before) {
+            assert!((moved.position[0] - was.position[0] - TIMESTEP).abs() < 1e-5, "particle {} went from {} to {}", was.id, was.position[0], moved.position[0]);
+            assert!(moved.velocity[1] < was.velocity[1], "particle {} didn't fall", was.id);
+        }
+    }
+
+    #[test]
+    fn verlet_keeps_energy_under_gravity_far_better_than_euler() {
+        let euler = energy_drift(IntegratorKind::Euler);
+        let verlet = energy_drift(IntegratorKind::VelocityVerlet);
+
+        assert!(euler > 1e-3, "euler drifted by {}", euler);
+        assert!(verlet < euler / 100.0, "verlet drifted by {} against euler's {}", verlet, euler);
+    }
+}
@@ -0,0 +1,108 @@
+// Writes sampled frames to a JSON lines file, one object per line, turned on with --jsonl
+//     {"frame":12,"particles":[{"id":0,"x":1.0,"y":2.0},..]}
+// with a z as well for 3D runs, so a run can be picked apart with jq or read straight into pandas
+// Frames are handed to a writer thread over a channel, so the collision thread never waits on the disk
+use crate::{axes, Particle};
+use log::warn;
+use serde::ser::{Serialize, SerializeMap, Serializer};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+// Written by hand so each axis is its own field named after it, rather than one array
+struct JsonlParticle<const N: usize> {
+    id: u64,
+    position: [f32; N],
+}
+
+impl<const N: usize> Serialize for JsonlParticle<N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(N + 1))?;
+        map.serialize_entry("id", &self.id)?;
+        for (axis, value) in self.position.iter().enumerate() {
+            map.serialize_entry(&axes::name(axis), value)?;
+        }
+        map.end()
+    }
+}
+
+#[derive(serde::Serialize)]
+struct JsonlFrame<const N: usize> {
+    frame: usize,
+    particles: Vec<JsonlParticle<N>>,
+}
+
+// The simulation's end of the file, dropping it waits for everything sent to be written
+pub struct JsonlWriter<const N: usize = 2> {
+    sender: Option<Sender<JsonlFrame<N>>>,
+    writer: Option<JoinHandle<io::Result<()>>>,
+    every: usize,
+}
+
+impl<const N: usize> JsonlWriter<N> {
+    // Create the file and start the writer thread, writing one line every `every` frames
+    pub fn create(path: &str, every: usize) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let (sender, receiver) = mpsc::channel::<JsonlFrame<N>>();
+
+        let writer = thread::spawn(move || {
+            for frame in receiver { // Ends once the sender has been dropped
+                serde_json::to_writer(&mut file, &frame)?;
+                writeln!(file)?;
+            }
+            file.flush()
+        });
+
+        Ok(JsonlWriter { sender: Some(sender), writer: Some(writer), every })
+    }
+
+    // Send a frame's positions to the writer thread if it is one to be sampled
+    pub fn publish(&self, frame: usize, particles: &[Particle<N>]) {
+        if !frame.is_multiple_of(self.every) {
+            return;
+        }
+
+        let particles = particles.iter().map(|p| JsonlParticle { id: p.id, position: p.position }).collect();
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(JsonlFrame { frame, particles }); // Only fails if the writer has stopped after an IO error, which the drop reports
+        }
+    }
+}
+
+impl<const N: usize> Drop for JsonlWriter<N> {
+    fn drop(&mut self) {
+        self.sender = None; // Hangs up the channel, which ends the writer loop
+        if let Some(writer) = self.writer.take() {
+            match writer.join() {
+                Ok(Err(error)) => warn!("Could not write JSON lines file: {}", error),
+                Err(_) => warn!("JSON lines writer thread panicked"),
+                Ok(Ok(())) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PARTICLE_RADIUS;
+
+    #[test]
+    fn sampled_frames_are_written_one_per_line() {
+        let path = std::env::temp_dir().join(format!("particles_jsonl_{}.ndjson", std::process::id()));
+        let particles = vec![Particle::new(1.0, 2.0, 0.0, 0.0, PARTICLE_RADIUS), Particle { id: 7, ..Particle::new(3.5, 4.0, 1.0, 0.0, PARTICLE_RADIUS) }];
+
+        let writer = JsonlWriter::create(path.to_str().unwrap(), 2).unwrap();
+        for frame in 0..5 {
+            writer.publish(frame, &particles);
+        }
+        drop(writer);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines : Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1], r#"{"frame":2,"particles":[{"id":0,"x":1.0,"y":2.0},{"id":7,"x":3.5,"y":4.0}]}"#);
+    }
+}
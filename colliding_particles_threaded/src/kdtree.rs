@@ -0,0 +1,129 @@
+use crate::Particle;
+
+// Balanced tree over a snapshot of particles for nearest neighbour queries, splitting on each of their N axes in turn
+pub struct KdTree<'a, const N: usize = 2> {
+    particles: &'a [Particle<N>],
+    nodes: Vec<usize>, // Particle indices, each range's median is the node splitting that range
+}
+
+impl<'a, const N: usize> KdTree<'a, N> {
+    pub fn new(particles: &'a [Particle<N>]) -> Self {
+        let mut nodes : Vec<usize> = (0..particles.len()).collect();
+        Self::build(particles, &mut nodes, 0);
+
+        KdTree { particles, nodes }
+    }
+
+    // Sort the range so its median splits it on the axis for this depth, then repeat for each half
+    fn build(particles: &[Particle<N>], nodes: &mut [usize], depth: usize) {
+        if nodes.len() <= 1 {
+            return;
+        }
+
+        let median = nodes.len() / 2;
+        let axis = depth % N;
+        nodes.select_nth_unstable_by(median, |&a, &b| particles[a].position[axis].total_cmp(&particles[b].position[axis]).then(a.cmp(&b)));
+
+        let (left, right) = nodes.split_at_mut(median);
+        Self::build(particles, left, depth + 1);
+        Self::build(particles, &mut right[1..], depth + 1);
+    }
+
+    // Index and squared distance of the closest particle to query, ignoring query itself if it is one of the tree's particles
+    // Ties are broken by picking the lowest index
+    pub fn nearest(&self, query: &Particle<N>) -> Option<(usize, f32)> {
+        let mut best : Option<(usize, f32)> = None;
+        self.search(query, 0, self.nodes.len(), 0, &mut best);
+        best
+    }
+
+    fn search(&self, query: &Particle<N>, start: usize, end: usize, depth: usize, best: &mut Option<(usize, f32)>) {
+        if start >= end {
+            return;
+        }
+
+        let median = start + (end - start) / 2;
+        let index = self.nodes[median];
+        let candidate = &self.particles[index];
+
+        if !std::ptr::eq(candidate, query) {
+            let squared_distance = candidate.squared_distance(query);
+
+            let is_better = match *best {
+                None => true,
+                Some((best_index, best_distance)) => squared_distance < best_distance || (squared_distance == best_distance && index < best_index),
+            };
+            if is_better {
+                *best = Some((index, squared_distance));
+            }
+        }
+
+        let axis = depth % N;
+        let offset = query.position[axis] - candidate.position[axis];
+        let (near, far) = if offset < 0.0 { ((start, median), (median + 1, end)) } else { ((median + 1, end), (start, median)) };
+
+        self.search(query, near.0, near.1, depth + 1, best);
+
+        // Only cross the splitting line if something on the far side could be as close, equal distances are kept for the tie break
+        if best.is_none_or(|(_, best_distance)| offset * offset <= best_distance) {
+            self.search(query, far.0, far.1, depth + 1, best);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{RngExt, SeedableRng};
+    use rand::rngs::StdRng;
+
+    fn particle(x: f32, y: f32) -> Particle {
+        Particle::new(x, y, 0.0, 0.0, 0.1)
+    }
+
+    #[test]
+    fn single_particle_has_no_neighbour() {
+        let particles = vec![particle(1.0, 1.0)];
+        let tree = KdTree::new(&particles);
+
+        assert_eq!(tree.nearest(&particles[0]), None);
+    }
+
+    #[test]
+    fn ties_pick_the_lowest_index() {
+        let particles = vec![particle(5.0, 5.0), particle(6.0, 5.0), particle(4.0, 5.0), particle(5.0, 6.0)];
+        let tree = KdTree::new(&particles);
+
+        assert_eq!(tree.nearest(&particles[0]), Some((1, 1.0)));
+    }
+
+    #[test]
+    fn nearest_uses_z_in_3d() {
+        let flat = |x: f32| Particle::new_3d(x, 5.0, 0.0, 0.0, 0.0, 0.0, 0.1);
+        let particles = vec![flat(5.0), flat(5.5), Particle::new_3d(5.0, 5.0, 0.2, 0.0, 0.0, 0.0, 0.1)];
+        let tree = KdTree::new(&particles);
+
+        assert_eq!(tree.nearest(&particles[0]).map(|(i, _)| i), Some(2));
+    }
+
+    #[test]
+    fn nearest_matches_brute_force() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let particles : Vec<Particle> = (0..500).map(|_| particle(rng.random::<f32>() * 10.0, rng.random::<f32>() * 10.0)).collect();
+        let tree = KdTree::new(&particles);
+
+        for (i, p) in particles.iter().enumerate() {
+            let mut expected : Option<(usize, f32)> = None;
+            for (j, other) in particles.iter().enumerate() {
+                let dist_x = other.position[0] - p.position[0];
+                let dist_y = other.position[1] - p.position[1];
+                let squared_distance = dist_x * dist_x + dist_y * dist_y;
+                if j != i && expected.is_none_or(|(_, best)| squared_distance < best) {
+                    expected = Some((j, squared_distance));
+                }
+            }
+
+            assert_eq!(tree.nearest(p), expected);
+        }
+    }
+}
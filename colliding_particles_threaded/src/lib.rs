@@ -0,0 +1,2464 @@
+pub mod analysis;
+pub mod atomic;
+mod axes;
+pub mod broadphase;
+pub mod ccd;
+pub mod checkpoint;
+pub mod config;
+pub mod control;
+pub mod emitter;
+pub mod energy;
+pub mod event_driven;
+pub mod events;
+pub mod float;
+pub mod forces;
+pub mod fps;
+pub mod frames;
+pub mod gnuplot;
+pub mod heatmap;
+pub mod integrator;
+pub mod jsonl;
+pub mod kdtree;
+pub mod lockstep;
+pub mod metrics;
+pub mod movement;
+pub mod outcome;
+pub mod profile;
+pub mod progress;
+pub mod render;
+pub mod replay;
+#[cfg(feature = "simd")]
+pub mod simd;
+pub mod soa;
+pub mod stream;
+pub mod trajectory;
+pub mod tui;
+pub mod walls;
+
+use broadphase::{make_strip_detector, CollisionDetector, GridShape, SpatialGrid};
+use ccd::SweptDetector;
+use config::SimConfig;
+use control::{RunControl, Ticker};
+use emitter::Emitter;
+use energy::{kinetic_energy, EnergyLog};
+use events::{CollisionEvent, CollisionLog};
+use float::Float;
+use integrator::ChunkMover;
+use fps::{FpsMonitor, ThreadCounters};
+use frames::{frame_channel, FrameReceiver, FrameSender};
+use heatmap::Heatmap;
+use jsonl::JsonlWriter;
+use lockstep::SyncMode;
+use metrics::{save_metrics, MetricsSampler};
+use outcome::CollisionOutcome;
+use profile::{LockProfile, LockProfiler, LockProfiles};
+use progress::{ProgressMonitor, StepCounter};
+use render::Renderer;
+use stream::FrameStreamer;
+use trajectory::{TrajectoryHandle, TrajectoryRecorder};
+use tui::TerminalView;
+use walls::{Wall, WallCounter, WallCounts, WallHits};
+use log::{debug, log_enabled, trace, warn, Level};
+use rand::{random, Rng, RngExt, SeedableRng};
+use serde::{Deserialize, Serialize};
+use rand::rngs::StdRng;
+use threadpool::ThreadPool;
+use std::any::Any;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+pub const THREAD_COUNT : usize = 10; // Move threads when the number of cores can't be found
+pub const COLLISION_THREAD_COUNT : usize = 1;
+pub const PARTICLE_COUNT : usize = 100;
+
+pub const ENCLOSURE_W : f32 = 10.0;
+pub const ENCLOSURE_H : f32 = 10.0;
+pub const ENCLOSURE_D : f32 = 0.0; // Zero keeps the simulation flat, any depth turns on 3D mode
+
+pub const PARTICLE_RADIUS : f32 = 0.05; // Two particles collide when closer than the sum of their radii, 0.1 by default
+
+pub const MAX_INITIAL_SPEED : f32 = 1.0;
+pub const PARTICLE_DENSITY : f32 = 1.0; // Mass per unit area, so a particle twice the radius is four times as heavy
+
+// Simulation values, these are the defaults and can be changed from the command line
+pub const SIMULATION_TIME : Duration = Duration::from_secs(10);
+pub const TIMESTEP : f32 = 0.01;
+pub const GRAVITY : f32 = 0.0; // Added to every vertical velocity per second, negative pulls particles down
+pub const REPULSION_STRENGTH : f32 = 0.0; // Zero turns the repulsion pass off
+pub const REPULSION_CUTOFF : f32 = 0.5;
+pub const MAX_SPEED : f32 = 100.0; // Fastest a particle moves after a step, a whole unit per timestep
+pub const DRAG : f32 = 0.0; // Fraction of its velocity a particle loses per second to the medium it moves through, zero turns it off
+pub const RECORD_EVERY_FRAMES : u32 = 10;
+pub const RENDER_EVERY_FRAMES : usize = 10;
+pub const PIXELS_PER_UNIT : f32 = 50.0;
+pub const STREAM_FRAMES_PER_SECOND : f32 = 30.0;
+const FRAME_WAIT_SLICE : Duration = Duration::from_millis(20); // Longest a collision thread waits for a frame before looking to see if the run was paused
+
+// A particle has one position and velocity value per axis, N of them: x and y in 2D, then z in 3D
+// Every axis goes through the same loops, so flat and deep systems run the same code without a z to carry round in 2D
+// Simulated in f32 unless another float type is given, the threaded runs and everything they write out use f32
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Particle<const N: usize = 2, F = f32> {
+    pub id: u64, // Stays with the particle for its whole life, unlike its index which changes as particles come and go
+    #[serde(with = "axes")]
+    pub position: [F; N],
+    #[serde(with = "axes")]
+    pub velocity: [F; N],
+    pub radius: F,
+    pub mass: F,
+    #[serde(default)] // Checkpoints from before species were added are all species 0
+    pub species: u8,
+}
+
+pub type ParticleF32<const N: usize = 2> = Particle<N, f32>;
+pub type ParticleF64<const N: usize = 2> = Particle<N, f64>;
+
+impl<F: Float> Particle<2, F> {
+    // Particles made outside a system have id 0, the system gives each of its own a unique one
+    pub fn new(x: F, y: F, vx: F, vy: F, radius: F) -> Self {
+        Particle::at([x, y], [vx, vy], radius)
+    }
+}
+
+impl<F: Float> Particle<3, F> {
+    pub fn new_3d(x: F, y: F, z: F, vx: F, vy: F, vz: F, radius: F) -> Self {
+        Particle::at([x, y, z], [vx, vy, vz], radius)
+    }
+}
+
+// The sum of a and b multiplied axis by axis, added up from the first axis so 2D and 3D round the same way on x and y
+pub(crate) fn dot<const N: usize, F: Float>(a: &[F; N], b: &[F; N]) -> F {
+    a.iter().zip(b).fold(F::zero(), |sum, (&a, &b)| sum + a * b)
+}
+
+impl<const N: usize, F: Float> Particle<N, F> {
+    // Mass defaults to the area of the particle times PARTICLE_DENSITY
+    // Anything with fewer than two axes has no x and y for the enclosure to hold in
+    pub fn at(position: [F; N], velocity: [F; N], radius: F) -> Self {
+        assert!(N >= 2, "particles need at least an x and a y, not {} axes", N);
+        Particle { id: 0, position, velocity, radius, mass: F::from_f32(PARTICLE_DENSITY) * F::PI() * radius * radius, species: 0 }
+    }
+
+    // Advance the particle along its velocity over the timestep dt
+    pub fn integrate(&mut self, dt: F) {
+        for (position, &velocity) in self.position.iter_mut().zip(&self.velocity) {
+            *position += velocity * dt;
+        }
+    }
+
+    pub fn speed(&self) -> F {
+        self.speed_squared().sqrt()
+    }
+
+    pub fn speed_squared(&self) -> F {
+        dot(&self.velocity, &self.velocity)
+    }
+
+    // Scale the velocity down to max_speed if it is any faster, keeping its direction
+    pub fn clamp_speed(&mut self, max_speed: F) {
+        let speed = self.speed();
+        if speed > max_speed {
+            let scale = max_speed / speed;
+            for velocity in &mut self.velocity {
+                *velocity *= scale;
+            }
+        }
+    }
+
+    // Bounce the particle off the enclosure walls, reversing its velocity away from any wall it has passed
+    // Periodic boundaries wrap x and y round to the other side instead, every axis after those always has walls at 0 and depth
+    // A depth of zero is a flat enclosure, with nothing to bounce off past x and y
+    // Returns the walls it bounced off, which is none at all if it didn't reach one
+    pub fn apply_boundary(&mut self, enclosure: &Enclosure, boundary: BoundaryMode, depth: f32) -> WallHits {
+        let mut hits = match boundary {
+            BoundaryMode::Reflect => enclosure.reflect(self),
+            BoundaryMode::Periodic => enclosure.wrap(self),
+        };
+        if depth > 0.0 {
+            for axis in 2..N {
+                reflect_axis(&mut self.position[axis], &mut self.velocity[axis], F::from_f32(depth), (Wall::Back, Wall::Front), &mut hits);
+            }
+        }
+        hits
+    }
+
+    // Summed over every axis
+    pub fn squared_distance(&self, other: &Particle<N, F>) -> F {
+        let difference : [F; N] = std::array::from_fn(|axis| self.position[axis] - other.position[axis]);
+        dot(&difference, &difference)
+    }
+
+    // Compare the distance between two particles, if the distance is less than the sum of their radii, they have collided
+    // (both sides are squared which saves square rooting the distance)
+    // Particles exactly touching, the sum of their radii apart, haven't collided
+    pub fn perform_collision_check(&self, other_particle: &Particle<N, F>) -> bool {
+        self.squared_distance(other_particle) < (self.radius + other_particle.radius).powi(2)
+    }
+
+    // As perform_collision_check, but measured the shortest way round if the enclosure's edges wrap
+    pub fn perform_collision_check_wrapped(&self, other_particle: &Particle<N, F>, wrap: Option<&Enclosure>) -> bool {
+        let squared_distance = match wrap {
+            Some(enclosure) => wrapped_distance_sq(self, other_particle, enclosure),
+            None => self.squared_distance(other_particle),
+        };
+        squared_distance < (self.radius + other_particle.radius).powi(2)
+    }
+
+    // Elastic collision between two particles, only the velocity components along the line between their centres change
+    // Each particle's share of the change is weighted by the other's mass, so momentum and kinetic energy are both conserved
+    // Overlapping particles are also pushed apart along the line between them until they just touch, half the overlap each,
+    // otherwise a pair that didn't separate in a step would be found overlapping and counted again in the next frame
+    pub fn resolve_collision(&mut self, other: &mut Particle<N, F>) {
+        let distance = self.squared_distance(other).sqrt();
+        let two = F::from_f32(2.0);
+
+        if distance == F::zero() { // Coincident particles have no line between them to collide along
+            return;
+        }
+
+        let normal : [F; N] = std::array::from_fn(|axis| (other.position[axis] - self.position[axis]) / distance);
+
+        let penetration = self.radius + other.radius - distance;
+        if penetration > F::zero() {
+            let push = penetration / two;
+            for (axis, &along) in normal.iter().enumerate() {
+                self.position[axis] -= push * along;
+                other.position[axis] += push * along;
+            }
+        }
+
+        let self_normal_v = dot(&self.velocity, &normal);
+        let other_normal_v = dot(&other.velocity, &normal);
+
+        if self_normal_v - other_normal_v <= F::zero() { // Already moving apart, so don't pull them back together
+            return;
+        }
+
+        let approach = self_normal_v - other_normal_v;
+        let total_mass = self.mass + other.mass;
+        let self_change = two * other.mass / total_mass * approach;
+        let other_change = two * self.mass / total_mass * approach;
+
+        for (axis, &along) in normal.iter().enumerate() {
+            self.velocity[axis] -= self_change * along;
+            other.velocity[axis] += other_change * along;
+        }
+    }
+
+    // This particle moved by whole enclosure widths and heights to whichever of its periodic images is nearest to, so the two
+    // can be collided or merged as if the edge between them wasn't there, the axes after x and y have walls so they are never moved
+    pub fn nearest_image_to(&self, to: &Particle<N, F>, enclosure: &Enclosure) -> Particle<N, F> {
+        let nearest = |position: F, target: F, size: F| position - size * ((position - target) / size).round();
+        let mut image = *self;
+        image.position[0] = nearest(self.position[0], to.position[0], F::from_f32(enclosure.width()));
+        image.position[1] = nearest(self.position[1], to.position[1], F::from_f32(enclosure.height()));
+        image
+    }
+
+    // The one particle two colliding particles become when they stick together, keeping this one's id and species
+    // It sits at their centre of mass with their combined mass and momentum, and its area is theirs added together,
+    // so two particles of the same density make one of that density too
+    pub fn merged_with(&self, other: &Particle<N, F>) -> Particle<N, F> {
+        let mass = self.mass + other.mass;
+        let weighted = |mine: &[F; N], theirs: &[F; N]| std::array::from_fn(|axis| (mine[axis] * self.mass + theirs[axis] * other.mass) / mass);
+
+        Particle {
+            id: self.id,
+            position: weighted(&self.position, &other.position),
+            velocity: weighted(&self.velocity, &other.velocity),
+            radius: (self.radius * self.radius + other.radius * other.radius).sqrt(),
+            mass,
+            species: self.species,
+        }
+    }
+}
+
+// Squared distance between the nearest images of a and b in a periodic enclosure, so particles either side of an edge are close
+// Only x and y wrap, the other axes are measured directly
+pub fn wrapped_distance_sq<const N: usize, F: Float>(a: &Particle<N, F>, b: &Particle<N, F>, enclosure: &Enclosure) -> F {
+    let nearest = |difference: F, size: F| {
+        let difference = difference.abs() % size;
+        difference.min(size - difference)
+    };
+
+    let mut difference : [F; N] = std::array::from_fn(|axis| a.position[axis] - b.position[axis]);
+    difference[0] = nearest(difference[0], F::from_f32(enclosure.width()));
+    difference[1] = nearest(difference[1], F::from_f32(enclosure.height()));
+    dot(&difference, &difference)
+}
+
+// reflect_into_range in place, noting which of the axis' walls at 0 and max was passed first
+fn reflect_axis<F: Float>(position: &mut F, velocity: &mut F, max: F, (low, high): (Wall, Wall), hits: &mut WallHits) {
+    if *position < F::zero() {
+        hits.add(low);
+    } else if *position > max {
+        hits.add(high);
+    }
+    (*position, *velocity) = reflect_into_range(*position, *velocity, max);
+}
+
+// Reflect a position back into 0..max, folding it back and forth so a large overshoot still lands inside
+fn reflect_into_range<F: Float>(position: F, velocity: F, max: F) -> (F, F) {
+    if position >= F::zero() && position <= max {
+        return (position, velocity);
+    }
+
+    let span = F::from_f32(2.0) * max;
+    let folded = position.rem_euclid(&span);
+    if folded > max { // An odd number of walls were passed, so the particle is now heading the other way
+        (span - folded, -velocity)
+    } else {
+        (folded, velocity)
+    }
+}
+
+// The walls in x and y, 3D systems add flat walls in z on top of either shape
+// A circle sits in the square from (0, 0) to twice its radius, so positions are never negative whichever shape is used
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Enclosure {
+    Rect { w: f32, h: f32 },
+    Circle { radius: f32 },
+}
+
+impl Default for Enclosure {
+    fn default() -> Self {
+        Enclosure::Rect { w: ENCLOSURE_W, h: ENCLOSURE_H }
+    }
+}
+
+impl Enclosure {
+    // The box the enclosure fits in, which grids and images are sized to
+    pub fn width(&self) -> f32 {
+        match *self {
+            Enclosure::Rect { w, .. } => w,
+            Enclosure::Circle { radius } => radius * 2.0,
+        }
+    }
+
+    pub fn height(&self) -> f32 {
+        match *self {
+            Enclosure::Rect { h, .. } => h,
+            Enclosure::Circle { radius } => radius * 2.0,
+        }
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        match *self {
+            Enclosure::Rect { w, h } => (0.0..=w).contains(&x) && (0.0..=h).contains(&y),
+            Enclosure::Circle { radius } => (x - radius).powi(2) + (y - radius).powi(2) <= radius * radius,
+        }
+    }
+
+    // The nearest point inside to (x, y), which is (x, y) itself if it's already inside
+    pub fn clamp(&self, x: f32, y: f32) -> (f32, f32) {
+        match *self {
+            Enclosure::Rect { w, h } => (x.clamp(0.0, w), y.clamp(0.0, h)),
+            Enclosure::Circle { radius } => {
+                let (dist_x, dist_y) = (x - radius, y - radius);
+                let distance = (dist_x * dist_x + dist_y * dist_y).sqrt();
+                if distance <= radius {
+                    return (x, y);
+                }
+                (radius + dist_x / distance * radius, radius + dist_y / distance * radius)
+            }
+        }
+    }
+
+    // The box a grid over the enclosure covers along each of N axes, the depth of a flat enclosure is zero
+    pub fn extent<const N: usize>(&self, depth: f32) -> [f32; N] {
+        std::array::from_fn(|axis| match axis {
+            0 => self.width(),
+            1 => self.height(),
+            _ => depth,
+        })
+    }
+
+    // A point picked uniformly inside, with every axis after x and y picked uniformly up to depth
+    // Points outside a circle are thrown away and picked again, a rectangle always takes the first one
+    pub fn random_position<const N: usize, R: Rng + ?Sized>(&self, depth: f32, rng: &mut R) -> [f32; N] {
+        let extent = self.extent::<N>(depth);
+        loop {
+            let position : [f32; N] = std::array::from_fn(|axis| if axis < 2 || depth > 0.0 { rng.random::<f32>() * extent[axis] } else { 0.0 });
+            if self.contains(position[0], position[1]) {
+                return position;
+            }
+        }
+    }
+
+    // Move a particle that has left one side of a rectangle in through the opposite side, keeping its velocity
+    // Circles have no opposite side, so periodic circles are rejected by the config and just reflect here
+    fn wrap<const N: usize, F: Float>(&self, p: &mut Particle<N, F>) -> WallHits {
+        match *self {
+            Enclosure::Rect { w, h } => {
+                p.position[0] = wrap_into_range(p.position[0], F::from_f32(w));
+                p.position[1] = wrap_into_range(p.position[1], F::from_f32(h));
+                WallHits::default()
+            }
+            Enclosure::Circle { .. } => self.reflect(p),
+        }
+    }
+
+    // Bring a particle that has passed a wall back inside, heading away from the wall
+    fn reflect<const N: usize, F: Float>(&self, p: &mut Particle<N, F>) -> WallHits {
+        let mut hits = WallHits::default();
+        let two = F::from_f32(2.0);
+        match *self {
+            Enclosure::Rect { w, h } => {
+                reflect_axis(&mut p.position[0], &mut p.velocity[0], F::from_f32(w), (Wall::Left, Wall::Right), &mut hits);
+                reflect_axis(&mut p.position[1], &mut p.velocity[1], F::from_f32(h), (Wall::Bottom, Wall::Top), &mut hits);
+            }
+            Enclosure::Circle { radius } => {
+                let radius = F::from_f32(radius);
+                let (dist_x, dist_y) = (p.position[0] - radius, p.position[1] - radius);
+                let distance = (dist_x * dist_x + dist_y * dist_y).sqrt();
+                if distance <= radius {
+                    return hits;
+                }
+                hits.add(Wall::Round);
+
+                // Reflect the velocity across the wall's normal where the particle crossed it, if it's still heading out
+                let (normal_x, normal_y) = (dist_x / distance, dist_y / distance);
+                let outward_v = p.velocity[0] * normal_x + p.velocity[1] * normal_y;
+                if outward_v > F::zero() {
+                    p.velocity[0] -= two * outward_v * normal_x;
+                    p.velocity[1] -= two * outward_v * normal_y;
+                }
+
+                // Fold the overshoot back inside along the normal, one that crosses the whole dish stops at the centre
+                let inside = (two * radius - distance).max(F::zero());
+                p.position[0] = radius + normal_x * inside;
+                p.position[1] = radius + normal_y * inside;
+            }
+        }
+        hits
+    }
+}
+
+// What happens to a particle reaching the edge of the enclosure
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BoundaryMode {
+    Reflect, // Bounces off the walls
+    Periodic, // Leaves one side and comes back in the opposite one, only for rectangles
+}
+
+impl BoundaryMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "reflect" => Some(BoundaryMode::Reflect),
+            "periodic" => Some(BoundaryMode::Periodic),
+            _ => None,
+        }
+    }
+}
+
+// Wrap a position into 0..max, rounding keeps a position just below zero from landing exactly on max
+fn wrap_into_range<F: Float>(position: F, max: F) -> F {
+    let wrapped = position.rem_euclid(&max);
+    if wrapped >= max { F::zero() } else { wrapped }
+}
+
+// How particle radii are picked when a system is created, a fixed radius gives every particle the same size
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum RadiusDistribution {
+    Fixed(f32),
+    Uniform { min: f32, max: f32 },
+}
+
+impl RadiusDistribution {
+    pub fn sample(&self, rng: &mut StdRng) -> f32 {
+        match *self {
+            RadiusDistribution::Fixed(radius) => radius,
+            RadiusDistribution::Uniform { min, max } => min + rng.random::<f32>() * (max - min),
+        }
+    }
+
+    // The largest radius a particle can be given, which sets how far apart a broadphase has to look
+    pub fn max(&self) -> f32 {
+        match *self {
+            RadiusDistribution::Fixed(radius) => radius,
+            RadiusDistribution::Uniform { max, .. } => max,
+        }
+    }
+}
+
+// One kind of particle in a system made of several, numbered by its place in the list it was given in
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Species {
+    pub count: usize,
+    pub radius: RadiusDistribution,
+    pub mass: Option<f32>, // Every particle of the species weighs this much, or it goes by area like any other particle
+}
+
+// The species of two colliding particles, lower first so (0, 1) and (1, 0) are counted together
+pub type SpeciesPair = (u8, u8);
+
+pub fn species_pair<const N: usize, F>(a: &Particle<N, F>, b: &Particle<N, F>) -> SpeciesPair {
+    (a.species.min(b.species), a.species.max(b.species))
+}
+
+// Particles are kept in order of id, so they can be looked up by id with a binary search
+#[derive(Clone)]
+pub struct ParticleSystem<const N: usize = 2, F = f32> {
+    pub particles: Vec<Particle<N, F>>,
+    next_id: u64, // Only ever goes up, so an id is never handed out twice even once its particle has gone
+}
+
+// A 3D system is built with ParticleSystemBuilder::<3>::default() instead
+impl ParticleSystem {
+    pub fn builder() -> ParticleSystemBuilder {
+        ParticleSystemBuilder::default()
+    }
+}
+
+impl<const N: usize> ParticleSystem<N> {
+    pub fn total_kinetic_energy(&self) -> f32 {
+        kinetic_energy(&self.particles)
+    }
+
+    // Weighted by mass
+    pub fn centre_of_mass(&self) -> [f32; N] {
+        metrics::centre_of_mass(&self.particles)
+    }
+
+    pub fn centre_of_mass_velocity(&self) -> [f32; N] {
+        metrics::centre_of_mass_velocity(&self.particles)
+    }
+
+    // Put the particles with ids a and b back where they touched if they passed through each other in the last dt
+    pub fn rewind_to_contact(&mut self, a: u64, b: u64, dt: f32) {
+        let (i, j) = match (self.index_of(a), self.index_of(b)) {
+            (Some(i), Some(j)) if i != j => (i.min(j), i.max(j)),
+            _ => return,
+        };
+
+        let (head, tail) = self.particles.split_at_mut(j);
+        ccd::rewind_to_contact(&mut head[i], &mut tail[0], dt);
+    }
+
+    // Replace every velocity with one drawn from the Maxwell-Boltzmann distribution at temperature, in units where Boltzmann's constant is 1
+    // Each component is Gaussian with variance temperature / mass, so heavy particles move slower and the average kinetic energy
+    // is half the temperature per dimension
+    pub fn thermalize(&mut self, temperature: f32, rng: &mut impl Rng) {
+        for p in &mut self.particles {
+            let spread = (temperature / p.mass).sqrt();
+            for velocity in &mut p.velocity {
+                *velocity = gaussian(rng) * spread;
+            }
+        }
+    }
+
+    // Ids of the particles whose centres are inside the rectangle with corners min and max, edges included, lowest first
+    // The corners can be given either way round, and one with no area finds the particles exactly on it
+    // A rectangle outside the enclosure only finds particles that have strayed out there, and one with a corner that isn't a number finds nothing
+    // This looks at every particle, for many queries on the same frame rebuild a SpatialGrid once and use query_region_in
+    pub fn query_region(&self, min: (f32, f32), max: (f32, f32)) -> Vec<u64> {
+        match region_bounds(min, max) {
+            Some(bounds) => self.particles.iter().filter(|p| in_region(p, bounds)).map(|p| p.id).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // The same as query_region, but only searching the cells of a grid last rebuilt with these particles that the rectangle overlaps
+    pub fn query_region_in(&self, grid: &SpatialGrid<N>, min: (f32, f32), max: (f32, f32)) -> Vec<u64> {
+        grid.query_region(&self.particles, min, max).into_iter().map(|i| self.particles[i].id).collect()
+    }
+
+    // Log every particle's position at trace level, six to a line
+    pub fn debug_print_particles(& self) {
+        if !log_enabled!(Level::Trace) {
+            return;
+        }
+
+        for row in self.particles.chunks(6) {
+            let line : Vec<String> = row.iter().map(|p| format!("{} : x {} y {}", p.id, p.position[0], p.position[1])).collect();
+            trace!("{}", line.join(" | "));
+        }
+    }
+}
+
+// Looking particles up by id and taking them in and out work the same whatever float type they are simulated in
+impl<const N: usize, F: Float> ParticleSystem<N, F> {
+    // The particles must already be in order of id, new ones are spawned after the highest
+    pub fn new(particles: Vec<Particle<N, F>>) -> Self {
+        let next_id = particles.last().map_or(0, |last| last.id + 1);
+        ParticleSystem { particles, next_id }
+    }
+
+    // The id the next spawned particle will be given
+    pub fn next_id(&self) -> u64 {
+        self.next_id
+    }
+
+    fn index_of(&self, id: u64) -> Option<usize> {
+        self.particles.binary_search_by_key(&id, |p| p.id).ok()
+    }
+
+    pub fn by_id(&self, id: u64) -> Option<&Particle<N, F>> {
+        self.index_of(id).map(|i| &self.particles[i])
+    }
+
+    // Add a particle to the system, giving it an id no particle has had before, which is returned
+    // During a run take the write lock first, the move threads pick up the new particle in their chunks the next time they take it
+    pub fn spawn(&mut self, p: Particle<N, F>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.particles.push(Particle { id, ..p }); // Still in order of id
+        id
+    }
+
+    // Take the particle with this id out of the system, returning whether there was one
+    pub fn remove(&mut self, id: u64) -> bool {
+        match self.index_of(id) {
+            Some(i) => {
+                self.particles.remove(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Take both particles out of the system, but only if neither has already gone, returning whether they were
+    pub fn annihilate(&mut self, a: u64, b: u64) -> bool {
+        if a == b || self.index_of(a).is_none() || self.index_of(b).is_none() {
+            return false;
+        }
+        self.remove(a) && self.remove(b)
+    }
+
+    // Merge two particles into one that keeps the lower of their ids, but only if neither has already gone, returning whether they were
+    // If the enclosure's edges wrap, a pair either side of an edge merges at their centre of mass the short way round, back inside the enclosure
+    pub fn merge(&mut self, a: u64, b: u64, wrap: Option<&Enclosure>) -> bool {
+        let (kept, merged) = match (self.index_of(a.min(b)), self.index_of(a.max(b))) {
+            (Some(kept), Some(merged)) if kept != merged => (kept, merged),
+            _ => return false,
+        };
+
+        self.particles[kept] = match wrap {
+            Some(enclosure) => {
+                let mut particle = self.particles[kept].merged_with(&self.particles[merged].nearest_image_to(&self.particles[kept], enclosure));
+                enclosure.wrap(&mut particle);
+                particle
+            }
+            None => self.particles[kept].merged_with(&self.particles[merged]),
+        };
+        self.particles.remove(merged);
+        true
+    }
+
+    // Resolve a collision between the particles with ids a and b, doing nothing if either has gone
+    // If the enclosure's edges wrap, a pair either side of an edge bounces along the short way between them and is pushed apart across the edge
+    pub fn resolve_collision(&mut self, a: u64, b: u64, wrap: Option<&Enclosure>) {
+        let (i, j) = match (self.index_of(a), self.index_of(b)) {
+            (Some(i), Some(j)) if i != j => (i.min(j), i.max(j)),
+            _ => return,
+        };
+
+        let (head, tail) = self.particles.split_at_mut(j);
+        match wrap {
+            Some(enclosure) => {
+                let mut image = tail[0].nearest_image_to(&head[i], enclosure);
+                head[i].resolve_collision(&mut image);
+                enclosure.wrap(&mut head[i]);
+                enclosure.wrap(&mut image);
+                tail[0] = image;
+            }
+            None => head[i].resolve_collision(&mut tail[0]),
+        }
+    }
+}
+
+// The lower and upper corners of a query rectangle given either way round, or None if any corner isn't a number
+pub(crate) fn region_bounds(min: (f32, f32), max: (f32, f32)) -> Option<((f32, f32), (f32, f32))> {
+    if [min.0, min.1, max.0, max.1].iter().any(|v| v.is_nan()) {
+        return None;
+    }
+    Some(((min.0.min(max.0), min.1.min(max.1)), (min.0.max(max.0), min.1.max(max.1))))
+}
+
+pub(crate) fn in_region<const N: usize>(p: &Particle<N>, (min, max): ((f32, f32), (f32, f32))) -> bool {
+    p.position[0] >= min.0 && p.position[0] <= max.0 && p.position[1] >= min.1 && p.position[1] <= max.1
+}
+
+pub const RING_RADIUS : f32 = 0.4; // Of the circle layout, as a fraction of the enclosure's smaller side
+pub const CLUSTER_RADIUS : f32 = 0.125; // Of each of the two clusters, as a fraction of the enclosure's smaller side
+
+// Where particles start off in the enclosure
+// Serialised by the same names from_name takes
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub enum Layout {
+    #[serde(rename = "origin")] Origin, // All in the corner at (0, 0), or the middle of a circle, so every pair overlaps on the first frame
+    #[serde(rename = "random")] RandomUniform,
+    #[serde(rename = "grid")] Grid, // Evenly spaced, one particle per cell
+    #[serde(rename = "circle")] Circle, // Evenly spaced round a ring about the middle of the enclosure
+    #[serde(rename = "two-clusters")] TwoClusters, // Half scattered in a ball on the left and half on the right, to watch them spread into each other
+}
+
+impl Layout {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "origin" => Some(Layout::Origin),
+            "random" => Some(Layout::RandomUniform),
+            "grid" => Some(Layout::Grid),
+            "circle" => Some(Layout::Circle),
+            "two-clusters" => Some(Layout::TwoClusters),
+            _ => None,
+        }
+    }
+}
+
+// Sets up a ParticleSystem of particles with N axes, anything not given falls back to the defaults in the constants
+pub struct ParticleSystemBuilder<const N: usize = 2> {
+    particle_count: usize,
+    enclosure: Enclosure,
+    depth: f32,
+    radius: RadiusDistribution,
+    species: Vec<Species>,
+    seed: Option<u64>,
+    layout: Layout,
+}
+
+impl<const N: usize> Default for ParticleSystemBuilder<N> {
+    fn default() -> Self {
+        ParticleSystemBuilder {
+            particle_count: PARTICLE_COUNT,
+            enclosure: Enclosure::default(),
+            depth: ENCLOSURE_D,
+            radius: RadiusDistribution::Fixed(PARTICLE_RADIUS),
+            species: Vec::new(),
+            seed: None,
+            layout: Layout::Origin,
+        }
+    }
+}
+
+impl<const N: usize> ParticleSystemBuilder<N> {
+    pub fn particle_count(mut self, particle_count: usize) -> Self {
+        self.particle_count = particle_count;
+        self
+    }
+
+    // Particles are only ever placed inside the enclosure's walls
+    pub fn enclosure(mut self, enclosure: Enclosure) -> Self {
+        self.enclosure = enclosure;
+        self
+    }
+
+    // How far the axes after x and y go, a 2D system has none so it makes no difference to one
+    pub fn depth(mut self, depth: f32) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    pub fn radius(mut self, radius: RadiusDistribution) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    // Build each species in turn, the first count particles are species 0 and so on
+    // Replaces the particle count and radius, an empty list goes back to them
+    pub fn species(mut self, species: &[Species]) -> Self {
+        self.species = species.to_vec();
+        if !species.is_empty() {
+            self.particle_count = species.iter().map(|s| s.count).sum();
+        }
+        self
+    }
+
+    // Without a seed every build is different
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn initial_layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    // The same settings and seed always give the same starting particles
+    // Only systems with a depth are given any velocity past x and y
+    pub fn build(self) -> ParticleSystem<N> {
+        let mut rng = StdRng::seed_from_u64(self.seed.unwrap_or_else(random));
+        let is_deep = self.depth > 0.0;
+        let mut created_particles = Vec::new();
+        let mut grid = if self.layout == Layout::Grid { self.grid_positions() } else { Vec::new() }.into_iter();
+
+        // The species of each particle in turn, or every particle species 0 sized by the builder's radius
+        let default_species = Species { count: self.particle_count, radius: self.radius, mass: None };
+        let species_list = if self.species.is_empty() { std::slice::from_ref(&default_species) } else { &self.species[..] };
+        let species_of = species_list.iter().enumerate().flat_map(|(index, species)| std::iter::repeat_n((index as u8, species), species.count));
+
+        for (i, (species_index, species)) in species_of.enumerate() {
+            let velocity : [f32; N] = std::array::from_fn(|axis| if axis < 2 || is_deep { (rng.random::<f32>() * 2.0 - 1.0) * MAX_INITIAL_SPEED } else { 0.0 });
+            let radius = species.radius.sample(&mut rng);
+            let position = match self.layout {
+                Layout::Origin => self.origin(),
+                Layout::RandomUniform => self.enclosure.random_position(self.depth, &mut rng),
+                Layout::Grid => grid.next().expect("grid_positions gives one cell per particle"),
+                Layout::Circle => self.ring_position(i),
+                Layout::TwoClusters => self.cluster_position(i, &mut rng),
+            };
+
+            let particle = Particle::at(position, velocity, radius);
+            created_particles.push(Particle { id: i as u64, species: species_index, mass: species.mass.unwrap_or(particle.mass), ..particle });
+        }
+
+        ParticleSystem::new(created_particles)
+    }
+
+    fn origin(&self) -> [f32; N] {
+        let mut origin = [0.0; N];
+        if let Enclosure::Circle { radius } = self.enclosure {
+            origin[0] = radius;
+            origin[1] = radius;
+        }
+        origin
+    }
+
+    fn middle(&self) -> [f32; N] {
+        self.enclosure.extent::<N>(self.depth).map(|length| length / 2.0)
+    }
+
+    // The ith of the particles spaced evenly round the ring, starting on its right and going anticlockwise
+    fn ring_position(&self, i: usize) -> [f32; N] {
+        let mut position = self.middle();
+        let radius = RING_RADIUS * self.enclosure.width().min(self.enclosure.height());
+        let angle = std::f32::consts::TAU * i as f32 / self.particle_count as f32;
+        position[0] += radius * angle.cos();
+        position[1] += radius * angle.sin();
+        position
+    }
+
+    // The first half of the particles go in the cluster a quarter of the way across, the rest in the one three quarters across
+    // Each is a disc, or a ball squashed to fit the depth in 3D, with the particles spread evenly through it
+    fn cluster_position<R: Rng + ?Sized>(&self, i: usize, rng: &mut R) -> [f32; N] {
+        let mut centre = self.middle();
+        centre[0] = self.enclosure.width() * if i < self.particle_count / 2 { 0.25 } else { 0.75 };
+        let radius = CLUSTER_RADIUS * self.enclosure.width().min(self.enclosure.height());
+        let depth_radius = radius.min(self.depth / 2.0);
+
+        // Pick points in the cube around the unit ball until one lands inside it
+        loop {
+            let offset : [f32; N] = std::array::from_fn(|axis| if axis < 2 || self.depth > 0.0 { rng.random::<f32>() * 2.0 - 1.0 } else { 0.0 });
+            if dot(&offset, &offset) <= 1.0 {
+                return std::array::from_fn(|axis| centre[axis] + offset[axis] * if axis < 2 { radius } else { depth_radius });
+            }
+        }
+    }
+
+    // Cell centres for every particle, roughly square cells with at least as many cells as particles
+    // A circle is tiled like its bounding square with the cells outside it skipped, adding cells until enough are left
+    fn grid_positions(&self) -> Vec<[f32; N]> {
+        let extent = self.enclosure.extent::<N>(self.depth);
+        let is_deep = N > 2 && self.depth > 0.0;
+
+        // The same number of cells along every axis of a deep system, the fewest whose N-th power holds every particle
+        let mut counts = [1; N];
+        if is_deep {
+            let mut side : usize = 1;
+            while side.pow(N as u32) < self.particle_count {
+                side += 1;
+            }
+            counts = [side; N];
+        } else {
+            let (width, height) = (extent[0], extent[1]);
+            counts[0] = ((self.particle_count as f32 * width / height).sqrt().ceil().max(1.0)) as usize;
+            counts[1] = self.particle_count.div_ceil(counts[0]);
+        }
+
+        loop {
+            let shape = GridShape::new(counts);
+            let cells : Vec<[f32; N]> = (0..shape.len())
+                .map(|index| {
+                    let cell = shape.cell_at(index);
+                    std::array::from_fn(|axis| (cell[axis] as f32 + 0.5) * extent[axis] / counts[axis] as f32)
+                })
+                .filter(|position: &[f32; N]| self.enclosure.contains(position[0], position[1]))
+                .take(self.particle_count)
+                .collect();
+
+            if cells.len() == self.particle_count {
+                return cells;
+            }
+
+            for (axis, count) in counts.iter_mut().enumerate() {
+                if axis < 2 || is_deep {
+                    *count += 1;
+                }
+            }
+        }
+    }
+}
+
+// Move all particles along their velocities, bouncing them off the enclosure walls
+// Gravity is applied before moving, so a particle resting on the floor is pulled into it and bounced straight back out
+pub fn move_particles<const N: usize, F: Float>(particle_list: &mut[Particle<N, F>], dt: F, gravity: F, enclosure: &Enclosure, boundary: BoundaryMode, depth: f32){
+    for p in particle_list {
+        p.velocity[1] += gravity * dt;
+        p.integrate(dt);
+        p.apply_boundary(enclosure, boundary, depth);
+    }
+}
+
+// A sample from the standard normal distribution, by the Box-Muller transform
+pub(crate) fn gaussian<R: Rng + ?Sized>(rng: &mut R) -> f32 {
+    let u1 = 1.0 - rng.random::<f32>(); // In (0, 1], so the log is finite
+    let u2 = rng.random::<f32>();
+    (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos()
+}
+
+// One move thread per core the machine says it can give this process, falling back to THREAD_COUNT
+pub fn default_thread_count() -> usize {
+    std::thread::available_parallelism().map_or(THREAD_COUNT, |count| count.get())
+}
+
+// Split 0..len into one contiguous range per thread with no gaps or overlap
+// The remainder is spread one each over the first threads, so no chunk is more than one particle bigger than another
+// With more threads than particles the last threads are given empty chunks
+pub fn chunk_ranges(len: usize, thread_count: usize) -> Vec<Range<usize>> {
+    (0..thread_count).map(|index| ChunkShare { index, count: thread_count }.range(len)).collect()
+}
+
+// One move thread's share of the particles, the index-th of count chunks
+// Move threads work their range out again every time they take the lock, so spawning or removing particles in between just
+// moves the chunk boundaries rather than leaving a chunk pointing past the end
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChunkShare {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl ChunkShare {
+    pub fn range(&self, len: usize) -> Range<usize> {
+        let (chunk_size, remainder) = (len / self.count, len % self.count);
+        let start = self.index * chunk_size + self.index.min(remainder);
+        let end = start + chunk_size + usize::from(self.index < remainder);
+        start..end
+    }
+}
+
+// What a move thread passes on after each of its moves
+// Every move thread counts its bounces off the walls and its frames, only the first is given the publishers and progress
+pub struct MoveOutputs<const N: usize = 2> {
+    pub publishers: Vec<FrameSender<N>>, // Publishes a copy of every particle to each collision thread
+    pub progress: Option<StepCounter>,
+    pub emitter: Option<Emitter<N>>, // Adds particles as this thread counts its iterations
+    pub walls: WallCounter,
+    pub frames: Option<ThreadCounters>, // Ticked at this thread's share index every iteration
+}
+
+impl<const N: usize> Default for MoveOutputs<N> {
+    fn default() -> Self {
+        MoveOutputs { publishers: Vec::new(), progress: None, emitter: None, walls: WallCounter::default(), frames: None }
+    }
+}
+
+// A move thread's copy of its chunk, taken under the read lock so the step itself runs without holding any lock
+// When the forces read other chunks the copy is of every particle, though only the chunk's are stepped
+// Kept between steps, so once running taking a copy doesn't allocate
+pub(crate) struct ChunkCopy<const N: usize = 2> {
+    particles: Vec<Particle<N>>,
+    start: usize, // Where the chunk begins in particles
+    original: Vec<Particle<N>>, // The chunk as copied, to tell what the step changed
+}
+
+impl<const N: usize> Default for ChunkCopy<N> {
+    fn default() -> Self {
+        ChunkCopy { particles: Vec::new(), start: 0, original: Vec::new() }
+    }
+}
+
+impl<const N: usize> ChunkCopy<N> {
+    pub(crate) fn take(&mut self, particles: &[Particle<N>], chunk: Range<usize>, everything: bool) {
+        let copied = if everything { 0..particles.len() } else { chunk.clone() };
+        self.start = chunk.start - copied.start;
+        self.particles.clear();
+        self.particles.extend_from_slice(&particles[copied]);
+        self.original.clear();
+        self.original.extend_from_slice(&particles[chunk]);
+    }
+
+    // Everything copied, with the chunk at chunk_range
+    pub(crate) fn particles_mut(&mut self) -> &mut [Particle<N>] {
+        &mut self.particles
+    }
+
+    pub(crate) fn chunk_range(&self) -> Range<usize> {
+        self.start..self.start + self.original.len()
+    }
+
+    pub(crate) fn chunk(&self) -> &[Particle<N>] {
+        &self.particles[self.chunk_range()]
+    }
+
+    pub(crate) fn chunk_mut(&mut self) -> &mut [Particle<N>] {
+        let chunk = self.chunk_range();
+        &mut self.particles[chunk]
+    }
+
+    // Write the stepped chunk back under the write lock, matching by id as particles may have been spawned or removed since the copy
+    // A particle left alone meanwhile takes its stepped values exactly, while one a collision thread changed, by bouncing it say,
+    // keeps that and has the step's own change to its position and velocity added on
+    // Either way keep_inside then sees where it ended up, as the step's change added to a bounced particle can take it past a wall
+    pub(crate) fn merge_into(&self, particles: &mut [Particle<N>], mut keep_inside: impl FnMut(&mut Particle<N>)) {
+        for (was, now) in self.original.iter().zip(self.chunk()) {
+            let p = match particles.binary_search_by_key(&was.id, |p| p.id) {
+                Ok(i) => &mut particles[i],
+                Err(_) => continue, // Gone since the copy
+            };
+            if p == was {
+                *p = *now;
+            } else {
+                for axis in 0..N {
+                    p.position[axis] += now.position[axis] - was.position[axis];
+                    p.velocity[axis] += now.velocity[axis] - was.velocity[axis];
+                }
+            }
+            keep_inside(p);
+        }
+    }
+}
+
+// Ballistic movement uses no randomness and the random models are seeded from the config, so a seeded chunk advances the same way on every run
+// Returns how many iterations it managed, stopping early if the run is stopped, or the panic that stopped it
+// Time spent paused doesn't count towards the run's length
+pub fn move_thread_main<const N: usize>(particle_system: Arc<RwLock<ParticleSystem<N>>>, share: ChunkShare, config: SimConfig, recorder: Option<TrajectoryHandle<N>>, mut outputs: MoveOutputs<N>, control: RunControl, mut profiler: LockProfiler) -> Result<u32, SimError> {
+    let mut iterations: u32 = 0;
+    let mut start_time = Instant::now();
+    let mut mover = ChunkMover::new(share.range(read_ignoring_poison(&particle_system).particles.len()), &config).with_wall_counter(outputs.walls.clone());
+    let mut ticker = Ticker::new(config.tick_hz);
+    let mut copy = ChunkCopy::default();
+
+    while config.keep_running(iterations, start_time) && !control.is_stopped() {
+        // Step a fresh copy of the chunk, so velocities the collision threads changed since the last iteration are picked up
+        // and other move threads can copy and step their own chunks at the same time
+        // The walls wait until the copy is merged back, in case a collision thread bounced one of its particles meanwhile
+        catch_panic(|| {
+            let everything = mover.reads_other_chunks();
+            let chunk = profiler.hold(|| read_ignoring_poison(&particle_system), |system| {
+                let chunk = share.range(system.particles.len());
+                copy.take(&system.particles, chunk.clone(), everything);
+                chunk
+            });
+            mover.set_chunk(chunk);
+            let stepped = copy.chunk_range();
+            mover.step_without_walls(copy.particles_mut(), stepped, TIMESTEP);
+
+            profiler.hold(|| write_ignoring_poison(&particle_system), |mut system| {
+                copy.merge_into(&mut system.particles, |p| mover.keep_inside(p));
+
+                if let Some(recorder) = &recorder {
+                    let chunk = share.range(system.particles.len());
+                    recorder.record(iterations, &system.particles[chunk]);
+                }
+
+                if let Some(emitter) = &mut outputs.emitter {
+                    emitter.emit(iterations, &mut system);
+                }
+
+                for publisher in &mut outputs.publishers {
+                    publisher.publish(&system.particles);
+                }
+            })
+        }).map_err(|message| SimError::MoveThreadPanicked { chunk: mover.chunk(), message })?;
+
+        if let Some(progress) = &outputs.progress {
+            progress.tick();
+        }
+        if let Some(frames) = &outputs.frames {
+            frames.tick(share.index);
+        }
+
+        iterations+=1;
+        start_time += control.wait_while_paused();
+        ticker.wait();
+    }
+
+    let chunk = mover.chunk();
+    debug!("Move thread for particles {} to {} finished after {} iterations", chunk.start, chunk.end, iterations);
+    Ok(iterations)
+}
+
+// A worker thread that panicked, the rest of the run carries on without it
+#[derive(Debug, Clone, PartialEq)]
+pub enum SimError {
+    MoveThreadPanicked { chunk: Range<usize>, message: String }, // The chunk's particles stop where the panic left them
+    CollisionThreadPanicked { message: String }, // Its counts are lost
+}
+
+impl fmt::Display for SimError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimError::MoveThreadPanicked { chunk, message } => write!(f, "Move thread for particles {} to {} panicked: {}", chunk.start, chunk.end, message),
+            SimError::CollisionThreadPanicked { message } => write!(f, "Collision thread panicked: {}", message),
+        }
+    }
+}
+
+// The text a panic was raised with, if it had any
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().map_or_else(|| "unknown panic".to_string(), |message| message.to_string()),
+    }
+}
+
+// Run f, returning the message of any panic instead of unwinding the thread
+pub(crate) fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(panic_message)
+}
+
+// A thread that panics while holding the lock leaves every particle as a valid, if half moved, value
+// so the poison is ignored rather than passed on to every other thread
+pub(crate) fn write_ignoring_poison<T>(lock: &RwLock<T>) -> std::sync::RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn read_ignoring_poison<T>(lock: &RwLock<T>) -> std::sync::RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn lock_ignoring_poison<T>(lock: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    lock.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// Counts from one collision thread
+#[derive(Debug, Clone, Default)]
+pub struct CollisionStats {
+    pub frames: usize, // Snapshots checked
+    pub collision_count: usize, // Distinct collisions, counted when a pair first starts overlapping
+    pub overlapping_frame_count: usize, // Every frame each pair spends overlapping
+    pub by_species: BTreeMap<SpeciesPair, usize>, // Distinct collisions split by the species of the two particles
+    pub annihilations: usize, // Pairs that disappeared rather than bouncing
+    pub merges: usize, // Pairs that stuck together as one particle
+    pub cluster_collisions: usize, // Pairs found closer than the cluster epsilon, counted once rather than as collisions
+}
+
+impl CollisionStats {
+    fn add(mut self, other: CollisionStats) -> CollisionStats {
+        for (species, count) in other.by_species {
+            *self.by_species.entry(species).or_insert(0) += count;
+        }
+
+        CollisionStats {
+            frames: self.frames + other.frames,
+            collision_count: self.collision_count + other.collision_count,
+            overlapping_frame_count: self.overlapping_frame_count + other.overlapping_frame_count,
+            by_species: self.by_species,
+            annihilations: self.annihilations + other.annihilations,
+            merges: self.merges + other.merges,
+            cluster_collisions: self.cluster_collisions + other.cluster_collisions,
+        }
+    }
+
+    // Count one snapshot's overlapping pairs, by id with their species, as new collisions unless they were overlapping in the snapshot before
+    pub(crate) fn count_overlaps(&mut self, overlaps: &[((u64, u64), SpeciesPair)], previous_overlaps: &mut HashSet<(u64, u64)>) {
+        self.overlapping_frame_count += overlaps.len();
+
+        for (ids, species) in overlaps {
+            if !previous_overlaps.contains(ids) {
+                self.collision_count += 1;
+                *self.by_species.entry(*species).or_insert(0) += 1;
+            }
+        }
+
+        *previous_overlaps = overlaps.iter().map(|&(ids, _)| ids).collect();
+    }
+}
+
+// Every pair of particles colliding in this snapshot with their species, lower index first, sorted and without duplicates
+// Pairs are resolved in this order, so three or more particles touching at once end up the same with any detector or thread count
+pub fn detect_collisions<const N: usize, D: CollisionDetector<N> + ?Sized>(particles: &[Particle<N>], detector: &mut D) -> Vec<(usize, usize, SpeciesPair)> {
+    let mut colliding_pairs = detector.detect(particles);
+
+    colliding_pairs.sort_unstable(); // Makes the result identical whichever detector, or how many threads, found the pairs
+    colliding_pairs.dedup();
+    colliding_pairs.into_iter().map(|(i, j)| (i, j, species_pair(&particles[i], &particles[j]))).collect()
+}
+
+// Where a collision thread writes what it sees, anything left as None isn't written
+// The heatmap and collision log can be shared between threads, but only one thread should be given the others so frames aren't written twice
+pub struct CollisionOutputs<const N: usize = 2> {
+    pub renderer: Option<Renderer>,
+    pub heatmap: Option<Arc<Mutex<Heatmap>>>,
+    pub energy: Option<Arc<Mutex<EnergyLog>>>,
+    pub stream: Option<FrameStreamer<N>>,
+    pub jsonl: Option<JsonlWriter<N>>,
+    pub collisions: Option<Arc<Mutex<CollisionLog>>>,
+}
+
+impl<const N: usize> Default for CollisionOutputs<N> {
+    fn default() -> Self {
+        CollisionOutputs { renderer: None, heatmap: None, energy: None, stream: None, jsonl: None, collisions: None }
+    }
+}
+
+// Tracks collisions across the snapshots one collision thread checks, passing each snapshot on to its outputs as it goes
+// New collisions are added to the heatmap at the midpoint between the two particles, and to the collision log with when they happened
+// With continuous detection, pairs that passed through each other since the last snapshot count as colliding too
+// With a cluster epsilon, a pair found sitting almost on top of each other is counted once as a cluster collision and left out of
+// everything else until it stops overlapping, rather than being bounced and counted over and over while it can't get apart
+pub(crate) struct CollisionTracker<const N: usize = 2, D: CollisionDetector<N> + ?Sized = dyn CollisionDetector<N> + Send> {
+    detector: Box<D>,
+    swept: Option<SweptDetector<N>>,
+    tunnelled: Vec<(u64, u64)>, // Found by the swept detector in the last snapshot
+    renderer: Option<Renderer>,
+    heatmap: Option<Arc<Mutex<Heatmap>>>,
+    energy: Option<Arc<Mutex<EnergyLog>>>,
+    stream: Option<FrameStreamer<N>>,
+    jsonl: Option<JsonlWriter<N>>,
+    collisions: Option<Arc<Mutex<CollisionLog>>>,
+    render_every: usize,
+    stats: CollisionStats,
+    previous_overlaps: HashSet<(u64, u64)>,
+    cluster_epsilon: Option<f32>,
+    clustered: HashSet<(u64, u64)>, // Pairs being skipped, as of the last snapshot
+}
+
+impl<const N: usize, D: CollisionDetector<N> + ?Sized> CollisionTracker<N, D> {
+    pub(crate) fn new(detector: Box<D>, outputs: CollisionOutputs<N>, render_every: usize) -> Self {
+        let CollisionOutputs { renderer, heatmap, energy, stream, jsonl, collisions } = outputs;
+        CollisionTracker { detector, swept: None, tunnelled: Vec::new(), renderer, heatmap, energy, stream, jsonl, collisions, render_every, stats: CollisionStats::default(), previous_overlaps: HashSet::new(), cluster_epsilon: None, clustered: HashSet::new() }
+    }
+
+    // Also look for pairs that passed through each other in the dt before each snapshot
+    pub(crate) fn with_ccd(mut self, dt: f32) -> Self {
+        self.swept = Some(SweptDetector::new(dt));
+        self
+    }
+
+    // Skip pairs whose centres come closer than epsilon, as above
+    pub(crate) fn with_cluster_epsilon(mut self, epsilon: f32) -> Self {
+        self.cluster_epsilon = Some(epsilon);
+        self
+    }
+
+    // Take out of pairs any that are clustered, counting those that only just came within the epsilon
+    // A pair stays clustered for as long as it keeps overlapping, however far apart its centres get in the meantime
+    fn skip_clusters(&mut self, particles: &[Particle<N>], pairs: &mut Vec<(usize, usize, SpeciesPair)>, epsilon: f32) {
+        let (clustered, stats) = (&self.clustered, &mut self.stats);
+        let mut still_clustered = HashSet::new();
+        pairs.retain(|&(i, j, _)| {
+            let ids = (particles[i].id, particles[j].id);
+            let skip = clustered.contains(&ids) || particles[i].squared_distance(&particles[j]) < epsilon * epsilon;
+            if skip {
+                if !clustered.contains(&ids) {
+                    stats.cluster_collisions += 1;
+                }
+                still_clustered.insert(ids);
+            }
+            !skip
+        });
+        self.clustered = still_clustered;
+    }
+
+    // Check one snapshot, returning the ids of every colliding pair for the caller to resolve
+    pub(crate) fn check(&mut self, particles: &[Particle<N>]) -> Vec<(u64, u64)> {
+        let mut colliding_pairs = detect_collisions(particles, self.detector.as_mut());
+        self.tunnelled.clear();
+        if let Some(swept) = &mut self.swept {
+            let tunnelled = swept.tunnelled_pairs(particles);
+            self.tunnelled.extend(tunnelled.iter().map(|&(i, j, _)| (particles[i].id, particles[j].id)));
+            colliding_pairs.extend(tunnelled);
+            colliding_pairs.sort_unstable(); // Neither detector finds a pair the other does, as one only finds overlaps and the other only pairs that aren't
+        }
+        if let Some(epsilon) = self.cluster_epsilon {
+            self.skip_clusters(particles, &mut colliding_pairs, epsilon);
+        }
+
+        let frame = self.stats.frames;
+        if let Some(energy) = &self.energy {
+            lock_ignoring_poison(energy).record(frame, particles); // Before anything is bounced, which doesn't change it anyway
+        }
+        if let Some(stream) = &mut self.stream {
+            stream.publish(frame, particles);
+        }
+        if let Some(jsonl) = &self.jsonl {
+            jsonl.publish(frame, particles);
+        }
+        if frame.is_multiple_of(self.render_every) {
+            if let Some(r) = &mut self.renderer {
+                if let Err(error) = r.render_frame(particles, &colliding_pairs, frame / self.render_every + 1) {
+                    warn!("Stopped rendering: {}", error);
+                    self.renderer = None;
+                }
+            }
+        }
+        self.stats.frames += 1;
+
+        // From here pairs are tracked by id, as the live system's indices may not match the snapshot's
+        let overlaps : Vec<((u64, u64), SpeciesPair)> = colliding_pairs.iter().map(|&(i, j, species)| ((particles[i].id, particles[j].id), species)).collect();
+
+        if let Some(heatmap) = &self.heatmap {
+            let mut heatmap = lock_ignoring_poison(heatmap);
+            for (&(i, j, _), (ids, _)) in colliding_pairs.iter().zip(&overlaps) {
+                if !self.previous_overlaps.contains(ids) {
+                    heatmap.record((particles[i].position[0] + particles[j].position[0]) * 0.5, (particles[i].position[1] + particles[j].position[1]) * 0.5);
+                }
+            }
+        }
+        if let Some(collisions) = &self.collisions {
+            let mut collisions = lock_ignoring_poison(collisions);
+            for &((a, b), _) in overlaps.iter().filter(|(ids, _)| !self.previous_overlaps.contains(ids)) {
+                collisions.record(frame, a, b);
+            }
+        }
+        self.stats.count_overlaps(&overlaps, &mut self.previous_overlaps);
+
+        overlaps.into_iter().map(|(ids, _)| ids).collect()
+    }
+
+    // Resolve the pairs the last check found with the config's outcome
+    // Pairs that passed through each other are put back where they touched first, so a bounce sends them back the way they came
+    pub(crate) fn resolve(&mut self, system: &mut ParticleSystem<N>, pairs: Vec<(u64, u64)>, config: &SimConfig) {
+        if let Some(swept) = &self.swept {
+            for &(a, b) in &self.tunnelled {
+                system.rewind_to_contact(a, b, swept.dt());
+            }
+        }
+        let resolved = config.outcome.resolve(system, pairs, config.wrap());
+        self.count_resolved(config.outcome, resolved);
+    }
+
+    // Count the pairs an outcome other than bouncing took out of the system
+    fn count_resolved(&mut self, outcome: CollisionOutcome, count: usize) {
+        match outcome {
+            CollisionOutcome::Bounce => {}
+            CollisionOutcome::Annihilate => self.stats.annihilations += count,
+            CollisionOutcome::Merge => self.stats.merges += count,
+        }
+    }
+
+    pub(crate) fn stats(&self) -> CollisionStats {
+        self.stats.clone()
+    }
+}
+
+// Runs until out of time, stopped, or the move threads finish, returning what it counted or the panic that stopped it
+// With a step count there is no time limit, and it runs until the move threads have done their steps
+// Any detector will do, make_detector picks the one the config asks for
+pub fn collision_thread_main<const N: usize, D: CollisionDetector<N> + ?Sized>(particle_system: Arc<RwLock<ParticleSystem<N>>>, mut frames: FrameReceiver<N>, detector: Box<D>, config: SimConfig, outputs: CollisionOutputs<N>, control: RunControl, mut profiler: LockProfiler) -> Result<CollisionStats, SimError> {
+    catch_panic(|| check_frames(&particle_system, &mut frames, detector, &config, outputs, &control, &mut profiler)).map_err(|message| SimError::CollisionThreadPanicked { message })
+}
+
+fn check_frames<const N: usize, D: CollisionDetector<N> + ?Sized>(particle_system: &RwLock<ParticleSystem<N>>, frames: &mut FrameReceiver<N>, detector: Box<D>, config: &SimConfig, outputs: CollisionOutputs<N>, control: &RunControl, profiler: &mut LockProfiler) -> CollisionStats {
+    let mut start_time = Instant::now();
+    let mut tracker = CollisionTracker::new(detector, outputs, config.render_every);
+    if config.ccd {
+        tracker = tracker.with_ccd(TIMESTEP);
+    }
+    if let Some(epsilon) = config.cluster_epsilon {
+        tracker = tracker.with_cluster_epsilon(epsilon);
+    }
+
+    let run_time = match config.steps {
+        Some(_) => Duration::MAX,
+        None => config.duration,
+    };
+
+    loop {
+        start_time += control.wait_while_paused();
+        let remaining = match run_time.checked_sub(start_time.elapsed()) {
+            Some(remaining) if !control.is_stopped() => remaining,
+            _ => break,
+        };
+
+        // Wait for the next frame from the move threads, used as a "snapshot" of collisions occuring
+        // The frame's buffer is recycled, so no lock is taken and nothing is allocated to read the particles
+        // The wait is cut short every so often, so a pause that starts while waiting is noticed
+        let particles = match frames.latest(remaining.min(FRAME_WAIT_SLICE)) {
+            Ok(particles) => particles,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break, // The move threads have finished
+        };
+
+        let colliding_ids = tracker.check(particles);
+
+        if !colliding_ids.is_empty() {
+            // Lock for write access to bounce, or otherwise resolve, the colliding particles
+            profiler.hold(|| write_ignoring_poison(particle_system), |mut system| tracker.resolve(&mut system, colliding_ids, config));
+        }
+    }
+
+    let stats = tracker.stats();
+    debug!("Collision thread finished after checking {} frames", stats.frames);
+    stats
+}
+
+// Everything a finished run produces, Display gives a summary with one "name: value" per line
+pub struct SimReport<const N: usize = 2> {
+    pub config: SimConfig, // What the run was asked to do
+    pub seed: u64,
+    pub total_frames: usize, // Snapshots checked, summed over every collision thread
+    pub unique_collisions: usize,
+    pub raw_collision_frames: usize, // Every frame each pair spent overlapping
+    pub annihilations: usize, // Colliding pairs that disappeared, only ever above 0 with the annihilate outcome
+    pub merges: usize, // Colliding pairs that became one particle, only ever above 0 with the merge outcome
+    pub cluster_collisions: usize, // Pairs counted once for coming within the cluster epsilon, only ever above 0 with one set
+    pub collisions_by_species: BTreeMap<SpeciesPair, usize>,
+    pub max_energy_drift: f64, // Furthest the total kinetic energy got from where it started, as a fraction of it
+    pub walls: WallCounts, // Bounces off the enclosure's walls, counted by every move thread
+    pub move_iterations: Vec<u32>, // One per move thread
+    pub avg_move_iterations_per_thread: f64,
+    pub wall_clock: Duration,
+    pub system: ParticleSystem<N>, // The particles as they were when the threads stopped
+    pub errors: Vec<SimError>, // Threads that panicked, empty if the run went cleanly
+    pub lock_profiles: Vec<LockProfile>, // One per thread that takes the lock, empty unless profiling
+    pub recent_collisions: Vec<CollisionEvent>, // The last few collisions, oldest first, as many as the config asks for
+}
+
+impl<const N: usize> SimReport<N> {
+    // Everything Display shows but the particles, as one JSON object with the config the run was given under "config"
+    // Durations are in seconds, and each species pair is a [a, b] array next to its count
+    pub fn to_json(&self) -> String {
+        let collisions_by_species : Vec<_> = self.collisions_by_species.iter().map(|(&(a, b), &count)| serde_json::json!({ "species": [a, b], "collisions": count })).collect();
+        let lock_profiles : Vec<_> = self.lock_profiles.iter().map(|profile| serde_json::json!({
+            "thread": profile.thread,
+            "acquisitions": profile.acquisitions,
+            "waiting_seconds": profile.waiting.as_secs_f64(),
+            "holding_seconds": profile.holding.as_secs_f64(),
+        })).collect();
+
+        serde_json::json!({
+            "config": self.config,
+            "seed": self.seed,
+            "wall_clock_seconds": self.wall_clock.as_secs_f64(),
+            "move_iterations": self.move_iterations,
+            "avg_move_iterations_per_thread": self.avg_move_iterations_per_thread,
+            "total_frames": self.total_frames,
+            "unique_collisions": self.unique_collisions,
+            "raw_collision_frames": self.raw_collision_frames,
+            "annihilations": self.annihilations,
+            "merges": self.merges,
+            "cluster_collisions": self.cluster_collisions,
+            "collisions_by_species": collisions_by_species,
+            "max_energy_drift": self.max_energy_drift,
+            "wall_collisions": self.walls.total(),
+            "wall_collisions_by_wall": self.walls.hit().map(|(wall, count)| (wall.name().to_string(), count.into())).collect::<serde_json::Map<_, _>>(),
+            "lock_profiles": lock_profiles,
+            "recent_collisions": self.recent_collisions,
+            "errors": self.errors.iter().map(SimError::to_string).collect::<Vec<_>>(),
+        }).to_string()
+    }
+}
+
+impl<const N: usize> fmt::Display for SimReport<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "seed: {}", self.seed)?;
+        writeln!(f, "wall_clock_seconds: {:.3}", self.wall_clock.as_secs_f64())?;
+        writeln!(f, "move_threads: {}", self.move_iterations.len())?;
+        writeln!(f, "avg_move_iterations_per_thread: {:.1}", self.avg_move_iterations_per_thread)?;
+        writeln!(f, "total_frames: {}", self.total_frames)?;
+        writeln!(f, "unique_collisions: {}", self.unique_collisions)?;
+        writeln!(f, "raw_collision_frames: {}", self.raw_collision_frames)?;
+        match self.config.outcome {
+            CollisionOutcome::Bounce => {}
+            CollisionOutcome::Annihilate => writeln!(f, "annihilations: {}", self.annihilations)?,
+            CollisionOutcome::Merge => writeln!(f, "merges: {}", self.merges)?,
+        }
+        if self.config.cluster_epsilon.is_some() {
+            writeln!(f, "cluster_collisions: {}", self.cluster_collisions)?;
+        }
+        writeln!(f, "max_energy_drift: {:.6}", self.max_energy_drift)?;
+        write!(f, "wall_collisions: {}", self.walls.total())?;
+        for (wall, count) in self.walls.hit() {
+            write!(f, "\nwall_collisions_{}: {}", wall.name(), count)?;
+        }
+        // Only broken down when there is more than one species to break it down by
+        if self.collisions_by_species.keys().any(|&pair| pair != (0, 0)) {
+            let total = |same: bool| self.collisions_by_species.iter().filter(|((a, b), _)| (a == b) == same).map(|(_, count)| count).sum::<usize>();
+            write!(f, "\nsame_species_collisions: {}", total(true))?;
+            write!(f, "\ncross_species_collisions: {}", total(false))?;
+            for ((a, b), count) in &self.collisions_by_species {
+                write!(f, "\ncollisions_between_species_{}_and_{}: {}", a, b, count)?;
+            }
+        }
+        if !self.lock_profiles.is_empty() {
+            let total = |duration: fn(&LockProfile) -> Duration| self.lock_profiles.iter().map(duration).sum::<Duration>().as_secs_f64();
+            write!(f, "\nlock_waiting_seconds: {:.3}", total(|profile| profile.waiting))?;
+            write!(f, "\nlock_holding_seconds: {:.3}", total(|profile| profile.holding))?;
+            for profile in &self.lock_profiles {
+                write!(f, "\nlock: {}", profile)?;
+            }
+        }
+        for event in &self.recent_collisions {
+            write!(f, "\nrecent_collision: frame {} at {:.3}s between {} and {}", event.frame, event.time.as_secs_f64(), event.a, event.b)?;
+        }
+        for error in &self.errors {
+            write!(f, "\nerror: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+// The particles a run with this config and seed starts from
+fn starting_system<const N: usize>(config: &SimConfig, seed: u64) -> ParticleSystem<N> {
+    let mut system = ParticleSystemBuilder::default()
+        .particle_count(config.particle_count)
+        .enclosure(config.enclosure)
+        .depth(config.depth)
+        .radius(config.radius)
+        .species(&config.species)
+        .initial_layout(config.layout)
+        .seed(seed)
+        .build();
+
+    // Drawn from a different stream than the builder's, so the speeds don't line up with the positions
+    if let Some(temperature) = config.temperature {
+        system.thermalize(temperature, &mut StdRng::seed_from_u64(!seed));
+    }
+    system
+}
+
+// Every move thread runs its own wall-clock loop, with the collision threads checking whichever frame was published last
+fn run_free_running<const N: usize>(particle_system: &Arc<RwLock<ParticleSystem<N>>>, config: &SimConfig, recorder: Option<&TrajectoryRecorder<N>>, outputs: CollisionOutputs<N>, control: &RunControl, profiles: &LockProfiles, progress: &StepCounter) -> RunOutcome {
+    let pool = ThreadPool::new(config.thread_count); // Create thread pool
+    let collision_pool = ThreadPool::new(config.collision_thread_count);
+
+    // One frame channel per collision thread, all fed by the first move thread
+    let (mut frame_senders, frame_receivers) : (Vec<FrameSender<N>>, Vec<FrameReceiver<N>>) = (0..config.collision_thread_count).map(|_| frame_channel()).unzip();
+
+    // Instance the move threads, each with its own share of the particles and its own slot for its iteration count
+    let walls = WallCounter::default();
+    let frame_counts = ThreadCounters::new(config.thread_count);
+    let fps = config.fps.then(|| FpsMonitor::start(frame_counts.clone()));
+    let emitter = config.emitter.map(|settings| Emitter::new(settings, config));
+    let mut first_move_outputs = Some(MoveOutputs { publishers: std::mem::take(&mut frame_senders), progress: Some(progress.clone()), emitter, walls: walls.clone(), frames: Some(frame_counts.clone()) });
+    let move_results = Arc::new(Mutex::new((0..config.thread_count).map(|_| Ok(0)).collect::<Vec<_>>()));
+    for index in 0..config.thread_count {
+        let share = ChunkShare { index, count: config.thread_count };
+        let system_clone = Arc::clone(particle_system);
+        let results = Arc::clone(&move_results);
+
+        let config_clone = config.clone();
+        let recorder_handle = recorder.map(TrajectoryRecorder::handle);
+        let move_outputs = first_move_outputs.take().unwrap_or_else(|| MoveOutputs { walls: walls.clone(), frames: Some(frame_counts.clone()), ..MoveOutputs::default() });
+        let control = control.clone();
+        let profiler = profiles.profiler(&format!("move thread {}", index));
+
+        pool.execute(move || {
+            let result = move_thread_main(system_clone, share, config_clone, recorder_handle, move_outputs, control, profiler);
+            lock_ignoring_poison(&results)[index] = result;
+        });
+    }
+
+    // Instance the collision checking threads, each sending back its counts when it finishes
+    // Each checks its own strip of the enclosure, so their counts add up without any pair being counted twice
+    // Only the first is given the renderer and energy log, the rest just share the heatmap and collision log
+    let shared_heatmap = outputs.heatmap.clone();
+    let shared_collisions = outputs.collisions.clone();
+    let mut first_outputs = Some(outputs);
+    let (stats_sender, stats_receiver) = mpsc::channel();
+    for (index, frames) in frame_receivers.into_iter().enumerate() {
+        let system_clone = Arc::clone(particle_system);
+        let config_clone = config.clone();
+        let outputs = first_outputs.take().unwrap_or_else(|| CollisionOutputs { heatmap: shared_heatmap.clone(), collisions: shared_collisions.clone(), ..CollisionOutputs::default() });
+        let stats_sender = stats_sender.clone();
+        let detector = make_strip_detector(config.broadphase, config, index, config.collision_thread_count);
+        let control = control.clone();
+        let profiler = profiles.profiler(&format!("collision thread {}", index));
+
+        collision_pool.execute(move || {
+            let _ = stats_sender.send(collision_thread_main(system_clone, frames, detector, config_clone, outputs, control, profiler));
+        });
+    }
+
+    drop(stats_sender);
+
+    pool.join();
+    collision_pool.join();
+    drop(fps);
+
+    let move_results = std::mem::take(&mut *lock_ignoring_poison(&move_results));
+    RunOutcome::new(move_results, stats_receiver.iter().collect(), walls.counts())
+}
+
+// What the threads of a run sent back, with any that panicked split out into errors
+pub(crate) struct RunOutcome {
+    collisions: CollisionStats,
+    move_iterations: Vec<u32>, // A move thread that panicked counts as 0
+    walls: WallCounts,
+    errors: Vec<SimError>,
+}
+
+impl RunOutcome {
+    pub(crate) fn new(move_results: Vec<Result<u32, SimError>>, collision_results: Vec<Result<CollisionStats, SimError>>, walls: WallCounts) -> Self {
+        let mut errors = Vec::new();
+
+        let move_iterations = move_results.into_iter().map(|result| result.unwrap_or_else(|error| {
+            errors.push(error);
+            0
+        })).collect();
+
+        let collisions = collision_results.into_iter().fold(CollisionStats::default(), |total, result| match result {
+            Ok(stats) => total.add(stats),
+            Err(error) => {
+                errors.push(error);
+                total
+            }
+        });
+
+        RunOutcome { collisions, move_iterations, walls, errors }
+    }
+}
+
+// Write the gnuplot script for a CSV that has just been saved, if --gnuplot asked for them
+fn write_gnuplot_script(config: &SimConfig, csv: &str, script: impl FnOnce() -> String) {
+    if config.gnuplot {
+        if let Err(error) = gnuplot::write_script(csv, &script()) {
+            warn!("Could not write gnuplot script for {}: {}", csv, error);
+        }
+    }
+}
+
+// Run a whole simulation on thread pools sized by the config, returning once every thread has finished
+// A thread that panics is listed in the report's errors, and the others carry on without it
+// A trajectory file or render directory that can't be created is reported and skipped rather than stopping the run
+// In barrier mode a seeded run always takes the same steps, though how many fit in the time depends on the machine
+// With a step count as well, which --deterministic insists on, the report's counts and particles are the same on every run
+// Free-running, seeding only fixes the starting state, as scheduling changes how moves and collision checks interleave
+// N is how many axes the particles have, 2 for a flat config and 3 or more for one with a depth
+pub fn run_simulation<const N: usize>(config: &SimConfig) -> SimReport<N> {
+    run_simulation_until(config, RunControl::default())
+}
+
+// As run_simulation, but every thread also finishes early once the control is stopped, e.g. from a Ctrl-C handler
+// The report then covers however far the run got, and time spent paused doesn't count towards the run's length
+pub fn run_simulation_until<const N: usize>(config: &SimConfig, control: RunControl) -> SimReport<N> {
+    assert!(config.is_3d() == (N > 2), "A config with a depth of {} can't be run with {} axes", config.depth, N);
+    let start_time = Instant::now();
+    let seed = config.seed.unwrap_or_else(random);
+    let config = &SimConfig { seed: Some(seed), ..config.clone() }; // So the movement models' streams come from the seed that is reported
+    if config.event_driven {
+        return event_driven::run_event_driven_simulation(config, &control);
+    }
+
+    let particle_system = Arc::new(RwLock::new(starting_system(config, seed)));
+
+    let recorder = match &config.record_path {
+        Some(path) => match TrajectoryRecorder::create(path, config.record_every) {
+            Ok(recorder) => Some(recorder),
+            Err(error) => {
+                warn!("Could not create trajectory file {}, not recording: {}", path, error);
+                None
+            }
+        },
+        None => None,
+    };
+
+    // 3D systems are drawn looking down the z axis
+    let renderer = match &config.render_dir {
+        Some(dir) => match Renderer::new(dir, config.enclosure.width(), config.enclosure.height(), PIXELS_PER_UNIT, config.trails, config.speed_colours) {
+            Ok(renderer) => Some(renderer),
+            Err(error) => {
+                warn!("Could not create render directory {}, not rendering: {}", dir, error);
+                None
+            }
+        },
+        None => None,
+    };
+
+    // Shared by every collision thread if there's more than one
+    let heatmap = config.heatmap_path.as_ref().map(|_| Arc::new(Mutex::new(Heatmap::new(config.enclosure.width(), config.enclosure.height()))));
+
+    // Always kept for the drift in the report, and only written out if there is a path for it
+    let energy = Arc::new(Mutex::new(EnergyLog::new(config.record_every as usize)));
+    // Also kept just for the recent collisions in the report, then only as long as it needs to be for them
+    let collision_log = match (&config.collision_log_path, config.recent_collisions) {
+        (Some(_), recent) => Some(config.collision_log_capacity.max(recent)),
+        (None, 0) => None,
+        (None, recent) => Some(recent),
+    }.map(|capacity| Arc::new(Mutex::new(CollisionLog::new(capacity))));
+
+    let stream = match config.serve_port {
+        Some(port) => match FrameStreamer::bind(port, config.serve_fps) {
+            Ok(stream) => Some(stream),
+            Err(error) => {
+                warn!("Could not listen on port {}, not streaming: {}", port, error);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let jsonl = match &config.jsonl_path {
+        Some(path) => match JsonlWriter::create(path, config.jsonl_every) {
+            Ok(jsonl) => Some(jsonl),
+            Err(error) => {
+                warn!("Could not create JSON lines file {}, not writing it: {}", path, error);
+                None
+            }
+        },
+        None => None,
+    };
+
+    let outputs = CollisionOutputs { renderer, heatmap: heatmap.clone(), energy: Some(Arc::clone(&energy)), stream, jsonl, collisions: collision_log.clone() };
+    let profiles = LockProfiles::new(config.profile);
+    let view = config.tui.then(|| TerminalView::start(Arc::clone(&particle_system), config.enclosure, config.trails, control.clone()));
+    let progress = StepCounter::default();
+    let monitor = config.progress.then(|| ProgressMonitor::start(config, progress.clone()));
+    let sampler = config.metrics_path.as_ref().map(|_| MetricsSampler::start(Arc::clone(&particle_system), config));
+
+    let RunOutcome { collisions, move_iterations, walls, errors } = match config.sync {
+        SyncMode::Barrier => lockstep::run_lockstep(&particle_system, config, recorder.as_ref(), outputs, &control, &profiles, &progress),
+        SyncMode::FreeRunning => run_free_running(&particle_system, config, recorder.as_ref(), outputs, &control, &profiles, &progress),
+    };
+    drop(view); // Give the terminal back before anything else is printed
+    drop(monitor);
+
+    if let (Some(path), Some(sampler)) = (&config.metrics_path, sampler) {
+        match save_metrics(&sampler.finish(), path) {
+            Ok(()) => write_gnuplot_script(config, path, || gnuplot::metrics_script(path)),
+            Err(error) => warn!("Could not write metrics {}: {}", path, error),
+        }
+    }
+
+    let energy = lock_ignoring_poison(&energy);
+    if let Some(path) = &config.energy_path {
+        match energy.save(path) {
+            Ok(()) => write_gnuplot_script(config, path, || gnuplot::energy_script(path)),
+            Err(error) => warn!("Could not write energy series {}: {}", path, error),
+        }
+    }
+
+    if let (Some(path), Some(heatmap)) = (&config.heatmap_path, heatmap) {
+        if let Err(error) = lock_ignoring_poison(&heatmap).save(path) {
+            warn!("Could not write heatmap {}: {}", path, error);
+        }
+    }
+
+    let recent_collisions = match collision_log {
+        Some(collision_log) => {
+            let collision_log = lock_ignoring_poison(&collision_log);
+            if let Some(path) = &config.collision_log_path {
+                if collision_log.dropped() > 0 {
+                    warn!("The collision log was full, so the first {} collisions were dropped from {}", collision_log.dropped(), path);
+                }
+                if let Err(error) = collision_log.save(path) {
+                    warn!("Could not write collision log {}: {}", path, error);
+                }
+            }
+            collision_log.latest(config.recent_collisions)
+        }
+        None => Vec::new(),
+    };
+
+    // The move threads have dropped their handles, so the writer can finish off the file
+    if let (Some(recorder), Some(path)) = (recorder, &config.record_path) {
+        match recorder.finish() {
+            Ok(()) => write_gnuplot_script(config, path, || gnuplot::trajectory_script(path, &config.enclosure, N == 3, config.record_every)),
+            Err(error) => warn!("Could not write trajectory file: {}", error),
+        }
+    }
+
+    let avg_move_iterations_per_thread = move_iterations.iter().map(|&count| count as f64).sum::<f64>() / move_iterations.len() as f64;
+    // Every thread has finished with the system by now, so it is handed over rather than copied, which matters most to a
+    // headless run that may never look at it
+    let system = match Arc::try_unwrap(particle_system) {
+        Ok(particle_system) => particle_system.into_inner().unwrap_or_else(PoisonError::into_inner),
+        Err(particle_system) => read_ignoring_poison(&particle_system).clone(),
+    };
+
+    if let Some(path) = &config.svg_path {
+        if let Err(error) = render::write_svg(&system.particles, path, &config.enclosure) {
+            warn!("Could not write SVG {}: {}", path, error);
+        }
+    }
+
+    SimReport {
+        config: config.clone(),
+        seed,
+        total_frames: collisions.frames,
+        unique_collisions: collisions.collision_count,
+        raw_collision_frames: collisions.overlapping_frame_count,
+        annihilations: collisions.annihilations,
+        merges: collisions.merges,
+        cluster_collisions: collisions.cluster_collisions,
+        collisions_by_species: collisions.by_species,
+        max_energy_drift: energy.max_relative_drift(),
+        walls,
+        move_iterations,
+        avg_move_iterations_per_thread,
+        wall_clock: start_time.elapsed(),
+        system,
+        errors,
+        lock_profiles: profiles.take(),
+        recent_collisions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use broadphase::{Broadphase, BruteForce, ParallelBruteForce, QuadTree, SpatialGrid, SpatialHash, SweepAndPrune};
+
+    #[test]
+    fn head_on_collision_conserves_momentum_and_energy() {
+        let mut a = Particle::new(1.0, 1.0, 1.0, 0.0, PARTICLE_RADIUS);
+        let mut b = Particle::new(1.05, 1.0, -0.5, 0.0, PARTICLE_RADIUS);
+
+        let momentum_before = (a.velocity[0] + b.velocity[0], a.velocity[1] + b.velocity[1]);
+        let energy_before = a.velocity[0] * a.velocity[0] + a.velocity[1] * a.velocity[1] + b.velocity[0] * b.velocity[0] + b.velocity[1] * b.velocity[1];
+
+        a.resolve_collision(&mut b);
+
+        let momentum_after = (a.velocity[0] + b.velocity[0], a.velocity[1] + b.velocity[1]);
+        let energy_after = a.velocity[0] * a.velocity[0] + a.velocity[1] * a.velocity[1] + b.velocity[0] * b.velocity[0] + b.velocity[1] * b.velocity[1];
+
+        assert!((momentum_before.0 - momentum_after.0).abs() < 1e-6);
+        assert!((momentum_before.1 - momentum_after.1).abs() < 1e-6);
+        assert!((energy_before - energy_after).abs() < 1e-6);
+
+        // Equal masses head on simply swap velocities
+        assert!((a.velocity[0] + 0.5).abs() < 1e-6);
+        assert!((b.velocity[0] - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn particles_can_be_simulated_in_f64() {
+        // A step too small for f32 to see this far from the origin still moves an f64 particle
+        let (mut narrow, mut wide) = (ParticleF32::new(5.0, 5.0, 1e-7, 0.0, PARTICLE_RADIUS), ParticleF64::new(5.0, 5.0, 1e-7, 0.0, 0.05));
+        narrow.integrate(1.0);
+        wide.integrate(1.0);
+        assert_eq!(narrow.position[0], 5.0);
+        assert_eq!(wide.position[0], 5.0 + 1e-7);
+
+        // Colliding, bouncing off the walls and being kicked by forces all work as they do in f32
+        let mut system : ParticleSystem<2, f64> = ParticleSystem::new(Vec::new());
+        system.spawn(ParticleF64::new(1.0, 1.0, 1.0, 0.0, 0.05));
+        system.spawn(ParticleF64::new(1.08, 1.0, -1.0, 0.0, 0.05));
+        assert!(system.particles[0].perform_collision_check(&system.particles[1]));
+        system.resolve_collision(0, 1, None);
+        assert_eq!((system.particles[0].velocity[0], system.particles[1].velocity[0]), (-1.0, 1.0));
+
+        move_particles(&mut system.particles, 2.0, 0.0, &Enclosure::default(), BoundaryMode::Reflect, 0.0);
+        let p = system.particles[0];
+        assert!((p.position[0] - 1.01).abs() < 1e-12 && p.velocity[0] == 1.0); // From 0.99, two units left folds back off the wall at 0
+
+        integrator::Integrator::after_forces(&mut integrator::Euler, &mut system.particles[..1], &[[0.0, -10.0]], 0.5);
+        assert_eq!(system.particles[0].velocity[1], -5.0);
+    }
+
+    #[test]
+    fn overlapping_particles_are_pushed_apart_until_they_touch() {
+        // Heading apart already, so only the positions change
+        let mut a : Particle<3> = Particle::new_3d(1.0, 1.0, 1.0, -1.0, 0.0, 0.0, 0.05);
+        let mut b = Particle::new_3d(1.03, 1.04, 1.0, 1.0, 0.0, 0.0, 0.1);
+        let middle = ((a.position[0] + b.position[0]) / 2.0, (a.position[1] + b.position[1]) / 2.0);
+
+        a.resolve_collision(&mut b);
+
+        assert!((a.squared_distance(&b).sqrt() - 0.15).abs() < 1e-6);
+        assert!(((a.position[0] + b.position[0]) / 2.0 - middle.0).abs() < 1e-6 && ((a.position[1] + b.position[1]) / 2.0 - middle.1).abs() < 1e-6); // Half each way
+        assert_eq!((a.velocity[0], b.velocity[0]), (-1.0, 1.0));
+
+        // Particles that aren't overlapping stay where they are
+        let mut c = Particle::new(3.0, 1.0, 1.0, 0.0, 0.05);
+        let mut d = Particle::new(3.2, 1.0, -1.0, 0.0, 0.05);
+        c.resolve_collision(&mut d);
+        assert_eq!((c.position[0], d.position[0]), (3.0, 3.2));
+    }
+
+    fn momentum(particles: &[&Particle]) -> (f32, f32) {
+        particles.iter().fold((0.0, 0.0), |(px, py), p| (px + p.mass * p.velocity[0], py + p.mass * p.velocity[1]))
+    }
+
+    #[test]
+    fn glancing_collision_of_unequal_masses_conserves_momentum() {
+        let mut a = Particle::new(1.0, 1.0, 0.7, 0.2, 0.05);
+        let mut b = Particle::new(1.08, 1.04, -0.3, -0.6, 0.1);
+
+        let momentum_before = momentum(&[&a, &b]);
+        a.resolve_collision(&mut b);
+        let momentum_after = momentum(&[&a, &b]);
+
+        assert!((momentum_before.0 - momentum_after.0).abs() < 1e-5);
+        assert!((momentum_before.1 - momentum_after.1).abs() < 1e-5);
+    }
+
+    #[test]
+    fn collisions_leave_the_centre_of_mass_moving_as_it_was() {
+        // Just touching, so neither is pushed and the centre stays put as well
+        let a = Particle { mass: 1.0, ..Particle::new(1.0, 1.0, 0.7, 0.2, 0.05) };
+        let b = Particle { id: 1, mass: 4.0, ..Particle::new(1.09, 1.0, -0.3, -0.6, 0.04) };
+        let mut system = ParticleSystem::new(vec![a, b]);
+        let (centre, velocity) = (system.centre_of_mass(), system.centre_of_mass_velocity());
+        assert!((centre[0] - 1.072).abs() < 1e-5 && (velocity[0] - (0.7 - 1.2) / 5.0).abs() < 1e-6);
+
+        system.resolve_collision(0, 1, None);
+        assert_ne!(system.particles[0].velocity[0], a.velocity[0]);
+        let (moved, changed) = (system.centre_of_mass(), system.centre_of_mass_velocity());
+        assert!((moved[0] - centre[0]).abs() < 1e-6 && (moved[1] - centre[1]).abs() < 1e-6);
+        assert!((changed[0] - velocity[0]).abs() < 1e-6 && (changed[1] - velocity[1]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn heavy_particle_barely_deflects_off_light_one() {
+        let mut heavy = Particle { mass: 1000.0, ..Particle::new(1.0, 1.0, 1.0, 0.0, PARTICLE_RADIUS) };
+        let mut light = Particle { mass: 1.0, ..Particle::new(1.05, 1.0, -1.0, 0.0, PARTICLE_RADIUS) };
+
+        heavy.resolve_collision(&mut light);
+
+        assert!((heavy.velocity[0] - 1.0).abs() < 0.01);
+        assert!((light.velocity[0] - 3.0).abs() < 0.01); // Bounces off at close to twice the heavy particle's speed plus its own
+    }
+
+    #[test]
+    fn particles_collide_within_the_sum_of_their_radii() {
+        let small = Particle::new(1.0, 1.0, 0.0, 0.0, 0.05);
+        let large = Particle::new(1.3, 1.0, 0.0, 0.0, 0.3);
+        let other_small = Particle::new(1.3, 1.0, 0.0, 0.0, 0.05);
+
+        assert!(small.perform_collision_check(&large));
+        assert!(!small.perform_collision_check(&other_small));
+    }
+
+    #[test]
+    fn particles_in_the_same_place_collide() {
+        let p = Particle::new(3.0, 4.0, 0.0, 0.0, PARTICLE_RADIUS);
+
+        assert!(p.perform_collision_check(&p.clone()));
+    }
+
+    #[test]
+    fn touching_particles_dont_collide() {
+        // 0.1 is the sum of the default radii, and doubling 0.05 is exact in f32 so both sides of the check are the same number
+        let a = Particle::new(0.0, 5.0, 0.0, 0.0, PARTICLE_RADIUS);
+        let touching = Particle::new(PARTICLE_RADIUS * 2.0, 5.0, 0.0, 0.0, PARTICLE_RADIUS);
+        let just_inside = Particle::new(0.0999, 5.0, 0.0, 0.0, PARTICLE_RADIUS);
+        let just_outside = Particle::new(0.1001, 5.0, 0.0, 0.0, PARTICLE_RADIUS);
+
+        assert_eq!(a.squared_distance(&touching), (a.radius + touching.radius).powi(2));
+        assert!(!a.perform_collision_check(&touching));
+        assert!(a.perform_collision_check(&just_inside));
+        assert!(!a.perform_collision_check(&just_outside));
+        assert!(!just_outside.perform_collision_check(&a)); // The same either way round
+    }
+
+    #[test]
+    fn opposite_corners_dont_collide() {
+        let bottom_left = Particle::new(0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS);
+        let top_right = Particle::new(ENCLOSURE_W, ENCLOSURE_H, 0.0, 0.0, PARTICLE_RADIUS);
+
+        assert!(!bottom_left.perform_collision_check(&top_right));
+        assert!(!bottom_left.perform_collision_check_wrapped(&top_right, None));
+    }
+
+    #[test]
+    fn coincident_particles_are_left_unchanged() {
+        let mut a = Particle::new(1.0, 1.0, 1.0, 0.0, PARTICLE_RADIUS);
+        let mut b = Particle::new(1.0, 1.0, -1.0, 0.0, PARTICLE_RADIUS);
+
+        a.resolve_collision(&mut b);
+
+        assert_eq!(a.velocity[0], 1.0);
+        assert_eq!(b.velocity[0], -1.0);
+    }
+
+    #[test]
+    fn gravity_pulls_particles_down_and_the_floor_bounces_them() {
+        let mut particles = vec![Particle::new(5.0, 5.0, 0.0, 0.0, PARTICLE_RADIUS)];
+
+        move_particles(&mut particles, TIMESTEP, -9.81, &Enclosure::default(), BoundaryMode::Reflect, ENCLOSURE_D);
+        assert!(particles[0].velocity[1] < 0.0 && particles[0].position[1] < 5.0);
+
+        let mut bounced = false;
+        for _ in 0..1000 {
+            move_particles(&mut particles, TIMESTEP, -9.81, &Enclosure::default(), BoundaryMode::Reflect, ENCLOSURE_D);
+            bounced |= particles[0].velocity[1] > 0.0;
+            assert!(particles[0].position[1] >= 0.0 && particles[0].position[1] <= ENCLOSURE_H);
+        }
+        assert!(bounced);
+
+        let mut still = vec![Particle::new(5.0, 5.0, 0.0, 0.0, PARTICLE_RADIUS)];
+        move_particles(&mut still, TIMESTEP, GRAVITY, &Enclosure::default(), BoundaryMode::Reflect, ENCLOSURE_D);
+        assert_eq!((still[0].position[1], still[0].velocity[1]), (5.0, 0.0)); // No gravity by default
+    }
+
+    #[test]
+    fn only_3d_enclosures_have_z_walls() {
+        let mut deep = Particle::new_3d(5.0, 5.0, 9.9, 0.0, 0.0, 1.0, PARTICLE_RADIUS);
+        let mut flat = Particle::new(5.0, 5.0, 0.0, 0.0, PARTICLE_RADIUS);
+
+        for _ in 0..20 {
+            deep.integrate(TIMESTEP);
+            deep.apply_boundary(&Enclosure::default(), BoundaryMode::Reflect, 10.0);
+            flat.integrate(TIMESTEP);
+            flat.apply_boundary(&Enclosure::default(), BoundaryMode::Reflect, ENCLOSURE_D);
+        }
+
+        assert!(deep.velocity[2] < 0.0 && deep.position[2] <= 10.0);
+        assert_eq!(flat.position, [5.0, 5.0]);
+    }
+
+    #[test]
+    fn particle_fired_at_wall_comes_back() {
+        let mut p = Particle::new(ENCLOSURE_W - 0.5, 5.0, 10.0, 0.0, PARTICLE_RADIUS);
+
+        for _ in 0..10 {
+            p.integrate(TIMESTEP);
+            p.apply_boundary(&Enclosure::default(), BoundaryMode::Reflect, ENCLOSURE_D);
+        }
+
+        assert!(p.velocity[0] < 0.0);
+        assert!(p.position[0] >= 0.0 && p.position[0] <= ENCLOSURE_W);
+        assert!((p.position[0] - (ENCLOSURE_W - 0.5)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn large_overshoot_is_reflected_in_bounds() {
+        let mut p = Particle::new(-ENCLOSURE_W * 2.5, ENCLOSURE_H * 3.25, -1.0, 1.0, PARTICLE_RADIUS);
+
+        p.apply_boundary(&Enclosure::default(), BoundaryMode::Reflect, ENCLOSURE_D);
+
+        assert!(p.position[0] >= 0.0 && p.position[0] <= ENCLOSURE_W);
+        assert!(p.position[1] >= 0.0 && p.position[1] <= ENCLOSURE_H);
+        assert!((p.position[0] - ENCLOSURE_W * 0.5).abs() < 1e-4);
+        assert!((p.position[1] - ENCLOSURE_H * 0.75).abs() < 1e-4);
+    }
+
+    #[test]
+    fn particle_moving_out_of_a_circle_bounces_back_to_the_centre() {
+        let dish = Enclosure::Circle { radius: 5.0 };
+        let mut p = Particle::new(8.0, 9.0, 3.0, 4.0, PARTICLE_RADIUS); // Heading straight out from the centre at (5, 5)
+
+        for _ in 0..20 {
+            p.integrate(TIMESTEP);
+            p.apply_boundary(&dish, BoundaryMode::Reflect, ENCLOSURE_D);
+            assert!(dish.contains(p.position[0], p.position[1]));
+        }
+
+        // Straight back the way it came
+        assert!((p.velocity[0] + 3.0).abs() < 1e-4 && (p.velocity[1] + 4.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn periodic_particles_wrap_to_the_opposite_side() {
+        let mut p = Particle::new(ENCLOSURE_W - 0.01, 0.01, 2.0, -2.0, PARTICLE_RADIUS);
+
+        p.integrate(TIMESTEP);
+        p.apply_boundary(&Enclosure::default(), BoundaryMode::Periodic, ENCLOSURE_D);
+
+        assert!((p.position[0] - 0.01).abs() < 1e-4);
+        assert!((p.position[1] - (ENCLOSURE_H - 0.01)).abs() < 1e-4);
+        assert_eq!((p.velocity[0], p.velocity[1]), (2.0, -2.0)); // Still heading the same way
+    }
+
+    #[test]
+    fn boundaries_say_which_walls_were_hit() {
+        let walls_hit = |x: f32, y: f32, z: f32, enclosure: Enclosure, boundary: BoundaryMode| {
+            Particle::new_3d(x, y, z, 1.0, 1.0, 1.0, PARTICLE_RADIUS).apply_boundary(&enclosure, boundary, 5.0).walls().collect::<Vec<_>>()
+        };
+        let (rect, dish) = (Enclosure::Rect { w: 10.0, h: 10.0 }, Enclosure::Circle { radius: 5.0 });
+
+        assert_eq!(walls_hit(5.0, 5.0, 2.0, rect, BoundaryMode::Reflect), vec![]);
+        assert_eq!(walls_hit(-0.1, 5.0, 2.0, rect, BoundaryMode::Reflect), vec![Wall::Left]);
+        assert_eq!(walls_hit(10.1, 10.1, 5.1, rect, BoundaryMode::Reflect), vec![Wall::Right, Wall::Top, Wall::Front]);
+        assert_eq!(walls_hit(5.0, -0.1, -0.1, rect, BoundaryMode::Reflect), vec![Wall::Bottom, Wall::Back]);
+        assert_eq!(walls_hit(-0.1, 10.1, 2.0, rect, BoundaryMode::Periodic), vec![]);
+        assert_eq!(walls_hit(9.0, 9.0, 2.0, dish, BoundaryMode::Reflect), vec![Wall::Round]);
+    }
+
+    #[test]
+    fn collisions_are_measured_across_a_periodic_seam() {
+        let enclosure = Enclosure::default();
+        let left = Particle::new(0.03, 5.0, 0.0, 0.0, PARTICLE_RADIUS);
+        let right = Particle::new(ENCLOSURE_W - 0.03, 5.0, 0.0, 0.0, PARTICLE_RADIUS);
+
+        assert!((wrapped_distance_sq(&left, &right, &enclosure) - 0.06 * 0.06).abs() < 1e-4);
+        assert!(left.perform_collision_check_wrapped(&right, Some(&enclosure)));
+        assert!(!left.perform_collision_check_wrapped(&right, None));
+        assert!(!left.perform_collision_check(&right));
+    }
+
+    #[test]
+    fn pairs_across_a_periodic_seam_bounce_the_short_way_round() {
+        let enclosure = Enclosure::Rect { w: 10.0, h: 10.0 };
+        let (left, right) = (Particle::new(0.05, 5.0, -1.0, 0.0, PARTICLE_RADIUS), Particle::new(9.97, 5.0, 1.0, 0.0, PARTICLE_RADIUS));
+
+        assert!((wrapped_distance_sq(&left, &right, &enclosure) - 0.08 * 0.08).abs() < 1e-5);
+        assert_eq!(right.nearest_image_to(&left, &enclosure).position[0], 9.97 - 10.0);
+        assert_eq!(left.nearest_image_to(&right, &enclosure).position[0], 10.05);
+        assert_eq!(left.nearest_image_to(&left, &enclosure), left);
+
+        // Heading into each other across the edge, so they swap velocities and are pushed apart through it
+        let mut system = ParticleSystem::new(vec![left, Particle { id: 1, ..right }]);
+        system.resolve_collision(0, 1, Some(&enclosure));
+        let (left, right) = (system.particles[0], system.particles[1]);
+        assert!((left.velocity[0] - 1.0).abs() < 1e-5 && (right.velocity[0] + 1.0).abs() < 1e-5);
+        assert!((left.position[0] - 0.06).abs() < 1e-5 && (right.position[0] - 9.96).abs() < 1e-5);
+        assert!((wrapped_distance_sq(&left, &right, &enclosure) - 0.1 * 0.1).abs() < 1e-5);
+
+        // Without wrapping they are nowhere near each other, so they are left alone
+        let mut system = ParticleSystem::new(vec![Particle::new(0.05, 5.0, -1.0, 0.0, PARTICLE_RADIUS), Particle { id: 1, ..Particle::new(9.97, 5.0, 1.0, 0.0, PARTICLE_RADIUS) }]);
+        let before = system.particles.clone();
+        system.resolve_collision(0, 1, None);
+        assert_eq!(system.particles, before);
+    }
+
+    #[test]
+    fn circular_systems_start_inside_the_circle() {
+        let dish = Enclosure::Circle { radius: 3.0 };
+
+        for layout in [Layout::RandomUniform, Layout::Grid, Layout::Origin, Layout::Circle, Layout::TwoClusters].iter() {
+            let system = ParticleSystem::builder().particle_count(50).enclosure(dish).initial_layout(*layout).seed(2).build();
+            assert_eq!(system.particles.len(), 50);
+            assert!(system.particles.iter().all(|p| dish.contains(p.position[0], p.position[1])));
+        }
+    }
+
+    #[test]
+    fn grid_layout_tiles_the_enclosure_without_overlaps() {
+        let system = ParticleSystem::builder().particle_count(30).enclosure(Enclosure::Rect { w: 20.0, h: 10.0 }).initial_layout(Layout::Grid).seed(1).build();
+
+        for (i, a) in system.particles.iter().enumerate() {
+            assert!(a.position[0] > 0.0 && a.position[0] < 20.0 && a.position[1] > 0.0 && a.position[1] < 10.0);
+            for b in &system.particles[i + 1..] {
+                assert!(!a.perform_collision_check(b));
+            }
+        }
+    }
+
+    #[test]
+    fn circle_layout_spaces_particles_evenly_round_a_ring() {
+        let system = ParticleSystem::builder().particle_count(40).enclosure(Enclosure::Rect { w: 20.0, h: 10.0 }).initial_layout(Layout::Circle).seed(1).build();
+        let distance_from_middle = |p: &Particle| ((p.position[0] - 10.0).powi(2) + (p.position[1] - 5.0).powi(2)).sqrt();
+        let spacing = |a: &Particle, b: &Particle| ((a.position[0] - b.position[0]).powi(2) + (a.position[1] - b.position[1]).powi(2)).sqrt();
+
+        assert!(system.particles.iter().all(|p| (distance_from_middle(p) - 4.0).abs() < 1e-4));
+        for pair in system.particles.windows(2) {
+            assert!((spacing(&pair[0], &pair[1]) - spacing(&system.particles[0], &system.particles[1])).abs() < 1e-4);
+            assert!(!pair[0].perform_collision_check(&pair[1]));
+        }
+    }
+
+    #[test]
+    fn two_clusters_layout_splits_the_particles_between_the_halves() {
+        let system = ParticleSystemBuilder::<3>::default().particle_count(100).enclosure(Enclosure::Rect { w: 20.0, h: 10.0 }).depth(2.0).initial_layout(Layout::TwoClusters).seed(1).build();
+        let within = |p: &Particle<3>, x: f32| ((p.position[0] - x).powi(2) + (p.position[1] - 5.0).powi(2)).sqrt() <= 1.25 && (p.position[2] - 1.0).abs() <= 1.0;
+
+        assert!(system.particles[..50].iter().all(|p| within(p, 5.0)));
+        assert!(system.particles[50..].iter().all(|p| within(p, 15.0)));
+    }
+
+    #[test]
+    fn particles_are_found_by_id() {
+        let system = ParticleSystem::builder().particle_count(20).seed(1).build();
+
+        assert_eq!(system.by_id(7).map(|p| p.id), Some(7));
+        assert!(system.by_id(20).is_none());
+    }
+
+    #[test]
+    fn region_queries_find_the_particles_inside_with_or_without_a_grid() {
+        let mut system = ParticleSystem::builder().particle_count(300).initial_layout(Layout::RandomUniform).seed(3).build();
+        system.particles[0].position[0] = -1.0; // Strayed outside the enclosure
+        system.particles[1].position[0] = 2.0;
+        system.particles[1].position[1] = 3.0;
+        let mut grid = SpatialGrid::new(ENCLOSURE_W, ENCLOSURE_H, ENCLOSURE_D, 0.5);
+        grid.rebuild(&system.particles);
+
+        let inside = |min: (f32, f32), max: (f32, f32)| {
+            let expected : Vec<u64> = system.particles.iter().filter(|p| p.position[0] >= min.0.min(max.0) && p.position[0] <= min.0.max(max.0) && p.position[1] >= min.1.min(max.1) && p.position[1] <= min.1.max(max.1)).map(|p| p.id).collect();
+            assert_eq!(system.query_region(min, max), expected);
+            assert_eq!(system.query_region_in(&grid, min, max), expected);
+            expected
+        };
+
+        assert!(inside((1.0, 1.0), (4.0, 6.0)).len() > 10);
+        assert_eq!(inside((4.0, 6.0), (1.0, 1.0)), inside((1.0, 1.0), (4.0, 6.0))); // Corners either way round
+        assert_eq!(inside((0.0, 0.0), (ENCLOSURE_W, ENCLOSURE_H)).len(), 299);
+        assert_eq!(inside((2.0, 3.0), (2.0, 3.0)), vec![1]); // No area, so only the particle right on it
+        assert_eq!(inside((-5.0, -5.0), (-0.5, ENCLOSURE_H)), vec![0]);
+        assert!(inside((20.0, 20.0), (30.0, 30.0)).is_empty());
+        assert_eq!(inside((f32::NEG_INFINITY, f32::NEG_INFINITY), (f32::INFINITY, f32::INFINITY)).len(), 300);
+        assert!(system.query_region((f32::NAN, 0.0), (1.0, 1.0)).is_empty());
+        assert!(system.query_region_in(&grid, (f32::NAN, 0.0), (1.0, 1.0)).is_empty());
+    }
+
+    #[test]
+    fn kinetic_energy_adds_up_every_particle() {
+        let system = ParticleSystem::new(vec![
+            Particle { mass: 2.0, ..Particle::new_3d(1.0, 1.0, 1.0, 3.0, 4.0, 0.0, PARTICLE_RADIUS) },
+            Particle { mass: 1.0, ..Particle::new_3d(2.0, 2.0, 2.0, 0.0, 0.0, 2.0, PARTICLE_RADIUS) },
+        ]);
+
+        assert!((system.total_kinetic_energy() - 27.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn same_seed_gives_same_particles() {
+        let radius = RadiusDistribution::Uniform { min: 0.01, max: 0.1 };
+        let builder = || ParticleSystem::builder().particle_count(50).radius(radius).initial_layout(Layout::RandomUniform);
+        let a = builder().seed(42).build();
+        let b = builder().seed(42).build();
+        let c = builder().seed(43).build();
+
+        let velocities = |system: &ParticleSystem| system.particles.iter().map(|p| (p.position[0], p.position[1], p.velocity[0], p.velocity[1], p.radius)).collect::<Vec<_>>();
+        assert_eq!(velocities(&a), velocities(&b));
+        assert_ne!(velocities(&a), velocities(&c));
+    }
+
+    #[test]
+    fn chunks_cover_every_particle_exactly_once() {
+        for (len, thread_count) in [(100, 10), (101, 10), (7, 3), (5, 1), (3, 8), (0, 4), (1000, 64)] {
+            let chunks = chunk_ranges(len, thread_count);
+            let mut covered = vec![0; len];
+            for chunk in chunks.clone() {
+                for i in chunk {
+                    covered[i] += 1;
+                }
+            }
+
+            assert_eq!(chunks.len(), thread_count);
+            assert!(covered.iter().all(|&count| count == 1), "{} particles over {} threads", len, thread_count);
+
+            let sizes : Vec<usize> = chunks.iter().map(|chunk| chunk.len()).collect();
+            assert!(sizes.iter().max().unwrap() - sizes.iter().min().unwrap() <= 1, "{} particles over {} threads gave {:?}", len, thread_count, sizes);
+        }
+    }
+
+    #[test]
+    fn spawned_particles_get_fresh_ids_and_removed_ones_are_gone() {
+        let mut system = ParticleSystem::builder().particle_count(5).seed(1).build();
+
+        assert_eq!(system.spawn(Particle::new(1.0, 2.0, 0.0, 0.0, PARTICLE_RADIUS)), 5);
+        assert_eq!(system.by_id(5).map(|p| (p.position[0], p.position[1])), Some((1.0, 2.0)));
+        assert!(system.remove(2));
+        assert!(!system.remove(2));
+        assert!(system.by_id(2).is_none());
+        assert_eq!(system.spawn(Particle::new(3.0, 3.0, 0.0, 0.0, PARTICLE_RADIUS)), 6);
+        assert_eq!(system.particles.iter().map(|p| p.id).collect::<Vec<_>>(), vec![0, 1, 3, 4, 5, 6]);
+        assert_eq!(system.by_id(4).map(|p| p.id), Some(4));
+    }
+
+    #[test]
+    fn ids_of_removed_particles_are_never_reused() {
+        let mut system = ParticleSystem::builder().particle_count(3).seed(1).build();
+
+        assert!(system.remove(2));
+        assert_eq!(system.spawn(Particle::new(1.0, 1.0, 0.0, 0.0, PARTICLE_RADIUS)), 3);
+        assert!(system.remove(3));
+        assert_eq!(system.spawn(Particle::new(2.0, 2.0, 0.0, 0.0, PARTICLE_RADIUS)), 4);
+        assert_eq!(system.particles.iter().map(|p| p.id).collect::<Vec<_>>(), vec![0, 1, 4]);
+    }
+
+    #[test]
+    fn stepped_copies_keep_what_changed_while_they_were_out() {
+        let mut system = ParticleSystem::builder().particle_count(4).seed(3).build();
+        let mut copy = ChunkCopy::default();
+        copy.take(&system.particles, 1..3, false);
+        for p in copy.chunk_mut() {
+            p.position[0] += 0.5;
+            p.velocity[0] += 1.0;
+        }
+
+        // Bounced, removed and spawned while the copy was being stepped
+        let (bounced, left_alone) = (system.particles[1], system.particles[2]);
+        system.particles[1].velocity[1] += 2.0;
+        system.remove(0);
+        system.spawn(Particle::new(1.0, 1.0, 0.0, 0.0, PARTICLE_RADIUS));
+        copy.merge_into(&mut system.particles, |_| ());
+
+        let p = system.by_id(1).unwrap();
+        assert!((p.position[0] - bounced.position[0] - 0.5).abs() < 1e-5 && (p.velocity[0] - bounced.velocity[0] - 1.0).abs() < 1e-5 && (p.velocity[1] - bounced.velocity[1] - 2.0).abs() < 1e-5);
+        assert_eq!(*system.by_id(2).unwrap(), Particle { position: [left_alone.position[0] + 0.5, left_alone.position[1]], velocity: [left_alone.velocity[0] + 1.0, left_alone.velocity[1]], ..left_alone });
+        assert_eq!(system.by_id(4).map(|p| (p.position[0], p.velocity[0])), Some((1.0, 0.0)));
+    }
+
+    #[test]
+    fn a_particle_bounced_while_its_copy_was_out_still_ends_up_inside() {
+        // Reflected back off the right wall at the speed it was knocked up to, or carried across the seam to the left edge
+        for &(boundary, x, vx) in &[(BoundaryMode::Reflect, 9.994, -2.0), (BoundaryMode::Periodic, 0.006, 2.0)] {
+            let config = SimConfig { boundary, ..SimConfig::default() };
+            let mut system = ParticleSystem::new(vec![Particle::new(9.995, 5.0, 1.0, 0.0, PARTICLE_RADIUS)]);
+            let mut mover = ChunkMover::new(0..1, &config);
+            let mut copy = ChunkCopy::default();
+            copy.take(&system.particles, 0..1, false);
+            let stepped = copy.chunk_range();
+            mover.step_without_walls(copy.particles_mut(), stepped, TIMESTEP);
+
+            // Pushed on a little and sped up by a particle hitting it from behind while the copy was out
+            system.particles[0].position[0] += 0.001;
+            system.particles[0].velocity[0] = 2.0;
+            copy.merge_into(&mut system.particles, |p| mover.keep_inside(p));
+
+            let p = system.particles[0];
+            assert!((p.position[0] - x).abs() < 1e-4 && p.velocity[0] == vx, "{:?} left it at {} moving at {}", boundary, p.position[0], p.velocity[0]);
+        }
+    }
+
+    #[test]
+    fn move_threads_follow_particles_spawned_and_removed_mid_run() {
+        let config = SimConfig { particle_count: 10, steps: Some(300), ..SimConfig::default() };
+        let system = Arc::new(RwLock::new(starting_system::<2>(&config, 2)));
+        let control = RunControl::default();
+
+        let threads : Vec<_> = (0..3).map(|index| {
+            let (system, config, control) = (Arc::clone(&system), config.clone(), control.clone());
+            std::thread::spawn(move || move_thread_main(system, ChunkShare { index, count: 3 }, config, None, MoveOutputs::default(), control, LockProfiler::disabled()))
+        }).collect();
+
+        // Grow to 40 particles then shrink to 4, taking them from the front so every chunk boundary moves
+        for _ in 0..30 {
+            write_ignoring_poison(&system).spawn(Particle::new(5.0, 5.0, 1.0, 1.0, PARTICLE_RADIUS));
+            std::thread::yield_now();
+        }
+        for _ in 0..36 {
+            let mut system = write_ignoring_poison(&system);
+            let first = system.particles[0].id;
+            assert!(system.remove(first));
+        }
+
+        let results : Vec<_> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        assert_eq!(results, vec![Ok(300); 3]);
+        let system = read_ignoring_poison(&system);
+        assert_eq!(system.particles.len(), 4);
+        assert!(system.particles.iter().all(|p| config.enclosure.contains(p.position[0], p.position[1])));
+    }
+
+    #[test]
+    fn other_move_threads_carry_on_past_a_poisoned_lock() {
+        let config = SimConfig { particle_count: 20, steps: Some(50), ..SimConfig::default() };
+        let system = Arc::new(RwLock::new(starting_system::<2>(&config, 2)));
+        let control = RunControl::default();
+
+        // Both take a share of a split into one chunk, so the second starts past the end of the particles and slicing it panics while holding the lock
+        let threads : Vec<_> = (0..2).map(|index| {
+            let (system, config, control) = (Arc::clone(&system), config.clone(), control.clone());
+            let share = ChunkShare { index, count: 1 };
+            std::thread::spawn(move || move_thread_main(system, share, config, None, MoveOutputs::default(), control, LockProfiler::disabled()))
+        }).collect();
+
+        let results : Vec<_> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        assert_eq!(results[0], Ok(50));
+        assert!(matches!(&results[1], Err(SimError::MoveThreadPanicked { .. })));
+    }
+
+    #[test]
+    fn thermalized_systems_have_the_kinetic_energy_of_their_temperature() {
+        let species = [
+            Species { count: 5000, radius: RadiusDistribution::Fixed(0.05), mass: Some(1.0) },
+            Species { count: 5000, radius: RadiusDistribution::Fixed(0.05), mass: Some(4.0) },
+        ];
+        let mut rng = StdRng::seed_from_u64(3);
+
+        // Half the temperature per particle for each of the two dimensions
+        let mut flat = ParticleSystem::builder().species(&species).seed(1).build();
+        flat.thermalize(2.0, &mut rng);
+        assert!((flat.total_kinetic_energy() / 10000.0 - 2.0).abs() < 0.05);
+
+        // Heavier particles move slower but carry the same energy on average
+        let mean_speed_squared = |species: u8| flat.particles.iter().filter(|p| p.species == species).map(|p| p.velocity[0] * p.velocity[0] + p.velocity[1] * p.velocity[1]).sum::<f32>() / 5000.0;
+        assert!((mean_speed_squared(0) / mean_speed_squared(1) - 4.0).abs() < 0.3);
+
+        let mut deep = ParticleSystemBuilder::<3>::default().species(&species).depth(5.0).seed(1).build();
+        deep.thermalize(2.0, &mut rng);
+        assert!((deep.total_kinetic_energy() / 10000.0 - 3.0).abs() < 0.08);
+    }
+
+    #[test]
+    fn species_are_built_in_order_with_their_own_sizes() {
+        let species = [
+            Species { count: 3, radius: RadiusDistribution::Fixed(0.05), mass: None },
+            Species { count: 2, radius: RadiusDistribution::Fixed(0.2), mass: Some(7.0) },
+        ];
+        let system = ParticleSystem::builder().species(&species).initial_layout(Layout::RandomUniform).seed(1).build();
+
+        assert_eq!(system.particles.iter().map(|p| p.species).collect::<Vec<_>>(), vec![0, 0, 0, 1, 1]);
+        assert_eq!((system.particles[4].radius, system.particles[4].mass), (0.2, 7.0));
+        assert_eq!(system.particles[0].mass, Particle::new(0.0, 0.0, 0.0, 0.0, 0.05).mass);
+    }
+
+    #[test]
+    fn collisions_are_counted_by_species_pair() {
+        let particle = |x, y, species| Particle { species, ..Particle::new(x, y, 0.0, 0.0, PARTICLE_RADIUS) };
+        // Species 0 on the left and species 1 on the right, so no collision crosses between them
+        let mut particles = vec![
+            particle(1.0, 1.0, 0), particle(1.05, 1.0, 0),
+            particle(8.0, 1.0, 1), particle(8.05, 1.0, 1),
+            particle(8.0, 8.0, 1), particle(8.05, 8.0, 1),
+            particle(2.0, 8.0, 0),
+        ];
+        for (id, p) in particles.iter_mut().enumerate() {
+            p.id = id as u64;
+        }
+
+        let mut tracker = CollisionTracker::new(Box::new(BruteForce::new()), CollisionOutputs::default(), RENDER_EVERY_FRAMES);
+        tracker.check(&particles);
+        tracker.check(&particles); // Still overlapping, so nothing new
+
+        let stats = tracker.stats();
+        assert_eq!(stats.collision_count, 3);
+        assert_eq!(stats.by_species.into_iter().collect::<Vec<_>>(), vec![((0, 0), 1), ((1, 1), 2)]);
+    }
+
+    #[test]
+    fn continuous_detection_bounces_pairs_that_tunnelled_through_each_other() {
+        // The fast particle started the step at 1.0, on the other side of the still one
+        let tunnelled = vec![Particle::new(2.0, 5.0, 100.0, 0.0, PARTICLE_RADIUS), Particle { id: 1, ..Particle::new(1.5, 5.0, 0.0, 0.0, PARTICLE_RADIUS) }];
+        let config = SimConfig::default();
+
+        let mut tracker = CollisionTracker::new(Box::new(BruteForce::new()), CollisionOutputs::default(), RENDER_EVERY_FRAMES);
+        assert!(tracker.check(&tunnelled).is_empty());
+
+        let mut tracker = tracker.with_ccd(TIMESTEP);
+        let pairs = tracker.check(&tunnelled);
+        assert_eq!(pairs, vec![(0, 1)]);
+        assert_eq!(tracker.stats().collision_count, 1);
+
+        // Put back where they touched and bounced there, so the still one takes all the speed
+        let mut system = ParticleSystem::new(tunnelled);
+        tracker.resolve(&mut system, pairs, &config);
+        assert!((system.particles[0].position[0] - 1.4).abs() < 1e-4 && system.particles[0].velocity[0].abs() < 1e-4);
+        assert!((system.particles[1].velocity[0] - 100.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn coincident_pairs_are_one_cluster_collision_until_they_separate() {
+        let coincident = vec![Particle::new(5.0, 5.0, 1.0, 0.0, PARTICLE_RADIUS), Particle { id: 1, ..Particle::new(5.0, 5.0, -1.0, 0.0, PARTICLE_RADIUS) }];
+        let mut tracker = CollisionTracker::new(Box::new(BruteForce::new()), CollisionOutputs::default(), RENDER_EVERY_FRAMES).with_cluster_epsilon(0.001);
+        assert!(tracker.check(&coincident).is_empty());
+
+        // Still overlapping but no longer within the epsilon, so skipped for having been clustered
+        let drifting = vec![Particle { position: [5.02, 5.0], ..coincident[0] }, Particle { position: [4.98, 5.0], ..coincident[1] }];
+        assert!(tracker.check(&drifting).is_empty());
+        assert_eq!((tracker.stats().cluster_collisions, tracker.stats().collision_count), (1, 0));
+
+        // Apart and back together again is an ordinary collision
+        let apart = vec![Particle { position: [6.0, 5.0], ..coincident[0] }, Particle { position: [4.0, 5.0], ..coincident[1] }];
+        assert!(tracker.check(&apart).is_empty());
+        assert_eq!(tracker.check(&drifting), vec![(0, 1)]);
+        assert_eq!((tracker.stats().cluster_collisions, tracker.stats().collision_count), (1, 1));
+    }
+
+    #[test]
+    fn only_the_clustered_pair_is_skipped_not_its_particles() {
+        let particles = vec![Particle::new(5.0, 5.0, 0.0, 0.0, PARTICLE_RADIUS), Particle { id: 1, ..Particle::new(5.0, 5.0, 0.0, 0.0, PARTICLE_RADIUS) }, Particle { id: 2, ..Particle::new(5.05, 5.0, 0.0, 0.0, PARTICLE_RADIUS) }, Particle { id: 3, ..Particle::new(5.12, 5.0, 0.0, 0.0, PARTICLE_RADIUS) }];
+        let mut tracker = CollisionTracker::new(Box::new(BruteForce::new()), CollisionOutputs::default(), RENDER_EVERY_FRAMES).with_cluster_epsilon(0.001);
+
+        // Both clustered particles still collide with the one overlapping them, on the snapshot after too
+        assert_eq!(tracker.check(&particles), vec![(0, 2), (1, 2), (2, 3)]);
+        assert_eq!(tracker.check(&particles), vec![(0, 2), (1, 2), (2, 3)]);
+        assert_eq!(tracker.stats().cluster_collisions, 1);
+    }
+
+    #[test]
+    fn detect_collisions_returns_sorted_unique_pairs() {
+        let particle = |x, y| Particle::new(x, y, 0.0, 0.0, PARTICLE_RADIUS);
+        let particles = vec![particle(5.0, 5.0), particle(1.0, 1.0), particle(5.05, 5.0), particle(1.0, 1.05), particle(5.0, 5.05)];
+
+        for broadphase in [&mut BruteForce::new() as &mut dyn Broadphase, &mut ParallelBruteForce::new(), &mut SpatialGrid::new(ENCLOSURE_W, ENCLOSURE_H, ENCLOSURE_D, PARTICLE_RADIUS * 2.0), &mut SpatialHash::new(PARTICLE_RADIUS * 2.0), &mut QuadTree::new(ENCLOSURE_W, ENCLOSURE_H, PARTICLE_RADIUS * 2.0), &mut SweepAndPrune::new()] {
+            assert_eq!(detect_collisions(&particles, broadphase), vec![(0, 2, (0, 0)), (0, 4, (0, 0)), (1, 3, (0, 0)), (2, 4, (0, 0))]);
+        }
+    }
+}
@@ -0,0 +1,261 @@
+// Barrier-synchronised timesteps, where every move thread advances exactly one step and then the collisions are checked once
+// on the fully updated system before anyone moves again
+//
+// Each step goes:
+// - The coordinator decides whether there is another step, everyone meets at the barrier and reads its answer
+// - Every move thread takes its chunk through the integrator's steps before and after the forces are measured, then meets again
+// - With repulsion or a potential on the forces depend on other chunks, so everyone meets before and after measuring them, while nobody is moving
+// - The coordinator checks for collisions and bounces them, and emits any new particle, while the move threads wait for the next step
+//
+// A move thread that panics keeps turning up at the barrier without moving, so the others aren't left waiting for it
+use crate::broadphase::make_detector;
+use crate::config::SimConfig;
+use crate::control::{RunControl, Ticker};
+use crate::emitter::Emitter;
+use crate::fps::{FpsMonitor, ThreadCounters};
+use crate::integrator::ChunkMover;
+use crate::profile::{LockProfiler, LockProfiles};
+use crate::progress::StepCounter;
+use crate::trajectory::{TrajectoryHandle, TrajectoryRecorder};
+use crate::walls::WallCounter;
+use crate::{catch_panic, ChunkCopy, ChunkShare, lock_ignoring_poison, read_ignoring_poison, write_ignoring_poison, CollisionOutputs, CollisionTracker, MoveOutputs, Particle, ParticleSystem, RunOutcome, SimError, TIMESTEP};
+use log::debug;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Barrier, Mutex, RwLock};
+use std::time::Instant;
+use threadpool::ThreadPool;
+
+// How the move and collision threads keep in step with each other
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SyncMode {
+    Barrier, // Everyone advances one timestep together
+    FreeRunning, // Every move thread runs its own loop, and the collision threads check whatever frame was published last
+}
+
+impl SyncMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "barrier" => Some(SyncMode::Barrier),
+            "free-running" => Some(SyncMode::FreeRunning),
+            _ => None,
+        }
+    }
+}
+
+// The barrier every thread meets at, and whether the coordinator wants another step
+pub struct Lockstep {
+    barrier: Barrier,
+    running: AtomicBool,
+}
+
+impl Lockstep {
+    // One slot per move thread plus one for the coordinator
+    pub fn new(move_thread_count: usize) -> Self {
+        Lockstep { barrier: Barrier::new(move_thread_count + 1), running: AtomicBool::new(true) }
+    }
+
+    // Called by the coordinator to start a step, or to tell everyone to finish if run is false
+    pub fn begin_step(&self, run: bool) -> bool {
+        self.running.store(run, Ordering::Relaxed); // The barrier orders this before every read below
+        self.barrier.wait();
+        run
+    }
+
+    // Called by the move threads, waits for the coordinator and returns whether to take another step
+    pub fn next_step(&self) -> bool {
+        self.barrier.wait();
+        self.running.load(Ordering::Relaxed)
+    }
+
+    // Wait for everyone to finish the current phase
+    pub fn wait(&self) {
+        self.barrier.wait();
+    }
+}
+
+// Copy the chunk out under the read lock, move the copy with f, then take the write lock only to put it back
+// Nothing else changes the particles while the move threads are moving, so the copy goes back exactly as f left it
+fn move_copy<const N: usize>(particle_system: &RwLock<ParticleSystem<N>>, share: ChunkShare, mover: &mut ChunkMover<N>, copy: &mut ChunkCopy<N>, profiler: &mut LockProfiler, f: impl FnOnce(&mut ChunkMover<N>, &mut [Particle<N>])) {
+    let chunk = profiler.hold(|| read_ignoring_poison(particle_system), |system| {
+        let chunk = share.range(system.particles.len());
+        copy.take(&system.particles, chunk.clone(), false);
+        chunk
+    });
+    mover.set_chunk(chunk);
+    f(mover, copy.chunk_mut());
+    profiler.hold(|| write_ignoring_poison(particle_system), |mut system| copy.merge_into(&mut system.particles, |_| ())); // f already kept them inside
+}
+
+// Move one chunk a step at a time in lockstep with the other move threads, returning how many steps it took or the panic that stopped it
+// The chunk is worked out from the particle count each time the lock is taken, in case particles have been spawned or removed
+// Each thread moves a copy of its chunk, so they only queue for the write lock to put their copies back
+// Only the outputs' wall and frame counters are used, the coordinator does the publishing and emitting
+pub fn lockstep_move_thread_main<const N: usize>(particle_system: Arc<RwLock<ParticleSystem<N>>>, share: ChunkShare, config: SimConfig, recorder: Option<TrajectoryHandle<N>>, lockstep: Arc<Lockstep>, outputs: MoveOutputs<N>, mut profiler: LockProfiler) -> Result<u32, SimError> {
+    let mut iterations: u32 = 0;
+    let mut mover = ChunkMover::new(share.range(read_ignoring_poison(&particle_system).particles.len()), &config).with_wall_counter(outputs.walls);
+    let mut failure : Option<String> = None;
+    let mut copy = ChunkCopy::default();
+
+    while lockstep.next_step() {
+        if mover.reads_other_chunks() {
+            // Everyone moves, then measures the forces on positions nobody is changing, then moves again
+            if failure.is_none() {
+                failure = catch_panic(|| move_copy(&particle_system, share, &mut mover, &mut copy, &mut profiler, |mover, chunk| mover.before_forces(chunk, TIMESTEP))).err();
+            }
+            lockstep.wait();
+            if failure.is_none() {
+                failure = catch_panic(|| profiler.hold(|| read_ignoring_poison(&particle_system), |system| {
+                    mover.set_chunk(share.range(system.particles.len()));
+                    mover.measure_forces(&system.particles)
+                })).err();
+            }
+            lockstep.wait();
+        }
+
+        if failure.is_none() {
+            failure = catch_panic(|| move_copy(&particle_system, share, &mut mover, &mut copy, &mut profiler, |mover, chunk| {
+                if mover.reads_other_chunks() {
+                    mover.after_forces(chunk, TIMESTEP);
+                } else {
+                    mover.step_chunk(chunk, TIMESTEP);
+                }
+
+                if let Some(recorder) = &recorder {
+                    recorder.record(iterations, chunk);
+                }
+            })).err();
+        }
+
+        lockstep.wait();
+        if failure.is_none() {
+            iterations += 1;
+            if let Some(frames) = &outputs.frames {
+                frames.tick(share.index);
+            }
+        }
+    }
+
+    let chunk = mover.chunk();
+    debug!("Move thread for particles {} to {} left the lockstep after {} steps", chunk.start, chunk.end, iterations);
+    match failure {
+        Some(message) => Err(SimError::MoveThreadPanicked { chunk, message }),
+        None => Ok(iterations),
+    }
+}
+
+// Run the move threads in lockstep, checking collisions on this thread between steps until out of time, out of steps, or stopped
+// While paused the coordinator waits between steps, and the move threads wait for it at the barrier
+// The collision thread count is ignored, as there is exactly one check per step
+// If the check panics the run ends there, as there is nothing left to bounce the particles
+pub(crate) fn run_lockstep<const N: usize>(particle_system: &Arc<RwLock<ParticleSystem<N>>>, config: &SimConfig, recorder: Option<&TrajectoryRecorder<N>>, outputs: CollisionOutputs<N>, control: &RunControl, profiles: &LockProfiles, progress: &StepCounter) -> RunOutcome {
+    let mut start_time = Instant::now();
+
+    let pool = ThreadPool::new(config.thread_count);
+    let lockstep = Arc::new(Lockstep::new(config.thread_count));
+    let walls = WallCounter::default();
+    let frame_counts = ThreadCounters::new(config.thread_count);
+    let fps = config.fps.then(|| FpsMonitor::start(frame_counts.clone()));
+
+    let move_results = Arc::new(Mutex::new((0..config.thread_count).map(|_| Ok(0)).collect::<Vec<_>>()));
+    for index in 0..config.thread_count {
+        let share = ChunkShare { index, count: config.thread_count };
+        let system_clone = Arc::clone(particle_system);
+        let results = Arc::clone(&move_results);
+
+        let config_clone = config.clone();
+        let recorder_handle = recorder.map(TrajectoryRecorder::handle);
+        let lockstep = Arc::clone(&lockstep);
+        let outputs = MoveOutputs { walls: walls.clone(), frames: Some(frame_counts.clone()), ..MoveOutputs::default() };
+        let profiler = profiles.profiler(&format!("move thread {}", index));
+
+        pool.execute(move || {
+            let result = lockstep_move_thread_main(system_clone, share, config_clone, recorder_handle, lockstep, outputs, profiler);
+            lock_ignoring_poison(&results)[index] = result;
+        });
+    }
+
+    let mut tracker = CollisionTracker::new(make_detector(config.broadphase, config), outputs, config.render_every);
+    if config.ccd {
+        tracker = tracker.with_ccd(TIMESTEP);
+    }
+    if let Some(epsilon) = config.cluster_epsilon {
+        tracker = tracker.with_cluster_epsilon(epsilon);
+    }
+    let mut profiler = profiles.profiler("coordinator");
+    let mut emitter = config.emitter.map(|settings| Emitter::new(settings, config));
+    let mut steps : u32 = 0;
+    let mut collision_result = Ok(());
+    let mut ticker = Ticker::new(config.tick_hz); // The move threads wait at the barrier for the coordinator, so pacing it paces them
+
+    while lockstep.begin_step(collision_result.is_ok() && config.keep_running(steps, start_time) && !control.is_stopped()) {
+        if config.has_pair_forces() {
+            lockstep.wait();
+            lockstep.wait();
+        }
+        lockstep.wait();
+
+        collision_result = catch_panic(|| profiler.hold(|| write_ignoring_poison(particle_system), |mut system| {
+            let colliding_ids = tracker.check(&system.particles);
+            tracker.resolve(&mut system, colliding_ids, config);
+            if let Some(emitter) = &mut emitter {
+                emitter.emit(steps, &mut system);
+            }
+        }));
+        steps += 1;
+        progress.tick();
+        start_time += control.wait_while_paused();
+        ticker.wait();
+    }
+
+    pool.join();
+    drop(fps);
+
+    let move_results = std::mem::take(&mut *lock_ignoring_poison(&move_results));
+    let collision_result = collision_result.map(|_| tracker.stats()).map_err(|message| SimError::CollisionThreadPanicked { message });
+    RunOutcome::new(move_results, vec![collision_result], walls.counts())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn every_move_thread_takes_the_same_steps() {
+        let config = SimConfig { particle_count: 30, thread_count: 3, duration: Duration::from_millis(100), repulsion: 0.1, seed: Some(4), ..SimConfig::default() };
+        let system = Arc::new(RwLock::new(crate::starting_system::<2>(&config, 4)));
+
+        let outcome = run_lockstep(&system, &config, None, CollisionOutputs::default(), &RunControl::default(), &LockProfiles::new(false), &StepCounter::default());
+
+        assert!(outcome.move_iterations[0] > 0);
+        assert!(outcome.move_iterations.iter().all(|&count| count == outcome.move_iterations[0]));
+        assert_eq!(outcome.collisions.frames, outcome.move_iterations[0] as usize); // One check after every step
+        assert!(outcome.errors.is_empty());
+    }
+
+    #[test]
+    fn a_panicking_move_thread_doesnt_hold_up_the_others() {
+        let config = SimConfig { particle_count: 20, thread_count: 2, ..SimConfig::default() };
+        let system = Arc::new(RwLock::new(crate::starting_system::<2>(&config, 4)));
+        let lockstep = Arc::new(Lockstep::new(2));
+
+        // Both take a share of a split into one chunk, so the second starts past the end of the particles and slicing it panics while holding the lock
+        let threads : Vec<_> = (0..2).map(|index| {
+            let (system, config, lockstep) = (Arc::clone(&system), config.clone(), Arc::clone(&lockstep));
+            let share = ChunkShare { index, count: 1 };
+            std::thread::spawn(move || lockstep_move_thread_main(system, share, config, None, lockstep, MoveOutputs::default(), LockProfiler::disabled()))
+        }).collect();
+
+        for _ in 0..5 {
+            lockstep.begin_step(true);
+            lockstep.wait();
+        }
+        lockstep.begin_step(false);
+
+        let results : Vec<_> = threads.into_iter().map(|thread| thread.join().unwrap()).collect();
+        assert_eq!(results[0], Ok(5));
+        assert!(matches!(&results[1], Err(SimError::MoveThreadPanicked { chunk, .. }) if *chunk == (20..40)));
+    }
+}
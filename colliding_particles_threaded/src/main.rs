@@ -1,159 +1,98 @@
+use particles::analysis::{fastest_speed, format_speed_histogram, nearest_neighbors, save_speed_histogram, speed_histogram, SPEED_HISTOGRAM_BINS};
+use particles::config::{SimConfig, USAGE};
+use particles::control::RunControl;
+use particles::replay::replay;
+use particles::run_simulation_until;
+use log::{error, info, warn, LevelFilter};
 use rand::random;
-use threadpool::ThreadPool;
-use std::sync::{Arc, Mutex};
-use std::time::Instant;
 
-const THREAD_COUNT : usize = 10;
-const COLLISION_THREAD_COUNT : usize = 1;
-const PARTICLE_COUNT : usize = 100;
-
-const ENCLOSURE_W : f32 = 10.0;
-const ENCLOSURE_H : f32 = 10.0;
-
-const PARTICLE_RADIUS_SQUARED : f32 = 0.01; // (0.01 == 0.1^2 which saves square rooting the distance)
-
-// Simulation values
-const SIMULATION_TIME_SECONDS : f32 = 10.0;
-
-#[derive(Debug, Copy, Clone)]
-struct Particle {
-    x: f32,
-    y: f32,
-}
-
-impl Particle {
-
-    // Compare the distance between two particles, if the distance is less than 0.1, they have collided
-    fn perform_collision_check(&self, other_particle: &Particle) -> bool {
-        let dist_x = self.x - other_particle.x;
-        let dist_y = self.y - other_particle.y;
-        let squared_distance = dist_x * dist_x + dist_y * dist_y;
-
-        return squared_distance < PARTICLE_RADIUS_SQUARED;
-    }
-}
-
-struct ParticleSystem {
-    particles: Vec<Particle>,
-}
+// Progress and warnings are logged to stderr at info level unless RUST_LOG says otherwise, e.g. RUST_LOG=debug for every thread's
+// iteration counts or RUST_LOG=trace for every particle's final position, while the report itself is printed to stdout
+// With --json stdout gets nothing but the report as one JSON object
+// With --headless only errors are logged, whatever RUST_LOG says, and the particles aren't looked over once the run is done
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
 
-impl ParticleSystem {
-    fn new() -> Self {
-        let mut created_particles = Vec::new();
-        
-        for _ in 0..PARTICLE_COUNT {
-            created_particles.push(Particle {
-                x: 0.0,
-                y: 0.0
-            });
+    let mut config = match SimConfig::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}\n{}", error, USAGE);
+            std::process::exit(2);
         }
+    };
 
-        ParticleSystem { particles: created_particles }
+    if config.headless {
+        log::set_max_level(LevelFilter::Error);
     }
 
-    // Print all particles and their positions to the console 
-    fn debug_print_particles(& self) {
-        let mut i = 0;
-        for p in & self.particles {
-            if i % 5 == 0 && i != 0 {
-                println!("{} : x {} y {}", i, p.x, p.y);
-            } else {
-                print!("{} : x {} y {} |", i, p.x, p.y);
+    if let Some(path) = &config.replay_path {
+        match replay(path) {
+            Ok(report) => {
+                println!("Replayed {} frames of {} particles, skipping {} incomplete frames at the end", report.frames, report.particle_count, report.dropped_frames);
+                println!("{} collisions occured ({} overlapping pair frames)", report.collisions.collision_count, report.collisions.overlapping_frame_count);
+            }
+            Err(error) => {
+                error!("{}", error);
+                std::process::exit(1);
             }
-            i+=1;
         }
-
-        println!("\n----");
+        return;
     }
-}
-
-// Move all particles by a random amount inside the enclosure
-fn random_move_particles(particle_list: &mut[Particle]){
-    for p in particle_list {
-        p.x = random::<f32>() * ENCLOSURE_W;
-        p.y = random::<f32>() * ENCLOSURE_H;
-    }
-}
-
-fn move_thread_main(particle_system: Arc<Mutex<ParticleSystem>>, start: usize, len: usize){
-    let mut iterations: u32 = 0;
-    let start_time = Instant::now();
 
-    let mut local_chunk : Vec<Particle> = { // Use scoped set to let the lock go out of scope
-        let system = particle_system.lock().unwrap();
-        system.particles[start..start + len].to_vec()
-    };
-
-    while start_time.elapsed().as_secs_f32() < SIMULATION_TIME_SECONDS {
-        random_move_particles(&mut local_chunk);
-
-        let mut system = particle_system.lock().unwrap(); // Only lock to update the local chunk
-        system.particles[start .. start + len].copy_from_slice(&local_chunk);
+    // Pick the seed here so it is printed before the run, in case it never finishes
+    let seed = *config.seed.get_or_insert_with(random);
+    info!("Seed {}", seed);
 
-        iterations+=1;
+    // Ctrl-C ends the run early, the threads notice on their next iteration and the report still gets printed
+    // With --tui the terminal view reads Ctrl-C itself, along with space to pause
+    let control = RunControl::default();
+    let handler_control = control.clone();
+    if let Err(error) = ctrlc::set_handler(move || handler_control.stop()) {
+        warn!("Could not install Ctrl-C handler: {}", error);
     }
 
-    println!("Ran {} in {}s", iterations, SIMULATION_TIME_SECONDS)
-}
-
-fn collision_thread_main(particle_system: Arc<Mutex<ParticleSystem>>) {
-    let start_time = Instant::now();
-
-    let mut collision_count : usize = 0;
-
-    while start_time.elapsed().as_secs_f32() < SIMULATION_TIME_SECONDS {
-        // Temporarily lock mutex to access particles and then release - use as "snapshot" of collisions occuring
-        let particles : Vec<Particle> = { // Use scoped set to let the lock go out of scope
-            let system = particle_system.lock().unwrap();
-            system.particles.to_vec()
-        };
-
-        for i in 0..particles.len() {
-            for j in i + 1..particles.len() {
-                if particles[i].perform_collision_check(&particles[j]) {
-                    collision_count += 1;
-                }
-            }
-        }
+    // 3D runs have a third axis on every particle, so they are a different instantiation of the whole run
+    let succeeded = if config.is_3d() { run::<3>(&config, control) } else { run::<2>(&config, control) };
+    if !succeeded {
+        std::process::exit(1);
     }
-
-    println!("{} collisions occured", collision_count);
 }
 
-fn main() {
-    let particle_system_mut = Arc::new(Mutex::new(ParticleSystem::new()));
-    let particles_len = particle_system_mut.lock().unwrap().particles.len();
-
-    let chunk_size = particles_len / THREAD_COUNT; // Split data into equal chunks
-
-    let pool = ThreadPool::new(THREAD_COUNT); // Create thread pool
-    let collision_pool = ThreadPool::new(COLLISION_THREAD_COUNT);
+// Run the simulation and print everything asked for about it, returning whether every thread finished cleanly
+fn run<const N: usize>(config: &SimConfig, control: RunControl) -> bool {
+    let report = run_simulation_until::<N>(config, control);
 
-    // Instance the random move threads
-    for i in 0..THREAD_COUNT {
-        let system_clone = Arc::clone(&particle_system_mut);
+    let system = &report.system;
+    if !config.headless {
+        system.debug_print_particles();
 
-        let chunk_idx = i * chunk_size;
-        let mut chunk_len = chunk_size;
-
-        if chunk_idx + chunk_len > particles_len - 1 {
-            chunk_len = particles_len - chunk_idx - 1;
+        // Report the tightest cluster as the particle closest to its nearest neighbour
+        let closest = nearest_neighbors(&system.particles).into_iter().enumerate().min_by(|(_, a), (_, b)| a.1.total_cmp(&b.1));
+        if let Some((i, (j, squared_distance))) = closest {
+            info!("Tightest cluster: particles {} and {} are {} apart", system.particles[i].id, system.particles[j].id, squared_distance.sqrt());
         }
-
-        pool.execute(move || move_thread_main(system_clone, chunk_idx, chunk_len));
     }
 
-    // Instance the collision checking threads
-    for i in 0..COLLISION_THREAD_COUNT {
-        let system_clone = Arc::clone(&particle_system_mut);
-
-        collision_pool.execute(move || collision_thread_main(system_clone));
+    if config.json {
+        println!("{}", report.to_json());
+    } else {
+        println!("{}", report);
     }
 
-    pool.join();
-    collision_pool.join();
+    // Worked out from the finished particles, so it means the same in the stepped and event-driven modes
+    if config.speed_bins.is_some() || config.speed_histogram_path.is_some() {
+        let histogram = speed_histogram(&system.particles, config.speed_bins.unwrap_or(SPEED_HISTOGRAM_BINS));
+        let fastest = fastest_speed(&system.particles);
+        if config.speed_bins.is_some() {
+            let table = format_speed_histogram(&histogram, fastest);
+            if config.json { eprint!("Speeds:\n{}", table) } else { print!("Speeds:\n{}", table) }
+        }
+        if let Some(path) = &config.speed_histogram_path {
+            if let Err(error) = save_speed_histogram(path, &histogram, fastest) {
+                warn!("Could not write speed histogram {}: {}", path, error);
+            }
+        }
+    }
 
-    // Bring particles back to the main thread
-    let system = particle_system_mut.lock().unwrap();
-    system.debug_print_particles();
+    report.errors.is_empty()
 }
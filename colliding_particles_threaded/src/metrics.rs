@@ -0,0 +1,156 @@
+// Whole-system measurements sampled through a run into a CSV time series, turned on with --metrics
+// Unlike trajectories there is one row per sample rather than per particle, so it stays small enough to plot quickly
+// The sampler thread copies the particles under a read lock and measures the copy, so the lock is held only as long as the copy takes
+use crate::broadphase::{make_detector, CollisionDetector};
+use crate::ccd::next_collision;
+use crate::config::SimConfig;
+use crate::energy::kinetic_energy;
+use crate::{axes, detect_collisions, read_ignoring_poison, Particle, ParticleSystem};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+pub const METRICS_INTERVAL : Duration = Duration::from_millis(100);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FrameMetrics<const N: usize = 2> {
+    pub time: Duration, // Since the sampler started, at the start of the run
+    pub active_collisions: usize, // Pairs overlapping in the sample, whether they have only just collided or not
+    pub kinetic_energy: f32,
+    pub average_speed: f32,
+    pub centre_of_mass: [f32; N],
+    pub centre_of_mass_velocity: [f32; N], // Only walls and forces change it, collisions between particles never do
+    pub next_collision: Option<f32>, // Seconds until the next pair not yet overlapping collides if nothing changes course, None if none ever will
+}
+
+impl<const N: usize> FrameMetrics<N> {
+    // Nothing is measured from an empty system, its speed and centre are all zero
+    pub fn measure(time: Duration, particles: &[Particle<N>], detector: &mut dyn CollisionDetector<N>) -> Self {
+        let total_speed : f32 = particles.iter().map(Particle::speed).sum();
+
+        FrameMetrics {
+            time,
+            active_collisions: detect_collisions(particles, detector).len(),
+            kinetic_energy: kinetic_energy(particles),
+            average_speed: if particles.is_empty() { 0.0 } else { total_speed / particles.len() as f32 },
+            centre_of_mass: centre_of_mass(particles),
+            centre_of_mass_velocity: centre_of_mass_velocity(particles),
+            next_collision: next_collision(particles).map(|(time, _, _)| time),
+        }
+    }
+}
+
+// The mean of a quantity over the particles weighted by their masses, summed in f64 like the kinetic energy, 0 if there's no mass
+fn mass_weighted_mean<const N: usize>(particles: &[Particle<N>], value: impl Fn(&Particle<N>) -> f32) -> f32 {
+    let total_mass : f64 = particles.iter().map(|p| p.mass as f64).sum();
+    if total_mass > 0.0 { (particles.iter().map(|p| p.mass as f64 * value(p) as f64).sum::<f64>() / total_mass) as f32 } else { 0.0 }
+}
+
+pub fn centre_of_mass<const N: usize>(particles: &[Particle<N>]) -> [f32; N] {
+    std::array::from_fn(|axis| mass_weighted_mean(particles, |p| p.position[axis]))
+}
+
+// The total momentum over the total mass, which with walls and no forces only changes when a particle bounces off a wall
+// A drift with nothing to push it points at a collision response or boundary that gives one side more than the other
+pub fn centre_of_mass_velocity<const N: usize>(particles: &[Particle<N>]) -> [f32; N] {
+    std::array::from_fn(|axis| mass_weighted_mean(particles, |p| p.velocity[axis]))
+}
+
+// The sampler thread, which takes one sample straight away and another every interval until finished
+pub struct MetricsSampler<const N: usize = 2> {
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Vec<FrameMetrics<N>>>>,
+}
+
+impl<const N: usize> MetricsSampler<N> {
+    pub fn start(particle_system: Arc<RwLock<ParticleSystem<N>>>, config: &SimConfig) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread_done = Arc::clone(&done);
+        let interval = config.metrics_interval;
+        let mut detector = make_detector(config.broadphase, config);
+
+        let thread = thread::spawn(move || {
+            let start_time = Instant::now();
+            let mut samples = Vec::new();
+            while !thread_done.load(Ordering::Relaxed) {
+                let particles = read_ignoring_poison(&particle_system).particles.clone();
+                samples.push(FrameMetrics::measure(start_time.elapsed(), &particles, detector.as_mut()));
+
+                // Slept in short slices, so a long interval doesn't hold up the end of the run
+                let next = start_time.elapsed() + interval;
+                while !thread_done.load(Ordering::Relaxed) && start_time.elapsed() < next {
+                    thread::sleep(interval.min(METRICS_INTERVAL));
+                }
+            }
+            samples
+        });
+
+        MetricsSampler { done, thread: Some(thread) }
+    }
+
+    // Stop sampling and return every sample taken, oldest first
+    pub fn finish(mut self) -> Vec<FrameMetrics<N>> {
+        self.done.store(true, Ordering::Relaxed);
+        self.thread.take().and_then(|thread| thread.join().ok()).unwrap_or_default()
+    }
+}
+
+impl<const N: usize> Drop for MetricsSampler<N> {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// One line per sample, with the time in seconds, and next_collision left empty if no pair is heading for a collision
+// There are always at least x, y and z columns for the centre, so 2D and 3D files have the same layout, with z 0 in 2D
+pub fn save_metrics<const N: usize>(samples: &[FrameMetrics<N>], path: &str) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let axes = N.max(3);
+    let columns = |prefix: &str| (0..axes).map(|axis| format!("{}{}", prefix, axes::name(axis))).collect::<Vec<_>>().join(",");
+    let values = |values: &[f32; N]| (0..axes).map(|axis| values.get(axis).copied().unwrap_or(0.0).to_string()).collect::<Vec<_>>().join(",");
+
+    writeln!(file, "seconds,active_collisions,kinetic_energy,average_speed,{},next_collision,{}", columns("centre_"), columns("centre_v"))?;
+    for sample in samples {
+        let next = sample.next_collision.map(|time| time.to_string()).unwrap_or_default();
+        writeln!(file, "{},{},{},{},{},{},{}", sample.time.as_secs_f64(), sample.active_collisions, sample.kinetic_energy, sample.average_speed, values(&sample.centre_of_mass), next, values(&sample.centre_of_mass_velocity))?;
+    }
+    file.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broadphase::BruteForce;
+    use crate::PARTICLE_RADIUS;
+
+    #[test]
+    fn samples_add_up_the_whole_system() {
+        let particles = vec![
+            Particle::new(1.0, 1.0, 3.0, 4.0, PARTICLE_RADIUS),
+            Particle { mass: Particle::new(0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS).mass * 3.0, ..Particle::new(1.05, 1.0, 0.0, 1.0, PARTICLE_RADIUS) },
+            Particle::new(5.0, 9.0, 0.0, 0.0, PARTICLE_RADIUS),
+        ];
+
+        let sample = FrameMetrics::measure(Duration::from_secs(2), &particles, &mut BruteForce::new());
+
+        assert_eq!(sample.active_collisions, 1);
+        assert_eq!(sample.kinetic_energy, kinetic_energy(&particles));
+        assert!((sample.average_speed - 2.0).abs() < 1e-6); // (5 + 1 + 0) / 3
+        let [x, y] = sample.centre_of_mass;
+        assert!((x - (1.0 + 3.15 + 5.0) / 5.0).abs() < 1e-5 && (y - (1.0 + 3.0 + 9.0) / 5.0).abs() < 1e-5);
+        let [vx, vy] = sample.centre_of_mass_velocity;
+        assert!((vx - 3.0 / 5.0).abs() < 1e-5 && (vy - (4.0 + 3.0) / 5.0).abs() < 1e-5);
+
+        assert_eq!(sample.next_collision, None); // 0 passes 1.6 from 2, and 1 heads straight up, nowhere near it
+
+        let heading = [Particle::new(5.0, 5.0, 1.0, 0.0, PARTICLE_RADIUS), Particle::new(6.0, 5.0, -1.0, 0.0, PARTICLE_RADIUS)];
+        assert!((FrameMetrics::measure(Duration::ZERO, &heading, &mut BruteForce::new()).next_collision.unwrap() - 0.45).abs() < 1e-6);
+        assert_eq!(FrameMetrics::<2>::measure(Duration::ZERO, &[], &mut BruteForce::new()).centre_of_mass, [0.0, 0.0]);
+    }
+}
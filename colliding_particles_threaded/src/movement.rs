@@ -0,0 +1,216 @@
+// How particles get from one place to the next when nothing pushes them, picked with --movement
+// Only ballistic particles feel forces, when there are any the integrator moves them instead so the forces can be mixed in
+use crate::config::SimConfig;
+use crate::{gaussian, Enclosure, Particle};
+use rand::Rng;
+use serde::Serialize;
+
+pub const DIFFUSION_COEFFICIENT : f32 = 0.04; // How fast Brownian particles spread out, in square units a second
+pub const JUMP_STDDEV : f32 = 0.05; // Standard deviation of each axis of a Gaussian jump, half the distance two default particles collide at
+
+// rng is a &mut dyn Rng rather than a &mut dyn RngCore, as rand 0.10 renamed RngCore to Rng and left the old name as a deprecated stub
+// It is the same object-safe trait RngCore was, so any generator can still be passed in
+pub trait MovementModel<const N: usize = 2>: Send {
+    fn step(&self, p: &mut Particle<N>, dt: f32, rng: &mut dyn Rng);
+}
+
+// Along the particle's velocity in a straight line
+pub struct Ballistic;
+
+impl<const N: usize> MovementModel<N> for Ballistic {
+    fn step(&self, p: &mut Particle<N>, dt: f32, _rng: &mut dyn Rng) {
+        p.integrate(dt);
+    }
+}
+
+// A normally distributed distance along each axis with a standard deviation of sqrt(2 * diffusion * dt), so the particle diffuses:
+// the mean squared distance it has gone along each axis grows as 2 * diffusion * t whatever the timestep
+// The velocity is left alone, it only matters when two particles bounce
+pub struct BrownianWalk {
+    diffusion: f32,
+    is_3d: bool,
+}
+
+impl BrownianWalk {
+    pub fn new(diffusion: f32, depth: f32) -> Self {
+        BrownianWalk { diffusion, is_3d: depth > 0.0 }
+    }
+}
+
+impl<const N: usize> MovementModel<N> for BrownianWalk {
+    fn step(&self, p: &mut Particle<N>, dt: f32, rng: &mut dyn Rng) {
+        let spread = (2.0 * self.diffusion * dt).sqrt();
+        for (axis, position) in p.position.iter_mut().enumerate() {
+            if axis < 2 || self.is_3d {
+                *position += gaussian(rng) * spread;
+            }
+        }
+    }
+}
+
+// Anywhere in the enclosure every step, however far that is from where the particle was
+// This is how particles moved before they had velocities, kept for comparison
+pub struct RandomTeleport {
+    enclosure: Enclosure,
+    depth: f32,
+}
+
+impl RandomTeleport {
+    pub fn new(enclosure: Enclosure, depth: f32) -> Self {
+        RandomTeleport { enclosure, depth }
+    }
+}
+
+impl<const N: usize> MovementModel<N> for RandomTeleport {
+    fn step(&self, p: &mut Particle<N>, _dt: f32, rng: &mut dyn Rng) {
+        p.position = self.enclosure.random_position(self.depth, rng);
+    }
+}
+
+// A normally distributed jump from where the particle is every step, with the same standard deviation along each axis whatever the timestep
+// Unlike teleporting, a particle stays near where it was, so the particles it collides with are ones it was already close to
+// A jump that would leave the enclosure stops at the nearest point inside it
+pub struct GaussianJump {
+    stddev: f32,
+    enclosure: Enclosure,
+    depth: f32,
+}
+
+impl GaussianJump {
+    pub fn new(stddev: f32, enclosure: Enclosure, depth: f32) -> Self {
+        GaussianJump { stddev, enclosure, depth }
+    }
+}
+
+impl<const N: usize> MovementModel<N> for GaussianJump {
+    fn step(&self, p: &mut Particle<N>, _dt: f32, rng: &mut dyn Rng) {
+        let x = p.position[0] + gaussian(rng) * self.stddev;
+        let y = p.position[1] + gaussian(rng) * self.stddev;
+        (p.position[0], p.position[1]) = self.enclosure.clamp(x, y);
+        if self.depth > 0.0 {
+            for position in &mut p.position[2..] {
+                *position = (*position + gaussian(rng) * self.stddev).clamp(0.0, self.depth);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MovementKind {
+    Ballistic,
+    Brownian,
+    Teleport,
+    Gaussian,
+}
+
+impl MovementKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ballistic" => Some(MovementKind::Ballistic),
+            "brownian" => Some(MovementKind::Brownian),
+            "teleport" => Some(MovementKind::Teleport),
+            "gaussian" => Some(MovementKind::Gaussian),
+            _ => None,
+        }
+    }
+
+    pub fn build<const N: usize>(&self, config: &SimConfig) -> Box<dyn MovementModel<N>> {
+        match self {
+            MovementKind::Ballistic => Box::new(Ballistic),
+            MovementKind::Brownian => Box::new(BrownianWalk::new(config.diffusion, config.depth)),
+            MovementKind::Teleport => Box::new(RandomTeleport::new(config.enclosure, config.depth)),
+            MovementKind::Gaussian => Box::new(GaussianJump::new(config.jump_stddev, config.enclosure, config.depth)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PARTICLE_RADIUS, TIMESTEP};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn each_model_moves_a_particle_its_own_way() {
+        let mut rng = StdRng::seed_from_u64(5);
+        let start = Particle::new(5.0, 5.0, 1.0, -2.0, PARTICLE_RADIUS);
+
+        let mut ballistic = start;
+        Ballistic.step(&mut ballistic, TIMESTEP, &mut rng);
+        assert_eq!((ballistic.position[0], ballistic.position[1]), (5.0 + TIMESTEP, 5.0 - 2.0 * TIMESTEP));
+
+        for _ in 0..100 {
+            let mut brownian = start;
+            BrownianWalk::new(DIFFUSION_COEFFICIENT, 0.0).step(&mut brownian, TIMESTEP, &mut rng);
+            assert!(brownian.position[0] != start.position[0] && brownian.position[1] != start.position[1]);
+            assert_eq!(brownian.velocity, start.velocity);
+        }
+
+        let dish = Enclosure::Circle { radius: 2.0 };
+        for _ in 0..100 {
+            let mut teleported = start;
+            RandomTeleport::new(dish, 0.0).step(&mut teleported, TIMESTEP, &mut rng);
+            assert!(dish.contains(teleported.position[0], teleported.position[1]));
+        }
+
+        for _ in 0..100 {
+            let mut jumped = start;
+            GaussianJump::new(JUMP_STDDEV, Enclosure::default(), 0.0).step(&mut jumped, TIMESTEP, &mut rng);
+            assert!((jumped.position[0] - start.position[0]).abs() < 10.0 * JUMP_STDDEV && jumped.position[0] != start.position[0]);
+            assert_eq!(jumped.velocity, start.velocity);
+        }
+    }
+
+    #[test]
+    fn gaussian_jumps_have_the_stddev_and_stay_inside() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let jump = GaussianJump::new(0.2, Enclosure::Rect { w: 10.0, h: 10.0 }, 10.0);
+        let jumps = 5000;
+        let mut squared_distance = [0.0f64; 3];
+        for _ in 0..jumps {
+            let mut p = Particle::new_3d(5.0, 5.0, 5.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS);
+            jump.step(&mut p, TIMESTEP, &mut rng);
+            for (total, along) in squared_distance.iter_mut().zip(p.position) {
+                *total += ((along - 5.0) * (along - 5.0)) as f64;
+            }
+        }
+        for total in squared_distance {
+            let variance = total / jumps as f64;
+            assert!((variance - 0.04).abs() < 0.004, "variance {} rather than 0.04", variance);
+        }
+
+        // Too far to stay inside, so they end up on the wall
+        let wide = GaussianJump::new(100.0, Enclosure::Circle { radius: 2.0 }, 1.0);
+        for _ in 0..100 {
+            let mut p = Particle::new_3d(2.0, 2.0, 0.5, 0.0, 0.0, 0.0, PARTICLE_RADIUS);
+            wide.step(&mut p, TIMESTEP, &mut rng);
+            assert!(((p.position[0] - 2.0).powi(2) + (p.position[1] - 2.0).powi(2) - 4.0).abs() < 1e-3);
+            assert!(p.position[2] == 0.0 || p.position[2] == 1.0);
+        }
+    }
+
+    #[test]
+    fn brownian_particles_spread_out_at_the_diffusion_rate() {
+        // After 100 steps, one second, the mean squared distance along each axis should be 2 * diffusion
+        let mut rng = StdRng::seed_from_u64(9);
+        let walk = BrownianWalk::new(0.5, 1.0);
+        let walkers = 2000;
+        let mut squared_distance = [0.0f64; 3];
+        for _ in 0..walkers {
+            let mut p = Particle::new_3d(0.0, 0.0, 0.0, 0.0, 0.0, 0.0, PARTICLE_RADIUS);
+            for _ in 0..100 {
+                walk.step(&mut p, TIMESTEP, &mut rng);
+            }
+            for (total, along) in squared_distance.iter_mut().zip(p.position) {
+                *total += (along * along) as f64;
+            }
+        }
+
+        for total in squared_distance {
+            let mean = total / walkers as f64;
+            assert!((mean - 1.0).abs() < 0.1, "spread {} rather than 1", mean);
+        }
+    }
+}
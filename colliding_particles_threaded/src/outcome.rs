@@ -0,0 +1,92 @@
+// What happens to two particles when they collide, picked with --outcome
+// Anything but a bounce changes the particle count mid-run, which the move threads pick up as they work their chunks out again
+use crate::{Enclosure, ParticleSystem};
+use serde::Serialize;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CollisionOutcome {
+    Bounce, // Off each other, keeping both
+    Annihilate, // Both particles disappear
+    Merge, // The two become one bigger particle, so the system coalesces over time
+}
+
+impl CollisionOutcome {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bounce" => Some(CollisionOutcome::Bounce),
+            "annihilate" => Some(CollisionOutcome::Annihilate),
+            "merge" => Some(CollisionOutcome::Merge),
+            _ => None,
+        }
+    }
+
+    // Apply the outcome to every pair of ids found colliding in one frame, returning how many pairs were annihilated or merged
+    // A particle can be in more than one pair, once it has gone its other pairs are skipped rather than taking their partners with it
+    // wrap is the enclosure when its edges wrap, so pairs found across an edge are bounced or merged the short way round
+    pub fn resolve<const N: usize>(&self, system: &mut ParticleSystem<N>, pairs: Vec<(u64, u64)>, wrap: Option<&Enclosure>) -> usize {
+        match self {
+            CollisionOutcome::Bounce => {
+                for (a, b) in pairs {
+                    system.resolve_collision(a, b, wrap);
+                }
+                0
+            }
+            CollisionOutcome::Annihilate => pairs.into_iter().filter(|&(a, b)| system.annihilate(a, b)).count(),
+            CollisionOutcome::Merge => pairs.into_iter().filter(|&(a, b)| system.merge(a, b, wrap)).count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Particle, PARTICLE_RADIUS};
+
+    #[test]
+    fn annihilated_particles_only_go_once() {
+        let mut system = ParticleSystem::new(Vec::new());
+        for x in [1.0, 1.05, 1.1, 5.0] {
+            system.spawn(Particle::new(x, 1.0, 0.0, 0.0, PARTICLE_RADIUS));
+        }
+
+        // 1 touches both its neighbours, so once it has gone with 0 the pair with 2 is left alone
+        assert_eq!(CollisionOutcome::Annihilate.resolve(&mut system, vec![(0, 1), (1, 2)], None), 1);
+        assert_eq!(system.particles.iter().map(|p| p.id).collect::<Vec<_>>(), vec![2, 3]);
+
+        assert_eq!(CollisionOutcome::Bounce.resolve(&mut system, vec![(2, 3)], None), 0);
+        assert_eq!(system.particles.len(), 2);
+    }
+
+    #[test]
+    fn merged_particles_conserve_mass_momentum_and_area() {
+        let mut system = ParticleSystem::new(Vec::new());
+        system.spawn(Particle::new(1.0, 1.0, 2.0, 0.0, 0.03));
+        system.spawn(Particle { mass: 3.0, ..Particle::new(1.04, 1.0, -1.0, 1.0, 0.04) });
+        system.spawn(Particle::new(1.1, 1.0, 0.0, 0.0, 0.03));
+        let (a, b) = (system.particles[0], system.particles[1]);
+
+        // Once 1 has merged into 0 the pair with 2 is left alone, as 1 has gone
+        assert_eq!(CollisionOutcome::Merge.resolve(&mut system, vec![(1, 0), (1, 2)], None), 1);
+        assert_eq!(system.particles.iter().map(|p| p.id).collect::<Vec<_>>(), vec![0, 2]);
+
+        let merged = system.particles[0];
+        assert_eq!(merged.mass, a.mass + b.mass);
+        assert!((merged.mass * merged.velocity[0] - (a.mass * a.velocity[0] + b.mass * b.velocity[0])).abs() < 1e-6);
+        assert!((merged.mass * merged.velocity[1] - (a.mass * a.velocity[1] + b.mass * b.velocity[1])).abs() < 1e-6);
+        assert!((merged.radius * merged.radius - (a.radius * a.radius + b.radius * b.radius)).abs() < 1e-7);
+        let (x, a_x, b_x) = (merged.position[0], a.position[0], b.position[0]);
+        assert!(x > a_x && x < b_x && x - a_x > b_x - x); // Nearer the heavier particle
+    }
+
+    #[test]
+    fn pairs_across_a_periodic_seam_merge_on_the_seam() {
+        let enclosure = Enclosure::Rect { w: 10.0, h: 10.0 };
+        let mut system = ParticleSystem::new(Vec::new());
+        system.spawn(Particle::new(0.05, 5.0, 0.0, 0.0, PARTICLE_RADIUS));
+        system.spawn(Particle::new(9.97, 5.0, 0.0, 0.0, PARTICLE_RADIUS));
+
+        assert_eq!(CollisionOutcome::Merge.resolve(&mut system, vec![(0, 1)], Some(&enclosure)), 1);
+        assert!((system.particles[0].position[0] - 0.01).abs() < 1e-5); // Halfway between them the short way round, not in the middle
+    }
+}
@@ -0,0 +1,133 @@
+// Timing of how long each thread waits for the particle system lock and how long it holds it, turned on with --profile
+// A thread that spends most of its time waiting is being held up by the others, so adding threads won't speed the run up
+use crate::lock_ignoring_poison;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// One thread's totals over a run
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockProfile {
+    pub thread: String,
+    pub acquisitions: u64,
+    pub waiting: Duration, // Between asking for the lock and getting it
+    pub holding: Duration, // Between getting the lock and letting it go
+}
+
+impl LockProfile {
+    fn new(thread: String) -> Self {
+        LockProfile { thread, acquisitions: 0, waiting: Duration::ZERO, holding: Duration::ZERO }
+    }
+}
+
+impl fmt::Display for LockProfile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} waited {:.3}s and held {:.3}s over {} acquisitions", self.thread, self.waiting.as_secs_f64(), self.holding.as_secs_f64(), self.acquisitions)
+    }
+}
+
+// Collects a profile from every thread of a run, in the order their profilers were handed out
+// When turned off every profiler it hands out does nothing, so no clock is read
+#[derive(Clone)]
+pub struct LockProfiles {
+    enabled: bool,
+    profiles: Arc<Mutex<Vec<LockProfile>>>,
+}
+
+impl LockProfiles {
+    pub fn new(enabled: bool) -> Self {
+        LockProfiles { enabled, profiles: Arc::new(Mutex::new(Vec::new())) }
+    }
+
+    // A profiler for one thread, its totals are filled in when it is dropped
+    pub fn profiler(&self, thread: &str) -> LockProfiler {
+        if !self.enabled {
+            return LockProfiler::disabled();
+        }
+
+        let mut profiles = lock_ignoring_poison(&self.profiles);
+        profiles.push(LockProfile::new(thread.to_string()));
+        LockProfiler { profile: LockProfile::new(thread.to_string()), sink: Some((self.clone(), profiles.len() - 1)) }
+    }
+
+    // Every profile so far, empty when profiling is off
+    pub fn take(&self) -> Vec<LockProfile> {
+        std::mem::take(&mut *lock_ignoring_poison(&self.profiles))
+    }
+}
+
+// Times the lock acquisitions of one thread
+pub struct LockProfiler {
+    profile: LockProfile,
+    sink: Option<(LockProfiles, usize)>, // Where to put the totals, None when profiling is off
+}
+
+impl LockProfiler {
+    pub fn disabled() -> Self {
+        LockProfiler { profile: LockProfile::new(String::new()), sink: None }
+    }
+
+    // Take a lock with lock and pass its guard to f, timing the wait and how long f held it
+    // f is given the guard itself, so the lock is released as soon as f returns
+    pub fn hold<G, T>(&mut self, lock: impl FnOnce() -> G, f: impl FnOnce(G) -> T) -> T {
+        if self.sink.is_none() {
+            return f(lock());
+        }
+
+        let asked = Instant::now();
+        let guard = lock();
+        let acquired = Instant::now();
+        let result = f(guard);
+
+        self.profile.acquisitions += 1;
+        self.profile.waiting += acquired - asked;
+        self.profile.holding += acquired.elapsed();
+        result
+    }
+}
+
+impl Drop for LockProfiler {
+    fn drop(&mut self) {
+        if let Some((profiles, index)) = &self.sink {
+            let profile = std::mem::replace(&mut self.profile, LockProfile::new(String::new()));
+            if let Some(slot) = lock_ignoring_poison(&profiles.profiles).get_mut(*index) {
+                *slot = profile;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::RwLock;
+
+    #[test]
+    fn profilers_report_in_the_order_they_were_handed_out() {
+        let profiles = LockProfiles::new(true);
+        let lock = RwLock::new(0);
+
+        let mut first = profiles.profiler("first");
+        let mut second = profiles.profiler("second");
+        second.hold(|| lock.write().unwrap(), |mut value| *value += 1);
+        first.hold(|| lock.write().unwrap(), |_| std::thread::sleep(Duration::from_millis(5)));
+        first.hold(|| lock.read().unwrap(), |_| ());
+        drop(second);
+        drop(first);
+
+        let profiles = profiles.take();
+        assert_eq!(profiles.iter().map(|p| (p.thread.as_str(), p.acquisitions)).collect::<Vec<_>>(), vec![("first", 2), ("second", 1)]);
+        assert!(profiles[0].holding >= Duration::from_millis(5));
+    }
+
+    #[test]
+    fn nothing_is_collected_when_profiling_is_off() {
+        let profiles = LockProfiles::new(false);
+        let lock = RwLock::new(0);
+
+        profiles.profiler("mover").hold(|| lock.write().unwrap(), |mut value| *value += 1);
+
+        assert_eq!(*lock.read().unwrap(), 1);
+        assert!(profiles.take().is_empty());
+    }
+}
@@ -0,0 +1,97 @@
+// A progress bar on stderr with the step rate and time left, turned on with --progress
+// It follows the steps in a run with a step count and the clock otherwise, and is cleared before the report is printed
+// Nothing is drawn when stderr isn't a terminal, so piped and batch runs stay quiet either way
+use crate::config::SimConfig;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const PROGRESS_INTERVAL : Duration = Duration::from_millis(100);
+
+// Steps taken so far, ticked by whichever thread sees every step: the coordinator in barrier mode, or the first move thread
+#[derive(Debug, Clone, Default)]
+pub struct StepCounter {
+    steps: Arc<AtomicU64>,
+}
+
+impl StepCounter {
+    pub fn tick(&self) {
+        self.steps.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.steps.load(Ordering::Relaxed)
+    }
+}
+
+// The monitor thread, which stops and clears the bar when this is dropped
+pub struct ProgressMonitor {
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl ProgressMonitor {
+    pub fn start(config: &SimConfig, steps: StepCounter) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread_done = Arc::clone(&done);
+
+        // In time mode the bar counts milliseconds, which indicatif's estimate of the time left works from just the same
+        let (length, template) = match config.steps {
+            Some(total) => (total as u64, "{wide_bar} {pos}/{len} steps, {msg}, {eta} left"),
+            None => (config.duration.as_millis() as u64, "{wide_bar} {elapsed} of the run, {msg}, {eta} left"),
+        };
+        let bar = ProgressBar::new(length);
+        bar.set_style(ProgressStyle::with_template(template).unwrap_or_else(|_| ProgressStyle::default_bar()));
+        let by_steps = config.steps.is_some();
+
+        let thread = thread::spawn(move || {
+            let start_time = Instant::now();
+            while !thread_done.load(Ordering::Relaxed) {
+                let elapsed = start_time.elapsed();
+                bar.set_position(if by_steps { steps.get() } else { elapsed.as_millis() as u64 });
+                bar.set_message(format!("{:.0} steps/s", steps_per_second(steps.get(), elapsed)));
+                thread::sleep(PROGRESS_INTERVAL);
+            }
+            bar.finish_and_clear();
+        });
+
+        ProgressMonitor { done, thread: Some(thread) }
+    }
+}
+
+impl Drop for ProgressMonitor {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn steps_per_second(steps: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        0.0
+    } else {
+        steps as f64 / elapsed.as_secs_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counters_share_their_steps_and_rates_are_per_second() {
+        let counter = StepCounter::default();
+        let clone = counter.clone();
+        for _ in 0..30 {
+            clone.tick();
+        }
+
+        assert_eq!(counter.get(), 30);
+        assert_eq!(steps_per_second(counter.get(), Duration::from_millis(1500)), 20.0);
+        assert_eq!(steps_per_second(counter.get(), Duration::ZERO), 0.0);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::broadphase::BruteForce;
+use crate::{detect_collisions, Enclosure, Particle, SpeciesPair, PIXELS_PER_UNIT};
+use image::{imageops, ImageResult, Rgba, RgbaImage};
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+const BACKGROUND : Rgba<u8> = Rgba([16, 16, 24, 255]);
+const PARTICLE_COLOUR : Rgba<u8> = Rgba([230, 230, 230, 255]);
+const COLLIDING_COLOUR : Rgba<u8> = Rgba([230, 60, 40, 255]);
+
+// The ends of the speed gradient, still particles are drawn in the first and those at the max speed or faster in the second
+const SLOW_COLOUR : [u8; 3] = [40, 80, 230];
+const FAST_COLOUR : [u8; 3] = [230, 60, 40];
+
+// The same colours for SVG output, on a white page so figures print well
+const SVG_OUTLINE : &str = "#202030";
+const SVG_PARTICLE : &str = "#606070";
+const SVG_COLLIDING : &str = "#e63c28";
+
+// Draws snapshots of the particles into numbered PNG files, e.g. frame_0001.png
+// With trails the particles are drawn onto a transparent layer kept from frame to frame, its alpha faded before each frame is drawn on it
+pub struct Renderer {
+    output_dir: PathBuf,
+    pixels_per_unit: f32,
+    width_px: u32,
+    height_px: u32,
+    trails: f32, // How much of the last frame's alpha is kept, 0 to start each frame afresh
+    speed_colours: Option<f32>, // The speed at the red end of the gradient, or None to pick out colliding particles instead
+    trail: Option<RgbaImage>,
+}
+
+impl Renderer {
+    // Create the output directory if it doesn't exist yet
+    pub fn new(output_dir: &str, width: f32, height: f32, pixels_per_unit: f32, trails: f32, speed_colours: Option<f32>) -> std::io::Result<Self> {
+        std::fs::create_dir_all(output_dir)?;
+
+        Ok(Renderer {
+            output_dir: PathBuf::from(output_dir),
+            pixels_per_unit,
+            width_px: ((width * pixels_per_unit).ceil() as u32).max(1),
+            height_px: ((height * pixels_per_unit).ceil() as u32).max(1),
+            trails,
+            speed_colours,
+            trail: None,
+        })
+    }
+
+    // Write one frame, with particles in any of the colliding pairs drawn in a different colour
+    pub fn render_frame<const N: usize>(&mut self, particles: &[Particle<N>], colliding_pairs: &[(usize, usize, SpeciesPair)], frame: usize) -> ImageResult<()> {
+        self.draw(particles, colliding_pairs).save(self.output_dir.join(format!("frame_{:04}.png", frame)))
+    }
+
+    // The faded layer goes down first, so the particles drawn this frame are always at full brightness
+    fn draw<const N: usize>(&mut self, particles: &[Particle<N>], colliding_pairs: &[(usize, usize, SpeciesPair)]) -> RgbaImage {
+        let mut layer = match self.trail.take() {
+            Some(mut layer) => {
+                for pixel in layer.pixels_mut() {
+                    pixel[3] = (pixel[3] as f32 * self.trails) as u8;
+                }
+                layer
+            }
+            None => RgbaImage::new(self.width_px, self.height_px), // Fully transparent
+        };
+
+        let mut colliding = vec![false; particles.len()];
+        for &(i, j, _) in colliding_pairs {
+            colliding[i] = true;
+            colliding[j] = true;
+        }
+
+        for (p, &is_colliding) in particles.iter().zip(&colliding) {
+            let colour = match self.speed_colours {
+                Some(max_speed) => {
+                    let [r, g, b] = speed_to_rgb(p.speed(), max_speed);
+                    Rgba([r, g, b, 255])
+                }
+                None if is_colliding => COLLIDING_COLOUR,
+                None => PARTICLE_COLOUR,
+            };
+            self.fill_circle(&mut layer, p, colour);
+        }
+
+        let mut image = RgbaImage::from_pixel(self.width_px, self.height_px, BACKGROUND);
+        imageops::overlay(&mut image, &layer, 0, 0);
+        if self.trails > 0.0 {
+            self.trail = Some(layer);
+        }
+        image
+    }
+
+    // Particles are drawn at their own radius, so they touch on screen when they collide
+    fn fill_circle<const N: usize>(&self, image: &mut RgbaImage, p: &Particle<N>, colour: Rgba<u8>) {
+        let centre_x = p.position[0] * self.pixels_per_unit;
+        let centre_y = (self.height_px as f32) - p.position[1] * self.pixels_per_unit; // Flip so y points up like the simulation
+        let radius = (p.radius * self.pixels_per_unit).max(1.0);
+
+        let min_x = (centre_x - radius).floor().max(0.0) as u32;
+        let max_x = ((centre_x + radius).ceil().max(0.0) as u32).min(self.width_px);
+        let min_y = (centre_y - radius).floor().max(0.0) as u32;
+        let max_y = ((centre_y + radius).ceil().max(0.0) as u32).min(self.height_px);
+
+        for py in min_y..max_y {
+            for px in min_x..max_x {
+                let dist_x = px as f32 + 0.5 - centre_x;
+                let dist_y = py as f32 + 0.5 - centre_y;
+                if dist_x * dist_x + dist_y * dist_y <= radius * radius {
+                    image.put_pixel(px, py, colour);
+                }
+            }
+        }
+    }
+}
+
+// Blue for a still particle through to red at max_speed, with anything faster clamped to red
+pub fn speed_to_rgb(speed: f32, max_speed: f32) -> [u8; 3] {
+    let t = (speed / max_speed).clamp(0.0, 1.0);
+    let mut rgb = [0; 3];
+    for ((channel, &slow), &fast) in rgb.iter_mut().zip(&SLOW_COLOUR).zip(&FAST_COLOUR) {
+        *channel = (slow as f32 + (fast as f32 - slow as f32) * t).round() as u8;
+    }
+    rgb
+}
+
+// Write the particles as an SVG, one <circle> each at its own radius inside an outline of the enclosure
+// Particles overlapping another are filled in a different colour, found by checking every pair directly
+// Units are simulation units with y flipped to point up, and the page is sized at PIXELS_PER_UNIT like the PNG frames
+pub fn write_svg<const N: usize>(particles: &[Particle<N>], path: &str, enclosure: &Enclosure) -> std::io::Result<()> {
+    std::fs::write(path, svg(particles, enclosure))
+}
+
+fn svg<const N: usize>(particles: &[Particle<N>], enclosure: &Enclosure) -> String {
+    let (width, height) = (enclosure.width(), enclosure.height());
+    let mut colliding = vec![false; particles.len()];
+    for (i, j, _) in detect_collisions(particles, &mut BruteForce::new()) {
+        colliding[i] = true;
+        colliding[j] = true;
+    }
+
+    let mut svg = String::new();
+    let _ = writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#, width * PIXELS_PER_UNIT, height * PIXELS_PER_UNIT, width, height);
+    let _ = writeln!(svg, r#"<g transform="translate(0 {}) scale(1 -1)">"#, height);
+
+    let outline_width = height.max(width) / 500.0;
+    let _ = match *enclosure {
+        Enclosure::Rect { w, h } => writeln!(svg, r#"<rect x="0" y="0" width="{}" height="{}" fill="none" stroke="{}" stroke-width="{}"/>"#, w, h, SVG_OUTLINE, outline_width),
+        Enclosure::Circle { radius } => writeln!(svg, r#"<circle cx="{0}" cy="{0}" r="{0}" fill="none" stroke="{1}" stroke-width="{2}"/>"#, radius, SVG_OUTLINE, outline_width),
+    };
+
+    for (p, &is_colliding) in particles.iter().zip(&colliding) {
+        let fill = if is_colliding { SVG_COLLIDING } else { SVG_PARTICLE };
+        let _ = writeln!(svg, r#"<circle cx="{}" cy="{}" r="{}" fill="{}"/>"#, p.position[0], p.position[1], p.radius, fill);
+    }
+
+    svg.push_str("</g>\n</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PARTICLE_RADIUS;
+
+    #[test]
+    fn trails_fade_behind_particles_drawn_at_full_brightness() {
+        let dir = std::env::temp_dir().join(format!("particles_trails_{}", std::process::id()));
+        let mut renderer = Renderer::new(dir.to_str().unwrap(), 10.0, 10.0, 10.0, 0.5, None).unwrap();
+        let mut plain = Renderer::new(dir.to_str().unwrap(), 10.0, 10.0, 10.0, 0.0, None).unwrap();
+        let _ = std::fs::remove_dir(&dir);
+        let (first, second) = ([Particle::new(2.0, 5.0, 0.0, 0.0, 0.5)], [Particle::new(8.0, 5.0, 0.0, 0.0, 0.5)]);
+
+        renderer.draw(&first, &[]);
+        let image = renderer.draw(&second, &[]);
+        assert_eq!(*image.get_pixel(80, 50), PARTICLE_COLOUR);
+        let trail = image.get_pixel(20, 50);
+        assert!(trail[0] > BACKGROUND[0] && trail[0] < PARTICLE_COLOUR[0]);
+
+        plain.draw(&first, &[]);
+        let image = plain.draw(&second, &[]);
+        assert_eq!((*image.get_pixel(80, 50), *image.get_pixel(20, 50)), (PARTICLE_COLOUR, BACKGROUND));
+    }
+
+    #[test]
+    fn speeds_run_from_blue_to_red() {
+        assert_eq!(speed_to_rgb(0.0, 2.0), SLOW_COLOUR);
+        assert_eq!(speed_to_rgb(2.0, 2.0), FAST_COLOUR);
+        assert_eq!(speed_to_rgb(10.0, 2.0), FAST_COLOUR);
+        assert_eq!(speed_to_rgb(1.0, 2.0), [135, 70, 135]);
+
+        let dir = std::env::temp_dir().join(format!("particles_speed_colours_{}", std::process::id()));
+        let mut renderer = Renderer::new(dir.to_str().unwrap(), 10.0, 10.0, 10.0, 0.0, Some(2.0)).unwrap();
+        let _ = std::fs::remove_dir(&dir);
+        let particles = [Particle::new(2.0, 5.0, 0.0, 0.0, 0.5), Particle::new(8.0, 5.0, 3.0, 4.0, 0.5)];
+        let image = renderer.draw(&particles, &[]);
+        let [r, g, b] = SLOW_COLOUR;
+        assert_eq!(*image.get_pixel(20, 50), Rgba([r, g, b, 255]));
+        let [r, g, b] = FAST_COLOUR; // Moving at 5, past the max
+        assert_eq!(*image.get_pixel(80, 50), Rgba([r, g, b, 255]));
+    }
+
+    #[test]
+    fn svg_has_one_circle_per_particle_with_colliding_ones_picked_out() {
+        let particles = vec![
+            Particle::new(1.0, 1.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new(1.05, 1.0, 0.0, 0.0, PARTICLE_RADIUS),
+            Particle::new(5.0, 5.0, 0.0, 0.0, PARTICLE_RADIUS),
+        ];
+
+        let svg = svg(&particles, &Enclosure::Rect { w: 10.0, h: 5.0 });
+
+        assert!(svg.contains(r#"viewBox="0 0 10 5""#));
+        assert!(svg.contains("<rect "));
+        assert_eq!(svg.matches("<circle ").count(), 3);
+        assert_eq!(svg.matches(SVG_COLLIDING).count(), 2);
+        assert!(svg.contains(&format!(r#"<circle cx="5" cy="5" r="0.05" fill="{}"/>"#, SVG_PARTICLE)));
+    }
+}
@@ -0,0 +1,199 @@
+use crate::broadphase::SpatialGrid;
+use crate::trajectory::header;
+use crate::{detect_collisions, CollisionStats, Particle};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+    Inconsistent(String),
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::Io(error) => write!(f, "Could not read trajectory file: {}", error),
+            ReplayError::Parse { line, message } => write!(f, "Could not parse trajectory file at line {}: {}", line, message),
+            ReplayError::Inconsistent(message) => write!(f, "Inconsistent trajectory file: {}", message),
+        }
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(error: io::Error) -> Self {
+        ReplayError::Io(error)
+    }
+}
+
+// What a replay found
+#[derive(Debug)]
+pub struct ReplayReport {
+    pub frames: usize,
+    pub particle_count: usize,
+    pub dropped_frames: usize, // Incomplete frames at the end, from move threads that stopped a few iterations before the others
+    pub collisions: CollisionStats,
+}
+
+// Read a file written by TrajectoryRecorder and run every complete frame through detect_collisions in frame order
+// Nothing is moved or bounced, so the same file always gives the same answer
+// The header says whether the run was 2D or 3D, anything that isn't a 3D header is read as 2D and rejected there if it isn't that either
+pub fn replay(path: &str) -> Result<ReplayReport, ReplayError> {
+    let first_line = BufReader::new(File::open(path)?).lines().next().transpose()?;
+    if first_line.is_some_and(|line| line.trim() == header(3)) {
+        replay_axes::<3>(path)
+    } else {
+        replay_axes::<2>(path)
+    }
+}
+
+fn replay_axes<const N: usize>(path: &str) -> Result<ReplayReport, ReplayError> {
+    let frames = read_frames::<N, _>(BufReader::new(File::open(path)?))?;
+    let (particle_count, complete_frames) = complete_frames(&frames)?;
+
+    // Size the grid to fit everything recorded, as the file doesn't say how big the enclosure was
+    let all_particles = || complete_frames.iter().flat_map(|frame| frame.iter());
+    let max_radius = all_particles().map(|p| p.radius).fold(0.0f32, f32::max);
+    let lengths : [f32; N] = std::array::from_fn(|axis| all_particles().map(|p| p.position[axis]).fold(0.0f32, f32::max));
+    let depth = lengths[2..].iter().copied().fold(0.0f32, f32::max);
+    let mut broadphase = SpatialGrid::new(lengths[0], lengths[1], depth, (max_radius * 2.0).max(f32::MIN_POSITIVE));
+
+    let mut collisions = CollisionStats { frames: complete_frames.len(), ..CollisionStats::default() };
+    let mut previous_overlaps : HashSet<(u64, u64)> = HashSet::new();
+
+    for particles in &complete_frames {
+        // The file doesn't say which species each particle was, so every collision is counted as species 0
+        let overlaps : Vec<_> = detect_collisions(particles, &mut broadphase).into_iter().map(|(i, j, species)| ((particles[i].id, particles[j].id), species)).collect();
+        collisions.count_overlaps(&overlaps, &mut previous_overlaps);
+    }
+
+    Ok(ReplayReport {
+        frames: complete_frames.len(),
+        particle_count,
+        dropped_frames: frames.len() - complete_frames.len(),
+        collisions,
+    })
+}
+
+// Rows from every move thread, grouped by frame number
+// The recorder writes batches in whatever order the threads send them, so rows for one frame can be spread through the file
+fn read_frames<const N: usize, R: BufRead>(reader: R) -> Result<BTreeMap<u32, Vec<Particle<N>>>, ReplayError> {
+    let mut lines = reader.lines();
+
+    let first_line = lines.next().ok_or_else(|| ReplayError::Parse { line: 1, message: "file is empty".to_string() })??;
+    if first_line.trim() != header(N) {
+        return Err(ReplayError::Parse { line: 1, message: format!("unexpected header {}, only files recorded with radii can be replayed", first_line.trim()) });
+    }
+    let column_count = N + 3;
+
+    let mut frames : BTreeMap<u32, Vec<Particle<N>>> = BTreeMap::new();
+
+    for (i, line) in lines.enumerate() {
+        let line_number = i + 2;
+        let line = line?;
+        let columns : Vec<&str> = line.trim().split(',').collect();
+        if columns.len() != column_count {
+            return Err(ReplayError::Parse { line: line_number, message: format!("expected {} columns, found {}", column_count, columns.len()) });
+        }
+
+        let parse_error = |column: &str| ReplayError::Parse { line: line_number, message: format!("invalid value {}", column) };
+        let number = |column: &str| column.parse::<f32>().map_err(|_| parse_error(column));
+
+        let frame : u32 = columns[0].parse().map_err(|_| parse_error(columns[0]))?;
+        let id : u64 = columns[1].parse().map_err(|_| parse_error(columns[1]))?;
+        let mut position = [0.0; N];
+        for (value, column) in position.iter_mut().zip(&columns[2..]) {
+            *value = number(column)?;
+        }
+        let radius = number(columns[column_count - 1])?;
+
+        frames.entry(frame).or_default().push(Particle { id, ..Particle::at(position, [0.0; N], radius) });
+    }
+
+    Ok(frames)
+}
+
+// Every frame must hold the same particles, apart from a run of incomplete frames at the very end
+fn complete_frames<const N: usize>(frames: &BTreeMap<u32, Vec<Particle<N>>>) -> Result<(usize, Vec<Vec<Particle<N>>>), ReplayError> {
+    let mut expected_ids : Option<Vec<u64>> = None;
+    let mut complete = Vec::new();
+    let mut first_incomplete : Option<u32> = None;
+
+    for (&frame, particles) in frames {
+        let mut particles = particles.clone();
+        particles.sort_by_key(|p| p.id);
+        let ids : Vec<u64> = particles.iter().map(|p| p.id).collect();
+
+        if ids.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(ReplayError::Inconsistent(format!("frame {} has the same particle more than once", frame)));
+        }
+
+        let expected = expected_ids.get_or_insert_with(|| ids.clone());
+        if ids == *expected {
+            if let Some(incomplete) = first_incomplete {
+                return Err(ReplayError::Inconsistent(format!("frame {} is missing particles but later frames aren't", incomplete)));
+            }
+            complete.push(particles);
+        } else if ids.len() < expected.len() && ids.iter().all(|id| expected.binary_search(id).is_ok()) {
+            first_incomplete.get_or_insert(frame);
+        } else {
+            return Err(ReplayError::Inconsistent(format!("frame {} has {} particles that don't match the {} in the first frame", frame, ids.len(), expected.len())));
+        }
+    }
+
+    let particle_count = expected_ids.map_or(0, |ids| ids.len());
+    if complete.is_empty() {
+        return Err(ReplayError::Inconsistent("no complete frames were recorded".to_string()));
+    }
+
+    Ok((particle_count, complete))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(contents: &str) -> Result<BTreeMap<u32, Vec<Particle>>, ReplayError> {
+        read_frames(contents.as_bytes())
+    }
+
+    #[test]
+    fn deep_files_keep_their_third_axis() {
+        let frames = read_frames::<3, _>("frame,particle_id,x,y,z,radius\n0,0,1,2,3,0.1\n".as_bytes()).unwrap();
+        assert_eq!(frames[&0][0].position, [1.0, 2.0, 3.0]);
+        assert!(matches!(read_frames::<3, _>("frame,particle_id,x,y,radius\n0,0,1,2,0.1\n".as_bytes()), Err(ReplayError::Parse { line: 1, .. })));
+    }
+
+    #[test]
+    fn rows_are_grouped_by_frame() {
+        let frames = frames("frame,particle_id,x,y,radius\n0,0,1,1,0.1\n10,0,2,2,0.1\n0,1,3,3,0.1\n").unwrap();
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[&0].len(), 2);
+        assert_eq!(frames[&10][0].position[0], 2.0);
+    }
+
+    #[test]
+    fn trailing_incomplete_frames_are_dropped() {
+        let frames = frames("frame,particle_id,x,y,radius\n0,0,1,1,0.1\n0,1,1.1,1,0.1\n10,0,2,2,0.1\n").unwrap();
+
+        let (particle_count, complete) = complete_frames(&frames).unwrap();
+        assert_eq!(particle_count, 2);
+        assert_eq!(complete.len(), 1);
+    }
+
+    #[test]
+    fn inconsistent_files_are_rejected() {
+        let gap = frames("frame,particle_id,x,y,radius\n0,0,1,1,0.1\n0,1,1,1,0.1\n10,0,2,2,0.1\n20,0,1,1,0.1\n20,1,1,1,0.1\n").unwrap();
+        assert!(matches!(complete_frames(&gap), Err(ReplayError::Inconsistent(_))));
+
+        let extra = frames("frame,particle_id,x,y,radius\n0,0,1,1,0.1\n10,0,2,2,0.1\n10,1,2,2,0.1\n").unwrap();
+        assert!(matches!(complete_frames(&extra), Err(ReplayError::Inconsistent(_))));
+
+        assert!(matches!(frames("frame,particle_id,x,y\n0,0,1,1\n"), Err(ReplayError::Parse { line: 1, .. })));
+        assert!(matches!(frames("frame,particle_id,x,y,radius\n0,0,1,oops,0.1\n"), Err(ReplayError::Parse { line: 2, .. })));
+    }
+}
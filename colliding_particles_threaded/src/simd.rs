@@ -0,0 +1,86 @@
+// Collision checks eight pairs at a time in the wide crate's vectors, used by the brute-force broadphases with the simd feature
+// Each lane does the same multiplies and adds in the same order as perform_collision_check, with nothing fused, so exactly
+// the same pairs are found as by checking them one at a time
+use crate::Particle;
+use std::convert::TryInto;
+use wide::f32x8;
+
+const LANES : usize = 8;
+
+// The particles' coordinates and radii as separate arrays, one for each axis, so eight neighbours load straight into a vector
+#[derive(Default)]
+pub struct Columns {
+    axes: Vec<Vec<f32>>,
+    radius: Vec<f32>,
+}
+
+impl Columns {
+    pub fn rebuild<const N: usize>(&mut self, particles: &[Particle<N>]) {
+        self.axes.resize_with(N, Vec::new);
+        for column in &mut self.axes {
+            column.clear();
+        }
+        self.radius.clear();
+        for p in particles {
+            for (column, &value) in self.axes.iter_mut().zip(&p.position) {
+                column.push(value);
+            }
+            self.radius.push(p.radius);
+        }
+    }
+
+    // Call f with every j after i whose particle collides with particle i, in order
+    // The few left over after the last whole vector are checked one at a time
+    pub fn for_each_collision_after(&self, i: usize, mut f: impl FnMut(usize)) {
+        let len = self.radius.len();
+        let radius = f32x8::splat(self.radius[i]);
+        let lanes = |column: &[f32], j: usize| f32x8::new(column[j..j + LANES].try_into().unwrap());
+
+        let mut j = i + 1;
+        while j + LANES <= len {
+            let distance_sq = self.axes.iter().fold(f32x8::ZERO, |sum, column| {
+                let dist = f32x8::splat(column[i]) - lanes(column, j);
+                sum + dist * dist
+            });
+            let reach = radius + lanes(&self.radius, j);
+            let colliding = distance_sq.simd_lt(reach * reach).to_bitmask();
+
+            for lane in 0..LANES {
+                if colliding & (1 << lane) != 0 {
+                    f(j + lane);
+                }
+            }
+            j += LANES;
+        }
+
+        for j in j..len {
+            let distance_sq = self.axes.iter().fold(0.0, |sum, column| sum + (column[i] - column[j]) * (column[i] - column[j]));
+            let reach = self.radius[i] + self.radius[j];
+            if distance_sq < reach * reach {
+                f(j);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Layout, ParticleSystem, RadiusDistribution};
+
+    #[test]
+    fn vectors_find_exactly_the_scalar_pairs() {
+        for &count in &[0, 5, 8, 9, 300] {
+            let particles = ParticleSystem::builder().particle_count(count).radius(RadiusDistribution::Uniform { min: 0.05, max: 0.5 }).initial_layout(Layout::RandomUniform).seed(2).build().particles;
+            let mut columns = Columns::default();
+            columns.rebuild(&particles);
+
+            for i in 0..count {
+                let mut found = Vec::new();
+                columns.for_each_collision_after(i, |j| found.push(j));
+                let expected : Vec<usize> = (i + 1..count).filter(|&j| particles[i].perform_collision_check(&particles[j])).collect();
+                assert_eq!(found, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+// The particles laid out as one array per field rather than one array of particles
+// Checking a pair only reads positions and radii, 12 of a 2D particle's 40 bytes, so with each field in its own array the pair
+// loop streams through just the bytes it needs and the compiler can vectorise it. Anything else still works on Vec<Particle>,
+// so the arrays are filled from a slice of particles and can be turned back into them
+use crate::{Particle, SpeciesPair};
+
+// position and velocity have one array per axis
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParticleSoA<const N: usize = 2> {
+    pub id: Vec<u64>,
+    pub position: [Vec<f32>; N],
+    pub velocity: [Vec<f32>; N],
+    pub radius: Vec<f32>,
+    pub mass: Vec<f32>,
+    pub species: Vec<u8>,
+}
+
+impl<const N: usize> Default for ParticleSoA<N> {
+    fn default() -> Self {
+        ParticleSoA { id: Vec::new(), position: std::array::from_fn(|_| Vec::new()), velocity: std::array::from_fn(|_| Vec::new()), radius: Vec::new(), mass: Vec::new(), species: Vec::new() }
+    }
+}
+
+impl<const N: usize> ParticleSoA<N> {
+    pub fn from_particles(particles: &[Particle<N>]) -> Self {
+        let mut soa = ParticleSoA::default();
+        soa.rebuild(particles);
+        soa
+    }
+
+    // Refill the arrays from particles, keeping their allocations for the next frame
+    pub fn rebuild(&mut self, particles: &[Particle<N>]) {
+        self.clear();
+        for p in particles {
+            self.push(p);
+        }
+    }
+
+    pub fn push(&mut self, p: &Particle<N>) {
+        self.id.push(p.id);
+        for axis in 0..N {
+            self.position[axis].push(p.position[axis]);
+            self.velocity[axis].push(p.velocity[axis]);
+        }
+        self.radius.push(p.radius);
+        self.mass.push(p.mass);
+        self.species.push(p.species);
+    }
+
+    fn clear(&mut self) {
+        self.id.clear();
+        for axis in 0..N {
+            self.position[axis].clear();
+            self.velocity[axis].clear();
+        }
+        self.radius.clear();
+        self.mass.clear();
+        self.species.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.id.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.id.is_empty()
+    }
+
+    pub fn particle(&self, i: usize) -> Particle<N> {
+        Particle {
+            id: self.id[i],
+            position: std::array::from_fn(|axis| self.position[axis][i]),
+            velocity: std::array::from_fn(|axis| self.velocity[axis][i]),
+            radius: self.radius[i],
+            mass: self.mass[i],
+            species: self.species[i],
+        }
+    }
+
+    pub fn to_particles(&self) -> Vec<Particle<N>> {
+        (0..self.len()).map(|i| self.particle(i)).collect()
+    }
+
+    // As detect_collisions with brute force, every colliding pair with their species, lower index first and sorted
+    // The distance is summed in the same order as perform_collision_check, so exactly the same pairs are found
+    pub fn detect_collisions(&self) -> Vec<(usize, usize, SpeciesPair)> {
+        let mut pairs = Vec::new();
+        for i in 0..self.len() {
+            for j in i + 1..self.len() {
+                let squared_distance = self.position.iter().fold(0.0, |sum, axis| {
+                    let distance = axis[i] - axis[j];
+                    sum + distance * distance
+                });
+                let reach = self.radius[i] + self.radius[j];
+                if squared_distance < reach * reach {
+                    pairs.push((i, j, (self.species[i].min(self.species[j]), self.species[i].max(self.species[j]))));
+                }
+            }
+        }
+        pairs
+    }
+}
+
+impl<const N: usize> From<&[Particle<N>]> for ParticleSoA<N> {
+    fn from(particles: &[Particle<N>]) -> Self {
+        ParticleSoA::from_particles(particles)
+    }
+}
+
+impl<const N: usize> From<&ParticleSoA<N>> for Vec<Particle<N>> {
+    fn from(soa: &ParticleSoA<N>) -> Self {
+        soa.to_particles()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::broadphase::BruteForce;
+    use crate::{detect_collisions, Layout, ParticleSystem, ParticleSystemBuilder, RadiusDistribution};
+
+    #[test]
+    fn particles_round_trip_through_the_arrays() {
+        let particles = ParticleSystemBuilder::<3>::default().particle_count(50).depth(3.0).initial_layout(Layout::RandomUniform).seed(4).build().particles;
+        let soa = ParticleSoA::from(&particles[..]);
+
+        assert_eq!(soa.len(), 50);
+        assert_eq!(soa.particle(7), particles[7]);
+        assert_eq!(Vec::from(&soa), particles);
+        assert!(ParticleSoA::<3>::from_particles(&[]).is_empty());
+    }
+
+    #[test]
+    fn the_arrays_find_exactly_the_brute_force_pairs() {
+        for &count in &[0, 1, 9, 500] {
+            let particles = ParticleSystem::builder().particle_count(count).radius(RadiusDistribution::Uniform { min: 0.05, max: 0.5 }).initial_layout(Layout::RandomUniform).seed(2).build().particles;
+            assert_eq!(ParticleSoA::from_particles(&particles).detect_collisions(), detect_collisions(&particles, &mut BruteForce::new()), "{} particles", count);
+        }
+    }
+}
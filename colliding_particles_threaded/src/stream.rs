@@ -0,0 +1,152 @@
+// Streams snapshots of the particles to any TCP clients that connect, for an external visualiser
+//
+// Every message is a 4 byte big-endian length followed by that many bytes of JSON:
+//     {"frame":12,"particles":[{"id":0,"position":[1.0,2.0],"velocity":[..,..],"radius":0.05,"mass":..},..]}
+// with one value in the position and velocity for each axis, so three in 3D runs
+//
+// The socket is written from its own thread, and frames are dropped rather than queued when it falls behind,
+// so a slow or missing client never holds up the simulation
+use crate::Particle;
+use log::warn;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+const WRITE_TIMEOUT : Duration = Duration::from_secs(1); // A client that stops reading for this long is dropped
+
+#[derive(Serialize)]
+struct Frame<'a, const N: usize> {
+    frame: usize,
+    particles: &'a [Particle<N>],
+}
+
+// The simulation's end of the stream, dropping it closes every connection and waits for the server thread
+pub struct FrameStreamer<const N: usize = 2> {
+    sender: Option<SyncSender<(usize, Vec<Particle<N>>)>>,
+    server: Option<JoinHandle<()>>,
+    port: u16,
+    interval: Duration, // Shortest time between frames sent
+    last_sent: Option<Instant>,
+}
+
+impl<const N: usize> FrameStreamer<N> {
+    // Listen on every interface, port 0 picks any free port
+    pub fn bind(port: u16, frames_per_second: f32) -> io::Result<Self> {
+        let listener = TcpListener::bind(("0.0.0.0", port))?;
+        listener.set_nonblocking(true)?; // Connections are picked up between frames
+        let port = listener.local_addr()?.port();
+
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let server = thread::spawn(move || serve(listener, receiver));
+
+        Ok(FrameStreamer { sender: Some(sender), server: Some(server), port, interval: Duration::from_secs_f32(1.0 / frames_per_second), last_sent: None })
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    // Pass a frame to the server thread unless one was sent too recently or it is still busy with the last
+    pub fn publish(&mut self, frame: usize, particles: &[Particle<N>]) {
+        if self.last_sent.is_some_and(|sent| sent.elapsed() < self.interval) {
+            return;
+        }
+
+        if let Some(sender) = &self.sender {
+            if sender.try_send((frame, particles.to_vec())).is_ok() {
+                self.last_sent = Some(Instant::now());
+            }
+        }
+    }
+}
+
+impl<const N: usize> Drop for FrameStreamer<N> {
+    fn drop(&mut self) {
+        self.sender = None; // Hangs up the channel, which ends the server loop
+        if let Some(server) = self.server.take() {
+            let _ = server.join();
+        }
+    }
+}
+
+// Send every frame to every client, picking up new clients before each and dropping any that can't be written to
+fn serve<const N: usize>(listener: TcpListener, frames: Receiver<(usize, Vec<Particle<N>>)>) {
+    let mut clients : Vec<TcpStream> = Vec::new();
+
+    for (frame, particles) in frames {
+        while let Ok((client, _)) = listener.accept() {
+            if client.set_nonblocking(false).and_then(|_| client.set_write_timeout(Some(WRITE_TIMEOUT))).is_ok() {
+                let _ = client.set_nodelay(true);
+                clients.push(client);
+            }
+        }
+
+        if clients.is_empty() {
+            continue;
+        }
+
+        let message = match serde_json::to_vec(&Frame { frame, particles: &particles }) {
+            Ok(message) => message,
+            Err(error) => {
+                warn!("Stopped streaming: {}", error);
+                return;
+            }
+        };
+        let length = (message.len() as u32).to_be_bytes();
+
+        clients.retain_mut(|client| client.write_all(&length).and_then(|_| client.write_all(&message)).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PARTICLE_RADIUS;
+    use std::io::Read;
+
+    fn read_message(client: &mut TcpStream) -> serde_json::Value {
+        let mut length = [0; 4];
+        client.read_exact(&mut length).unwrap();
+        let mut message = vec![0; u32::from_be_bytes(length) as usize];
+        client.read_exact(&mut message).unwrap();
+        serde_json::from_slice(&message).unwrap()
+    }
+
+    #[test]
+    fn clients_get_length_prefixed_frames_and_can_leave_at_any_time() {
+        let mut streamer = FrameStreamer::bind(0, 1000.0).unwrap();
+        let particles = vec![Particle::new(1.0, 2.0, 0.0, 0.0, PARTICLE_RADIUS), Particle::new(3.0, 4.0, 0.0, 0.0, PARTICLE_RADIUS)];
+
+        let mut client = TcpStream::connect(("127.0.0.1", streamer.port())).unwrap();
+        streamer.publish(7, &particles);
+
+        let message = read_message(&mut client);
+        assert_eq!(message["frame"], 7);
+        assert_eq!(message["particles"].as_array().unwrap().len(), 2);
+        assert_eq!(message["particles"][1]["position"][1], 4.0);
+
+        // Frames keep going after the client hangs up, and the server shuts down cleanly
+        drop(client);
+        for frame in 8..20 {
+            thread::sleep(Duration::from_millis(2));
+            streamer.publish(frame, &particles);
+        }
+        drop(streamer);
+    }
+
+    #[test]
+    fn frames_are_throttled() {
+        let mut streamer = FrameStreamer::bind(0, 1.0).unwrap();
+        let particles = vec![Particle::new(1.0, 2.0, 0.0, 0.0, PARTICLE_RADIUS)];
+
+        streamer.publish(0, &particles);
+        let first = streamer.last_sent;
+        streamer.publish(1, &particles);
+
+        assert!(first.is_some());
+        assert_eq!(streamer.last_sent, first);
+    }
+}
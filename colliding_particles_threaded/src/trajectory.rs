@@ -0,0 +1,83 @@
+use crate::{axes, Particle};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+// Position of one particle on one frame of a move thread
+struct TrajectorySample<const N: usize> {
+    frame: u32,
+    particle_id: u64,
+    position: [f32; N],
+    radius: f32,
+}
+
+// The first line of a file recorded from particles with axis_count axes
+pub(crate) fn header(axis_count: usize) -> String {
+    let columns : Vec<String> = (0..axis_count).map(axes::name).collect();
+    format!("frame,particle_id,{},radius", columns.join(","))
+}
+
+// Writes particle positions to a CSV file from a dedicated thread, so the move threads never wait on the disk
+pub struct TrajectoryRecorder<const N: usize = 2> {
+    sender: Sender<Vec<TrajectorySample<N>>>,
+    every: u32,
+    writer: JoinHandle<io::Result<()>>,
+}
+
+// Cheap to clone handle given to each move thread for sending samples to the recorder
+#[derive(Clone)]
+pub struct TrajectoryHandle<const N: usize = 2> {
+    sender: Sender<Vec<TrajectorySample<N>>>,
+    every: u32,
+}
+
+impl<const N: usize> TrajectoryRecorder<N> {
+    // Create the CSV file and start the writer thread, recording one row per particle every `every` frames
+    // There is a column for each axis, so 2D files have x and y and 3D ones a z as well
+    // The radius is recorded too so the file can be replayed through the collision checks
+    pub fn create(path: &str, every: u32) -> io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "{}", header(N))?;
+
+        let (sender, receiver) = mpsc::channel::<Vec<TrajectorySample<N>>>();
+
+        let writer = thread::spawn(move || {
+            for batch in receiver { // Ends once every sender has been dropped
+                for sample in batch {
+                    write!(file, "{},{}", sample.frame, sample.particle_id)?;
+                    for value in sample.position {
+                        write!(file, ",{}", value)?;
+                    }
+                    writeln!(file, ",{}", sample.radius)?;
+                }
+            }
+            file.flush()
+        });
+
+        Ok(TrajectoryRecorder { sender, every, writer })
+    }
+
+    pub fn handle(&self) -> TrajectoryHandle<N> {
+        TrajectoryHandle { sender: self.sender.clone(), every: self.every }
+    }
+
+    // Wait for everything sent so far to be written and close the file
+    // Every handle must have been dropped first, or this waits forever
+    pub fn finish(self) -> io::Result<()> {
+        drop(self.sender);
+        self.writer.join().expect("trajectory writer thread panicked")
+    }
+}
+
+impl<const N: usize> TrajectoryHandle<N> {
+    // Send the positions of a chunk of particles, by id, if this frame is one to be sampled
+    pub fn record(&self, frame: u32, particles: &[Particle<N>]) {
+        if !frame.is_multiple_of(self.every) {
+            return;
+        }
+
+        let batch = particles.iter().map(|p| TrajectorySample { frame, particle_id: p.id, position: p.position, radius: p.radius }).collect();
+        let _ = self.sender.send(batch); // Only fails if the writer has stopped after an IO error, which finish will report
+    }
+}
@@ -0,0 +1,179 @@
+// A live view of the enclosure drawn in the terminal with braille characters, turned on with --tui
+// Each character is a 2 by 4 grid of dots, and a dot is lit wherever at least one particle's centre falls
+// The view thread copies the particles under a read lock and draws from the copy, so the lock is held only as long as the copy takes
+// The terminal is put in raw mode so keys are read as they are pressed: space pauses and resumes the run, q or Ctrl-C stops it
+use crate::control::RunControl;
+use crate::{read_ignoring_poison, Enclosure, Particle, ParticleSystem};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+pub const TUI_COLUMNS : usize = 80;
+pub const TUI_ROWS : usize = 24;
+const TUI_FRAME_INTERVAL : Duration = Duration::from_millis(50);
+
+const HIDE_CURSOR : &str = "\x1b[?25l";
+const SHOW_CURSOR : &str = "\x1b[?25h";
+const CLEAR_SCREEN : &str = "\x1b[2J";
+const CURSOR_HOME : &str = "\x1b[H";
+
+// Bit for each dot of a braille character, indexed by [row][column] from the top left
+const BRAILLE_DOTS : [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+// Trail dots fainter than this are left dark, so a trail ends rather than fading out forever
+const TRAIL_CUTOFF : f32 = 0.1;
+
+// The enclosure as rows of braille characters, looking down the z axis with y pointing up
+pub fn braille_frame<const N: usize>(particles: &[Particle<N>], enclosure: &Enclosure, columns: usize, rows: usize) -> Vec<String> {
+    BrailleTrails::new(0.0, columns, rows).frame(particles, enclosure)
+}
+
+// How bright every dot has been left by the frames drawn so far, faded by the same factor each frame
+// A dot is lit until it fades below the cutoff, so a moving particle draws a short tail of dots behind it
+pub struct BrailleTrails {
+    fade: f32,
+    columns: usize,
+    rows: usize,
+    brightness: Vec<f32>, // By dot, row by row from the top left
+}
+
+impl BrailleTrails {
+    pub fn new(fade: f32, columns: usize, rows: usize) -> Self {
+        BrailleTrails { fade, columns, rows, brightness: vec![0.0; columns * 2 * rows * 4] }
+    }
+
+    // Fade what earlier frames left first, then light the dots under the particles at full brightness
+    pub fn frame<const N: usize>(&mut self, particles: &[Particle<N>], enclosure: &Enclosure) -> Vec<String> {
+        let (dots_wide, dots_high) = (self.columns * 2, self.rows * 4);
+        for dot in &mut self.brightness {
+            *dot *= self.fade;
+        }
+        for p in particles {
+            let dot_x = ((p.position[0] / enclosure.width() * dots_wide as f32) as usize).min(dots_wide - 1);
+            let dot_y = (((enclosure.height() - p.position[1]) / enclosure.height() * dots_high as f32).max(0.0) as usize).min(dots_high - 1);
+            self.brightness[dot_y * dots_wide + dot_x] = 1.0;
+        }
+
+        let mut cells = vec![0u32; self.columns * self.rows];
+        for (index, _) in self.brightness.iter().enumerate().filter(|(_, &brightness)| brightness >= TRAIL_CUTOFF) {
+            let (dot_x, dot_y) = (index % dots_wide, index / dots_wide);
+            cells[(dot_y / 4) * self.columns + dot_x / 2] |= BRAILLE_DOTS[dot_y % 4][dot_x % 2];
+        }
+
+        cells.chunks(self.columns).map(|row| row.iter().map(|&dots| char::from_u32(0x2800 + dots).unwrap_or(' ')).collect()).collect()
+    }
+}
+
+// The view thread, which stops and puts the terminal back how it was when this is dropped
+pub struct TerminalView {
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TerminalView {
+    pub fn start<const N: usize>(particle_system: Arc<RwLock<ParticleSystem<N>>>, enclosure: Enclosure, trails: f32, control: RunControl) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let thread_done = Arc::clone(&done);
+        let _ = terminal::enable_raw_mode(); // Without a terminal there are no keys to read, and the view still draws
+
+        let thread = thread::spawn(move || {
+            let mut stdout = io::stdout();
+            let _ = write!(stdout, "{}{}", HIDE_CURSOR, CLEAR_SCREEN);
+
+            let mut frame : usize = 0;
+            let mut view = BrailleTrails::new(trails, TUI_COLUMNS, TUI_ROWS);
+            while !thread_done.load(Ordering::Relaxed) {
+                let particles = read_ignoring_poison(&particle_system).particles.clone();
+
+                let mut screen = String::from(CURSOR_HOME);
+                for row in view.frame(&particles, &enclosure) {
+                    screen.push_str(&row);
+                    screen.push_str("\r\n"); // Raw mode doesn't go back to the start of the line by itself
+                }
+                let state = if control.is_paused() { "paused, space to resume" } else { "space to pause" };
+                screen.push_str(&format!("frame {}, {} particles, {}, q to stop\x1b[K\r\n", frame, particles.len(), state));
+
+                let _ = stdout.write_all(screen.as_bytes()).and_then(|_| stdout.flush());
+                frame += 1;
+                wait_for_keys(TUI_FRAME_INTERVAL, &control);
+            }
+        });
+
+        TerminalView { done, thread: Some(thread) }
+    }
+}
+
+// Act on any keys pressed in the next interval, or just sleep through it if there's no terminal to read from
+// Raw mode turns Ctrl-C into a key rather than a signal, so it is handled here too
+fn wait_for_keys(interval: Duration, control: &RunControl) {
+    match event::poll(interval) {
+        Ok(true) => {
+            if let Ok(Event::Key(key)) = event::read() {
+                match key.code {
+                    _ if key.kind != KeyEventKind::Press => {}
+                    KeyCode::Char(' ') => { control.toggle_pause(); }
+                    KeyCode::Char('q') => control.stop(),
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => control.stop(),
+                    _ => {}
+                }
+            }
+        }
+        Ok(false) => {}
+        Err(_) => thread::sleep(interval),
+    }
+}
+
+impl Drop for TerminalView {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        let _ = terminal::disable_raw_mode();
+
+        let mut stdout = io::stdout();
+        let _ = write!(stdout, "{}", SHOW_CURSOR).and_then(|_| stdout.flush());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PARTICLE_RADIUS;
+
+    #[test]
+    fn particles_light_the_dot_under_them() {
+        let enclosure = Enclosure::Rect { w: 10.0, h: 10.0 };
+        let particles = vec![
+            Particle::new(0.1, 0.1, 0.0, 0.0, PARTICLE_RADIUS), // Bottom left dot of the bottom left character
+            Particle::new(9.9, 9.9, 0.0, 0.0, PARTICLE_RADIUS), // Top right dot of the top right character
+            Particle::new(9.8, 9.8, 0.0, 0.0, PARTICLE_RADIUS), // Same dot again
+        ];
+
+        let frame = braille_frame(&particles, &enclosure, 4, 2);
+
+        assert_eq!(frame.len(), 2);
+        assert_eq!(frame[0], "\u{2800}\u{2800}\u{2800}\u{2808}");
+        assert_eq!(frame[1], "\u{2840}\u{2800}\u{2800}\u{2800}");
+    }
+
+    #[test]
+    fn trails_stay_lit_until_they_fade_out() {
+        let enclosure = Enclosure::Rect { w: 10.0, h: 10.0 };
+        let mut view = BrailleTrails::new(0.5, 4, 2);
+
+        view.frame(&[Particle::new(0.1, 0.1, 0.0, 0.0, PARTICLE_RADIUS)], &enclosure);
+        let frame = view.frame(&[Particle::new(9.9, 9.9, 0.0, 0.0, PARTICLE_RADIUS)], &enclosure);
+        assert_eq!(frame, vec!["\u{2800}\u{2800}\u{2800}\u{2808}", "\u{2840}\u{2800}\u{2800}\u{2800}"]);
+
+        // Once nothing is drawn over them both fade below the cutoff
+        for _ in 0..3 {
+            view.frame::<2>(&[], &enclosure);
+        }
+        assert_eq!(view.frame::<2>(&[], &enclosure), vec!["\u{2800}".repeat(4); 2]);
+    }
+}
@@ -0,0 +1,126 @@
+// Which walls of the enclosure particles bounce off, counted over a run to check the reflections happen where they should
+// Periodic edges aren't walls, so a particle that wraps round to the other side isn't counted
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+const WALL_COUNT : usize = 7;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Wall {
+    Left, // x = 0
+    Right,
+    Bottom, // y = 0, the floor gravity pulls particles onto
+    Top,
+    Back, // z = 0, only 3D enclosures have walls in z
+    Front,
+    Round, // The one wall of a circular enclosure
+}
+
+impl Wall {
+    pub const ALL : [Wall; WALL_COUNT] = [Wall::Left, Wall::Right, Wall::Bottom, Wall::Top, Wall::Back, Wall::Front, Wall::Round];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Wall::Left => "left",
+            Wall::Right => "right",
+            Wall::Bottom => "bottom",
+            Wall::Top => "top",
+            Wall::Back => "back",
+            Wall::Front => "front",
+            Wall::Round => "round",
+        }
+    }
+}
+
+// The walls one particle bounced off in a single move, one heading into a corner hits both of its walls
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct WallHits(u8);
+
+impl WallHits {
+    pub fn add(&mut self, wall: Wall) {
+        self.0 |= 1 << wall as u8;
+    }
+
+    pub fn contains(&self, wall: Wall) -> bool {
+        self.0 & (1 << wall as u8) != 0
+    }
+
+    pub fn any(&self) -> bool {
+        self.0 != 0
+    }
+
+    pub fn walls(self) -> impl Iterator<Item = Wall> {
+        Wall::ALL.iter().copied().filter(move |&wall| self.contains(wall))
+    }
+}
+
+// Bounces off every wall so far, cloned into every move thread of a run
+#[derive(Debug, Clone, Default)]
+pub struct WallCounter {
+    counts: Arc<[AtomicUsize; WALL_COUNT]>,
+}
+
+impl WallCounter {
+    pub fn record(&self, hits: WallHits) {
+        for wall in hits.walls() {
+            self.counts[wall as usize].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn counts(&self) -> WallCounts {
+        let mut counts = WallCounts::default();
+        for (count, counter) in counts.0.iter_mut().zip(self.counts.iter()) {
+            *count = counter.load(Ordering::Relaxed);
+        }
+        counts
+    }
+}
+
+// How many times each wall was hit over a run
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct WallCounts([usize; WALL_COUNT]);
+
+impl WallCounts {
+    pub fn get(&self, wall: Wall) -> usize {
+        self.0[wall as usize]
+    }
+
+    pub fn total(&self) -> usize {
+        self.0.iter().sum()
+    }
+
+    // Only the walls that were hit at least once
+    pub fn hit(&self) -> impl Iterator<Item = (Wall, usize)> + '_ {
+        Wall::ALL.iter().map(move |&wall| (wall, self.get(wall))).filter(|&(_, count)| count > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hits_from_every_thread_add_up_by_wall() {
+        let counter = WallCounter::default();
+        let mut corner = WallHits::default();
+        corner.add(Wall::Left);
+        corner.add(Wall::Top);
+        assert!(corner.any() && corner.contains(Wall::Top) && !corner.contains(Wall::Bottom));
+
+        let threads : Vec<_> = (0..4).map(|_| {
+            let counter = counter.clone();
+            std::thread::spawn(move || for _ in 0..100 { counter.record(corner) })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        counter.record(WallHits::default());
+
+        let counts = counter.counts();
+        assert_eq!((counts.get(Wall::Left), counts.get(Wall::Top), counts.get(Wall::Right)), (400, 400, 0));
+        assert_eq!(counts.total(), 800);
+        assert_eq!(counts.hit().collect::<Vec<_>>(), vec![(Wall::Left, 400), (Wall::Top, 400)]);
+    }
+}
@@ -0,0 +1,36 @@
+// Golden numbers for a small fixed run, so a refactor that changes the physics fails here rather than going unnoticed
+// The run is deterministic, so however the particles are split between threads or searched for pairs the counts come out the same.
+// If a change is meant to alter the physics, rerun it and update the numbers in the same commit, saying why
+use particles::broadphase::BroadphaseKind;
+use particles::config::SimConfig;
+use particles::{run_simulation, Layout, RadiusDistribution};
+
+const UNIQUE_COLLISIONS : usize = 28;
+const RAW_COLLISION_FRAMES : usize = 30;
+const WALL_COLLISIONS : usize = 8;
+
+fn golden_config() -> SimConfig {
+    // Particles big enough for the second they run to see a few dozen collisions
+    SimConfig { particle_count: 50, radius: RadiusDistribution::Fixed(0.3), layout: Layout::RandomUniform, steps: Some(100), deterministic: true, seed: Some(42), ..SimConfig::default() }
+}
+
+#[test]
+fn seed_42_collides_as_it_always_has() {
+    let report = run_simulation::<2>(&golden_config());
+
+    assert_eq!(report.unique_collisions, UNIQUE_COLLISIONS);
+    assert_eq!(report.raw_collision_frames, RAW_COLLISION_FRAMES);
+    assert_eq!(report.walls.total(), WALL_COLLISIONS);
+}
+
+#[test]
+fn every_detector_and_thread_count_gives_the_golden_numbers() {
+    let detectors = [BroadphaseKind::BruteForce, BroadphaseKind::ParallelBruteForce, BroadphaseKind::SpatialGrid, BroadphaseKind::SpatialHash, BroadphaseKind::QuadTree, BroadphaseKind::SweepAndPrune];
+    for &broadphase in &detectors {
+        for &thread_count in &[1, 3] {
+            let report = run_simulation::<2>(&SimConfig { broadphase, thread_count, ..golden_config() });
+            let counts = (report.unique_collisions, report.raw_collision_frames, report.walls.total());
+            assert_eq!(counts, (UNIQUE_COLLISIONS, RAW_COLLISION_FRAMES, WALL_COLLISIONS), "{:?} with {} threads", broadphase, thread_count);
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use particles::config::SimConfig;
+use particles::control::RunControl;
+use particles::emitter::EmitterSettings;
+use particles::broadphase::BroadphaseKind;
+use particles::lockstep::SyncMode;
+use particles::movement::MovementKind;
+use particles::outcome::CollisionOutcome;
+use particles::walls::Wall;
+use particles::{run_simulation, run_simulation_until, RadiusDistribution};
+use std::time::Duration;
+
+#[test]
+fn short_run_keeps_every_particle_in_the_enclosure() {
+    let config = SimConfig { particle_count: 50, thread_count: 2, duration: Duration::from_millis(200), seed: Some(1), ..SimConfig::default() };
+
+    let report = run_simulation::<2>(&config);
+
+    assert_eq!(report.seed, 1);
+    assert_eq!(report.system.particles.len(), 50);
+    assert_eq!(report.move_iterations.len(), 2);
+    assert!(report.move_iterations.iter().all(|&count| count > 0));
+    for p in &report.system.particles {
+        assert!(config.enclosure.contains(p.position[0], p.position[1]));
+    }
+}
+
+#[test]
+fn free_running_mode_still_runs() {
+    let config = SimConfig { particle_count: 50, thread_count: 2, duration: Duration::from_millis(200), sync: SyncMode::FreeRunning, seed: Some(1), ..SimConfig::default() };
+
+    let report = run_simulation::<2>(&config);
+
+    assert_eq!(report.system.particles.len(), 50);
+    assert!(report.move_iterations.iter().all(|&count| count > 0));
+}
+
+#[test]
+fn collision_threads_share_the_enclosure_between_them() {
+    let config = SimConfig { particle_count: 200, thread_count: 2, collision_thread_count: 3, duration: Duration::from_millis(200), sync: SyncMode::FreeRunning, seed: Some(2), ..SimConfig::default() };
+
+    let report = run_simulation::<2>(&config);
+
+    assert_eq!(report.system.particles.len(), 200);
+    assert!(report.errors.is_empty());
+    assert!(report.total_frames > 0);
+}
+
+#[test]
+fn annihilated_pairs_leave_the_system() {
+    for &sync in &[SyncMode::Barrier, SyncMode::FreeRunning] {
+        let config = SimConfig { particle_count: 60, thread_count: 3, steps: Some(100), sync, outcome: CollisionOutcome::Annihilate, seed: Some(6), ..SimConfig::default() };
+
+        let report = run_simulation::<2>(&config);
+
+        assert!(report.annihilations > 0); // Every particle starts in the same corner
+        assert_eq!(report.system.particles.len(), 60 - 2 * report.annihilations);
+        assert!(report.errors.is_empty());
+        assert!(report.to_string().contains("annihilations: "));
+    }
+}
+
+#[test]
+fn merged_particles_keep_their_mass_and_unique_ids() {
+    let config = SimConfig { particle_count: 60, thread_count: 3, steps: Some(100), outcome: CollisionOutcome::Merge, seed: Some(6), ..SimConfig::default() };
+    let total_mass = |particles: &[particles::Particle]| particles.iter().map(|p| p.mass).sum::<f32>();
+    let start_mass = total_mass(&run_simulation::<2>(&SimConfig { steps: Some(0), ..config.clone() }).system.particles);
+
+    let report = run_simulation::<2>(&config);
+
+    let particles = &report.system.particles;
+    assert!(report.merges > 0);
+    assert_eq!(particles.len(), 60 - report.merges);
+    assert!(particles.windows(2).all(|pair| pair[0].id < pair[1].id));
+    assert!((total_mass(particles) - start_mass).abs() < start_mass * 1e-4);
+    assert!(report.to_string().contains("merges: "));
+}
+
+#[test]
+fn emitters_add_particles_up_to_their_cap() {
+    for &sync in &[SyncMode::Barrier, SyncMode::FreeRunning] {
+        let emitter = EmitterSettings { every: 5, position: Some((2.0, 5.0)), max_particles: 25, ..EmitterSettings::default() };
+        let config = SimConfig { particle_count: 10, thread_count: 2, steps: Some(50), layout: particles::Layout::Grid, sync, emitter: Some(emitter), seed: Some(3), ..SimConfig::default() };
+
+        assert_eq!(run_simulation::<2>(&config).system.particles.len(), 20); // One on every fifth of the 50 steps
+        assert_eq!(run_simulation::<2>(&SimConfig { steps: Some(200), ..config }).system.particles.len(), 25);
+    }
+}
+
+#[test]
+fn setting_stop_ends_the_run_early() {
+    let config = SimConfig { particle_count: 50, duration: Duration::from_secs(60), seed: Some(1), ..SimConfig::default() };
+    let control = RunControl::default();
+
+    let stopper = control.clone();
+    let handle = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(100));
+        stopper.stop();
+    });
+
+    let report = run_simulation_until::<2>(&config, control);
+    handle.join().unwrap();
+
+    assert!(report.wall_clock < Duration::from_secs(10));
+    assert_eq!(report.system.particles.len(), 50);
+}
+
+#[test]
+fn paused_time_doesnt_count_towards_the_run() {
+    for &sync in &[SyncMode::Barrier, SyncMode::FreeRunning] {
+        let config = SimConfig { particle_count: 50, thread_count: 2, duration: Duration::from_millis(300), sync, seed: Some(1), ..SimConfig::default() };
+        let control = RunControl::default();
+
+        let pauser = control.clone();
+        let handle = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            pauser.set_paused(true);
+            std::thread::sleep(Duration::from_millis(400));
+            pauser.set_paused(false);
+        });
+
+        let report = run_simulation_until::<2>(&config, control);
+        handle.join().unwrap();
+
+        assert!(report.wall_clock >= Duration::from_millis(700), "{:?} run ended after {:?}", sync, report.wall_clock);
+        assert!(report.total_frames > 0 && report.errors.is_empty());
+    }
+}
+
+#[test]
+fn seeded_barrier_runs_with_a_step_count_are_identical() {
+    let config = SimConfig { particle_count: 60, thread_count: 3, steps: Some(200), repulsion: 0.05, seed: Some(7), ..SimConfig::default() };
+
+    let first = run_simulation::<2>(&config);
+    let second = run_simulation::<2>(&config);
+
+    assert!(first.move_iterations.iter().all(|&count| count == 200));
+    assert_eq!(first.total_frames, 200);
+
+    // Everything but the wall clock time
+    assert_eq!(first.system.particles, second.system.particles);
+    assert_eq!((first.unique_collisions, first.raw_collision_frames), (second.unique_collisions, second.raw_collision_frames));
+    assert_eq!(first.move_iterations, second.move_iterations);
+}
+
+#[test]
+fn every_new_collision_is_logged_with_its_time() {
+    let path = std::env::temp_dir().join(format!("particles_collision_log_{}.csv", std::process::id())).to_string_lossy().into_owned();
+    let config = SimConfig { particle_count: 200, radius: RadiusDistribution::Fixed(0.2), thread_count: 2, steps: Some(100), seed: Some(4), collision_log_path: Some(path.clone()), recent_collisions: 3, ..SimConfig::default() };
+
+    let report = run_simulation::<2>(&config);
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let mut lines = contents.lines();
+    assert_eq!(lines.next(), Some("frame,seconds,a,b"));
+    let events : Vec<(usize, f64, u64, u64)> = lines.map(|line| {
+        let fields : Vec<&str> = line.split(',').collect();
+        (fields[0].parse().unwrap(), fields[1].parse().unwrap(), fields[2].parse().unwrap(), fields[3].parse().unwrap())
+    }).collect();
+
+    assert!(report.unique_collisions > 0);
+    assert_eq!(events.len(), report.unique_collisions);
+    assert!(events.iter().all(|&(frame, _, a, b)| frame < 100 && a < b));
+    assert!(events.windows(2).all(|pair| pair[0].0 <= pair[1].0 && pair[0].1 <= pair[1].1)); // In the order they happened
+
+    // The report lists the last few, which are the end of the log
+    let recent : Vec<(usize, u64, u64)> = report.recent_collisions.iter().map(|e| (e.frame, e.a, e.b)).collect();
+    let last : Vec<(usize, u64, u64)> = events[events.len() - 3..].iter().map(|&(frame, _, a, b)| (frame, a, b)).collect();
+    assert_eq!(recent, last);
+    assert_eq!(report.to_string().matches("recent_collision: ").count(), 3);
+}
+
+#[test]
+fn sampled_frames_are_written_as_json_lines() {
+    let path = std::env::temp_dir().join(format!("particles_jsonl_run_{}.ndjson", std::process::id())).to_string_lossy().into_owned();
+    let config = SimConfig { particle_count: 30, thread_count: 2, steps: Some(50), seed: Some(3), jsonl_path: Some(path.clone()), jsonl_every: 5, ..SimConfig::default() };
+
+    let report = run_simulation::<2>(&config);
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let frames : Vec<serde_json::Value> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(frames.len(), report.total_frames.div_ceil(5));
+    for (index, frame) in frames.iter().enumerate() {
+        assert_eq!(frame["frame"], index * 5);
+        let particles = frame["particles"].as_array().unwrap();
+        assert_eq!(particles.len(), 30);
+        assert!(particles.iter().all(|p| p["id"].is_u64() && p["x"].is_f64() && p["y"].is_f64() && p.get("z").is_none()));
+    }
+}
+
+#[test]
+fn gnuplot_scripts_are_written_next_to_the_csvs() {
+    let dir = std::env::temp_dir();
+    let (trajectory, energy) = (dir.join(format!("particles_gp_trajectory_{}.csv", std::process::id())), dir.join(format!("particles_gp_energy_{}.csv", std::process::id())));
+    let config = SimConfig { particle_count: 20, thread_count: 2, steps: Some(20), seed: Some(8), gnuplot: true, record_path: Some(trajectory.to_string_lossy().into_owned()), energy_path: Some(energy.to_string_lossy().into_owned()), ..SimConfig::default() };
+
+    run_simulation::<2>(&config);
+    let trajectory_script = std::fs::read_to_string(trajectory.with_extension("gp")).unwrap();
+    let energy_script = std::fs::read_to_string(energy.with_extension("gp")).unwrap();
+    for path in &[&trajectory, &energy, &trajectory.with_extension("gp"), &energy.with_extension("gp")] {
+        std::fs::remove_file(path).unwrap();
+    }
+
+    let name = |path: &std::path::Path| path.file_name().unwrap().to_string_lossy().into_owned();
+    assert!(trajectory_script.contains(&name(&trajectory)) && trajectory_script.contains(&format!("set xrange [0:{}]", config.enclosure.width())));
+    assert!(energy_script.contains(&name(&energy)) && energy_script.contains("using 1:2 with lines"));
+}
+
+#[test]
+fn metrics_are_sampled_through_the_run() {
+    let path = std::env::temp_dir().join(format!("particles_metrics_{}.csv", std::process::id())).to_string_lossy().into_owned();
+    let config = SimConfig { particle_count: 100, thread_count: 2, duration: Duration::from_millis(300), seed: Some(5), metrics_path: Some(path.clone()), metrics_interval: Duration::from_millis(50), ..SimConfig::default() };
+
+    run_simulation::<2>(&config);
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let mut lines = contents.lines();
+    assert_eq!(lines.next(), Some("seconds,active_collisions,kinetic_energy,average_speed,centre_x,centre_y,centre_z,next_collision,centre_vx,centre_vy,centre_vz"));
+    // No time to the next collision while the particles are still all overlapping or flying apart from the corner they start in
+    let rows : Vec<Vec<f64>> = lines.map(|line| line.split(',').map(|field| if field.is_empty() { f64::NAN } else { field.parse().unwrap() }).collect()).collect();
+
+    assert!(rows.len() >= 3, "only {} samples", rows.len());
+    assert!(rows.windows(2).all(|pair| pair[0][0] < pair[1][0]));
+    for row in &rows {
+        assert!(row[2] > 0.0 && row[3] > 0.0 && (row[7].is_nan() || row[7] > 0.0));
+        assert!(config.enclosure.contains(row[4] as f32, row[5] as f32));
+        assert!(row[8..].iter().all(|v| v.is_finite()));
+    }
+}
+
+#[test]
+fn elastic_collisions_and_walls_keep_the_energy_flat() {
+    let config = SimConfig { particle_count: 100, thread_count: 2, steps: Some(500), seed: Some(3), ..SimConfig::default() };
+
+    let report = run_simulation::<2>(&config);
+
+    assert!(report.unique_collisions > 0);
+    assert!(report.max_energy_drift < 1e-3, "energy drifted by {}", report.max_energy_drift);
+}
+
+#[test]
+fn every_detector_resolves_collisions_to_the_same_state() {
+    // Crowded enough that particles often touch two others at once, where the order pairs are bounced in matters
+    let kinds = [BroadphaseKind::BruteForce, BroadphaseKind::ParallelBruteForce, BroadphaseKind::SpatialGrid, BroadphaseKind::SpatialHash, BroadphaseKind::QuadTree, BroadphaseKind::SweepAndPrune];
+    let config = SimConfig { particle_count: 400, radius: RadiusDistribution::Fixed(0.2), layout: particles::Layout::RandomUniform, temperature: Some(1.0), thread_count: 3, steps: Some(100), deterministic: true, seed: Some(12), ..SimConfig::default() };
+
+    let expected = run_simulation::<2>(&SimConfig { broadphase: BroadphaseKind::BruteForce, ..config.clone() });
+    assert!(expected.unique_collisions > 0);
+    for kind in kinds {
+        let report = run_simulation::<2>(&SimConfig { broadphase: kind, ..config.clone() });
+        assert_eq!(report.unique_collisions, expected.unique_collisions, "{:?}", kind);
+        assert_eq!(report.system.particles, expected.system.particles, "{:?}", kind);
+    }
+}
+
+#[test]
+fn event_driven_runs_bounce_without_overlapping() {
+    let config = SimConfig { particle_count: 100, radius: RadiusDistribution::Fixed(0.2), layout: particles::Layout::RandomUniform, temperature: Some(0.5), steps: Some(300), event_driven: true, seed: Some(3), ..SimConfig::default() };
+
+    let report = run_simulation::<2>(&config);
+
+    assert_eq!(report.total_frames, 300);
+    assert!(report.unique_collisions > 0 && report.walls.total() > 0);
+    assert!(report.max_energy_drift < 1e-3, "energy drifted by {}", report.max_energy_drift);
+    for p in &report.system.particles {
+        assert!(config.enclosure.contains(p.position[0], p.position[1]));
+    }
+}
+
+#[test]
+fn particles_starting_on_top_of_each_other_are_cluster_collisions() {
+    // Everything starts at the origin, and is still within 0.02 of everything else by the first snapshot a step later
+    let config = SimConfig { particle_count: 20, steps: Some(50), seed: Some(8), ..SimConfig::default() };
+    let clustered = run_simulation::<2>(&SimConfig { cluster_epsilon: Some(0.02), ..config.clone() });
+    let unclustered = run_simulation::<2>(&config);
+
+    assert_eq!(clustered.cluster_collisions, 20 * 19 / 2);
+    assert_eq!(unclustered.cluster_collisions, 0);
+    assert!(clustered.unique_collisions < unclustered.unique_collisions);
+    assert!(clustered.to_string().contains("cluster_collisions: 190") && !unclustered.to_string().contains("cluster_collisions"));
+}
+
+#[test]
+fn a_tick_rate_caps_the_steps_taken() {
+    // 50 steps a second for 200ms is 10 steps, and flat out is far more
+    for &sync in &[SyncMode::Barrier, SyncMode::FreeRunning] {
+        let config = SimConfig { particle_count: 20, thread_count: 2, duration: Duration::from_millis(200), tick_hz: 50.0, sync, ..SimConfig::default() };
+        let report = run_simulation::<2>(&config);
+        assert!(report.move_iterations.iter().all(|&steps| (5..=12).contains(&steps)), "{:?} took {:?}", sync, report.move_iterations);
+    }
+}
+
+#[test]
+fn profiled_runs_report_every_thread_that_takes_the_lock() {
+    let config = SimConfig { particle_count: 40, thread_count: 2, steps: Some(50), profile: true, ..SimConfig::default() };
+
+    let report = run_simulation::<2>(&config);
+
+    let threads : Vec<&str> = report.lock_profiles.iter().map(|profile| profile.thread.as_str()).collect();
+    assert_eq!(threads, vec!["move thread 0", "move thread 1", "coordinator"]);
+    assert!(report.lock_profiles.iter().all(|profile| profile.acquisitions >= 50));
+    assert!(report.to_string().contains("lock_waiting_seconds"));
+    assert!(run_simulation::<2>(&SimConfig { profile: false, ..config }).lock_profiles.is_empty());
+}
+
+#[test]
+fn any_thread_count_runs_every_particle() {
+    for thread_count in [1, 8] {
+        let config = SimConfig { particle_count: 3, thread_count, steps: Some(20), ..SimConfig::default() };
+
+        let report = run_simulation::<2>(&config);
+
+        assert_eq!(report.system.particles.len(), 3);
+        assert_eq!(report.move_iterations, vec![20; thread_count]);
+        assert!(report.errors.is_empty());
+    }
+}
+
+#[test]
+fn json_report_carries_the_counts_and_the_config() {
+    let config = SimConfig { particle_count: 40, thread_count: 2, steps: Some(20), seed: Some(6), ..SimConfig::default() };
+
+    let report = run_simulation::<2>(&config);
+    let json : serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+
+    assert_eq!(json["seed"], 6);
+    assert_eq!(json["unique_collisions"], report.unique_collisions);
+    assert_eq!(json["total_frames"], report.total_frames);
+    assert_eq!(json["move_iterations"], serde_json::json!([20, 20]));
+    assert_eq!(json["config"]["particle_count"], 40);
+    assert_eq!(json["config"]["broadphase"], "grid");
+    assert_eq!(json["config"]["sync"], "barrier");
+    assert!(json["max_energy_drift"].is_number());
+    assert!(json["errors"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn wall_bounces_are_counted_by_wall() {
+    for &sync in &[SyncMode::Barrier, SyncMode::FreeRunning] {
+        let config = SimConfig { particle_count: 50, thread_count: 2, steps: Some(300), sync, seed: Some(4), ..SimConfig::default() };
+
+        let report = run_simulation::<2>(&config);
+        let json : serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+
+        // They all start in the corner at the origin, so plenty head straight into the left and bottom walls
+        assert!(report.walls.get(Wall::Left) > 0 && report.walls.get(Wall::Bottom) > 0, "{:?}", report.walls);
+        assert_eq!(report.walls.get(Wall::Back) + report.walls.get(Wall::Front) + report.walls.get(Wall::Round), 0); // Flat and square
+        assert!(report.to_string().contains(&format!("wall_collisions: {}", report.walls.total())));
+        assert_eq!(json["wall_collisions"], report.walls.total());
+        assert_eq!(json["wall_collisions_by_wall"]["left"], report.walls.get(Wall::Left));
+    }
+}
+
+#[test]
+fn deterministic_runs_give_the_same_report_every_time() {
+    let configs = [
+        SimConfig { particle_count: 200, thread_count: 3, steps: Some(100), repulsion: 0.05, gravity: -1.0, deterministic: true, seed: Some(21), ..SimConfig::default() },
+        SimConfig { particle_count: 200, thread_count: 3, steps: Some(100), movement: MovementKind::Brownian, deterministic: true, seed: Some(22), ..SimConfig::default() },
+    ];
+
+    for config in &configs {
+        let first = run_simulation::<2>(config);
+        let second = run_simulation::<2>(config);
+
+        assert!(first.unique_collisions > 0);
+        assert_eq!(first.unique_collisions, second.unique_collisions);
+        assert_eq!(first.raw_collision_frames, second.raw_collision_frames);
+        assert_eq!(first.system.particles, second.system.particles);
+    }
+}